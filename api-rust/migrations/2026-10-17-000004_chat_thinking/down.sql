@@ -0,0 +1,2 @@
+ALTER TABLE chats DROP COLUMN thinking_budget;
+ALTER TABLE chats DROP COLUMN thinking;
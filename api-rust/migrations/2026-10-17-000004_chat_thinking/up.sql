@@ -0,0 +1,3 @@
+-- Per-chat extended thinking switch and token budget
+ALTER TABLE chats ADD COLUMN thinking BOOLEAN;
+ALTER TABLE chats ADD COLUMN thinking_budget INTEGER;
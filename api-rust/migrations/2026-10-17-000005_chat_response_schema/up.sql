@@ -0,0 +1,2 @@
+-- Per-chat JSON Schema for structured (JSON) answers
+ALTER TABLE chats ADD COLUMN response_schema TEXT;
@@ -0,0 +1,2 @@
+DROP TABLE message_usage;
+DROP TABLE model_prices;
@@ -0,0 +1,36 @@
+-- Admin price overrides, versioned per model (USD per 1M tokens)
+CREATE TABLE model_prices (
+    id VARCHAR(64) PRIMARY KEY NOT NULL,
+    model_id VARCHAR(255) NOT NULL,
+    version INTEGER NOT NULL,
+    input_price DOUBLE PRECISION NOT NULL,
+    output_price DOUBLE PRECISION NOT NULL,
+    cache_read_price DOUBLE PRECISION,
+    cache_write_price DOUBLE PRECISION,
+    created_by VARCHAR(64),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX idx_model_prices_model_id_version ON model_prices(model_id, version);
+
+-- Token usage and cost of every model call behind a message (answers,
+-- chat summaries); kept when chats are deleted
+CREATE TABLE message_usage (
+    id VARCHAR(64) PRIMARY KEY NOT NULL,
+    message_id VARCHAR(64) NOT NULL,
+    chat_id VARCHAR(64) NOT NULL,
+    user_id VARCHAR(64) NOT NULL,
+    model_id VARCHAR(255) NOT NULL,
+    input_tokens INTEGER NOT NULL,
+    output_tokens INTEGER NOT NULL,
+    cache_read_input_tokens INTEGER NOT NULL,
+    cache_write_input_tokens INTEGER NOT NULL,
+    cost DOUBLE PRECISION,
+    price_version INTEGER,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_message_usage_message_id ON message_usage(message_id);
+CREATE INDEX idx_message_usage_user_id ON message_usage(user_id);
+CREATE INDEX idx_message_usage_chat_id ON message_usage(chat_id);
+CREATE INDEX idx_message_usage_created_at ON message_usage(created_at);
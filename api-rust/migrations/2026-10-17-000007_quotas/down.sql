@@ -0,0 +1,2 @@
+DROP TABLE quota_resets;
+DROP TABLE quota_limits;
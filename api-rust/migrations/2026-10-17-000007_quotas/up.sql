@@ -0,0 +1,27 @@
+-- Quota limits per role or per user; a user's limit overrides the role's
+-- for the same metric and period, a negative limit means unlimited
+CREATE TABLE quota_limits (
+    id VARCHAR(64) PRIMARY KEY NOT NULL,
+    scope VARCHAR(16) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    metric VARCHAR(32) NOT NULL,
+    period VARCHAR(16) NOT NULL,
+    limit_value DOUBLE PRECISION NOT NULL,
+    created_by VARCHAR(64),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX idx_quota_limits_scope_subject_metric_period
+    ON quota_limits(scope, subject, metric, period);
+
+-- Admin resets: a user's usage of the metric counts from the latest reset
+CREATE TABLE quota_resets (
+    id VARCHAR(64) PRIMARY KEY NOT NULL,
+    user_id VARCHAR(64) NOT NULL,
+    metric VARCHAR(32) NOT NULL,
+    created_by VARCHAR(64),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_quota_resets_user_id ON quota_resets(user_id);
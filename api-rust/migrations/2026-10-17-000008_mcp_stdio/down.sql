@@ -0,0 +1,3 @@
+ALTER TABLE mcp_servers DROP COLUMN env;
+ALTER TABLE mcp_servers DROP COLUMN args;
+ALTER TABLE mcp_servers DROP COLUMN command;
@@ -0,0 +1,5 @@
+-- STDIO MCP servers: the command spawned, its args (JSON array) and
+-- environment (JSON object)
+ALTER TABLE mcp_servers ADD COLUMN command TEXT;
+ALTER TABLE mcp_servers ADD COLUMN args TEXT;
+ALTER TABLE mcp_servers ADD COLUMN env TEXT;
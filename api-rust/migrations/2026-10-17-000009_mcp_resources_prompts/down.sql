@@ -0,0 +1,3 @@
+ALTER TABLE mcp_servers DROP COLUMN prompts;
+ALTER TABLE mcp_servers DROP COLUMN resource_templates;
+ALTER TABLE mcp_servers DROP COLUMN resources;
@@ -0,0 +1,5 @@
+-- MCP resources, resource templates and prompts (JSON arrays), listed
+-- along with the tools
+ALTER TABLE mcp_servers ADD COLUMN resources TEXT;
+ALTER TABLE mcp_servers ADD COLUMN resource_templates TEXT;
+ALTER TABLE mcp_servers ADD COLUMN prompts TEXT;
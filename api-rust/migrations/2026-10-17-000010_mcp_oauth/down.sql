@@ -0,0 +1,2 @@
+DROP TABLE mcp_oauth_states;
+DROP TABLE mcp_oauth_tokens;
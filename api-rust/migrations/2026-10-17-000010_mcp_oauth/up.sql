@@ -0,0 +1,25 @@
+-- MCP OAuth tokens per user and server, used by the backend (chats,
+-- background tool calls) when the client supplies none
+CREATE TABLE mcp_oauth_tokens (
+    id VARCHAR(64) PRIMARY KEY NOT NULL,
+    user_id VARCHAR(64) NOT NULL,
+    server_id VARCHAR(64) NOT NULL,
+    access_token TEXT NOT NULL,
+    refresh_token TEXT,
+    scope TEXT,
+    expires_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX idx_mcp_oauth_tokens_user_id_server_id
+    ON mcp_oauth_tokens(user_id, server_id);
+
+-- Authorization requests awaiting their callback: `id` is the OAuth state
+CREATE TABLE mcp_oauth_states (
+    id VARCHAR(64) PRIMARY KEY NOT NULL,
+    user_id VARCHAR(64) NOT NULL,
+    server_id VARCHAR(64) NOT NULL,
+    code_verifier TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
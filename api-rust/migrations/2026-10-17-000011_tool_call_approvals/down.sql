@@ -0,0 +1,3 @@
+DROP TABLE tool_call_approvals;
+ALTER TABLE mcp_servers DROP COLUMN approval_tools;
+ALTER TABLE mcp_servers DROP COLUMN requires_approval;
@@ -0,0 +1,22 @@
+-- MCP tools needing the user's approval before they run: every tool of a
+-- server, or the ones listed (JSON array of tool names)
+ALTER TABLE mcp_servers ADD COLUMN requires_approval BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE mcp_servers ADD COLUMN approval_tools TEXT;
+
+-- Tool calls of a generation awaiting (or given) the user's decision
+CREATE TABLE tool_call_approvals (
+    id VARCHAR(64) PRIMARY KEY NOT NULL,
+    user_id VARCHAR(64) NOT NULL,
+    chat_id VARCHAR(64) NOT NULL,
+    message_id VARCHAR(64) NOT NULL,
+    tool_call_id TEXT NOT NULL,
+    tool_name TEXT NOT NULL,
+    server_id VARCHAR(64),
+    arguments TEXT NOT NULL,
+    status VARCHAR(16) NOT NULL,
+    reason TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_tool_call_approvals_chat_id ON tool_call_approvals(chat_id);
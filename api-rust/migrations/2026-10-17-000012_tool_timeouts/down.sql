@@ -0,0 +1,2 @@
+ALTER TABLE mcp_servers DROP COLUMN tool_timeouts;
+ALTER TABLE mcp_servers DROP COLUMN tool_timeout_ms;
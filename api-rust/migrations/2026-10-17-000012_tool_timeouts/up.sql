@@ -0,0 +1,4 @@
+-- Deadlines of MCP tool calls: for every tool of a server, or per tool
+-- (JSON object of tool name to milliseconds)
+ALTER TABLE mcp_servers ADD COLUMN tool_timeout_ms INTEGER;
+ALTER TABLE mcp_servers ADD COLUMN tool_timeouts TEXT;
@@ -0,0 +1,31 @@
+-- Quota-counted creations (chats, user messages, generated images,
+-- documents); kept when the counted rows are deleted, so deleting them
+-- does not give the quota back
+CREATE TABLE quota_usage (
+    id VARCHAR(64) PRIMARY KEY NOT NULL,
+    user_id VARCHAR(64) NOT NULL,
+    metric VARCHAR(32) NOT NULL,
+    amount DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_quota_usage_user_id_metric ON quota_usage(user_id, metric);
+
+-- What was created so far; the rows keep the ids of what they count
+-- (UUIDs, so they cannot collide across tables)
+INSERT INTO quota_usage (id, user_id, metric, amount, created_at)
+SELECT id, user_id, 'CHATS', 1, created_at
+FROM chats WHERE user_id IS NOT NULL;
+
+INSERT INTO quota_usage (id, user_id, metric, amount, created_at)
+SELECT id, user_id, 'MESSAGES', 1, created_at
+FROM messages WHERE user_id IS NOT NULL AND role = 'user';
+
+INSERT INTO quota_usage (id, user_id, metric, amount, created_at)
+SELECT chat_files.id, chats.user_id, 'IMAGES', 1, chat_files.created_at
+FROM chat_files INNER JOIN chats ON chats.id = chat_files.chat_id
+WHERE chats.user_id IS NOT NULL AND chat_files.type = 'image';
+
+INSERT INTO quota_usage (id, user_id, metric, amount, created_at)
+SELECT id, owner_id, 'DOCUMENTS', 1, created_at
+FROM documents;
@@ -0,0 +1,2 @@
+ALTER TABLE mcp_oauth_tokens DROP COLUMN auth_config;
+ALTER TABLE mcp_oauth_states DROP COLUMN auth_config;
@@ -0,0 +1,4 @@
+-- OAuth client registered for one user of a shared MCP server (JSON auth
+-- config): kept with the user's authorization, not on the shared server
+ALTER TABLE mcp_oauth_states ADD COLUMN auth_config TEXT;
+ALTER TABLE mcp_oauth_tokens ADD COLUMN auth_config TEXT;
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 use std::env;
 
+use crate::services::retry::RetryPolicy;
+use crate::services::tools::ToolExecutionPolicy;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
     pub database_url: String,
@@ -43,6 +46,14 @@ pub struct AppConfig {
     // OpenAI
     pub openai_api_key: Option<String>,
 
+    // Anthropic
+    pub anthropic_api_key: Option<String>,
+    pub anthropic_api_url: Option<String>,
+
+    // Google Gemini
+    pub gemini_api_key: Option<String>,
+    pub gemini_api_url: Option<String>,
+
     // Yandex
     pub yandex_api_key: Option<String>,
     pub yandex_folder_id: Option<String>,
@@ -50,6 +61,15 @@ pub struct AppConfig {
     pub yandex_search_api_key: Option<String>,
     pub yandex_search_api_url: Option<String>,
 
+    // Web search tool backend: yandex (default), searxng, brave, tavily or
+    // json; URL and key of the non-Yandex backends
+    pub web_search_backend: Option<String>,
+    pub web_search_api_url: Option<String>,
+    pub web_search_api_key: Option<String>,
+    pub web_search_results_path: Option<String>,
+    // The URL is from the user's settings: public addresses only
+    pub web_search_user_url: bool,
+
     // SQS (RAG documents pipeline: parse commands out, index commands in)
     pub sqs_endpoint: Option<String>,
     pub sqs_region: Option<String>,
@@ -64,6 +84,12 @@ pub struct AppConfig {
 
     // Enabled API providers
     pub enabled_api_providers: Vec<String>,
+
+    // Retries of transient provider failures (429 / 5xx / timeouts)
+    pub retry: RetryPolicy,
+
+    // In-chat tool calls: concurrency, deadline and output cap
+    pub tool_execution: ToolExecutionPolicy,
 }
 
 impl AppConfig {
@@ -90,6 +116,14 @@ impl AppConfig {
             // OpenAI
             openai_api_key: env::var("OPENAI_API_KEY").ok(),
 
+            // Anthropic
+            anthropic_api_key: env::var("ANTHROPIC_API_KEY").ok(),
+            anthropic_api_url: env::var("ANTHROPIC_API_URL").ok(),
+
+            // Google Gemini
+            gemini_api_key: env::var("GEMINI_API_KEY").ok(),
+            gemini_api_url: env::var("GEMINI_API_URL").ok(),
+
             // Yandex
             yandex_api_key: env::var("YANDEX_FM_API_KEY").ok(),
             yandex_folder_id: env::var("YANDEX_FM_API_FOLDER").ok(),
@@ -99,6 +133,13 @@ impl AppConfig {
                 .ok(),
             yandex_search_api_url: env::var("YANDEX_SEARCH_API_URL").ok(),
 
+            // Web search
+            web_search_backend: env::var("WEB_SEARCH_BACKEND").ok(),
+            web_search_api_url: env::var("WEB_SEARCH_API_URL").ok(),
+            web_search_api_key: env::var("WEB_SEARCH_API_KEY").ok(),
+            web_search_results_path: env::var("WEB_SEARCH_RESULTS_PATH").ok(),
+            web_search_user_url: false,
+
             // SQS
             sqs_endpoint: env::var("SQS_ENDPOINT").ok(),
             sqs_region: env::var("SQS_REGION").ok(),
@@ -147,6 +188,12 @@ impl AppConfig {
             // Enabled API providers
             enabled_api_providers: Self::parse_enabled_providers(),
 
+            // AI_RETRY_MAX_ATTEMPTS / _BASE_DELAY_MS / _MAX_DELAY_MS / _DEADLINE_MS
+            retry: RetryPolicy::from_env(),
+
+            // TOOL_CALL_CONCURRENCY / TOOL_CALL_TIMEOUT_MS / TOOL_OUTPUT_MAX_CHARS
+            tool_execution: Self::parse_tool_execution(),
+
             // Default admin emails
             default_admin_emails: match env::var("DEFAULT_ADMIN_EMAILS") {
                 Ok(value) => value.split(',').map(|s| s.trim().to_string()).collect(),
@@ -157,12 +204,29 @@ impl AppConfig {
         }
     }
 
+    fn parse_tool_execution() -> ToolExecutionPolicy {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(default)
+        }
+        let defaults = ToolExecutionPolicy::default();
+        ToolExecutionPolicy {
+            concurrency: var("TOOL_CALL_CONCURRENCY", defaults.concurrency).max(1),
+            timeout_ms: var("TOOL_CALL_TIMEOUT_MS", defaults.timeout_ms),
+            max_output_chars: var("TOOL_OUTPUT_MAX_CHARS", defaults.max_output_chars),
+        }
+    }
+
     fn parse_enabled_providers() -> Vec<String> {
         let all_providers = vec![
             "AWS_BEDROCK".to_string(),
             "OPEN_AI".to_string(),
             "YANDEX_AI".to_string(),
             "CUSTOM_REST_API".to_string(),
+            "ANTHROPIC".to_string(),
+            "GEMINI".to_string(),
         ];
 
         match env::var("ENABLED_API_PROVIDERS") {
@@ -210,13 +274,22 @@ impl AppConfig {
             return config;
         };
 
+        fn non_blank(value: &Option<String>) -> Option<String> {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(String::from)
+        }
         fn merge(target: &mut Option<String>, value: &Option<String>) {
-            if let Some(value) = value.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
-                *target = Some(value.to_string());
+            if let Some(value) = non_blank(value) {
+                *target = Some(value);
             }
         }
 
         merge(&mut config.openai_api_key, &settings.openai_api_key);
+        merge(&mut config.anthropic_api_key, &settings.anthropic_api_key);
+        merge(&mut config.gemini_api_key, &settings.gemini_api_key);
 
         merge(&mut config.yandex_api_key, &settings.yandex_fm_api_key);
         merge(
@@ -224,6 +297,17 @@ impl AppConfig {
             &settings.yandex_fm_api_folder_id,
         );
 
+        // The search endpoint and its key are taken as a set: a user's URL
+        // never gets the server's key, nor the user's key the server's URL
+        let user_search_url = non_blank(&settings.web_search_api_url);
+        let user_search_key = non_blank(&settings.web_search_api_key);
+        if user_search_url.is_some() || user_search_key.is_some() {
+            merge(&mut config.web_search_backend, &settings.web_search_backend);
+            config.web_search_user_url = user_search_url.is_some();
+            config.web_search_api_url = user_search_url;
+            config.web_search_api_key = user_search_key;
+        }
+
         merge(&mut config.aws_bedrock_region, &settings.aws_bedrock_region);
         merge(
             &mut config.aws_bedrock_profile_name,
@@ -279,4 +363,47 @@ mod tests {
         let unchanged = config.with_user_settings(None);
         assert_eq!(unchanged.openai_api_key.as_deref(), Some("env-key"));
     }
+
+    #[test]
+    fn user_search_url_never_gets_the_server_key() {
+        let mut config = AppConfig::from_env();
+        config.web_search_backend = Some("brave".to_string());
+        config.web_search_api_url = None;
+        config.web_search_api_key = Some("server-key".to_string());
+
+        let url_only = JsonUserSettings {
+            web_search_api_url: Some("https://search.example.com".to_string()),
+            ..JsonUserSettings::default()
+        };
+        let merged = config.with_user_settings(Some(&url_only));
+        assert_eq!(merged.web_search_backend.as_deref(), Some("brave"));
+        assert_eq!(
+            merged.web_search_api_url.as_deref(),
+            Some("https://search.example.com")
+        );
+        assert_eq!(merged.web_search_api_key, None);
+        assert!(merged.web_search_user_url);
+        assert_eq!(
+            crate::services::search_backend::SearchBackend::from_config(&merged),
+            None
+        );
+
+        // the user's backend comes with the user's own key only
+        let backend_only = JsonUserSettings {
+            web_search_backend: Some("tavily".to_string()),
+            ..JsonUserSettings::default()
+        };
+        let merged = config.with_user_settings(Some(&backend_only));
+        assert_eq!(merged.web_search_backend.as_deref(), Some("brave"));
+        assert_eq!(merged.web_search_api_key.as_deref(), Some("server-key"));
+
+        let own_key = JsonUserSettings {
+            web_search_backend: Some("tavily".to_string()),
+            web_search_api_key: Some("user-key".to_string()),
+            ..JsonUserSettings::default()
+        };
+        let merged = config.with_user_settings(Some(&own_key));
+        assert_eq!(merged.web_search_backend.as_deref(), Some("tavily"));
+        assert_eq!(merged.web_search_api_key.as_deref(), Some("user-key"));
+    }
 }
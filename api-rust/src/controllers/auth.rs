@@ -5,6 +5,8 @@ use oauth2::reqwest::async_http_client;
 use oauth2::{AuthorizationCode, CsrfToken, Scope, TokenResponse};
 use reqwest;
 use rocket::form::FromForm;
+use rocket::http::Status;
+use rocket::response::content::RawHtml;
 use rocket::serde::json::Json;
 use rocket::serde::{Deserialize, Serialize};
 use rocket::{get, response::Redirect, routes, Route, State};
@@ -22,6 +24,7 @@ pub struct OAuthCallbackQuery {
     pub code: Option<String>,
     pub state: Option<String>,
     pub error: Option<String>,
+    pub error_description: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -54,7 +57,8 @@ pub fn routes() -> Vec<Route> {
         google_oauth,
         google_callback,
         github_oauth,
-        github_callback
+        github_callback,
+        mcp_callback
     ]
 }
 
@@ -565,3 +569,111 @@ pub async fn github_callback(
         frontend_url, token
     )))
 }
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Popup page closing the MCP authorization: tells the opener window how it
+/// went (`mcp-oauth-callback` / `mcp-oauth-error`). Tokens stay on the
+/// server. Without a configured `frontend_url` nothing is posted: the
+/// result is never sent to an unknown origin.
+fn mcp_oauth_page(
+    config: &AppConfig,
+    title: &str,
+    text: &str,
+    message: serde_json::Value,
+) -> String {
+    // JSON in a script: keep `</script>` out of it
+    let notify = match config.frontend_url.as_deref() {
+        Some(origin) => format!(
+            "if (window.opener) {{ window.opener.postMessage({}, {}); }}",
+            message.to_string().replace('<', "\\u003c"),
+            serde_json::Value::from(origin)
+                .to_string()
+                .replace('<', "\\u003c"),
+        ),
+        None => String::new(),
+    };
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+  <head><title>{title}</title></head>
+  <body style="font-family: system-ui, sans-serif; text-align: center; padding: 2rem">
+    <h1>{title}</h1>
+    <p>{text}</p>
+    <p>You can close this window.</p>
+    <script>
+      {notify}
+      setTimeout(() => window.close(), 1500);
+    </script>
+  </body>
+</html>"#,
+        title = escape_html(title),
+        text = escape_html(text),
+    )
+}
+
+/// Authorization callback of MCP servers (`startMcpOAuth`): exchanges the
+/// code and stores the tokens for the user who started the flow.
+#[get("/mcp/callback?<query..>")]
+pub async fn mcp_callback(
+    query: OAuthCallbackQuery,
+    config: &State<AppConfig>,
+    db_pool: &State<DbPool>,
+) -> (Status, RawHtml<String>) {
+    let failed = |status: Status, error: &str, description: &str| {
+        (
+            status,
+            RawHtml(mcp_oauth_page(
+                config,
+                "MCP Authorization Failed",
+                description,
+                serde_json::json!({ "type": "mcp-oauth-error", "error": error }),
+            )),
+        )
+    };
+
+    if let Some(error) = query.error.as_deref() {
+        warn!("MCP OAuth error: {} {:?}", error, query.error_description);
+        return failed(
+            Status::BadRequest,
+            error,
+            query.error_description.as_deref().unwrap_or(error),
+        );
+    }
+    let (Some(code), Some(state)) = (query.code.as_deref(), query.state.as_deref()) else {
+        return failed(
+            Status::BadRequest,
+            "missing_code_or_state",
+            "Missing authorization code or state",
+        );
+    };
+
+    let mut conn = match db_pool.get() {
+        Ok(conn) => conn,
+        Err(e) => {
+            warn!("MCP OAuth callback: {}", e);
+            return failed(Status::InternalServerError, "server_error", "Server error");
+        }
+    };
+    match crate::services::mcp_oauth::complete(&mut conn, config, state, code).await {
+        Ok(server) => (
+            Status::Ok,
+            RawHtml(mcp_oauth_page(
+                config,
+                "MCP Authorization Successful",
+                &format!("{} is now authorized.", server.name),
+                serde_json::json!({ "type": "mcp-oauth-callback", "serverId": server.id }),
+            )),
+        ),
+        Err(e) => {
+            warn!("MCP OAuth callback failed: {}", e);
+            failed(Status::BadRequest, "token_exchange_failed", &e.to_string())
+        }
+    }
+}
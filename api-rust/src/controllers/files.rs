@@ -17,8 +17,10 @@ use crate::models::document::{
     ChatDocument, Document, GqlDocument, GqlDocumentStatusMessage, DOCUMENT_STATUS_ERROR,
     DOCUMENT_STATUS_PARSING, DOCUMENT_STATUS_STORAGE_UPLOAD, DOCUMENT_STATUS_UPLOAD,
 };
+use crate::models::QuotaMetric;
 use crate::schema::{chat_documents, chats, documents};
 use crate::services::pubsub::get_global_pubsub;
+use crate::services::quota;
 use crate::services::s3::S3Service;
 use crate::services::sqs::SqsService;
 use crate::utils::errors::AppError;
@@ -243,10 +245,22 @@ async fn upload_document(
         updated_at: now,
         metadata: None,
     };
-    diesel::insert_into(documents::table)
-        .values(&document)
-        .execute(&mut conn)
-        .map_err(|e| AppError::Database(e.to_string()))?;
+    // only new documents count against the quota, duplicates are reused
+    quota::reserve(
+        &mut conn,
+        config,
+        &user.0,
+        &[
+            (QuotaMetric::Documents, 1.0),
+            (QuotaMetric::StorageBytes, file_size as f64),
+        ],
+        |conn| {
+            diesel::insert_into(documents::table)
+                .values(&document)
+                .execute(conn)
+                .map_err(|e| AppError::Database(e.to_string()))
+        },
+    )?;
     pubsub.publish_document_status(GqlDocumentStatusMessage::from_document(&document));
 
     link_to_chat(&mut conn, &document.id)?;
@@ -7,20 +7,32 @@ use tracing::{error, info, instrument, warn};
 use crate::graphql::GraphQLContext;
 use crate::log_user_action;
 use crate::models::{
-    message, AuthProvider, AuthResponse, Chat, CreateChatInput, CreateCustomModelInput,
-    CreateMessageInput, DeleteModelInput, EditMessageResponse, GqlChat, GqlMessage, GqlModel,
-    GqlModelsList, GqlNewMessage, GqlProviderInfo, LoginInput, Message, MessageRole, Model,
-    NewChat, NewUser, ProviderDetail, RegisterInput, TestCustomModelInput, TestModelInput,
-    UpdateChatInput, UpdateCustomModelInput, UpdateModelStatusInput, UpdateUserInput, User,
-    ROLE_ADMIN, ROLE_USER,
+    message, stdio_env_json, tool_timeouts_json, AuthProvider, AuthResponse, Chat, CreateChatInput,
+    CreateCustomModelInput, CreateMessageInput, CreateVirtualModelInput, DeleteModelInput,
+    EditMessageResponse, GqlChat, GqlMessage, GqlModel, GqlModelsList, GqlNewMessage,
+    GqlProviderInfo, GqlQuotaLimit, LoginInput, Message, MessageRole, Model, ModelPrice, NewChat,
+    NewUser, ProviderDetail, QuotaLimit, QuotaMetric, QuotaPeriod, QuotaScope, QuotaStatus,
+    RegisterInput, SetModelPriceInput, SetQuotaLimitInput, TestCustomModelInput, TestModelInput,
+    UpdateChatInput, UpdateCustomModelInput, UpdateModelStatusInput, UpdateUserInput,
+    UpdateVirtualModelInput, User, MCP_TRANSPORT_STDIO, MCP_TRANSPORT_STREAMABLE_HTTP,
+    MESSAGE_STATUS_CANCELLED, ROLE_ADMIN, ROLE_USER,
+};
+use crate::schema::{
+    chat_files, chat_folders, chats, messages, model_prices, models, quota_limits, users,
 };
-use crate::schema::{chat_files, chat_folders, chats, messages, models, users};
 use crate::services::ai::{
     AIProviderService, AIProviderWrapper, AIService, GenerateImagesRequest, StreamCallbacks,
+    UsageTracker,
 };
 use crate::services::chat::{ChatService, GetChatStatsResult};
+use crate::services::compaction;
+use crate::services::fallback::{self, FallbackChain};
+use crate::services::generation;
 use crate::services::pubsub::get_global_pubsub;
+use crate::services::quota;
 use crate::services::s3::S3Service;
+use crate::services::side_by_side;
+use crate::services::tool_approval::{self, ToolApprovalDecision, ToolApprover};
 use crate::utils::errors::AppError;
 use crate::utils::jwt;
 
@@ -208,10 +220,18 @@ impl Mutation {
             input.system_prompt,
         );
 
-        let chat = diesel::insert_into(chats::table)
-            .values(&new_chat)
-            .get_result::<Chat>(&mut conn)
-            .map_err(|e| AppError::Database(e.to_string()))?;
+        let chat = quota::reserve(
+            &mut conn,
+            &gql_ctx.config,
+            user,
+            &[(QuotaMetric::Chats, 1.0)],
+            |conn| {
+                diesel::insert_into(chats::table)
+                    .values(&new_chat)
+                    .get_result::<Chat>(conn)
+                    .map_err(|e| AppError::Database(e.to_string()))
+            },
+        )?;
 
         Ok(GqlChat::from(chat))
     }
@@ -233,12 +253,23 @@ impl Mutation {
 
         // The client sends generation settings as a nested object (Node API
         // shape); flat fields are kept for backwards compatibility. Settings
-        // without a backing column (thinking, voice, …) are not persisted.
+        // without a backing column (voice, cache retention, …) are not
+        // persisted.
         let settings = input.settings.unwrap_or_default();
         let temperature = settings.temperature.or(input.temperature);
         let max_tokens = settings.max_tokens.or(input.max_tokens);
         let top_p = settings.top_p.or(input.top_p);
 
+        // An empty schema switches structured output off; anything else
+        // must be a valid object schema, stored normalized
+        let response_schema = match settings.response_schema.as_deref().map(str::trim) {
+            None => None,
+            Some("") => Some(None),
+            Some(raw) => Some(Some(
+                crate::services::structured::parse_schema(raw)?.to_string(),
+            )),
+        };
+
         // tools are stored as a JSON array in the chats.tools column
         let tools_json = match input.tools {
             Some(tools) => Some(
@@ -267,6 +298,11 @@ impl Mutation {
             top_p.map(|p| chats::top_p.eq(p)),
             settings.system_prompt.map(|s| chats::system_prompt.eq(s)),
             settings.images_count.map(|c| chats::images_count.eq(c)),
+            settings.thinking.map(|t| chats::thinking.eq(t)),
+            settings
+                .thinking_budget
+                .map(|b| chats::thinking_budget.eq(b)),
+            response_schema.map(|s| chats::response_schema.eq(s)),
             input.is_pinned.map(|p| chats::is_pinned.eq(p)),
             tools_json.map(|t| chats::tools.eq(t)),
             match &input.folder_id {
@@ -330,25 +366,56 @@ impl Mutation {
         let user = gql_ctx.require_user()?;
         let mut conn = gql_ctx.db_pool.get()?;
 
+        let transport_type = input
+            .transport_type
+            .unwrap_or_else(|| MCP_TRANSPORT_STREAMABLE_HTTP.to_string());
+        let stdio = transport_type == MCP_TRANSPORT_STDIO;
+        let shared = input.access.as_deref() == Some("SHARED");
+        // STDIO servers run commands on the API host
+        if (stdio || shared) && user.role != ROLE_ADMIN {
+            return Err(async_graphql::Error::new("Access denied"));
+        }
+        let url = input.url.unwrap_or_default();
+        if stdio && input.command.as_deref().unwrap_or("").trim().is_empty() {
+            return Err(
+                AppError::Validation("Command is required for STDIO servers".to_string()).into(),
+            );
+        }
+        if !stdio && url.trim().is_empty() {
+            return Err(AppError::Validation("URL is required".to_string()).into());
+        }
+
         let now = Utc::now().naive_utc();
         let server = crate::models::McpServer {
             id: uuid::Uuid::new_v4().to_string(),
             name: input.name,
-            url: input.url,
+            url,
             description: input.description,
-            transport_type: input
-                .transport_type
-                .unwrap_or_else(|| "STREAMABLE_HTTP".to_string()),
+            transport_type,
             auth_type: input.auth_type.unwrap_or_else(|| "NONE".to_string()),
             auth_config: input
                 .auth_config
                 .as_ref()
                 .map(|c| serde_json::to_string(c).unwrap_or_default()),
             tools: None,
+            resources: None,
+            resource_templates: None,
+            prompts: None,
             is_active: true,
-            user_id: Some(user.id.clone()),
+            user_id: (!shared).then(|| user.id.clone()),
             created_at: now,
             updated_at: now,
+            command: input.command.filter(|_| stdio),
+            args: stdio.then(|| {
+                serde_json::to_string(&input.args.unwrap_or_default()).unwrap_or_default()
+            }),
+            env: stdio.then(|| stdio_env_json(&input.env.unwrap_or_default())),
+            requires_approval: input.requires_approval.unwrap_or(false),
+            approval_tools: input
+                .approval_tools
+                .map(|tools| serde_json::to_string(&tools).unwrap_or_default()),
+            tool_timeout_ms: input.tool_timeout_ms,
+            tool_timeouts: input.tool_timeouts.as_deref().map(tool_timeouts_json),
         };
 
         let server: crate::models::McpServer =
@@ -357,9 +424,16 @@ impl Mutation {
                 .get_result(&mut conn)
                 .map_err(|e| AppError::Database(e.to_string()))?;
 
-        log_user_action!(&user.id, "create_mcp_server", url = %server.url);
+        log_user_action!(
+            &user.id,
+            "create_mcp_server",
+            url = %server.url,
+            command = ?server.command
+        );
         Ok(crate::models::GqlMcpServerResponse {
-            server: Some(server.into()),
+            server: Some(
+                crate::models::GqlMcpServer::from(server).for_viewer(user.role == ROLE_ADMIN),
+            ),
             error: None,
         })
     }
@@ -375,30 +449,78 @@ impl Mutation {
         let user = gql_ctx.require_user()?;
         let mut conn = gql_ctx.db_pool.get()?;
 
-        let server: crate::models::McpServer = diesel::update(
-            mcp_servers::table
-                .filter(mcp_servers::id.eq(&input.id))
-                .filter(mcp_servers::user_id.eq(&user.id)),
-        )
-        .set((
-            input.name.map(|n| mcp_servers::name.eq(n)),
-            input.url.map(|u| mcp_servers::url.eq(u)),
-            input.description.map(|d| mcp_servers::description.eq(d)),
-            input
-                .transport_type
-                .map(|t| mcp_servers::transport_type.eq(t)),
-            input.auth_type.map(|t| mcp_servers::auth_type.eq(t)),
-            input
-                .auth_config
-                .as_ref()
-                .map(|c| mcp_servers::auth_config.eq(serde_json::to_string(c).unwrap_or_default())),
-            mcp_servers::updated_at.eq(Utc::now().naive_utc()),
-        ))
-        .get_result(&mut conn)
-        .map_err(|_| async_graphql::Error::new("MCP server not found"))?;
+        let existing = editable_mcp_server(&mut conn, user, &input.id)
+            .ok_or_else(|| async_graphql::Error::new("MCP server not found"))?;
+        let stdio = input
+            .transport_type
+            .as_deref()
+            .unwrap_or(&existing.transport_type)
+            == MCP_TRANSPORT_STDIO;
+        let changes_command =
+            input.command.is_some() || input.args.is_some() || input.env.is_some();
+        if (stdio || changes_command) && user.role != ROLE_ADMIN {
+            return Err(async_graphql::Error::new("Access denied"));
+        }
+        let command = input.command.as_ref().or(existing.command.as_ref());
+        if stdio && command.is_none_or(|c| c.trim().is_empty()) {
+            return Err(
+                AppError::Validation("Command is required for STDIO servers".to_string()).into(),
+            );
+        }
+
+        // non-admins are shown no client secret: keep the stored one
+        let auth_config = input.auth_config.map(|mut auth| {
+            if auth.client_secret.is_none() {
+                auth.client_secret = existing
+                    .auth_config
+                    .as_deref()
+                    .and_then(|s| serde_json::from_str::<crate::models::GqlMcpAuthConfig>(s).ok())
+                    .and_then(|stored| stored.client_secret);
+            }
+            auth
+        });
+
+        let server: crate::models::McpServer =
+            diesel::update(mcp_servers::table.filter(mcp_servers::id.eq(&existing.id)))
+                .set((
+                    input.name.map(|n| mcp_servers::name.eq(n)),
+                    input.url.map(|u| mcp_servers::url.eq(u)),
+                    input.description.map(|d| mcp_servers::description.eq(d)),
+                    input
+                        .transport_type
+                        .map(|t| mcp_servers::transport_type.eq(t)),
+                    input.auth_type.map(|t| mcp_servers::auth_type.eq(t)),
+                    auth_config.as_ref().map(|c| {
+                        mcp_servers::auth_config.eq(serde_json::to_string(c).unwrap_or_default())
+                    }),
+                    input.command.map(|c| mcp_servers::command.eq(c)),
+                    input.args.map(|a| {
+                        mcp_servers::args.eq(serde_json::to_string(&a).unwrap_or_default())
+                    }),
+                    input.env.map(|e| mcp_servers::env.eq(stdio_env_json(&e))),
+                    input
+                        .requires_approval
+                        .map(|r| mcp_servers::requires_approval.eq(r)),
+                    input.approval_tools.map(|t| {
+                        mcp_servers::approval_tools
+                            .eq(serde_json::to_string(&t).unwrap_or_default())
+                    }),
+                    input
+                        .tool_timeout_ms
+                        .map(|t| mcp_servers::tool_timeout_ms.eq(t)),
+                    input
+                        .tool_timeouts
+                        .map(|t| mcp_servers::tool_timeouts.eq(tool_timeouts_json(&t))),
+                    mcp_servers::updated_at.eq(Utc::now().naive_utc()),
+                ))
+                .get_result(&mut conn)
+                .map_err(|_| async_graphql::Error::new("MCP server not found"))?;
+        crate::services::mcp_stdio::shutdown(&server.id);
 
         Ok(crate::models::GqlMcpServerResponse {
-            server: Some(server.into()),
+            server: Some(
+                crate::models::GqlMcpServer::from(server).for_viewer(user.role == ROLE_ADMIN),
+            ),
             error: None,
         })
     }
@@ -414,13 +536,13 @@ impl Mutation {
         let user = gql_ctx.require_user()?;
         let mut conn = gql_ctx.db_pool.get()?;
 
-        let deleted = diesel::delete(
-            mcp_servers::table
-                .filter(mcp_servers::id.eq(&input.id))
-                .filter(mcp_servers::user_id.eq(&user.id)),
-        )
-        .execute(&mut conn)
-        .map_err(|e| AppError::Database(e.to_string()))?;
+        let Some(server) = editable_mcp_server(&mut conn, user, &input.id) else {
+            return Ok(false);
+        };
+        let deleted = diesel::delete(mcp_servers::table.filter(mcp_servers::id.eq(&server.id)))
+            .execute(&mut conn)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+        crate::services::mcp_stdio::shutdown(&server.id);
         Ok(deleted > 0)
     }
 
@@ -436,37 +558,53 @@ impl Mutation {
         let user = gql_ctx.require_user()?;
         let mut conn = gql_ctx.db_pool.get()?;
 
-        let server: crate::models::McpServer = match mcp_servers::table
-            .filter(mcp_servers::id.eq(&server_id))
-            .filter(mcp_servers::user_id.eq(&user.id))
-            .first(&mut conn)
-        {
-            Ok(server) => server,
-            Err(_) => {
+        let server = match editable_mcp_server(&mut conn, user, &server_id) {
+            Some(server) => server,
+            None => {
                 return Ok(crate::models::GqlMcpServerResponse {
                     server: None,
                     error: Some("MCP server not found".to_string()),
                 })
             }
         };
+        let auth_token =
+            crate::services::mcp_oauth::resolve_token(&mut conn, &user.id, &server, auth_token)
+                .await;
         drop(conn);
 
         let mut client =
-            crate::services::mcp::McpClient::for_server(&server, auth_token.as_deref());
+            crate::services::mcp::McpClient::for_server(&server, &user.id, auth_token.as_deref());
         match client.list_tools().await {
             Ok(tools) => {
+                use crate::services::mcp::descriptors_to_stored_json;
+                crate::services::mcp::take_tools_changed(&server_id);
                 let stored = crate::services::mcp::tools_to_stored_json(&tools);
+                let (resources, templates, prompts) = client.list_resources_and_prompts().await;
                 let mut conn = gql_ctx.db_pool.get()?;
                 let server: crate::models::McpServer =
                     diesel::update(mcp_servers::table.filter(mcp_servers::id.eq(&server_id)))
                         .set((
                             mcp_servers::tools.eq(stored),
+                            mcp_servers::resources.eq(descriptors_to_stored_json::<
+                                crate::models::GqlMcpResource,
+                            >(&resources)),
+                            mcp_servers::resource_templates.eq(descriptors_to_stored_json::<
+                                crate::models::GqlMcpResourceTemplate,
+                            >(
+                                &templates
+                            )),
+                            mcp_servers::prompts.eq(descriptors_to_stored_json::<
+                                crate::models::GqlMcpPrompt,
+                            >(&prompts)),
                             mcp_servers::updated_at.eq(Utc::now().naive_utc()),
                         ))
                         .get_result(&mut conn)
                         .map_err(|e| AppError::Database(e.to_string()))?;
                 Ok(crate::models::GqlMcpServerResponse {
-                    server: Some(server.into()),
+                    server: Some(
+                        crate::models::GqlMcpServer::from(server)
+                            .for_viewer(user.role == ROLE_ADMIN),
+                    ),
                     error: None,
                 })
             }
@@ -483,18 +621,13 @@ impl Mutation {
         ctx: &Context<'_>,
         input: crate::models::TestMcpToolInput,
     ) -> Result<crate::models::GqlMcpToolTestResponse> {
-        use crate::schema::mcp_servers;
         let gql_ctx = ctx.data::<GraphQLContext>()?;
         let user = gql_ctx.require_user()?;
         let mut conn = gql_ctx.db_pool.get()?;
 
-        let server: crate::models::McpServer = match mcp_servers::table
-            .filter(mcp_servers::id.eq(&input.server_id))
-            .filter(mcp_servers::user_id.eq(&user.id))
-            .first(&mut conn)
-        {
-            Ok(server) => server,
-            Err(_) => {
+        let server = match editable_mcp_server(&mut conn, user, &input.server_id) {
+            Some(server) => server,
+            None => {
                 return Ok(crate::models::GqlMcpToolTestResponse {
                     result: None,
                     error: Some("MCP server not found".to_string()),
@@ -516,8 +649,18 @@ impl Mutation {
             _ => serde_json::json!({}),
         };
 
+        let mut conn = gql_ctx.db_pool.get()?;
+        let auth_token = crate::services::mcp_oauth::resolve_token(
+            &mut conn,
+            &user.id,
+            &server,
+            input.auth_token.clone(),
+        )
+        .await;
+        drop(conn);
+
         let mut client =
-            crate::services::mcp::McpClient::for_server(&server, input.auth_token.as_deref());
+            crate::services::mcp::McpClient::for_server(&server, &user.id, auth_token.as_deref());
         match client.call_tool(&input.tool_name, args).await {
             Ok(result) => Ok(crate::models::GqlMcpToolTestResponse {
                 result: Some(result),
@@ -530,6 +673,59 @@ impl Mutation {
         }
     }
 
+    /// Authorize the current user for an OAUTH2 MCP server: the URL to open
+    /// (authorization code grant, completed by `/auth/mcp/callback`), or
+    /// connected right away (client credentials grant)
+    async fn start_mcp_oauth(
+        &self,
+        ctx: &Context<'_>,
+        server_id: String,
+    ) -> Result<crate::models::GqlMcpOAuthStartResponse> {
+        use crate::services::mcp_oauth::{self, OAuthStart};
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        let mut conn = gql_ctx.db_pool.get()?;
+
+        let server = usable_mcp_server(&mut conn, &user.id, &server_id)?;
+        let response = match mcp_oauth::start(
+            &mut conn,
+            &gql_ctx.config,
+            &user.id,
+            user.role == ROLE_ADMIN,
+            &server,
+        )
+        .await
+        {
+            Ok(OAuthStart::Authorize(url)) => crate::models::GqlMcpOAuthStartResponse {
+                authorization_url: Some(url),
+                connected: false,
+                error: None,
+            },
+            Ok(OAuthStart::Connected) => crate::models::GqlMcpOAuthStartResponse {
+                authorization_url: None,
+                connected: true,
+                error: None,
+            },
+            Err(e) => crate::models::GqlMcpOAuthStartResponse {
+                authorization_url: None,
+                connected: false,
+                error: Some(e.to_string()),
+            },
+        };
+        log_user_action!(&user.id, "start_mcp_oauth", server_id = %server_id);
+        Ok(response)
+    }
+
+    /// Forget the current user's OAuth tokens for an MCP server
+    async fn disconnect_mcp_oauth(&self, ctx: &Context<'_>, server_id: String) -> Result<bool> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        let mut conn = gql_ctx.db_pool.get()?;
+        Ok(crate::services::mcp_oauth::disconnect(
+            &mut conn, &user.id, &server_id,
+        )?)
+    }
+
     /// Create a chat folder
     async fn create_folder(
         &self,
@@ -726,13 +922,23 @@ impl Mutation {
             .first(&mut conn)
             .map_err(|_| async_graphql::Error::new("Chat not found"))?;
 
-        let model_id = input.model_id.clone().unwrap_or_else(|| {
-            chat.model_id.clone().unwrap_or_else(|| {
-                user.default_model_id
-                    .clone()
-                    .unwrap_or("default".to_string())
+        // Side-by-side requests answer with the first of `model_ids`
+        let model_id = input
+            .model_id
+            .clone()
+            .or_else(|| {
+                input
+                    .model_ids
+                    .as_ref()
+                    .and_then(|ids| ids.first().cloned())
             })
-        });
+            .unwrap_or_else(|| {
+                chat.model_id.clone().unwrap_or_else(|| {
+                    user.default_model_id
+                        .clone()
+                        .unwrap_or("default".to_string())
+                })
+            });
 
         if model_id.is_empty() {
             return Err(AppError::Validation("Model ID is required".to_string()).into());
@@ -749,28 +955,67 @@ impl Mutation {
             return Err(AppError::Validation("Model is not active".to_string()).into());
         }
 
+        // The other requested models answer side by side
+        let side_models =
+            side_by_side_models(&mut conn, &user.id, &model_id, input.model_ids.as_deref())?;
+        if !side_models.is_empty()
+            && (model.type_ != "chat"
+                || chat.response_schema.is_some()
+                || input
+                    .document_ids
+                    .as_ref()
+                    .is_some_and(|ids| !ids.is_empty()))
+        {
+            return Err(AppError::Validation(
+                "Several models can only answer streamed chat messages".to_string(),
+            )
+            .into());
+        }
+
+        // token and spend limits block further messages once reached; the
+        // images an images-generation model will make are reserved up
+        // front, along with the message (user messages only count)
+        quota::check_chat_messages(&mut conn, &gql_ctx.config, user, &chat.id)?;
+        let role = input.role.clone().unwrap_or_else(|| "user".to_string());
+        let mut requested = vec![(QuotaMetric::Tokens, 0.0), (QuotaMetric::Spend, 0.0)];
+        if role == "user" {
+            requested.push((QuotaMetric::Messages, 1.0));
+        }
+        if model.type_ == "image_generation" {
+            let images_count = chat.images_count.unwrap_or(1).max(1);
+            requested.push((QuotaMetric::Images, images_count as f64));
+        }
+
+        // An MCP prompt makes the message text, MCP resources go along with
+        // it as context
+        let (content, mcp_resources) = mcp_message_context(&mut conn, &user.id, &input).await?;
+
         let mut new_message = Message::new(
             input.chat_id.clone(),
             Some(user.id.clone()),
-            input.content.clone(),
-            input.role.unwrap_or_else(|| "user".to_string()),
+            content.clone(),
+            role,
             model_id.clone(),
             Some(model.name.clone()),
         );
         // RAG requests keep their documentIds on the user message (Node
         // parity — the client's RAG panel and regeneration read it)
-        if let Some(document_ids) = input.document_ids.clone().filter(|ids| !ids.is_empty()) {
+        let document_ids = input.document_ids.clone().filter(|ids| !ids.is_empty());
+        if document_ids.is_some() || mcp_resources.is_some() {
             let metadata = crate::models::MessageMetadata {
-                document_ids: Some(document_ids),
+                document_ids,
+                mcp_resources,
                 ..Default::default()
             };
             new_message.metadata = serde_json::to_string(&metadata).ok();
         }
 
-        let message = diesel::insert_into(messages::table)
-            .values(&new_message)
-            .get_result::<Message>(&mut conn)
-            .map_err(|e| AppError::Database(e.to_string()))?;
+        let message = quota::reserve(&mut conn, &gql_ctx.config, user, &requested, |conn| {
+            diesel::insert_into(messages::table)
+                .values(&new_message)
+                .get_result::<Message>(conn)
+                .map_err(|e| AppError::Database(e.to_string()))
+        })?;
 
         diesel::update(
             chats::table
@@ -788,6 +1033,7 @@ impl Mutation {
             message: Some(GqlMessage::from(message.clone())),
             streaming: Some(false),
             chat: None,
+            tool_call_approval: None,
         };
 
         if let Err(e) = pubsub.publish_to_chat(&input.chat_id, gql_message).await {
@@ -797,9 +1043,17 @@ impl Mutation {
         // profile-settings credentials take precedence over env (Node parity)
         let effective_config = gql_ctx.config.with_user_settings(user.settings.as_ref());
         let ai_service = AIService::new(effective_config.clone());
-        let provider = ai_service
-            .get_provider_for_model(&model)
+        // A virtual model resolves to its ordered targets; a regular one to
+        // a chain of one
+        let chain = FallbackChain::resolve(&mut conn, &ai_service, &user.id, &model)
             .map_err(async_graphql::Error::from)?;
+        let side_by_side = side_models
+            .into_iter()
+            .map(|side_model| {
+                FallbackChain::resolve(&mut conn, &ai_service, &user.id, &side_model)
+                    .map(|side_chain| (side_model, side_chain))
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
 
         // Images-generation models bypass the chat/streaming path entirely:
         // the user message is the prompt, the response is a set of images
@@ -819,27 +1073,33 @@ impl Mutation {
             return generate_images_reply(
                 gql_ctx,
                 &effective_config,
-                &provider,
+                &chain.primary().provider,
                 &chat,
                 &message,
-                &model,
-                input.content.clone(),
+                &chain.primary().model,
+                content.clone(),
                 images_count,
             )
             .await;
         }
 
         // RAG: a message with linked documents gets a structured answer
-        // built from the ranked document chunks (sync, no streaming)
-        if let Some(document_ids) = input.document_ids.clone().filter(|ids| !ids.is_empty()) {
+        // built from the ranked document chunks (sync, no streaming); with
+        // the document search tool the model searches them itself instead
+        let document_search = chat_has_tool(chat.tools.as_deref(), "search_documents");
+        if let Some(document_ids) = input
+            .document_ids
+            .clone()
+            .filter(|ids| !ids.is_empty() && !document_search)
+        {
             return generate_rag_reply(
                 gql_ctx,
                 &ai_service,
-                &provider,
+                &chain,
                 user,
                 &message,
                 &model,
-                input.content.clone(),
+                content.clone(),
                 document_ids,
                 input.temperature,
                 input.max_tokens,
@@ -857,26 +1117,43 @@ impl Mutation {
             .load(&mut conn)
             .map_err(|e| AppError::Database(e.to_string()))?;
 
-        // Reverse to chronological order and add current user message
-        let mut input_messages = previous_messages;
+        // Reverse to chronological order and add current user message;
+        // side-by-side prompts continue from their selected answer only
+        let mut input_messages = side_by_side::without_unselected_answers(previous_messages);
         input_messages.reverse();
         input_messages.push(message.clone());
 
+        // A compacted chat sends its latest summary in place of the turns
+        // it covers
+        let summary = compaction::latest_summary(&mut conn, &chat.id)?;
+        let input_messages = compaction::without_summarized(input_messages, summary.as_ref());
+
         // Convert database messages to AI service format and preprocess
-        let model_messages = preprocess_messages(convert_messages_to_model_format(&input_messages));
+        let model_messages = with_summary(
+            preprocess_messages(convert_messages_to_model_format(&input_messages)),
+            summary.as_ref(),
+        );
 
-        // Tools enabled on this chat (web search / MCP servers)
+        // Tools enabled on this chat (web search / document search / MCP
+        // servers)
         let executable_tools = build_chat_tools(
             &mut conn,
+            &gql_ctx.db_pool,
             &effective_config,
             &user.id,
+            &chat.id,
             chat.tools.as_deref(),
+            input.document_ids.as_deref().unwrap_or_default(),
             input.mcp_tokens.as_deref(),
         )
         .await;
 
+        // Extended thinking is a per-chat setting
+        let thinking_budget =
+            crate::services::ai::thinking_budget(chat.thinking, chat.thinking_budget);
+
         // Create invoke request with preprocessed message context
-        let invoke_request = crate::services::ai::InvokeModelRequest {
+        let mut invoke_request = crate::services::ai::InvokeModelRequest {
             model_id: model_id.clone(),
             messages: model_messages,
             temperature: input.temperature,
@@ -884,191 +1161,256 @@ impl Mutation {
             top_p: input.top_p,
             system_prompt: user.default_system_prompt.clone(),
             tools: (!executable_tools.is_empty()).then_some(executable_tools),
+            thinking_budget,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: effective_config.tool_execution.clone(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
         };
 
-        let ai_msg_data = Message::new(
-            input.chat_id.clone(),
-            None,
-            String::new(), // Placeholder for AI response
-            String::from(MessageRole::Assistant),
-            model_id.clone(),
-            Some(model.name.clone()),
-        );
-        let ai_message = diesel::insert_into(messages::table)
-            .values(&ai_msg_data)
-            .get_result::<Message>(&mut conn)
-            .map_err(|e| AppError::Database(e.to_string()))?;
-
-        // Create a thread-safe accumulator for all tokens
-        let accumulated_content = Arc::new(Mutex::new(String::new()));
-
-        let callbacks = StreamCallbacks {
-            on_token: |token: String| {
-                let pubsub = pubsub.clone();
-                let chat_id = input.chat_id.clone();
-                let mut ai_message_pub = ai_message.clone();
-                let accumulated_content = accumulated_content.clone();
-
-                // Accumulate tokens in a thread-safe way
-                if let Ok(mut content) = accumulated_content.lock() {
-                    content.push_str(&token);
-                    // Update the message with accumulated content
-                    ai_message_pub.content = content.clone();
+        // Near the input window the older turns are folded into a new
+        // summary; failing that, the history is cut below
+        if let Some(window) = chain.input_window() {
+            match compaction::compact_if_needed(
+                &mut conn,
+                &ai_service,
+                user,
+                &chat.id,
+                &invoke_request,
+                window,
+                &input_messages,
+                summary.as_ref(),
+            )
+            .await
+            {
+                Ok(Some(new_summary)) => {
+                    let input_messages =
+                        compaction::without_summarized(input_messages, Some(&new_summary));
+                    invoke_request.messages = with_summary(
+                        preprocess_messages(convert_messages_to_model_format(&input_messages)),
+                        Some(&new_summary),
+                    );
+                    publish_chat_message(&chat.id, new_summary).await;
                 }
+                Ok(None) => {}
+                Err(e) => warn!("Failed to compact chat {}: {}", chat.id, e),
+            }
+        }
 
-                Box::pin(async move {
-                    let pub_message = message::GqlNewMessage {
-                        r#type: String::from(message::MessageType::Message),
-                        error: None,
-                        message: Some(GqlMessage::from(ai_message_pub)),
-                        streaming: Some(true),
-                        chat: None,
-                    };
-
-                    if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
-                        warn!("Failed to publish message to subscribers: {:?}", e);
-                    }
-                })
-            },
-            on_error: |error: AppError| {
-                let pubsub = pubsub.clone();
-                let chat_id = input.chat_id.clone();
-                let error_message = format!("Model inference error: {:?}", error);
-
-                let mut conn_cb = match gql_ctx
-                    .db_pool
-                    .get()
-                    .map_err(|e| AppError::Database(e.to_string()))
-                {
-                    Ok(conn) => conn,
-                    Err(e) => {
-                        error!(
-                            "Failed to get database connection in error callback: {:?}",
-                            e
-                        );
-                        return Box::pin(async move {
-                            let pub_message = message::GqlNewMessage {
-                                r#type: String::from(message::MessageType::Message),
-                                error: Some(format!("Database connection error: {:?}", e)),
-                                message: None,
-                                streaming: Some(false),
-                                chat: None,
-                            };
-
-                            if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
-                                warn!("Failed to publish error to subscribers: {:?}", e);
-                            }
-                        });
-                    }
+        // Side-by-side models get the same request, fitted to their own
+        // input windows
+        let side_requests: Vec<_> = side_by_side
+            .iter()
+            .map(|(side_model, side_chain)| {
+                let request = crate::services::ai::InvokeModelRequest {
+                    model_id: side_model.model_id.clone(),
+                    ..invoke_request.clone()
                 };
+                (side_model, side_chain, request)
+            })
+            .collect();
 
-                // Update message in database with error
-                let _ = diesel::update(messages::table.filter(messages::id.eq(&ai_message.id)))
-                    .set((
-                        messages::content.eq(error_message.clone()),
-                        messages::role.eq(String::from(MessageRole::Error)),
-                        messages::updated_at.eq(Utc::now().naive_utc()),
-                    ))
-                    .execute(&mut conn_cb)
-                    .map_err(|e| {
-                        error!("Failed to write error message: {:?}", e);
-                    });
+        // Long histories are cut to the model's input window
+        let context_trim = chain.fit_context(&mut invoke_request);
+        if let Some(trim) = &context_trim {
+            info!(
+                "Chat {}: dropped {} and trimmed {} message(s) to fit {} input tokens",
+                chat.id, trim.dropped_messages, trim.trimmed_messages, trim.input_tokens_budget
+            );
+        }
 
-                let mut error_ai_message = ai_message.clone();
-                error_ai_message.content = error_message;
-                error_ai_message.role = String::from(MessageRole::Error);
-
-                Box::pin(async move {
-                    let pub_message: GqlNewMessage = message::GqlNewMessage {
-                        r#type: String::from(message::MessageType::Message),
-                        error: error_ai_message.content.clone().into(),
-                        message: Some(GqlMessage::from(error_ai_message)),
-                        streaming: Some(false),
-                        chat: None,
-                    };
-
-                    if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
-                        warn!("Failed to publish message to subscribers: {:?}", e);
-                    }
-                })
-            },
+        // Structured output: the answer is a JSON object validated against
+        // the chat's response schema (sync, no streaming, no chat tools)
+        if let Some(schema) = chat.response_schema.as_deref() {
+            let schema = crate::services::structured::parse_schema(schema)?;
+            return generate_structured_reply(
+                gql_ctx,
+                &chain,
+                &message,
+                &model,
+                crate::services::ai::InvokeModelRequest {
+                    tools: None,
+                    ..invoke_request
+                },
+                schema,
+                context_trim,
+            )
+            .await;
+        }
+
+        // Side-by-side: every model answers the same prompt concurrently,
+        // each fitted to its own input window
+        let mut replies = Vec::with_capacity(side_requests.len() + 1);
+        for (side_model, side_chain, mut request) in side_requests {
+            let trim = side_chain.fit_context(&mut request);
+            replies.push(stream_reply(
+                gql_ctx,
+                user,
+                &input.chat_id,
+                side_chain,
+                side_model,
+                request,
+                trim,
+                Some(&message.id),
+            ));
+        }
+        let linked_to = (!replies.is_empty()).then_some(message.id.as_str());
+        replies.insert(
+            0,
+            stream_reply(
+                gql_ctx,
+                user,
+                &input.chat_id,
+                &chain,
+                &model,
+                invoke_request,
+                context_trim,
+                linked_to,
+            ),
+        );
 
-            on_complete: |content: String| {
-                let pubsub = pubsub.clone();
-                let chat_id = input.chat_id.clone();
+        // Failed answers publish their own error; the request fails only
+        // when none of them made it
+        let results = futures_util::future::join_all(replies).await;
+        if results.iter().all(Result::is_err) {
+            if let Some(Err(e)) = results.into_iter().next() {
+                return Err(e.into());
+            }
+        }
+        Ok(GqlMessage::from(message))
+    }
 
-                let mut conn_cb = match gql_ctx
-                    .db_pool
-                    .get()
-                    .map_err(|e| AppError::Database(e.to_string()))
-                {
-                    Ok(conn) => conn,
-                    Err(e) => {
-                        error!(
-                            "Failed to get database connection in complete callback: {:?}",
-                            e
-                        );
-                        return Box::pin(async move {
-                            let pub_message = message::GqlNewMessage {
-                                r#type: String::from(message::MessageType::Message),
-                                error: Some(format!("Database connection error: {:?}", e)),
-                                message: None,
-                                streaming: Some(false),
-                                chat: None,
-                            };
-                            if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
-                                warn!("Failed to publish error to subscribers: {:?}", e);
-                            }
-                        });
-                    }
-                };
+    /// Stop the streaming generation of an assistant message: the partial
+    /// answer is saved with the `cancelled` status and published. False
+    /// when the message is not being generated (anymore), or is generated
+    /// by another API instance.
+    async fn stop_message(&self, ctx: &Context<'_>, message_id: async_graphql::ID) -> Result<bool> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
 
-                // Update message in database
-                let _ = diesel::update(messages::table.filter(messages::id.eq(&ai_message.id)))
-                    .set((
-                        messages::content.eq(content.clone()),
-                        messages::updated_at.eq(Utc::now().naive_utc()),
-                    ))
-                    .execute(&mut conn_cb)
-                    .map_err(|e| {
-                        error!("Failed to write assistant message: {:?}", e);
-                    });
+        let stopped = generation::stop(message_id.as_str(), &user.id);
+        if stopped {
+            log_user_action!(&user.id, "stop_message", message_id = message_id.as_str());
+        }
+        Ok(stopped)
+    }
 
-                let mut res_ai_message = ai_message.clone();
-                res_ai_message.content = content;
-
-                Box::pin(async move {
-                    let pub_message: GqlNewMessage = message::GqlNewMessage {
-                        r#type: String::from(message::MessageType::Message),
-                        error: None,
-                        message: Some(GqlMessage::from(res_ai_message)),
-                        streaming: Some(false),
-                        chat: None,
-                    };
-
-                    if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
-                        warn!("Failed to publish message to subscribers: {:?}", e);
-                    }
-                })
+    /// Let a tool call awaiting approval run, with edited `arguments` (a
+    /// JSON object) when given
+    async fn approve_tool_call(
+        &self,
+        ctx: &Context<'_>,
+        id: async_graphql::ID,
+        arguments: Option<String>,
+    ) -> Result<crate::models::GqlToolCallApproval> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        let arguments = match arguments {
+            Some(json) => match serde_json::from_str::<serde_json::Value>(&json) {
+                Ok(value) if value.is_object() => Some(value),
+                _ => {
+                    return Err(
+                        AppError::Validation("Arguments must be a JSON object".to_string()).into(),
+                    )
+                }
             },
+            None => None,
         };
+        let mut conn = gql_ctx.db_pool.get()?;
 
-        let executed_tools = provider
-            .invoke_model_stream(invoke_request, callbacks)
-            .await
-            .map_err(|e| AppError::Internal(e.to_string()))?;
+        let approval = decide_tool_call(
+            &mut conn,
+            &user.id,
+            id.as_str(),
+            ToolApprovalDecision::Approved { arguments },
+        )
+        .await?;
+        log_user_action!(&user.id, "approve_tool_call", tool = %approval.tool_name);
+        Ok(approval)
+    }
 
-        // Record tool activity in the assistant message metadata (Node's
-        // toolCalls/tools) and re-publish so the client shows tool badges.
-        if !executed_tools.is_empty() {
-            if let Err(e) =
-                record_tool_metadata(gql_ctx, &input.chat_id, &ai_message.id, &executed_tools).await
-            {
-                warn!("Failed to record tool metadata: {:?}", e);
-            }
-        }
+    /// Refuse a tool call awaiting approval; the model is told so, with the
+    /// `reason` when given
+    async fn reject_tool_call(
+        &self,
+        ctx: &Context<'_>,
+        id: async_graphql::ID,
+        reason: Option<String>,
+    ) -> Result<crate::models::GqlToolCallApproval> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        let mut conn = gql_ctx.db_pool.get()?;
 
-        Ok(GqlMessage::from(message))
+        let reason = reason.filter(|r| !r.trim().is_empty());
+        let approval = decide_tool_call(
+            &mut conn,
+            &user.id,
+            id.as_str(),
+            ToolApprovalDecision::Rejected { reason },
+        )
+        .await?;
+        log_user_action!(&user.id, "reject_tool_call", tool = %approval.tool_name);
+        Ok(approval)
+    }
+
+    /// Select the side-by-side answer later turns continue from; returns
+    /// the prompt, whose metadata holds the selection
+    async fn select_answer(
+        &self,
+        ctx: &Context<'_>,
+        message_id: async_graphql::ID,
+    ) -> Result<GqlMessage> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        let mut conn = gql_ctx
+            .db_pool
+            .get()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        // The answer must belong to one of the user's chats
+        let answer: Message = messages::table
+            .inner_join(chats::table.on(chats::id.eq(messages::chat_id)))
+            .filter(messages::id.eq(message_id.as_str()))
+            .filter(chats::user_id.eq(&user.id))
+            .select(messages::all_columns)
+            .first(&mut conn)
+            .map_err(|_| async_graphql::Error::new("Message not found"))?;
+
+        let prompt = side_by_side::select_answer(&mut conn, &answer)?;
+        publish_chat_message(&prompt.chat_id, prompt.clone()).await;
+        Ok(GqlMessage::from(prompt))
+    }
+
+    /// Summarize again the turns a chat summary replaces
+    async fn regenerate_chat_summary(
+        &self,
+        ctx: &Context<'_>,
+        id: async_graphql::ID,
+    ) -> Result<GqlMessage> {
+        let id = id.to_string();
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        let mut conn = gql_ctx
+            .db_pool
+            .get()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        // The summary must belong to one of the user's chats
+        let summary: Message = messages::table
+            .inner_join(chats::table.on(chats::id.eq(messages::chat_id)))
+            .filter(messages::id.eq(&id))
+            .filter(chats::user_id.eq(&user.id))
+            .select(messages::all_columns)
+            .first(&mut conn)
+            .map_err(|_| async_graphql::Error::new("Message not found"))?;
+
+        let effective_config = gql_ctx.config.with_user_settings(user.settings.as_ref());
+        let ai_service = AIService::new(effective_config);
+        let summary = compaction::regenerate(&mut conn, &ai_service, user, summary).await?;
+
+        publish_chat_message(&summary.chat_id, summary.clone()).await;
+        Ok(GqlMessage::from(summary))
     }
 
     /// Delete message and optionally following messages
@@ -1278,14 +1620,15 @@ impl Mutation {
         }
 
         let ai_service = AIService::new(gql_ctx.config.with_user_settings(user.settings.as_ref()));
-        let provider = ai_service
-            .get_provider_for_model(&model)
+        let chain = FallbackChain::resolve(&mut conn, &ai_service, &user.id, &model)
             .map_err(async_graphql::Error::from)?;
 
         // Embedding models have no chat endpoint — test with an embeddings request
         if model.type_ == "embedding" {
-            let embedding = provider
-                .get_embeddings(&model.model_id, &input.text)
+            let primary = chain.primary();
+            let embedding = primary
+                .provider
+                .get_embeddings(&primary.model.model_id, &input.text)
                 .await
                 .map_err(async_graphql::Error::from)?;
             let timestamp = Utc::now().naive_utc();
@@ -1327,11 +1670,17 @@ impl Mutation {
             top_p: None,
             system_prompt: None,
             tools: None,
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
         };
 
         // Test the model
-        match provider.invoke_model(invoke_request).await {
-            Ok(response) => {
+        match chain.invoke_model(invoke_request).await {
+            Ok((response, answered_by)) => {
                 let timestamp = Utc::now().naive_utc();
                 info!(
                     "Model test successful for model: {}, response length: {}",
@@ -1348,7 +1697,7 @@ impl Mutation {
                     content: response.content,
                     role: "assistant".to_string(),
                     model_id: Some(model.model_id.clone()),
-                    model_name: Some(model.name.clone()),
+                    model_name: Some(answered_by.name.clone()),
                     created_at: timestamp,
                     updated_at: timestamp,
                     json_content: None,
@@ -1401,6 +1750,9 @@ impl Mutation {
             api_key: input.api_key,
             model_name: Some(input.model_name),
             protocol: Some(input.protocol),
+            keep_alive: input.keep_alive,
+            num_ctx: input.num_ctx,
+            ..Default::default()
         };
 
         let now = Utc::now().naive_utc();
@@ -1453,6 +1805,7 @@ impl Mutation {
             .filter(models::id.eq(&input.id))
             .filter(models::user_id.eq(&user.id))
             .filter(models::is_custom.eq(true))
+            .filter(models::api_provider.ne(fallback::API_PROVIDER_VIRTUAL))
             .first(&mut conn)
             .map_err(|_| async_graphql::Error::new("Custom model not found"))?;
 
@@ -1460,16 +1813,13 @@ impl Mutation {
             .custom_settings
             .as_ref()
             .and_then(|s| serde_json::from_str(s).ok())
-            .unwrap_or(crate::models::model::CustomModelSettings {
-                endpoint: None,
-                api_key: None,
-                model_name: None,
-                protocol: None,
-            });
+            .unwrap_or_default();
 
         settings.endpoint = Some(input.endpoint);
         settings.model_name = Some(input.model_name);
         settings.protocol = Some(input.protocol);
+        settings.keep_alive = input.keep_alive;
+        settings.num_ctx = input.num_ctx;
         // API key changes only when explicitly provided (empty string clears)
         if let Some(api_key) = input.api_key {
             settings.api_key = if api_key.is_empty() {
@@ -1498,8 +1848,120 @@ impl Mutation {
         Ok(GqlModel::from_model(&updated, user.clone()))
     }
 
-    /// Delete a custom model
-    async fn delete_model(&self, ctx: &Context<'_>, input: DeleteModelInput) -> Result<bool> {
+    /// Create a virtual model: an alias over an ordered list of the user's
+    /// chat models, tried in turn when one fails with a transient error
+    async fn create_virtual_model(
+        &self,
+        ctx: &Context<'_>,
+        input: CreateVirtualModelInput,
+    ) -> Result<GqlModel> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        let mut conn = gql_ctx
+            .db_pool
+            .get()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let existing: Option<Model> = models::table
+            .filter(models::model_id.eq(&input.model_id))
+            .filter(models::user_id.eq(&user.id))
+            .first(&mut conn)
+            .optional()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+        if existing.is_some() {
+            return Err(async_graphql::Error::new(format!(
+                "Model with ID '{}' already exists",
+                input.model_id
+            )));
+        }
+
+        let targets = fallback::load_virtual_targets(&mut conn, &user.id, &input.fallback_models)?;
+        let settings = crate::models::model::CustomModelSettings {
+            fallback_models: Some(input.fallback_models),
+            ..Default::default()
+        };
+
+        let now = Utc::now().naive_utc();
+        let model = Model {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: input.name,
+            model_id: input.model_id,
+            description: input.description,
+            user_id: Some(user.id.clone()),
+            provider: Some("Virtual".to_string()),
+            api_provider: fallback::API_PROVIDER_VIRTUAL.to_string(),
+            type_: "chat".to_string(),
+            streaming: true,
+            // Capabilities every target can serve
+            image_input: targets.iter().all(|m| m.image_input),
+            max_input_tokens: targets.iter().filter_map(|m| m.max_input_tokens).min(),
+            tools: targets[0].tools.clone(),
+            features: None,
+            custom_settings: Some(
+                serde_json::to_string(&settings).map_err(|e| AppError::Internal(e.to_string()))?,
+            ),
+            is_active: true,
+            is_custom: true,
+            created_at: now,
+            updated_at: now,
+        };
+
+        let model: Model = diesel::insert_into(models::table)
+            .values(&model)
+            .get_result(&mut conn)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        log_user_action!(&user.id, "create_virtual_model", model_id = %model.model_id);
+        Ok(GqlModel::from_model(&model, user.clone()))
+    }
+
+    /// Update a virtual model's name, description and target list
+    async fn update_virtual_model(
+        &self,
+        ctx: &Context<'_>,
+        input: UpdateVirtualModelInput,
+    ) -> Result<GqlModel> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        let mut conn = gql_ctx
+            .db_pool
+            .get()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let model: Model = models::table
+            .filter(models::id.eq(&input.id))
+            .filter(models::user_id.eq(&user.id))
+            .filter(models::api_provider.eq(fallback::API_PROVIDER_VIRTUAL))
+            .first(&mut conn)
+            .map_err(|_| async_graphql::Error::new("Virtual model not found"))?;
+
+        let targets = fallback::load_virtual_targets(&mut conn, &user.id, &input.fallback_models)?;
+        let settings = crate::models::model::CustomModelSettings {
+            fallback_models: Some(input.fallback_models),
+            ..Default::default()
+        };
+
+        let updated: Model = diesel::update(models::table.filter(models::id.eq(&model.id)))
+            .set((
+                models::name.eq(&input.name),
+                models::description.eq(&input.description),
+                models::image_input.eq(targets.iter().all(|m| m.image_input)),
+                models::max_input_tokens
+                    .eq(targets.iter().filter_map(|m| m.max_input_tokens).min()),
+                models::tools.eq(&targets[0].tools),
+                models::custom_settings.eq(serde_json::to_string(&settings)
+                    .map_err(|e| AppError::Internal(e.to_string()))?),
+                models::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .get_result(&mut conn)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        log_user_action!(&user.id, "update_virtual_model", model_id = %updated.model_id);
+        Ok(GqlModel::from_model(&updated, user.clone()))
+    }
+
+    /// Delete a custom model
+    async fn delete_model(&self, ctx: &Context<'_>, input: DeleteModelInput) -> Result<bool> {
         let gql_ctx = ctx.data::<GraphQLContext>()?;
         let user = gql_ctx.require_user()?;
         let mut conn = gql_ctx
@@ -1558,6 +2020,9 @@ impl Mutation {
             api_key,
             model_name: Some(input.model_name.clone()),
             protocol: Some(input.protocol.clone()),
+            keep_alive: input.keep_alive.clone(),
+            num_ctx: input.num_ctx,
+            ..Default::default()
         };
         let service = crate::services::custom::CustomService::from_settings(&settings)
             .map_err(async_graphql::Error::from)?;
@@ -1597,6 +2062,12 @@ impl Mutation {
                 top_p: None,
                 system_prompt: Some("You are a helpful assistant.".to_string()),
                 tools: None,
+                thinking_budget: None,
+                response_schema: None,
+                tool_approver: None,
+                tool_execution: Default::default(),
+                tool_calls_ran: Default::default(),
+                executed_tools: Default::default(),
             };
             service
                 .invoke_model(invoke_request)
@@ -1661,6 +2132,194 @@ impl Mutation {
         Ok(GqlModel::from_model(&updated_model, user.clone()))
     }
 
+    /// Admin: set a model's price (USD per 1M tokens) as its next price
+    /// version; later answers are priced with it, earlier ones keep theirs
+    async fn set_model_price(
+        &self,
+        ctx: &Context<'_>,
+        input: SetModelPriceInput,
+    ) -> Result<ModelPrice> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        if user.role != ROLE_ADMIN {
+            return Err(async_graphql::Error::new("Access denied"));
+        }
+        let prices = [
+            Some(input.input_price),
+            Some(input.output_price),
+            input.cache_read_price,
+            input.cache_write_price,
+        ];
+        if input.model_id.trim().is_empty()
+            || prices.iter().flatten().any(|p| !p.is_finite() || *p < 0.0)
+        {
+            return Err(AppError::Validation(
+                "Model ID and non-negative prices are required".to_string(),
+            )
+            .into());
+        }
+        let mut conn = gql_ctx
+            .db_pool
+            .get()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let version = crate::services::usage::next_price_version(&mut conn, &input.model_id)?;
+        let price = ModelPrice::new(input, version, user.id.clone());
+        diesel::insert_into(model_prices::table)
+            .values(&price)
+            .execute(&mut conn)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        log_user_action!(
+            &user.id,
+            "set_model_price",
+            model_id = price.model_id.as_str(),
+            version = price.version
+        );
+        Ok(price)
+    }
+
+    /// Admin: set a role's or a user's limit of a metric per period
+    /// (replacing the previous one); a negative limit means unlimited
+    async fn set_quota_limit(
+        &self,
+        ctx: &Context<'_>,
+        input: SetQuotaLimitInput,
+    ) -> Result<GqlQuotaLimit> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        if user.role != ROLE_ADMIN {
+            return Err(async_graphql::Error::new("Access denied"));
+        }
+        if input.subject.trim().is_empty() || !input.limit.is_finite() {
+            return Err(AppError::Validation(
+                "Subject and a finite limit are required".to_string(),
+            )
+            .into());
+        }
+        // storage is what the user stores now, it has no period
+        if input.metric == QuotaMetric::StorageBytes && input.period != QuotaPeriod::Total {
+            return Err(AppError::Validation(
+                "Storage limits apply to the TOTAL period only".to_string(),
+            )
+            .into());
+        }
+        let mut conn = gql_ctx
+            .db_pool
+            .get()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        if input.scope == QuotaScope::User {
+            users::table
+                .find(&input.subject)
+                .select(users::id)
+                .first::<String>(&mut conn)
+                .map_err(|_| {
+                    AppError::NotFound(format!("User not found, id: {}", input.subject))
+                })?;
+        }
+
+        let existing: Option<QuotaLimit> = quota_limits::table
+            .filter(quota_limits::scope.eq(input.scope.as_str()))
+            .filter(quota_limits::subject.eq(&input.subject))
+            .filter(quota_limits::metric.eq(input.metric.as_str()))
+            .filter(quota_limits::period.eq(input.period.as_str()))
+            .first(&mut conn)
+            .optional()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let limit = match existing {
+            Some(mut limit) => {
+                limit.limit_value = input.limit;
+                limit.created_by = Some(user.id.clone());
+                limit.updated_at = Utc::now().naive_utc();
+                diesel::update(quota_limits::table.find(&limit.id))
+                    .set((
+                        quota_limits::limit_value.eq(limit.limit_value),
+                        quota_limits::created_by.eq(&limit.created_by),
+                        quota_limits::updated_at.eq(limit.updated_at),
+                    ))
+                    .execute(&mut conn)
+                    .map_err(|e| AppError::Database(e.to_string()))?;
+                limit
+            }
+            None => {
+                let limit = QuotaLimit::new(&input, user.id.clone());
+                diesel::insert_into(quota_limits::table)
+                    .values(&limit)
+                    .execute(&mut conn)
+                    .map_err(|e| AppError::Database(e.to_string()))?;
+                limit
+            }
+        };
+
+        log_user_action!(
+            &user.id,
+            "set_quota_limit",
+            scope = limit.scope.as_str(),
+            subject = limit.subject.as_str(),
+            metric = limit.metric.as_str(),
+            period = limit.period.as_str(),
+            limit = limit.limit_value
+        );
+        Ok(GqlQuotaLimit::from(limit))
+    }
+
+    /// Admin: remove a quota limit, the role's or environment one applies
+    async fn delete_quota_limit(&self, ctx: &Context<'_>, id: async_graphql::ID) -> Result<bool> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        if user.role != ROLE_ADMIN {
+            return Err(async_graphql::Error::new("Access denied"));
+        }
+        let mut conn = gql_ctx
+            .db_pool
+            .get()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let deleted = diesel::delete(quota_limits::table.find(id.as_str()))
+            .execute(&mut conn)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+        log_user_action!(&user.id, "delete_quota_limit", limit_id = id.as_str());
+        Ok(deleted > 0)
+    }
+
+    /// Admin: count a user's usage from now on, of one metric or of all of
+    /// them (storage is never reset, documents have to be deleted)
+    async fn reset_user_quota(
+        &self,
+        ctx: &Context<'_>,
+        user_id: String,
+        metric: Option<QuotaMetric>,
+    ) -> Result<Vec<QuotaStatus>> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        if user.role != ROLE_ADMIN {
+            return Err(async_graphql::Error::new("Access denied"));
+        }
+        if metric == Some(QuotaMetric::StorageBytes) {
+            return Err(AppError::Validation("Storage usage cannot be reset".to_string()).into());
+        }
+        let mut conn = gql_ctx
+            .db_pool
+            .get()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let target: User = users::table
+            .find(&user_id)
+            .first(&mut conn)
+            .map_err(|_| AppError::NotFound(format!("User not found, id: {}", user_id)))?;
+        quota::reset(&mut conn, &target.id, metric, &user.id)?;
+
+        log_user_action!(
+            &user.id,
+            "reset_user_quota",
+            target_user_id = target.id.as_str(),
+            metric = metric.map(|m| m.as_str()).unwrap_or("ALL")
+        );
+        Ok(quota::status(&mut conn, &gql_ctx.config, &target)?)
+    }
+
     /// Reload models from providers  
     #[instrument(skip(self, ctx))]
     async fn reload_models(&self, ctx: &Context<'_>) -> Result<GqlModelsList> {
@@ -1734,6 +2393,7 @@ async fn generate_images_reply(
     images_count: i32,
 ) -> Result<GqlMessage> {
     let pubsub = get_global_pubsub();
+    let user_id = gql_ctx.require_user()?.id.clone();
 
     let publish = |msg: Option<GqlMessage>, error: Option<String>| {
         let chat_id = chat.id.clone();
@@ -1744,6 +2404,7 @@ async fn generate_images_reply(
                 message: msg,
                 streaming: Some(false),
                 chat: None,
+                tool_call_approval: None,
             };
             if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
                 warn!("Failed to publish message to subscribers: {:?}", e);
@@ -1752,6 +2413,9 @@ async fn generate_images_reply(
     };
 
     if prompt.trim().is_empty() {
+        if let Ok(mut conn) = gql_ctx.db_pool.get() {
+            settle_images(&mut conn, &user_id, images_count, 0);
+        }
         return Err(AppError::Validation("Image prompt is required".to_string()).into());
     }
 
@@ -1777,6 +2441,7 @@ async fn generate_images_reply(
                 let _ = diesel::insert_into(messages::table)
                     .values(&error_message)
                     .execute(&mut conn);
+                settle_images(&mut conn, &user_id, images_count, 0);
             }
             publish(Some(GqlMessage::from(error_message)), Some(e.to_string())).await;
             return Err(async_graphql::Error::from(e));
@@ -1808,7 +2473,7 @@ async fn generate_images_reply(
         .execute(&mut conn)
         .map_err(|e| AppError::Database(e.to_string()))?;
 
-    for image in &images {
+    for (stored, image) in images.iter().enumerate() {
         let extension = image.mime.strip_prefix("image/").unwrap_or("png");
         let file_name = format!(
             "{}/{}/{}.{}",
@@ -1829,6 +2494,7 @@ async fn generate_images_reply(
                     messages::role.eq(String::from(MessageRole::Error)),
                 ))
                 .execute(&mut conn);
+            settle_images(&mut conn, &user_id, images_count, stored);
             return Err(async_graphql::Error::from(e));
         }
 
@@ -1871,6 +2537,8 @@ async fn generate_images_reply(
             .get_result(&mut conn)
             .map_err(|e| AppError::Database(e.to_string()))?;
 
+    settle_images(&mut conn, &user_id, images_count, images.len());
+
     // Track generated-images count on the chat (used by demo limits).
     let _ = diesel::update(chats::table.filter(chats::id.eq(&chat.id)))
         .set(chats::images_count.eq(chat.images_count.unwrap_or(0) + images.len() as i32))
@@ -1888,6 +2556,26 @@ async fn generate_images_reply(
     Ok(GqlMessage::from(user_message.clone()))
 }
 
+/// Settle the images reserved by `createMessage` with the ones made: give
+/// back the missing ones, count any extra.
+fn settle_images(
+    conn: &mut crate::database::DbConnection,
+    user_id: &str,
+    reserved: i32,
+    made: usize,
+) {
+    let difference = made as f64 - reserved as f64;
+    if difference == 0.0 {
+        return;
+    }
+    if let Err(e) = quota::record(conn, user_id, QuotaMetric::Images, difference) {
+        warn!(
+            "Failed to settle the images quota of user {}: {:?}",
+            user_id, e
+        );
+    }
+}
+
 /// RAG message flow (Node's sendRagMessage): rank the linked documents'
 /// chunks against the question, ask the chat model for a structured
 /// answer and record ragResponse/relevantsChunks metadata.
@@ -1895,7 +2583,7 @@ async fn generate_images_reply(
 async fn generate_rag_reply(
     gql_ctx: &GraphQLContext,
     ai_service: &AIService,
-    provider: &AIProviderWrapper,
+    chain: &FallbackChain,
     user: &User,
     user_message: &Message,
     model: &Model,
@@ -1938,6 +2626,7 @@ async fn generate_rag_reply(
                 message: Some(GqlMessage::from(message)),
                 streaming: Some(streaming),
                 chat: None,
+                tool_call_approval: None,
             };
             if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
                 warn!("Failed to publish RAG message: {:?}", e);
@@ -1986,7 +2675,7 @@ async fn generate_rag_reply(
         }
 
         let prompt = rag::rag_request(&chunks, &question);
-        let response = provider
+        let (response, answered_by) = chain
             .invoke_model(crate::services::ai::InvokeModelRequest {
                 model_id: model.model_id.clone(),
                 messages: vec![crate::services::ai::ModelMessage::text(
@@ -1998,6 +2687,12 @@ async fn generate_rag_reply(
                 top_p: None,
                 system_prompt: Some(prompt.system_prompt),
                 tools: None,
+                thinking_budget: None,
+                response_schema: None,
+                tool_approver: None,
+                tool_execution: Default::default(),
+                tool_calls_ran: Default::default(),
+                executed_tools: Default::default(),
             })
             .await?;
 
@@ -2049,10 +2744,20 @@ async fn generate_rag_reply(
             })
             .collect();
 
+        let usage = record_usage(
+            &mut conn,
+            &ai_message,
+            &user.id,
+            answered_by,
+            response.usage.as_ref(),
+        );
         let metadata = crate::models::MessageMetadata {
+            usage,
             document_ids: Some(document_ids.clone()),
             rag_response: Some(rag_response),
             relevants_chunks: Some(relevants_chunks),
+            answered_by_model_id: chain.is_virtual().then(|| answered_by.model_id.clone()),
+            answered_by_model_name: chain.is_virtual().then(|| answered_by.name.clone()),
             ..Default::default()
         };
         Ok((content, metadata))
@@ -2088,6 +2793,130 @@ async fn generate_rag_reply(
     Ok(GqlMessage::from(user_message.clone()))
 }
 
+/// Structured-output message flow: the answer is requested as a JSON
+/// object, validated against the chat's response schema (with bounded
+/// repair retries) and stored as the assistant message `json_content`.
+async fn generate_structured_reply(
+    gql_ctx: &GraphQLContext,
+    chain: &FallbackChain,
+    user_message: &Message,
+    model: &Model,
+    request: crate::services::ai::InvokeModelRequest,
+    schema: serde_json::Value,
+    context_trim: Option<crate::models::ContextTrim>,
+) -> Result<GqlMessage> {
+    let mut conn = gql_ctx
+        .db_pool
+        .get()
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    let pubsub = get_global_pubsub();
+    let chat_id = user_message.chat_id.clone();
+
+    let ai_msg_data = Message::new(
+        chat_id.clone(),
+        None,
+        String::new(),
+        String::from(MessageRole::Assistant),
+        request.model_id.clone(),
+        Some(model.name.clone()),
+    );
+    let mut ai_message = diesel::insert_into(messages::table)
+        .values(&ai_msg_data)
+        .get_result::<Message>(&mut conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+
+    let publish = |message: Message, streaming: bool, error: Option<String>| {
+        let pubsub = pubsub.clone();
+        let chat_id = chat_id.clone();
+        async move {
+            let pub_message = message::GqlNewMessage {
+                r#type: String::from(message::MessageType::Message),
+                error,
+                message: Some(GqlMessage::from(message)),
+                streaming: Some(streaming),
+                chat: None,
+                tool_call_approval: None,
+            };
+            if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
+                warn!("Failed to publish structured message: {:?}", e);
+            }
+        }
+    };
+    publish(ai_message.clone(), true, None).await;
+
+    let result = crate::services::structured::invoke_structured(request, &schema, |request| {
+        chain.invoke_model(request)
+    })
+    .await;
+
+    let error = match result {
+        Ok((value, response, answered_by)) => {
+            let usage = record_usage(
+                &mut conn,
+                &ai_message,
+                user_message.user_id.as_deref().unwrap_or_default(),
+                answered_by,
+                response.usage.as_ref(),
+            );
+            let metadata = crate::models::MessageMetadata {
+                usage,
+                reasoning: response
+                    .reasoning
+                    .as_deref()
+                    .map(str::trim)
+                    .filter(|r| !r.is_empty())
+                    .map(|r| {
+                        vec![crate::models::ReasoningChunk {
+                            text: r.to_string(),
+                            timestamp: Some(ai_message.created_at),
+                            id: None,
+                        }]
+                    }),
+                answered_by_model_id: chain.is_virtual().then(|| answered_by.model_id.clone()),
+                answered_by_model_name: chain.is_virtual().then(|| answered_by.name.clone()),
+                context_trim,
+                ..Default::default()
+            };
+
+            ai_message.content = format!(
+                "```json\n{}\n```",
+                serde_json::to_string_pretty(&value).unwrap_or_default()
+            );
+            ai_message.json_content =
+                serde_json::to_string(&[crate::models::ModelMessageContent {
+                    content: value.to_string(),
+                    content_type: Some("json".to_string()),
+                    file_name: None,
+                    mime_type: Some("application/json".to_string()),
+                }])
+                .ok();
+            ai_message.metadata = serde_json::to_string(&metadata).ok();
+            None
+        }
+        Err(e) => {
+            error!("Structured output flow failed: {}", e);
+            ai_message.content = e.to_string();
+            ai_message.role = String::from(MessageRole::Error);
+            Some(e.to_string())
+        }
+    };
+
+    ai_message.updated_at = Utc::now().naive_utc();
+    diesel::update(messages::table.filter(messages::id.eq(&ai_message.id)))
+        .set((
+            messages::content.eq(&ai_message.content),
+            messages::role.eq(&ai_message.role),
+            messages::json_content.eq(&ai_message.json_content),
+            messages::metadata.eq(&ai_message.metadata),
+            messages::updated_at.eq(ai_message.updated_at),
+        ))
+        .execute(&mut conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    publish(ai_message, false, error).await;
+
+    Ok(GqlMessage::from(user_message.clone()))
+}
+
 /// Re-queue a document for parsing (`processDocument`) or reset it into
 /// the indexing flow (`reindexDocument`).
 async fn enqueue_document_command(
@@ -2224,19 +3053,33 @@ async fn change_chat_documents(
     })
 }
 
-/// Build the executable tools for a chat from its stored tools config:
-/// the web search tool (when Yandex Search credentials are configured) and
-/// the tools of each referenced active MCP server. Servers whose tool list
-/// was never fetched are refreshed and stored on the way (Node's
-/// fetchAndStoreTools). Failures only shrink the tool list.
+/// Whether the chat's stored tools config enables a tool type
+fn chat_has_tool(tools_json: Option<&str>, tool_type: &str) -> bool {
+    tools_json
+        .and_then(|json| serde_json::from_str::<Vec<crate::models::ChatTool>>(json).ok())
+        .unwrap_or_default()
+        .iter()
+        .any(|t| t.r#type == tool_type)
+}
+
+/// Build the executable tools for a chat from its stored tools config:
+/// the web search tool (when a search backend is configured),
+/// the document search tool over the chat's documents and the message's
+/// `document_ids`, and the tools of each referenced active MCP server.
+/// Servers whose tool list was never fetched are refreshed and stored on
+/// the way (Node's fetchAndStoreTools). Failures only shrink the tool list.
+#[allow(clippy::too_many_arguments)]
 async fn build_chat_tools(
     conn: &mut crate::database::DbConnection,
+    db_pool: &crate::database::DbPool,
     config: &crate::config::AppConfig,
     user_id: &str,
+    chat_id: &str,
     tools_json: Option<&str>,
+    document_ids: &[String],
     mcp_tokens: Option<&[crate::models::McpAuthTokenInput]>,
 ) -> Vec<crate::services::ai::ExecutableTool> {
-    use crate::schema::mcp_servers;
+    use crate::schema::{chat_documents, documents, mcp_servers};
     use crate::services::ai::{ExecutableTool, ToolBackend, ToolSpec};
 
     let chat_tools: Vec<crate::models::ChatTool> = tools_json
@@ -2251,7 +3094,48 @@ async fn build_chat_tools(
     if chat_tools.iter().any(|t| t.r#type == "web_search") {
         match crate::services::web_search::web_search_tool(config) {
             Some(tool) => result.push(tool),
-            None => warn!("Web search tool requested but Yandex Search is not configured"),
+            None => warn!("Web search tool requested but no search backend is configured"),
+        }
+    }
+    // Web search comes with the URL fetch tool, to read the pages it finds
+    if chat_tools
+        .iter()
+        .any(|t| t.r#type == "web_search" || t.r#type == "url_fetch")
+    {
+        result.push(crate::services::web_search::url_fetch_tool());
+    }
+
+    if chat_tools.iter().any(|t| t.r#type == "search_documents") {
+        let mut searched: Vec<String> = chat_documents::table
+            .filter(chat_documents::chat_id.eq(chat_id))
+            .select(chat_documents::document_id)
+            .load(conn)
+            .unwrap_or_default();
+        searched.extend(document_ids.iter().cloned());
+        // Only documents with embeddings can be searched
+        let searchable: Vec<(String, String)> = documents::table
+            .filter(documents::id.eq_any(&searched))
+            .filter(documents::owner_id.eq(user_id))
+            .filter(documents::embeddings_model_id.is_not_null())
+            .order(documents::created_at.asc())
+            .select((documents::id, documents::file_name))
+            .load(conn)
+            .unwrap_or_default();
+        if searchable.is_empty() {
+            warn!(
+                "Document search tool requested but chat {} has no indexed documents",
+                chat_id
+            );
+        } else {
+            let context = crate::services::rag::DocumentSearchContext {
+                db_pool: db_pool.clone(),
+                config: config.clone(),
+            };
+            result.push(crate::services::rag::search_documents_tool(
+                context,
+                user_id,
+                &searchable,
+            ));
         }
     }
 
@@ -2279,17 +3163,24 @@ async fn build_chat_tools(
         let auth_token = mcp_tokens
             .and_then(|tokens| tokens.iter().find(|t| t.server_id == server_id))
             .map(|t| t.access_token.clone());
+        // else the user's stored OAuth token
+        let auth_token =
+            crate::services::mcp_oauth::resolve_token(conn, user_id, &server, auth_token).await;
 
-        // Fetch and store the tool list if the server has none yet
+        // Fetch and store the tool list if the server has none yet, or
+        // announced a change
         let has_tools = server
             .tools
             .as_deref()
             .and_then(|s| serde_json::from_str::<Vec<serde_json::Value>>(s).ok())
             .map(|tools| !tools.is_empty())
             .unwrap_or(false);
-        if !has_tools {
-            let mut client =
-                crate::services::mcp::McpClient::for_server(&server, auth_token.as_deref());
+        if !has_tools || crate::services::mcp::take_tools_changed(&server.id) {
+            let mut client = crate::services::mcp::McpClient::for_server(
+                &server,
+                user_id,
+                auth_token.as_deref(),
+            );
             match client.list_tools().await {
                 Ok(listed) => {
                     let stored = crate::services::mcp::tools_to_stored_json(&listed);
@@ -2342,9 +3233,12 @@ async fn build_chat_tools(
                 },
                 backend: ToolBackend::Mcp {
                     server: Box::new(server.clone()),
+                    user_id: user_id.to_string(),
                     tool_name: tool_name.to_string(),
                     auth_token: auth_token.clone(),
                 },
+                requires_approval: server.tool_requires_approval(tool_name),
+                timeout: server.tool_timeout(tool_name),
             });
         }
     }
@@ -2352,30 +3246,149 @@ async fn build_chat_tools(
     result
 }
 
-/// Persist executed tool calls into the assistant message metadata
-/// (toolCalls + tools, Node parity) and re-publish the final message so
-/// subscribers get the tool badges.
-async fn record_tool_metadata(
-    gql_ctx: &GraphQLContext,
-    chat_id: &str,
-    message_id: &str,
-    executed: &[crate::services::ai::ExecutedToolCall],
-) -> Result<(), AppError> {
-    let mut conn = gql_ctx
-        .db_pool
-        .get()
-        .map_err(|e| AppError::Database(e.to_string()))?;
+/// Record the decision on a tool call and publish its new state; fails
+/// when the call expired instead.
+async fn decide_tool_call(
+    conn: &mut crate::database::DbConnection,
+    user_id: &str,
+    approval_id: &str,
+    decision: ToolApprovalDecision,
+) -> Result<crate::models::GqlToolCallApproval, AppError> {
+    let approval = tool_approval::decide(conn, user_id, approval_id, decision)?;
+    let expired = approval.status == crate::models::TOOL_CALL_EXPIRED;
+    let approval = crate::models::GqlToolCallApproval::from(approval);
+    tool_approval::publish(approval.clone()).await;
+    if expired {
+        return Err(AppError::Validation(
+            "The generation no longer waits for this tool call".to_string(),
+        ));
+    }
+    Ok(approval)
+}
 
-    let mut message: Message = messages::table
-        .filter(messages::id.eq(message_id))
-        .first(&mut conn)
-        .map_err(|e| AppError::Database(e.to_string()))?;
+/// An active MCP server the user may use: their own or a shared one.
+fn usable_mcp_server(
+    conn: &mut crate::database::DbConnection,
+    user_id: &str,
+    server_id: &str,
+) -> Result<crate::models::McpServer, AppError> {
+    use crate::schema::mcp_servers;
+    mcp_servers::table
+        .filter(mcp_servers::id.eq(server_id))
+        .filter(
+            mcp_servers::user_id
+                .eq(user_id)
+                .or(mcp_servers::user_id.is_null()),
+        )
+        .filter(mcp_servers::is_active.eq(true))
+        .first(conn)
+        .map_err(|_| AppError::NotFound(format!("MCP server not found, id: {}", server_id)))
+}
 
-    let mut metadata: crate::models::MessageMetadata = message
-        .metadata
-        .as_deref()
-        .and_then(|m| serde_json::from_str(m).ok())
-        .unwrap_or_default();
+/// Text of a new message (its MCP prompt, rendered, then its content) and
+/// the MCP resources sent with it.
+async fn mcp_message_context(
+    conn: &mut crate::database::DbConnection,
+    user_id: &str,
+    input: &CreateMessageInput,
+) -> Result<(String, Option<Vec<crate::models::MessageMcpResource>>), AppError> {
+    let client_token = |server_id: &str| {
+        input
+            .mcp_tokens
+            .as_deref()
+            .and_then(|tokens| tokens.iter().find(|t| t.server_id == server_id))
+            .map(|t| t.access_token.clone())
+    };
+
+    let mut content = input.content.clone();
+    if let Some(prompt) = &input.mcp_prompt {
+        let server = usable_mcp_server(conn, user_id, &prompt.server_id)?;
+        let arguments: serde_json::Map<String, serde_json::Value> = prompt
+            .arguments
+            .iter()
+            .flatten()
+            .map(|arg| (arg.name.clone(), serde_json::Value::from(arg.value.clone())))
+            .collect();
+        let auth_token = crate::services::mcp_oauth::resolve_token(
+            conn,
+            user_id,
+            &server,
+            client_token(&server.id),
+        )
+        .await;
+        let mut client =
+            crate::services::mcp::McpClient::for_server(&server, user_id, auth_token.as_deref());
+        let text = client.get_prompt(&prompt.name, &arguments).await?;
+        content = if content.trim().is_empty() {
+            text
+        } else {
+            format!("{}\n\n{}", text, content)
+        };
+    }
+
+    let mut resources = Vec::new();
+    for resource in input.mcp_resources.iter().flatten() {
+        let server = usable_mcp_server(conn, user_id, &resource.server_id)?;
+        let auth_token = crate::services::mcp_oauth::resolve_token(
+            conn,
+            user_id,
+            &server,
+            client_token(&server.id),
+        )
+        .await;
+        let mut client =
+            crate::services::mcp::McpClient::for_server(&server, user_id, auth_token.as_deref());
+        resources.extend(client.read_resource(&server.id, &resource.uri).await?);
+    }
+    Ok((content, (!resources.is_empty()).then_some(resources)))
+}
+
+/// Assistant message metadata carrying the reasoning streamed so far as a
+/// single chunk; `None` while there is none.
+fn reasoning_metadata(reasoning: &str, started_at: chrono::NaiveDateTime) -> Option<String> {
+    let reasoning = reasoning.trim();
+    if reasoning.is_empty() {
+        return None;
+    }
+    serde_json::to_string(&crate::models::MessageMetadata {
+        reasoning: Some(vec![crate::models::ReasoningChunk {
+            text: reasoning.to_string(),
+            timestamp: Some(started_at),
+            id: None,
+        }]),
+        ..Default::default()
+    })
+    .ok()
+}
+
+/// An MCP server the user may manage: their own, or a shared one for admins.
+fn editable_mcp_server(
+    conn: &mut crate::database::DbConnection,
+    user: &User,
+    server_id: &str,
+) -> Option<crate::models::McpServer> {
+    use crate::schema::mcp_servers;
+    let server: crate::models::McpServer = mcp_servers::table
+        .filter(mcp_servers::id.eq(server_id))
+        .first(conn)
+        .ok()?;
+    match server.user_id.as_deref() {
+        Some(owner) if owner == user.id => Some(server),
+        None if user.role == ROLE_ADMIN => Some(server),
+        _ => None,
+    }
+}
+
+/// Put executed tool calls into message metadata (toolCalls + tools, Node
+/// parity), with the document chunks the answer cites.
+fn record_tool_calls(
+    metadata: &mut crate::models::MessageMetadata,
+    executed: &[crate::services::ai::ExecutedToolCall],
+    content: &str,
+) {
+    if executed.is_empty() {
+        return;
+    }
     metadata.tool_calls = Some(
         executed
             .iter()
@@ -2383,7 +3396,7 @@ async fn record_tool_metadata(
                 name: call.name.clone(),
                 call_id: Some(call.id.clone()),
                 type_: Some("function".to_string()),
-                error: None,
+                error: call.error.clone(),
                 args: Some(call.args_json.clone()),
             })
             .collect(),
@@ -2395,10 +3408,65 @@ async fn record_tool_metadata(
                 call_id: Some(call.id.clone()),
                 name: call.name.clone(),
                 content: call.content.clone(),
+                duration_ms: Some(call.duration_ms as i64),
+                truncated: Some(call.truncated),
             })
             .collect(),
     );
 
+    // Chunks found by document searches that the answer cites
+    let cited = crate::services::rag::cited_chunks(
+        executed
+            .iter()
+            .filter(|call| call.name == crate::services::rag::SEARCH_DOCUMENTS_TOOL_NAME)
+            .map(|call| call.content.as_str()),
+        content,
+    );
+    if !cited.is_empty() {
+        metadata.relevants_chunks = Some(cited);
+    }
+}
+
+/// Persist executed tool calls (toolCalls + tools, Node parity), the
+/// context cut and, for virtual models, the answering model into the
+/// assistant message metadata, then re-publish the final message so
+/// subscribers pick them up.
+async fn record_response_metadata(
+    gql_ctx: &GraphQLContext,
+    chat_id: &str,
+    message_id: &str,
+    executed: &[crate::services::ai::ExecutedToolCall],
+    answered_by: Option<&Model>,
+    context_trim: Option<crate::models::ContextTrim>,
+    usage: Option<crate::models::MessageUsage>,
+) -> Result<(), AppError> {
+    let mut conn = gql_ctx
+        .db_pool
+        .get()
+        .map_err(|e| AppError::Database(e.to_string()))?;
+
+    let mut message: Message = messages::table
+        .filter(messages::id.eq(message_id))
+        .first(&mut conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+
+    let mut metadata: crate::models::MessageMetadata = message
+        .metadata
+        .as_deref()
+        .and_then(|m| serde_json::from_str(m).ok())
+        .unwrap_or_default();
+    if let Some(model) = answered_by {
+        metadata.answered_by_model_id = Some(model.model_id.clone());
+        metadata.answered_by_model_name = Some(model.name.clone());
+    }
+    if context_trim.is_some() {
+        metadata.context_trim = context_trim;
+    }
+    if usage.is_some() {
+        metadata.usage = usage;
+    }
+    record_tool_calls(&mut metadata, executed, &message.content);
+
     let metadata_json = serde_json::to_string(&metadata)
         .map_err(|e| AppError::Internal(format!("Failed to serialize metadata: {}", e)))?;
     diesel::update(messages::table.filter(messages::id.eq(message_id)))
@@ -2416,16 +3484,477 @@ async fn record_tool_metadata(
         message: Some(GqlMessage::from(message)),
         streaming: Some(false),
         chat: None,
+        tool_call_approval: None,
     };
     if let Err(e) = get_global_pubsub()
         .publish_to_chat(chat_id, pub_message)
         .await
     {
-        warn!("Failed to publish tool metadata update: {:?}", e);
+        warn!("Failed to publish response metadata update: {:?}", e);
+    }
+    Ok(())
+}
+
+/// Record the token usage of an answer in the usage ledger; failures are
+/// logged and leave the answer unpriced.
+fn record_usage(
+    conn: &mut crate::database::DbConnection,
+    ai_message: &Message,
+    user_id: &str,
+    answered_by: &Model,
+    usage: Option<&crate::services::ai::Usage>,
+) -> Option<crate::models::MessageUsage> {
+    crate::services::usage::record(
+        conn,
+        &ai_message.id,
+        &ai_message.chat_id,
+        user_id,
+        answered_by,
+        usage?,
+    )
+    .map_err(|e| warn!("Failed to record usage of message {}: {}", ai_message.id, e))
+    .ok()
+}
+
+/// Most models answering one prompt side by side, the primary included.
+const MAX_SIDE_BY_SIDE_MODELS: usize = 4;
+
+/// The user's models answering side by side with `primary_model_id`:
+/// active chat models, without duplicates or the primary one.
+fn side_by_side_models(
+    conn: &mut crate::database::DbConnection,
+    user_id: &str,
+    primary_model_id: &str,
+    model_ids: Option<&[String]>,
+) -> Result<Vec<Model>, AppError> {
+    let mut ids: Vec<&String> = Vec::new();
+    for id in model_ids.unwrap_or_default() {
+        if id != primary_model_id && !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    if ids.len() + 1 > MAX_SIDE_BY_SIDE_MODELS {
+        return Err(AppError::Validation(format!(
+            "At most {} models can answer side by side",
+            MAX_SIDE_BY_SIDE_MODELS
+        )));
+    }
+
+    let mut side_models = Vec::with_capacity(ids.len());
+    for id in ids {
+        let side_model: Model = models::table
+            .filter(models::model_id.eq(id))
+            .filter(models::user_id.eq(user_id))
+            .first(conn)
+            .map_err(|_| AppError::NotFound(format!("Model not found: {}", id)))?;
+        if !side_model.is_active || side_model.type_ != "chat" {
+            return Err(AppError::Validation(format!(
+                "Model {} cannot answer side by side",
+                side_model.name
+            )));
+        }
+        side_models.push(side_model);
+    }
+    Ok(side_models)
+}
+
+/// Stream a model's answer into a new assistant message, published over
+/// the chat subscription as tokens arrive; tool calls, usage and context
+/// trimming end up in its metadata. `linked_to` ties side-by-side answers
+/// to their prompt.
+#[allow(clippy::too_many_arguments)]
+async fn stream_reply(
+    gql_ctx: &GraphQLContext,
+    user: &User,
+    chat_id: &str,
+    chain: &FallbackChain,
+    model: &Model,
+    mut invoke_request: crate::services::ai::InvokeModelRequest,
+    context_trim: Option<crate::models::ContextTrim>,
+    linked_to: Option<&str>,
+) -> Result<(), AppError> {
+    let pubsub = get_global_pubsub();
+    let mut conn = gql_ctx
+        .db_pool
+        .get()
+        .map_err(|e| AppError::Database(e.to_string()))?;
+
+    let mut ai_msg_data = Message::new(
+        chat_id.to_string(),
+        None,
+        String::new(), // Placeholder for AI response
+        String::from(MessageRole::Assistant),
+        model.model_id.clone(),
+        Some(model.name.clone()),
+    );
+    ai_msg_data.linked_to_message_id = linked_to.map(str::to_string);
+    let ai_message = diesel::insert_into(messages::table)
+        .values(&ai_msg_data)
+        .get_result::<Message>(&mut conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+
+    // Create a thread-safe accumulator for all tokens
+    let accumulated_content = Arc::new(Mutex::new(String::new()));
+    // Reasoning is streamed and stored apart from the answer, as
+    // metadata.reasoning
+    let accumulated_reasoning = Arc::new(Mutex::new(String::new()));
+    let current_reasoning_metadata = || {
+        accumulated_reasoning
+            .lock()
+            .ok()
+            .and_then(|reasoning| reasoning_metadata(&reasoning, ai_message.created_at))
+    };
+
+    // `stopMessage` cancels the generation through this registration
+    let generation_handle = generation::register(&ai_message.id, &user.id);
+    let executed_tools = invoke_request.executed_tools.clone();
+    // Calls of tools that require approval wait for the user's decision
+    invoke_request.tool_approver = Some(ToolApprover::new(
+        gql_ctx.db_pool.clone(),
+        &user.id,
+        chat_id,
+        &ai_message.id,
+    ));
+
+    let usage_tracker = UsageTracker::default();
+    let callbacks = StreamCallbacks {
+        on_token: |token: String| {
+            let pubsub = pubsub.clone();
+            let chat_id = chat_id.to_string();
+            let mut ai_message_pub = ai_message.clone();
+            let accumulated_content = accumulated_content.clone();
+
+            // Accumulate tokens in a thread-safe way
+            if let Ok(mut content) = accumulated_content.lock() {
+                content.push_str(&token);
+                // Update the message with accumulated content
+                ai_message_pub.content = content.clone();
+            }
+            ai_message_pub.metadata = current_reasoning_metadata();
+
+            Box::pin(async move {
+                let pub_message = message::GqlNewMessage {
+                    r#type: String::from(message::MessageType::Message),
+                    error: None,
+                    message: Some(GqlMessage::from(ai_message_pub)),
+                    streaming: Some(true),
+                    chat: None,
+                    tool_call_approval: None,
+                };
+
+                if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
+                    warn!("Failed to publish message to subscribers: {:?}", e);
+                }
+            })
+        },
+        on_error: |error: AppError| {
+            let pubsub = pubsub.clone();
+            let chat_id = chat_id.to_string();
+            let error_message = format!("Model inference error: {:?}", error);
+
+            let mut conn_cb = match gql_ctx
+                .db_pool
+                .get()
+                .map_err(|e| AppError::Database(e.to_string()))
+            {
+                Ok(conn) => conn,
+                Err(e) => {
+                    error!(
+                        "Failed to get database connection in error callback: {:?}",
+                        e
+                    );
+                    return Box::pin(async move {
+                        let pub_message = message::GqlNewMessage {
+                            r#type: String::from(message::MessageType::Message),
+                            error: Some(format!("Database connection error: {:?}", e)),
+                            message: None,
+                            streaming: Some(false),
+                            chat: None,
+                            tool_call_approval: None,
+                        };
+
+                        if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
+                            warn!("Failed to publish error to subscribers: {:?}", e);
+                        }
+                    });
+                }
+            };
+
+            // Update message in database with error
+            let _ = diesel::update(messages::table.filter(messages::id.eq(&ai_message.id)))
+                .set((
+                    messages::content.eq(error_message.clone()),
+                    messages::role.eq(String::from(MessageRole::Error)),
+                    messages::updated_at.eq(Utc::now().naive_utc()),
+                ))
+                .execute(&mut conn_cb)
+                .map_err(|e| {
+                    error!("Failed to write error message: {:?}", e);
+                });
+
+            let mut error_ai_message = ai_message.clone();
+            error_ai_message.content = error_message;
+            error_ai_message.role = String::from(MessageRole::Error);
+
+            Box::pin(async move {
+                let pub_message: GqlNewMessage = message::GqlNewMessage {
+                    r#type: String::from(message::MessageType::Message),
+                    error: error_ai_message.content.clone().into(),
+                    message: Some(GqlMessage::from(error_ai_message)),
+                    streaming: Some(false),
+                    chat: None,
+                    tool_call_approval: None,
+                };
+
+                if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
+                    warn!("Failed to publish message to subscribers: {:?}", e);
+                }
+            })
+        },
+
+        on_reasoning: |delta: String| {
+            let pubsub = pubsub.clone();
+            let chat_id = chat_id.to_string();
+            let mut ai_message_pub = ai_message.clone();
+
+            if let Ok(mut reasoning) = accumulated_reasoning.lock() {
+                reasoning.push_str(&delta);
+            }
+            if let Ok(content) = accumulated_content.lock() {
+                ai_message_pub.content = content.clone();
+            }
+            ai_message_pub.metadata = current_reasoning_metadata();
+
+            Box::pin(async move {
+                let pub_message = message::GqlNewMessage {
+                    r#type: String::from(message::MessageType::Message),
+                    error: None,
+                    message: Some(GqlMessage::from(ai_message_pub)),
+                    streaming: Some(true),
+                    chat: None,
+                    tool_call_approval: None,
+                };
+
+                if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
+                    warn!("Failed to publish message to subscribers: {:?}", e);
+                }
+            })
+        },
+
+        on_complete: |content: String| {
+            let pubsub = pubsub.clone();
+            let chat_id = chat_id.to_string();
+            let reasoning_metadata = current_reasoning_metadata();
+
+            let mut conn_cb = match gql_ctx
+                .db_pool
+                .get()
+                .map_err(|e| AppError::Database(e.to_string()))
+            {
+                Ok(conn) => conn,
+                Err(e) => {
+                    error!(
+                        "Failed to get database connection in complete callback: {:?}",
+                        e
+                    );
+                    return Box::pin(async move {
+                        let pub_message = message::GqlNewMessage {
+                            r#type: String::from(message::MessageType::Message),
+                            error: Some(format!("Database connection error: {:?}", e)),
+                            message: None,
+                            streaming: Some(false),
+                            chat: None,
+                            tool_call_approval: None,
+                        };
+                        if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
+                            warn!("Failed to publish error to subscribers: {:?}", e);
+                        }
+                    });
+                }
+            };
+
+            // Update message in database
+            let _ = diesel::update(messages::table.filter(messages::id.eq(&ai_message.id)))
+                .set((
+                    messages::content.eq(content.clone()),
+                    reasoning_metadata
+                        .clone()
+                        .map(|metadata| messages::metadata.eq(metadata)),
+                    messages::updated_at.eq(Utc::now().naive_utc()),
+                ))
+                .execute(&mut conn_cb)
+                .map_err(|e| {
+                    error!("Failed to write assistant message: {:?}", e);
+                });
+
+            let mut res_ai_message = ai_message.clone();
+            res_ai_message.content = content;
+            res_ai_message.metadata = reasoning_metadata;
+
+            Box::pin(async move {
+                let pub_message: GqlNewMessage = message::GqlNewMessage {
+                    r#type: String::from(message::MessageType::Message),
+                    error: None,
+                    message: Some(GqlMessage::from(res_ai_message)),
+                    streaming: Some(false),
+                    chat: None,
+                    tool_call_approval: None,
+                };
+
+                if let Err(e) = pubsub.publish_to_chat(&chat_id, pub_message).await {
+                    warn!("Failed to publish message to subscribers: {:?}", e);
+                }
+            })
+        },
+        usage: usage_tracker.clone(),
+    };
+
+    let streamed = generation_handle
+        .run(chain.invoke_model_stream(invoke_request, callbacks))
+        .await;
+    let Some(streamed) = streamed else {
+        // Stopped: keep what was streamed so far, priced as the primary
+        // model (a failover target is not known once the stream is gone)
+        let content = accumulated_content
+            .lock()
+            .map(|content| content.clone())
+            .unwrap_or_default();
+        let usage = record_usage(
+            &mut conn,
+            &ai_message,
+            &user.id,
+            &chain.primary().model,
+            usage_tracker.total().as_ref(),
+        );
+        let executed = executed_tools
+            .lock()
+            .map(|executed| executed.clone())
+            .unwrap_or_default();
+        let cancelled = save_cancelled_message(
+            &mut conn,
+            ai_message.clone(),
+            content,
+            current_reasoning_metadata(),
+            &executed,
+            usage,
+        )?;
+        // Calls left awaiting approval have nobody to run them anymore
+        match crate::services::tool_approval::expire_pending(
+            &mut conn,
+            &ai_message.id,
+            "The generation was stopped",
+        ) {
+            Ok(expired) => {
+                for approval in expired {
+                    crate::services::tool_approval::publish(approval.into()).await;
+                }
+            }
+            Err(e) => warn!("Failed to expire tool call approvals: {:?}", e),
+        }
+        publish_chat_message(chat_id, cancelled).await;
+        return Ok(());
+    };
+    let (executed_tools, answered_by) = streamed.map_err(|e| AppError::Internal(e.to_string()))?;
+    let usage = record_usage(
+        &mut conn,
+        &ai_message,
+        &user.id,
+        answered_by,
+        usage_tracker.total().as_ref(),
+    );
+
+    // Record tool activity in the assistant message metadata (Node's
+    // toolCalls/tools), the model that answered for virtual models and
+    // the token usage, then re-publish so the client shows them.
+    let answered_by = chain.is_virtual().then_some(answered_by);
+    if !executed_tools.is_empty()
+        || answered_by.is_some()
+        || context_trim.is_some()
+        || usage.is_some()
+    {
+        if let Err(e) = record_response_metadata(
+            gql_ctx,
+            chat_id,
+            &ai_message.id,
+            &executed_tools,
+            answered_by,
+            context_trim,
+            usage,
+        )
+        .await
+        {
+            warn!("Failed to record response metadata: {:?}", e);
+        }
     }
+
     Ok(())
 }
 
+/// Save the partial answer of a stopped generation with the `cancelled`
+/// status, along with its reasoning, the tool calls that ran and the usage
+/// reported until then.
+fn save_cancelled_message(
+    conn: &mut crate::database::DbConnection,
+    mut ai_message: Message,
+    content: String,
+    reasoning_metadata: Option<String>,
+    executed: &[crate::services::ai::ExecutedToolCall],
+    usage: Option<crate::models::MessageUsage>,
+) -> Result<Message, AppError> {
+    let mut metadata: crate::models::MessageMetadata = reasoning_metadata
+        .as_deref()
+        .and_then(|m| serde_json::from_str(m).ok())
+        .unwrap_or_default();
+    if usage.is_some() {
+        metadata.usage = usage;
+    }
+    record_tool_calls(&mut metadata, executed, &content);
+    ai_message.content = content;
+    ai_message.metadata = serde_json::to_string(&metadata).ok();
+    ai_message.status = Some(MESSAGE_STATUS_CANCELLED.to_string());
+    ai_message.updated_at = Utc::now().naive_utc();
+
+    diesel::update(messages::table.filter(messages::id.eq(&ai_message.id)))
+        .set((
+            messages::content.eq(&ai_message.content),
+            messages::metadata.eq(&ai_message.metadata),
+            messages::status.eq(&ai_message.status),
+            messages::updated_at.eq(ai_message.updated_at),
+        ))
+        .execute(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    Ok(ai_message)
+}
+
+/// Put the chat summary, if any, ahead of the remaining turns.
+fn with_summary(
+    mut messages: Vec<crate::services::ai::ModelMessage>,
+    summary: Option<&Message>,
+) -> Vec<crate::services::ai::ModelMessage> {
+    if let Some(summary) = summary {
+        messages.insert(0, compaction::summary_message(summary));
+    }
+    messages
+}
+
+/// Publish a final (non-streaming) message to the chat's subscribers.
+async fn publish_chat_message(chat_id: &str, message: Message) {
+    let pub_message = message::GqlNewMessage {
+        r#type: String::from(message::MessageType::Message),
+        error: None,
+        message: Some(GqlMessage::from(message)),
+        streaming: Some(false),
+        chat: None,
+        tool_call_approval: None,
+    };
+    if let Err(e) = get_global_pubsub()
+        .publish_to_chat(chat_id, pub_message)
+        .await
+    {
+        warn!("Failed to publish message to subscribers: {:?}", e);
+    }
+}
+
 /// Convert database Message to AI service ModelMessage format
 fn convert_messages_to_model_format(
     messages: &[Message],
@@ -2438,7 +3967,7 @@ fn convert_messages_to_model_format(
                 "system" => crate::services::ai::MessageRole::System,
                 _ => crate::services::ai::MessageRole::User,
             },
-            content: msg.content.clone(),
+            content: with_mcp_resources(msg),
             timestamp: Some(msg.created_at.and_utc()),
             tool_calls: None,
             tool_call_id: None,
@@ -2446,6 +3975,28 @@ fn convert_messages_to_model_format(
         .collect()
 }
 
+/// Longest MCP resource text sent to the model, in characters
+const MCP_RESOURCE_MAX_CHARS: usize = 100_000;
+
+/// Message text followed by the MCP resources attached to it.
+fn with_mcp_resources(message: &Message) -> String {
+    let resources = message
+        .metadata
+        .as_deref()
+        .and_then(|m| serde_json::from_str::<crate::models::MessageMetadata>(m).ok())
+        .and_then(|m| m.mcp_resources)
+        .unwrap_or_default();
+    let mut content = message.content.clone();
+    for resource in resources {
+        let text: String = resource.text.chars().take(MCP_RESOURCE_MAX_CHARS).collect();
+        content.push_str(&format!(
+            "\n\n<resource uri=\"{}\">\n{}\n</resource>",
+            resource.uri, text
+        ));
+    }
+    content
+}
+
 /// Preprocess messages: sort by timestamp and join consecutive messages from the same role
 fn preprocess_messages(
     mut messages: Vec<crate::services::ai::ModelMessage>,
@@ -7,13 +7,17 @@ use crate::models::chat_file::{
     GqlImagesList, CHAT_FILE_TYPE_IMAGE, CHAT_FILE_TYPE_INLINE_DOCUMENT,
 };
 use crate::models::{
-    AuthResponse, Chat, ChatFile, GqlAmount, GqlChat, GqlChatsList, GqlCostsInfo, GqlMessage,
-    GqlMessagesList, GqlModel, GqlModelsList, GqlProviderInfo, GqlServiceCostInfo, Message, Model,
-    ProviderDetail, User, ROLE_ADMIN,
+    AuthResponse, Chat, ChatFile, GetUsageStatsInput, GqlAmount, GqlChat, GqlChatsList,
+    GqlCostsInfo, GqlMessage, GqlMessagesList, GqlModel, GqlModelsList, GqlProviderInfo,
+    GqlQuotaLimit, GqlServiceCostInfo, Message, Model, ModelPrice, ProviderDetail, QuotaLimit,
+    QuotaScope, QuotaStatus, UsageGroupBy, UsageStatsResponse, User, ROLE_ADMIN,
+};
+use crate::schema::{
+    chat_files, chat_folders, chats, messages, model_prices, models, quota_limits, users,
 };
-use crate::schema::{chat_files, chat_folders, chats, messages, models, users};
 use crate::services::ai::ApiProvider;
 use crate::services::chat::{ChatService, GetChatStatsResult};
+use crate::services::{quota, usage};
 use crate::utils::errors::AppError;
 
 #[derive(Default)]
@@ -143,6 +147,8 @@ impl Query {
         for provider in &config.enabled_api_providers {
             let from_settings = match provider.as_str() {
                 "OPEN_AI" => settings.is_some_and(|s| has(&s.openai_api_key)),
+                "ANTHROPIC" => settings.is_some_and(|s| has(&s.anthropic_api_key)),
+                "GEMINI" => settings.is_some_and(|s| has(&s.gemini_api_key)),
                 "YANDEX_AI" => settings.is_some_and(|s| has(&s.yandex_fm_api_key)),
                 "AWS_BEDROCK" => settings.is_some_and(|s| {
                     has(&s.aws_bedrock_access_key_id) || has(&s.aws_bedrock_profile)
@@ -176,8 +182,8 @@ impl Query {
             s3_connected,
             token,
             credentials_source,
-            reasoning_min_token_budget: Some(1024),
-            reasoning_max_token_budget: Some(16_000),
+            reasoning_min_token_budget: Some(crate::services::ai::REASONING_MIN_TOKEN_BUDGET),
+            reasoning_max_token_budget: Some(crate::services::ai::REASONING_MAX_TOKEN_BUDGET),
             context_messages_limit: Some(100),
         })
     }
@@ -292,6 +298,7 @@ impl Query {
     async fn mcp_servers(&self, ctx: &Context<'_>) -> Result<crate::models::GqlMcpServersList> {
         let gql_ctx = ctx.data::<GraphQLContext>()?;
         let user = gql_ctx.require_user()?;
+        let is_admin = user.role == ROLE_ADMIN;
         let mut conn = gql_ctx.db_pool.get()?;
 
         let servers: Vec<crate::models::McpServer> = crate::schema::mcp_servers::table
@@ -304,8 +311,16 @@ impl Query {
             .load(&mut conn)
             .map_err(|e| AppError::Database(e.to_string()))?;
 
-        let servers: Vec<crate::models::GqlMcpServer> =
-            servers.into_iter().map(Into::into).collect();
+        let servers: Vec<crate::models::GqlMcpServer> = servers
+            .into_iter()
+            .map(|server| {
+                let health = crate::services::mcp::health(&server.id);
+                crate::models::GqlMcpServer {
+                    health,
+                    ..crate::models::GqlMcpServer::from(server).for_viewer(is_admin)
+                }
+            })
+            .collect();
         Ok(crate::models::GqlMcpServersList {
             total: Some(servers.len() as i32),
             servers,
@@ -338,10 +353,13 @@ impl Query {
                 })
             }
         };
+        let auth_token =
+            crate::services::mcp_oauth::resolve_token(&mut conn, &user.id, &server, auth_token)
+                .await;
         drop(conn);
 
         let mut client =
-            crate::services::mcp::McpClient::for_server(&server, auth_token.as_deref());
+            crate::services::mcp::McpClient::for_server(&server, &user.id, auth_token.as_deref());
         match client.list_tools().await {
             Ok(tools) => {
                 let stored = crate::services::mcp::tools_to_stored_json(&tools);
@@ -359,6 +377,39 @@ impl Query {
         }
     }
 
+    /// MCP servers the current user has authorized (OAuth tokens stored)
+    async fn get_mcp_oauth_connections(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Vec<crate::models::GqlMcpOAuthConnection>> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        let mut conn = gql_ctx.db_pool.get()?;
+        Ok(
+            crate::services::mcp_oauth::connections(&mut conn, &user.id)?
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        )
+    }
+
+    /// Tool calls of a chat waiting for the user's approval
+    async fn get_pending_tool_calls(
+        &self,
+        ctx: &Context<'_>,
+        chat_id: async_graphql::ID,
+    ) -> Result<Vec<crate::models::GqlToolCallApproval>> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        let mut conn = gql_ctx.db_pool.get()?;
+        Ok(
+            crate::services::tool_approval::pending(&mut conn, &user.id, chat_id.as_str())?
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        )
+    }
+
     /// Get all chats for the current user
     async fn get_chats(
         &self,
@@ -880,6 +931,133 @@ impl Query {
         })
     }
 
+    /// Token usage and cost of model answers, grouped by chat, model, user
+    /// or day. Users see their own usage; admins anyone's, or everyone's
+    /// when grouping by user.
+    async fn get_usage_stats(
+        &self,
+        ctx: &Context<'_>,
+        input: GetUsageStatsInput,
+    ) -> Result<UsageStatsResponse> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        let is_admin = user.role == ROLE_ADMIN;
+        let user_id = match (&input.user_id, input.group_by) {
+            (Some(user_id), _) if *user_id == user.id => Some(user_id.as_str()),
+            (Some(user_id), _) if is_admin => Some(user_id.as_str()),
+            (None, UsageGroupBy::User) if is_admin => None,
+            (None, group_by) if group_by != UsageGroupBy::User => Some(user.id.as_str()),
+            _ => return Err(async_graphql::Error::new("Access denied")),
+        };
+        let mut conn = gql_ctx
+            .db_pool
+            .get()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let records = usage::load_records(
+            &mut conn,
+            user_id,
+            input.start_time.and_then(usage::timestamp),
+            input.end_time.and_then(usage::timestamp),
+        )?;
+        let (mut items, total) = usage::aggregate(&records, input.group_by);
+        usage::add_labels(&mut conn, &mut items, input.group_by)?;
+
+        Ok(UsageStatsResponse {
+            items,
+            total,
+            currency: usage::USAGE_CURRENCY.to_string(),
+        })
+    }
+
+    /// Admin: price overrides, every version, newest first
+    async fn get_model_prices(
+        &self,
+        ctx: &Context<'_>,
+        model_id: Option<String>,
+    ) -> Result<Vec<ModelPrice>> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        if user.role != ROLE_ADMIN {
+            return Err(async_graphql::Error::new("Access denied"));
+        }
+        let mut conn = gql_ctx
+            .db_pool
+            .get()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let mut query = model_prices::table.into_boxed();
+        if let Some(model_id) = model_id {
+            query = query.filter(model_prices::model_id.eq(model_id));
+        }
+        Ok(query
+            .order((model_prices::model_id.asc(), model_prices::version.desc()))
+            .load(&mut conn)
+            .map_err(|e| AppError::Database(e.to_string()))?)
+    }
+
+    /// Limits in force for the current user (admin: any user) and the
+    /// usage counted against them
+    async fn get_quota_status(
+        &self,
+        ctx: &Context<'_>,
+        user_id: Option<String>,
+    ) -> Result<Vec<QuotaStatus>> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        let mut conn = gql_ctx
+            .db_pool
+            .get()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let target = match user_id.filter(|id| *id != user.id) {
+            None => user.clone(),
+            Some(_) if user.role != ROLE_ADMIN => {
+                return Err(async_graphql::Error::new("Access denied"));
+            }
+            Some(id) => users::table
+                .find(&id)
+                .first::<User>(&mut conn)
+                .map_err(|_| AppError::NotFound(format!("User not found, id: {}", id)))?,
+        };
+        Ok(quota::status(&mut conn, &gql_ctx.config, &target)?)
+    }
+
+    /// Admin: quota limits set for roles and users
+    async fn get_quota_limits(
+        &self,
+        ctx: &Context<'_>,
+        scope: Option<QuotaScope>,
+        subject: Option<String>,
+    ) -> Result<Vec<GqlQuotaLimit>> {
+        let gql_ctx = ctx.data::<GraphQLContext>()?;
+        let user = gql_ctx.require_user()?;
+        if user.role != ROLE_ADMIN {
+            return Err(async_graphql::Error::new("Access denied"));
+        }
+        let mut conn = gql_ctx
+            .db_pool
+            .get()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let mut query = quota_limits::table.into_boxed();
+        if let Some(scope) = scope {
+            query = query.filter(quota_limits::scope.eq(scope.as_str()));
+        }
+        if let Some(subject) = subject {
+            query = query.filter(quota_limits::subject.eq(subject));
+        }
+        let limits: Vec<QuotaLimit> = query
+            .order((
+                quota_limits::scope.asc(),
+                quota_limits::subject.asc(),
+                quota_limits::metric.asc(),
+            ))
+            .load(&mut conn)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+        Ok(limits.into_iter().map(GqlQuotaLimit::from).collect())
+    }
+
     /// Admin: global usage stats
     async fn get_admin_stats(&self, ctx: &Context<'_>) -> Result<AdminStatsResponse> {
         let gql_ctx = ctx.data::<GraphQLContext>()?;
@@ -56,6 +56,7 @@ impl SubscriptionRoot {
                 message: None,
                 streaming: None,
                 chat: None,
+                tool_call_approval: None,
             };
             if let Err(e) = pubsub_clone.publish_to_chat(&chat_id, system_message).await {
                 error!("Failed to send initial system message: {:?}", e);
@@ -58,6 +58,9 @@ pub struct Chat {
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub folder_id: Option<String>,
+    pub thinking: Option<bool>,
+    pub thinking_budget: Option<i32>,
+    pub response_schema: Option<String>,
 }
 
 // Custom struct for the joined chat query result
@@ -101,6 +104,12 @@ pub struct ChatWithStats {
     pub created_at: chrono::NaiveDateTime,
     #[diesel(sql_type = Timestamp)]
     pub updated_at: chrono::NaiveDateTime,
+    #[diesel(sql_type = Nullable<Bool>)]
+    pub thinking: Option<bool>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub thinking_budget: Option<i32>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub response_schema: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Insertable)]
@@ -163,8 +172,8 @@ pub struct CreateChatInput {
 /// Chat generation settings exposed as a nested object (the Node API moved
 /// per-chat settings into one JSON column; api-rust keeps flat columns and
 /// assembles/destructures this object at the GraphQL boundary). Fields
-/// without a backing column (thinking, voice, …) are accepted but not
-/// persisted yet.
+/// without a backing column (voice, cache retention, …) are accepted but
+/// not persisted yet.
 #[derive(Debug, Clone, Default, Serialize, Deserialize, SimpleObject, InputObject)]
 #[graphql(input_name = "ChatSettingsInput")]
 pub struct ChatSettings {
@@ -178,6 +187,9 @@ pub struct ChatSettings {
     pub disable_top_p: Option<bool>,
     pub thinking: Option<bool>,
     pub thinking_budget: Option<i32>,
+    /// JSON Schema (serialized) the assistant answers must conform to;
+    /// an empty string switches structured output off.
+    pub response_schema: Option<String>,
     pub cache_retention: Option<String>,
     pub voice: Option<String>,
     pub selected_rag_doc_ids: Option<Vec<String>>,
@@ -266,12 +278,16 @@ fn chat_settings(
     max_tokens: Option<i32>,
     top_p: Option<f32>,
     images_count: Option<i32>,
+    thinking: Option<bool>,
+    thinking_budget: Option<i32>,
 ) -> ChatSettings {
     ChatSettings {
         temperature,
         max_tokens,
         top_p,
         images_count,
+        thinking,
+        thinking_budget,
         system_prompt: system_prompt.clone(),
         ..ChatSettings::default()
     }
@@ -283,13 +299,18 @@ impl From<Chat> for GqlChat {
             .tools
             .as_ref()
             .and_then(|s| serde_json::from_str::<Vec<ChatTool>>(s).ok());
-        let settings = Some(chat_settings(
-            &chat.system_prompt,
-            chat.temperature,
-            chat.max_tokens,
-            chat.top_p,
-            chat.images_count,
-        ));
+        let settings = Some(ChatSettings {
+            response_schema: chat.response_schema.clone(),
+            ..chat_settings(
+                &chat.system_prompt,
+                chat.temperature,
+                chat.max_tokens,
+                chat.top_p,
+                chat.images_count,
+                chat.thinking,
+                chat.thinking_budget,
+            )
+        });
         Self {
             id: chat.id,
             title: chat.title,
@@ -323,13 +344,18 @@ impl From<ChatWithStats> for GqlChat {
             .tools
             .as_ref()
             .and_then(|s| serde_json::from_str::<Vec<ChatTool>>(s).ok());
-        let settings = Some(chat_settings(
-            &chat.system_prompt,
-            chat.temperature,
-            chat.max_tokens,
-            chat.top_p,
-            chat.images_count,
-        ));
+        let settings = Some(ChatSettings {
+            response_schema: chat.response_schema.clone(),
+            ..chat_settings(
+                &chat.system_prompt,
+                chat.temperature,
+                chat.max_tokens,
+                chat.top_p,
+                chat.images_count,
+                chat.thinking,
+                chat.thinking_budget,
+            )
+        });
         Self {
             id: chat.id,
             title: chat.title,
@@ -1,11 +1,18 @@
 //! MCP servers: CRUD over the `mcp_servers` table plus tool listing /
-//! test invocation through the minimal Streamable-HTTP client in
-//! `services/mcp.rs`. In-chat tool execution lands with the tool loop.
+//! test invocation through the minimal client in `services/mcp.rs`
+//! (Streamable HTTP, or a local process over stdio).
 
 use async_graphql::{InputObject, SimpleObject};
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+pub const MCP_TRANSPORT_STREAMABLE_HTTP: &str = "STREAMABLE_HTTP";
+/// A command spawned by the API, speaking JSON-RPC over stdin/stdout. Only
+/// admins register them.
+pub const MCP_TRANSPORT_STDIO: &str = "STDIO";
 
 #[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
 #[diesel(table_name = crate::schema::mcp_servers)]
@@ -22,9 +29,108 @@ pub struct McpServer {
     pub user_id: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    /// STDIO: command, args (JSON array) and environment (JSON object)
+    pub command: Option<String>,
+    pub args: Option<String>,
+    pub env: Option<String>,
+    /// Listed with the tools (JSON arrays of the descriptors below)
+    pub resources: Option<String>,
+    pub resource_templates: Option<String>,
+    pub prompts: Option<String>,
+    /// Tools that wait for the user's approval when a model calls them:
+    /// all of them, or the ones in `approval_tools` (JSON array of names)
+    pub requires_approval: bool,
+    pub approval_tools: Option<String>,
+    /// Deadline of a tool call: the tool's entry in `tool_timeouts` (JSON
+    /// object of tool name to milliseconds), or this one
+    pub tool_timeout_ms: Option<i32>,
+    pub tool_timeouts: Option<String>,
+}
+
+impl McpServer {
+    pub fn is_stdio(&self) -> bool {
+        self.transport_type == MCP_TRANSPORT_STDIO
+    }
+
+    pub fn stdio_args(&self) -> Vec<String> {
+        self.args
+            .as_deref()
+            .and_then(|s| serde_json::from_str(s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn stdio_env(&self) -> BTreeMap<String, String> {
+        self.env
+            .as_deref()
+            .and_then(|s| serde_json::from_str(s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn approval_tools(&self) -> Vec<String> {
+        self.approval_tools
+            .as_deref()
+            .and_then(|s| serde_json::from_str(s).ok())
+            .unwrap_or_default()
+    }
+
+    /// Whether a call of the tool must be approved by the user first
+    pub fn tool_requires_approval(&self, tool_name: &str) -> bool {
+        self.requires_approval || self.approval_tools().iter().any(|name| name == tool_name)
+    }
+
+    pub fn tool_timeouts(&self) -> BTreeMap<String, i32> {
+        self.tool_timeouts
+            .as_deref()
+            .and_then(|s| serde_json::from_str(s).ok())
+            .unwrap_or_default()
+    }
+
+    /// The deadline of a call of the tool, when the server sets one
+    pub fn tool_timeout(&self, tool_name: &str) -> Option<Duration> {
+        self.tool_timeouts()
+            .get(tool_name)
+            .copied()
+            .or(self.tool_timeout_ms)
+            .filter(|ms| *ms > 0)
+            .map(|ms| Duration::from_millis(ms as u64))
+    }
+}
+
+/// Deadline of the calls of one tool of a server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, SimpleObject, InputObject)]
+#[graphql(name = "MCPToolTimeout", input_name = "MCPToolTimeoutInput")]
+pub struct GqlMcpToolTimeout {
+    pub tool_name: String,
+    pub timeout_ms: i32,
+}
+
+/// `tool_timeouts` column value for the timeouts
+pub fn tool_timeouts_json(timeouts: &[GqlMcpToolTimeout]) -> String {
+    let timeouts: BTreeMap<&str, i32> = timeouts
+        .iter()
+        .map(|t| (t.tool_name.as_str(), t.timeout_ms))
+        .collect();
+    serde_json::to_string(&timeouts).unwrap_or_default()
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize, SimpleObject, InputObject)]
+/// Environment variable of a STDIO server's process
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, SimpleObject, InputObject)]
+#[graphql(name = "MCPEnvVariable", input_name = "MCPEnvVariableInput")]
+pub struct GqlMcpEnvVariable {
+    pub name: String,
+    pub value: String,
+}
+
+/// `env` column value for the variables
+pub fn stdio_env_json(env: &[GqlMcpEnvVariable]) -> String {
+    let env: BTreeMap<&str, &str> = env
+        .iter()
+        .map(|var| (var.name.as_str(), var.value.as_str()))
+        .collect();
+    serde_json::to_string(&env).unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, SimpleObject, InputObject)]
 #[graphql(name = "MCPAuthConfig", input_name = "MCPAuthConfigInput")]
 #[serde(rename_all = "camelCase", default)]
 pub struct GqlMcpAuthConfig {
@@ -34,6 +140,75 @@ pub struct GqlMcpAuthConfig {
     pub token_url: Option<String>,
     pub authorization_url: Option<String>,
     pub scope: Option<String>,
+    /// OAUTH2: `authorization_code` (default, PKCE) or `client_credentials`
+    pub grant_type: Option<String>,
+    /// OAUTH2: RFC 8707 resource indicator sent with token requests, set
+    /// when the server publishes protected-resource metadata
+    pub resource: Option<String>,
+}
+
+/// OAuth tokens of a user for a server (`auth_type` OAUTH2)
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = crate::schema::mcp_oauth_tokens)]
+pub struct McpOAuthToken {
+    pub id: String,
+    pub user_id: String,
+    pub server_id: String,
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    /// Auth config (JSON) of a client registered for this user alone, when
+    /// the user may not change the server's
+    pub auth_config: Option<String>,
+}
+
+/// Authorization request awaiting its callback; `id` is the OAuth state
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = crate::schema::mcp_oauth_states)]
+pub struct McpOAuthState {
+    pub id: String,
+    pub user_id: String,
+    pub server_id: String,
+    pub code_verifier: String,
+    pub created_at: NaiveDateTime,
+    /// As `McpOAuthToken::auth_config`
+    pub auth_config: Option<String>,
+}
+
+/// A server the user has authorized, without the tokens themselves
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+#[graphql(name = "MCPOAuthConnection")]
+pub struct GqlMcpOAuthConnection {
+    pub server_id: String,
+    pub scope: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub has_refresh_token: bool,
+    pub updated_at: NaiveDateTime,
+}
+
+impl From<McpOAuthToken> for GqlMcpOAuthConnection {
+    fn from(token: McpOAuthToken) -> Self {
+        Self {
+            server_id: token.server_id,
+            scope: token.scope,
+            expires_at: token.expires_at,
+            has_refresh_token: token.refresh_token.is_some(),
+            updated_at: token.updated_at,
+        }
+    }
+}
+
+/// `startMcpOAuth`: the URL to open for the authorization code grant, or
+/// `connected` when a token was obtained right away (client credentials)
+#[derive(Debug, Serialize, Deserialize, SimpleObject)]
+#[graphql(name = "MCPOAuthStartResponse")]
+pub struct GqlMcpOAuthStartResponse {
+    pub authorization_url: Option<String>,
+    pub connected: bool,
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, SimpleObject)]
@@ -46,6 +221,62 @@ pub struct GqlMcpTool {
     pub output_schema: Option<String>,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize, SimpleObject)]
+#[graphql(name = "MCPResource")]
+#[serde(rename_all = "camelCase", default)]
+pub struct GqlMcpResource {
+    pub uri: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+/// A parameterized resource: `uriTemplate` is an RFC 6570 URI template
+#[derive(Debug, Clone, Default, Serialize, Deserialize, SimpleObject)]
+#[graphql(name = "MCPResourceTemplate")]
+#[serde(rename_all = "camelCase", default)]
+pub struct GqlMcpResourceTemplate {
+    pub uri_template: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, SimpleObject)]
+#[graphql(name = "MCPPromptArgument")]
+#[serde(rename_all = "camelCase", default)]
+pub struct GqlMcpPromptArgument {
+    pub name: String,
+    pub description: Option<String>,
+    pub required: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, SimpleObject)]
+#[graphql(name = "MCPPrompt")]
+#[serde(rename_all = "camelCase", default)]
+pub struct GqlMcpPrompt {
+    pub name: String,
+    pub description: Option<String>,
+    pub arguments: Option<Vec<GqlMcpPromptArgument>>,
+}
+
+pub const MCP_HEALTH_HEALTHY: &str = "HEALTHY";
+pub const MCP_HEALTH_UNHEALTHY: &str = "UNHEALTHY";
+
+/// Outcome of the latest requests to a server (JSON-RPC errors count as
+/// healthy: the server answered)
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+#[graphql(name = "MCPServerHealth")]
+pub struct GqlMcpServerHealth {
+    /// HEALTHY or UNHEALTHY
+    pub status: String,
+    pub last_checked_at: NaiveDateTime,
+    pub last_success_at: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: i32,
+    pub latency_ms: Option<i32>,
+}
+
 #[derive(Debug, Serialize, Deserialize, SimpleObject)]
 #[graphql(name = "MCPServer")]
 pub struct GqlMcpServer {
@@ -59,13 +290,45 @@ pub struct GqlMcpServer {
     pub access: String,
     pub auth_config: Option<GqlMcpAuthConfig>,
     pub tools: Option<Vec<GqlMcpTool>>,
+    pub resources: Option<Vec<GqlMcpResource>>,
+    pub resource_templates: Option<Vec<GqlMcpResourceTemplate>>,
+    pub prompts: Option<Vec<GqlMcpPrompt>>,
     pub is_active: bool,
+    pub command: Option<String>,
+    pub args: Option<Vec<String>>,
+    /// Values are blank for non-admins (see `for_viewer`)
+    pub env: Option<Vec<GqlMcpEnvVariable>>,
+    pub requires_approval: bool,
+    pub approval_tools: Vec<String>,
+    pub tool_timeout_ms: Option<i32>,
+    pub tool_timeouts: Vec<GqlMcpToolTimeout>,
+    /// `mcpServers` only; none until the server was called
+    pub health: Option<GqlMcpServerHealth>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
 }
 
 impl From<McpServer> for GqlMcpServer {
     fn from(server: McpServer) -> Self {
+        let (args, env) = if server.is_stdio() {
+            let env = server
+                .stdio_env()
+                .into_iter()
+                .map(|(name, value)| GqlMcpEnvVariable { name, value })
+                .collect();
+            (Some(server.stdio_args()), Some(env))
+        } else {
+            (None, None)
+        };
+        let approval_tools = server.approval_tools();
+        let tool_timeouts = server
+            .tool_timeouts()
+            .into_iter()
+            .map(|(tool_name, timeout_ms)| GqlMcpToolTimeout {
+                tool_name,
+                timeout_ms,
+            })
+            .collect();
         let auth_config = server
             .auth_config
             .as_ref()
@@ -74,6 +337,18 @@ impl From<McpServer> for GqlMcpServer {
             .tools
             .as_ref()
             .and_then(|s| serde_json::from_str(s).ok());
+        let resources = server
+            .resources
+            .as_ref()
+            .and_then(|s| serde_json::from_str(s).ok());
+        let resource_templates = server
+            .resource_templates
+            .as_ref()
+            .and_then(|s| serde_json::from_str(s).ok());
+        let prompts = server
+            .prompts
+            .as_ref()
+            .and_then(|s| serde_json::from_str(s).ok());
         Self {
             id: server.id,
             name: server.name,
@@ -89,13 +364,49 @@ impl From<McpServer> for GqlMcpServer {
             auth_type: server.auth_type,
             auth_config,
             tools,
+            resources,
+            resource_templates,
+            prompts,
             is_active: server.is_active,
+            command: server.command,
+            args,
+            env,
+            requires_approval: server.requires_approval,
+            approval_tools,
+            tool_timeout_ms: server.tool_timeout_ms,
+            tool_timeouts,
+            health: None,
             created_at: server.created_at,
             updated_at: server.updated_at,
         }
     }
 }
 
+impl GqlMcpServer {
+    /// What a non-admin may see of a server: the environment of a STDIO
+    /// server holds API keys and tokens, so only its variable names are
+    /// kept, a shared server's command line is hidden as well, and so is
+    /// the OAuth client secret.
+    pub fn for_viewer(mut self, is_admin: bool) -> Self {
+        if is_admin {
+            return self;
+        }
+        if let Some(auth) = self.auth_config.as_mut() {
+            auth.client_secret = None;
+        }
+        if let Some(env) = self.env.as_mut() {
+            for var in env.iter_mut() {
+                var.value.clear();
+            }
+        }
+        if self.user_id.is_none() {
+            self.command = None;
+            self.args = None;
+        }
+        self
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, SimpleObject)]
 #[graphql(name = "MCPServersList")]
 pub struct GqlMcpServersList {
@@ -108,12 +419,26 @@ pub struct GqlMcpServersList {
 #[graphql(name = "CreateMCPServerInput")]
 pub struct CreateMcpServerInput {
     pub name: String,
-    pub url: String,
+    /// Required unless `transportType` is STDIO
+    pub url: Option<String>,
     pub description: Option<String>,
     pub transport_type: Option<String>,
     pub auth_type: Option<String>,
     pub auth_config: Option<GqlMcpAuthConfig>,
+    /// SHARED (admins only): available to every user
     pub access: Option<String>,
+    /// STDIO (admins only): the command to spawn, its args and environment
+    pub command: Option<String>,
+    pub args: Option<Vec<String>>,
+    pub env: Option<Vec<GqlMcpEnvVariable>>,
+    /// Every tool call waits for the user's approval
+    pub requires_approval: Option<bool>,
+    /// Names of the tools whose calls wait for the user's approval
+    pub approval_tools: Option<Vec<String>>,
+    /// Deadline of every tool call, in milliseconds
+    pub tool_timeout_ms: Option<i32>,
+    /// Deadlines of single tools, overriding `toolTimeoutMs`
+    pub tool_timeouts: Option<Vec<GqlMcpToolTimeout>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, InputObject)]
@@ -126,6 +451,13 @@ pub struct UpdateMcpServerInput {
     pub transport_type: Option<String>,
     pub auth_type: Option<String>,
     pub auth_config: Option<GqlMcpAuthConfig>,
+    pub command: Option<String>,
+    pub args: Option<Vec<String>>,
+    pub env: Option<Vec<GqlMcpEnvVariable>>,
+    pub requires_approval: Option<bool>,
+    pub approval_tools: Option<Vec<String>>,
+    pub tool_timeout_ms: Option<i32>,
+    pub tool_timeouts: Option<Vec<GqlMcpToolTimeout>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, InputObject)]
@@ -186,3 +518,77 @@ pub struct GqlFoldersList {
     pub folders: Vec<GqlFolder>,
     pub error: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stdio_server(user_id: Option<&str>) -> McpServer {
+        let now = chrono::Utc::now().naive_utc();
+        McpServer {
+            id: "server".to_string(),
+            name: "fs".to_string(),
+            url: String::new(),
+            description: None,
+            transport_type: MCP_TRANSPORT_STDIO.to_string(),
+            auth_type: "NONE".to_string(),
+            auth_config: None,
+            tools: None,
+            is_active: true,
+            user_id: user_id.map(str::to_string),
+            created_at: now,
+            updated_at: now,
+            command: Some("npx".to_string()),
+            args: Some(r#"["-y", "server"]"#.to_string()),
+            env: Some(r#"{"API_KEY": "secret"}"#.to_string()),
+            resources: None,
+            resource_templates: None,
+            prompts: None,
+            requires_approval: false,
+            approval_tools: None,
+            tool_timeout_ms: None,
+            tool_timeouts: None,
+        }
+    }
+
+    fn env_values(server: &GqlMcpServer) -> Vec<(&str, &str)> {
+        server
+            .env
+            .iter()
+            .flatten()
+            .map(|var| (var.name.as_str(), var.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn only_admins_see_stdio_secrets() {
+        let admin = GqlMcpServer::from(stdio_server(None)).for_viewer(true);
+        assert_eq!(env_values(&admin), vec![("API_KEY", "secret")]);
+        assert_eq!(admin.command.as_deref(), Some("npx"));
+
+        let shared = GqlMcpServer::from(stdio_server(None)).for_viewer(false);
+        assert_eq!(env_values(&shared), vec![("API_KEY", "")]);
+        assert_eq!(shared.command, None);
+        assert_eq!(shared.args, None);
+
+        let private = GqlMcpServer::from(stdio_server(Some("user"))).for_viewer(false);
+        assert_eq!(env_values(&private), vec![("API_KEY", "")]);
+        assert_eq!(private.command.as_deref(), Some("npx"));
+    }
+
+    #[test]
+    fn only_admins_see_the_oauth_client_secret() {
+        let mut server = stdio_server(None);
+        server.auth_type = "OAUTH2".to_string();
+        server.auth_config = Some(r#"{"clientId": "kate", "clientSecret": "secret"}"#.to_string());
+
+        let admin = GqlMcpServer::from(server.clone()).for_viewer(true);
+        let auth = admin.auth_config.unwrap();
+        assert_eq!(auth.client_secret.as_deref(), Some("secret"));
+
+        let viewer = GqlMcpServer::from(server).for_viewer(false);
+        let auth = viewer.auth_config.unwrap();
+        assert_eq!(auth.client_id.as_deref(), Some("kate"));
+        assert_eq!(auth.client_secret, None);
+    }
+}
@@ -48,6 +48,10 @@ impl From<MessageRole> for String {
     }
 }
 
+/// `messages.status` of an answer stopped by the user (`stopMessage`);
+/// its content is what was streamed until then
+pub const MESSAGE_STATUS_CANCELLED: &str = "cancelled";
+
 #[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
 #[diesel(table_name = messages)]
 pub struct Message {
@@ -144,9 +148,37 @@ pub struct CreateMessageInput {
     /// file content blocks are not ported yet.
     pub files: Option<Vec<FileInput>>,
     pub document_ids: Option<Vec<String>>,
+    /// Side-by-side answers: every model answers the prompt in its own
+    /// assistant message, linked to it; `model_id` (else the first of
+    /// these) is the one selected by default
+    pub model_ids: Option<Vec<String>>,
     /// MCP auth tokens — accepted for schema compatibility; MCP is not
     /// ported yet.
     pub mcp_tokens: Option<Vec<McpAuthTokenInput>>,
+    /// MCP resources read and sent along with the message as context
+    pub mcp_resources: Option<Vec<McpResourceInput>>,
+    /// Start the message from an MCP prompt: its rendered text, followed
+    /// by `content` if any
+    pub mcp_prompt: Option<McpPromptInput>,
+}
+
+#[derive(Debug, Serialize, Deserialize, InputObject)]
+pub struct McpResourceInput {
+    pub server_id: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, InputObject)]
+pub struct McpPromptInput {
+    pub server_id: String,
+    pub name: String,
+    pub arguments: Option<Vec<McpPromptArgumentInput>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, InputObject)]
+pub struct McpPromptArgumentInput {
+    pub name: String,
+    pub value: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, InputObject)]
@@ -180,6 +212,9 @@ pub struct MessageUsage {
     pub output_tokens: i32,
     pub cache_read_input_tokens: Option<i32>,
     pub cache_write_input_tokens: Option<i32>,
+    /// USD, when the model's price is known
+    #[serde(default)]
+    pub cost: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
@@ -193,11 +228,23 @@ pub struct MessageRelevantChunk {
     pub relevance: f64,
 }
 
+/// Content of an MCP resource attached to a user message
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct MessageMcpResource {
+    pub server_id: String,
+    pub uri: String,
+    pub mime_type: Option<String>,
+    pub text: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
 pub struct ChatToolCallResult {
     pub call_id: Option<String>,
     pub name: String,
     pub content: String,
+    pub duration_ms: Option<i64>,
+    /// The content was cut to the tool output cap
+    pub truncated: Option<bool>,
 }
 
 /// RAG structured answer. Field names intentionally keep the Node API's
@@ -246,6 +293,20 @@ pub struct ReasoningChunk {
     pub id: Option<String>,
 }
 
+/// Chat history cut to fit the model's input window (token estimates).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, SimpleObject)]
+pub struct ContextTrim {
+    /// Oldest messages left out of the request
+    pub dropped_messages: i32,
+    /// Messages sent with only their tail
+    pub trimmed_messages: i32,
+    pub dropped_tokens: i32,
+    /// Input tokens sent, and the most the window allowed after reserving
+    /// room for the answer
+    pub input_tokens: i32,
+    pub input_tokens_budget: i32,
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize, SimpleObject)]
 pub struct MessageMetadata {
     pub usage: Option<MessageUsage>,
@@ -259,6 +320,17 @@ pub struct MessageMetadata {
     pub reasoning: Option<Vec<ReasoningChunk>>,
     pub context_messages: Option<Vec<String>>,
     pub tokens_count: Option<i32>,
+    /// Virtual models: the target model that actually produced the answer
+    pub answered_by_model_id: Option<String>,
+    pub answered_by_model_name: Option<String>,
+    pub context_trim: Option<ContextTrim>,
+    /// Chat summaries: the messages the summary replaces in the context
+    pub summarized_message_ids: Option<Vec<String>>,
+    /// Prompts answered side by side: the answer later turns continue from
+    /// (the first one until another is selected)
+    pub selected_answer_id: Option<String>,
+    /// MCP resources sent with the message
+    pub mcp_resources: Option<Vec<MessageMcpResource>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
@@ -335,6 +407,8 @@ pub struct McpAuthTokenInput {
 pub enum MessageType {
     Message,
     System,
+    /// A tool call awaiting (or given) the user's approval
+    ToolApproval,
 }
 
 impl From<MessageType> for String {
@@ -342,6 +416,7 @@ impl From<MessageType> for String {
         match msg_type {
             MessageType::Message => "message".to_string(),
             MessageType::System => "system".to_string(),
+            MessageType::ToolApproval => "tool_approval".to_string(),
         }
     }
 }
@@ -351,6 +426,7 @@ impl From<String> for MessageType {
         match msg_type.as_str() {
             "message" => MessageType::Message,
             "system" => MessageType::System,
+            "tool_approval" => MessageType::ToolApproval,
             &_ => todo!(),
         }
     }
@@ -380,4 +456,6 @@ pub struct GqlNewMessage {
     pub error: Option<String>,
     pub streaming: Option<bool>,
     pub r#type: String,
+    /// Set with the `tool_approval` type
+    pub tool_call_approval: Option<crate::models::GqlToolCallApproval>,
 }
@@ -5,6 +5,9 @@ pub mod folder;
 pub mod mcp_server;
 pub mod message;
 pub mod model;
+pub mod quota;
+pub mod tool_call_approval;
+pub mod usage;
 pub mod user;
 
 pub use chat::*;
@@ -14,4 +17,7 @@ pub use folder::*;
 pub use mcp_server::*;
 pub use message::*;
 pub use model::*;
+pub use quota::*;
+pub use tool_call_approval::*;
+pub use usage::*;
 pub use user::*;
@@ -119,13 +119,24 @@ impl Model {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, SimpleObject)]
 #[serde(rename_all = "camelCase")] // match the Node API's stored JSON keys
 pub struct CustomModelSettings {
     pub endpoint: Option<String>,
     pub api_key: Option<String>,
     pub model_name: Option<String>,
     pub protocol: Option<String>,
+    /// OLLAMA protocol: how long the server keeps the model loaded
+    /// (`keep_alive`, e.g. "10m" or "-1").
+    #[serde(default)]
+    pub keep_alive: Option<String>,
+    /// OLLAMA protocol: context window size (`options.num_ctx`).
+    #[serde(default)]
+    pub num_ctx: Option<i32>,
+    /// VIRTUAL models: `model_id`s of the real models to try, in priority
+    /// order.
+    #[serde(default)]
+    pub fallback_models: Option<Vec<String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, SimpleObject)]
@@ -225,6 +236,8 @@ pub struct CreateCustomModelInput {
     pub streaming: Option<bool>,
     pub image_input: Option<bool>,
     pub max_input_tokens: Option<i32>,
+    pub keep_alive: Option<String>,
+    pub num_ctx: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, InputObject)]
@@ -242,6 +255,27 @@ pub struct UpdateCustomModelInput {
     pub streaming: Option<bool>,
     pub image_input: Option<bool>,
     pub max_input_tokens: Option<i32>,
+    pub keep_alive: Option<String>,
+    pub num_ctx: Option<i32>,
+}
+
+/// A virtual model answers with the first of `fallback_models` that
+/// succeeds, failing over on rate limits, server errors and timeouts.
+#[derive(Debug, Serialize, Deserialize, InputObject)]
+pub struct CreateVirtualModelInput {
+    pub name: String,
+    pub model_id: String,
+    pub description: Option<String>,
+    /// Target `modelId`s in priority order.
+    pub fallback_models: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, InputObject)]
+pub struct UpdateVirtualModelInput {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub fallback_models: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, InputObject)]
@@ -258,6 +292,8 @@ pub struct TestCustomModelInput {
     #[graphql(name = "type", default_with = "ModelType::Chat")]
     pub type_: ModelType,
     pub protocol: String,
+    pub keep_alive: Option<String>,
+    pub num_ctx: Option<i32>,
     pub text: String,
 }
 
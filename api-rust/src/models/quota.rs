@@ -0,0 +1,217 @@
+//! Quota limits (`quota_limits`) and admin resets (`quota_resets`), see
+//! `services::quota`.
+
+use async_graphql::{Enum, InputObject, SimpleObject};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::schema::{quota_limits, quota_resets, quota_usage};
+
+/// What a quota counts. Storage is the size of the stored documents, the
+/// others count what was created in the period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Enum)]
+pub enum QuotaMetric {
+    Chats,
+    Messages,
+    Tokens,
+    Spend,
+    Images,
+    Documents,
+    StorageBytes,
+}
+
+impl QuotaMetric {
+    pub const ALL: [QuotaMetric; 7] = [
+        QuotaMetric::Chats,
+        QuotaMetric::Messages,
+        QuotaMetric::Tokens,
+        QuotaMetric::Spend,
+        QuotaMetric::Images,
+        QuotaMetric::Documents,
+        QuotaMetric::StorageBytes,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaMetric::Chats => "CHATS",
+            QuotaMetric::Messages => "MESSAGES",
+            QuotaMetric::Tokens => "TOKENS",
+            QuotaMetric::Spend => "SPEND",
+            QuotaMetric::Images => "IMAGES",
+            QuotaMetric::Documents => "DOCUMENTS",
+            QuotaMetric::StorageBytes => "STORAGE_BYTES",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.as_str() == value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Enum)]
+pub enum QuotaPeriod {
+    /// UTC calendar day
+    Day,
+    /// UTC calendar month
+    Month,
+    Total,
+}
+
+impl QuotaPeriod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaPeriod::Day => "DAY",
+            QuotaPeriod::Month => "MONTH",
+            QuotaPeriod::Total => "TOTAL",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [QuotaPeriod::Day, QuotaPeriod::Month, QuotaPeriod::Total]
+            .into_iter()
+            .find(|p| p.as_str() == value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum QuotaScope {
+    /// `subject` is a role name
+    Role,
+    /// `subject` is a user id
+    User,
+}
+
+impl QuotaScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaScope::Role => "ROLE",
+            QuotaScope::User => "USER",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ROLE" => Some(QuotaScope::Role),
+            "USER" => Some(QuotaScope::User),
+            _ => None,
+        }
+    }
+}
+
+/// Where an effective limit comes from: the `DEMO_MAX_*` environment
+/// defaults, a role limit or a user limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Enum)]
+pub enum QuotaSource {
+    Config,
+    Role,
+    User,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[diesel(table_name = quota_limits)]
+pub struct QuotaLimit {
+    pub id: String,
+    pub scope: String,
+    pub subject: String,
+    pub metric: String,
+    pub period: String,
+    pub limit_value: f64,
+    pub created_by: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl QuotaLimit {
+    pub fn new(input: &SetQuotaLimitInput, created_by: String) -> Self {
+        let now = Utc::now().naive_utc();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            scope: input.scope.as_str().to_string(),
+            subject: input.subject.clone(),
+            metric: input.metric.as_str().to_string(),
+            period: input.period.as_str().to_string(),
+            limit_value: input.limit,
+            created_by: Some(created_by),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+#[graphql(name = "QuotaLimit")]
+pub struct GqlQuotaLimit {
+    pub id: String,
+    pub scope: Option<QuotaScope>,
+    pub subject: String,
+    pub metric: Option<QuotaMetric>,
+    pub period: Option<QuotaPeriod>,
+    /// Negative: unlimited
+    pub limit: f64,
+    pub created_by: Option<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+impl From<QuotaLimit> for GqlQuotaLimit {
+    fn from(limit: QuotaLimit) -> Self {
+        Self {
+            scope: QuotaScope::parse(&limit.scope),
+            metric: QuotaMetric::parse(&limit.metric),
+            period: QuotaPeriod::parse(&limit.period),
+            id: limit.id,
+            subject: limit.subject,
+            limit: limit.limit_value,
+            created_by: limit.created_by,
+            updated_at: limit.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Queryable, Insertable)]
+#[diesel(table_name = quota_resets)]
+pub struct QuotaReset {
+    pub id: String,
+    pub user_id: String,
+    pub metric: String,
+    pub created_by: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// A creation counted against a quota, kept when the created row is
+/// deleted
+#[derive(Debug, Queryable, Insertable)]
+#[diesel(table_name = quota_usage)]
+pub struct QuotaUsage {
+    pub id: String,
+    pub user_id: String,
+    pub metric: String,
+    pub amount: f64,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, InputObject)]
+pub struct SetQuotaLimitInput {
+    pub scope: QuotaScope,
+    /// Role name or user id
+    pub subject: String,
+    pub metric: QuotaMetric,
+    pub period: QuotaPeriod,
+    /// Negative: unlimited, overriding a role or environment limit
+    pub limit: f64,
+}
+
+/// Effective limit of a user with the usage counted against it.
+#[derive(Debug, Clone, PartialEq, SimpleObject)]
+pub struct QuotaStatus {
+    pub metric: QuotaMetric,
+    pub period: QuotaPeriod,
+    pub source: QuotaSource,
+    pub limit: f64,
+    pub used: f64,
+    pub remaining: f64,
+    /// Usage counts from here: the period start or the latest reset
+    pub counted_since: Option<NaiveDateTime>,
+    pub resets_at: Option<NaiveDateTime>,
+}
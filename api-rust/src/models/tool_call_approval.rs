@@ -0,0 +1,71 @@
+//! Tool calls waiting for the user's decision (`tool_call_approvals`), see
+//! `services::tool_approval`.
+
+use async_graphql::SimpleObject;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const TOOL_CALL_PENDING: &str = "PENDING";
+pub const TOOL_CALL_APPROVED: &str = "APPROVED";
+pub const TOOL_CALL_REJECTED: &str = "REJECTED";
+/// Not answered in time, or the generation ended before the answer
+pub const TOOL_CALL_EXPIRED: &str = "EXPIRED";
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = crate::schema::tool_call_approvals)]
+pub struct ToolCallApproval {
+    pub id: String,
+    pub user_id: String,
+    pub chat_id: String,
+    /// The assistant message being generated
+    pub message_id: String,
+    /// The provider's id of the call
+    pub tool_call_id: String,
+    /// The MCP tool name (not the name advertised to the model)
+    pub tool_name: String,
+    pub server_id: Option<String>,
+    /// JSON object; the edited arguments once approved with changes
+    pub arguments: String,
+    pub status: String,
+    /// Rejection reason
+    pub reason: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+#[graphql(name = "ToolCallApproval")]
+pub struct GqlToolCallApproval {
+    pub id: String,
+    pub chat_id: String,
+    pub message_id: String,
+    pub tool_call_id: String,
+    pub tool_name: String,
+    pub server_id: Option<String>,
+    /// JSON string of the call arguments
+    pub arguments: String,
+    /// PENDING, APPROVED, REJECTED or EXPIRED
+    pub status: String,
+    pub reason: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl From<ToolCallApproval> for GqlToolCallApproval {
+    fn from(approval: ToolCallApproval) -> Self {
+        Self {
+            id: approval.id,
+            chat_id: approval.chat_id,
+            message_id: approval.message_id,
+            tool_call_id: approval.tool_call_id,
+            tool_name: approval.tool_name,
+            server_id: approval.server_id,
+            arguments: approval.arguments,
+            status: approval.status,
+            reason: approval.reason,
+            created_at: approval.created_at,
+            updated_at: approval.updated_at,
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Token usage ledger (`message_usage`) and admin price overrides
+//! (`model_prices`), see `services::usage`.
+
+use async_graphql::{Enum, InputObject, SimpleObject};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::schema::{message_usage, model_prices};
+
+/// Usage ledger entry of a model call behind a message (a regenerated
+/// summary adds another entry). Input tokens include the cached ones,
+/// whatever the provider reports.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[diesel(table_name = message_usage)]
+pub struct MessageUsageRecord {
+    pub id: String,
+    pub message_id: String,
+    pub chat_id: String,
+    pub user_id: String,
+    pub model_id: String,
+    pub input_tokens: i32,
+    pub output_tokens: i32,
+    pub cache_read_input_tokens: i32,
+    pub cache_write_input_tokens: i32,
+    /// USD; `None` when no price is known for the model
+    pub cost: Option<f64>,
+    /// `model_prices` version the cost was computed with, 0 for the
+    /// built-in price list
+    pub price_version: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+/// Admin-set price of a model, USD per 1M tokens. Every change is a new
+/// version; the latest one applies.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable, SimpleObject)]
+#[diesel(table_name = model_prices)]
+pub struct ModelPrice {
+    pub id: String,
+    pub model_id: String,
+    pub version: i32,
+    pub input_price: f64,
+    pub output_price: f64,
+    pub cache_read_price: Option<f64>,
+    pub cache_write_price: Option<f64>,
+    pub created_by: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl ModelPrice {
+    pub fn new(input: SetModelPriceInput, version: i32, created_by: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            model_id: input.model_id,
+            version,
+            input_price: input.input_price,
+            output_price: input.output_price,
+            cache_read_price: input.cache_read_price,
+            cache_write_price: input.cache_write_price,
+            created_by: Some(created_by),
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+}
+
+#[derive(Debug, InputObject)]
+pub struct SetModelPriceInput {
+    pub model_id: String,
+    pub input_price: f64,
+    pub output_price: f64,
+    /// Defaults to the input price
+    pub cache_read_price: Option<f64>,
+    /// Defaults to the input price
+    pub cache_write_price: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum UsageGroupBy {
+    Chat,
+    Model,
+    User,
+    Day,
+}
+
+#[derive(Debug, InputObject)]
+pub struct GetUsageStatsInput {
+    pub group_by: UsageGroupBy,
+    /// Unix seconds
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+    /// Admin: another user's usage
+    pub user_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct UsageStats {
+    /// Chat id, model id, user id or `YYYY-MM-DD` (UTC)
+    pub key: String,
+    /// Chat title or user email
+    pub label: Option<String>,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub cache_read_input_tokens: i64,
+    pub cache_write_input_tokens: i64,
+    pub cost: f64,
+    /// Answers without a known price, left out of `cost`
+    pub unpriced_requests: i64,
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+pub struct UsageStatsResponse {
+    pub items: Vec<UsageStats>,
+    pub total: UsageStats,
+    pub currency: String,
+}
@@ -39,9 +39,19 @@ pub struct JsonUserSettings {
     pub openai_api_key: Option<String>,
     pub openai_api_admin_key: Option<String>,
 
+    pub anthropic_api_key: Option<String>,
+
+    pub gemini_api_key: Option<String>,
+
     pub yandex_fm_api_key: Option<String>,
     pub yandex_fm_api_folder_id: Option<String>,
 
+    /// Web search backend (yandex, searxng, brave, tavily, json) with its
+    /// URL and key, instead of the server's
+    pub web_search_backend: Option<String>,
+    pub web_search_api_url: Option<String>,
+    pub web_search_api_key: Option<String>,
+
     pub default_model_id: Option<String>,
     pub default_system_prompt: Option<String>,
     pub default_temperature: Option<f32>,
@@ -60,6 +60,11 @@ diesel::table! {
         updated_at -> Timestamp,
         // added by ALTER TABLE (2026-07-22 chat_folders migration) — physically last
         folder_id -> Nullable<Text>,
+        // added by ALTER TABLE (2026-10-17 chat_thinking migration)
+        thinking -> Nullable<Bool>,
+        thinking_budget -> Nullable<Integer>,
+        // added by ALTER TABLE (2026-10-17 chat_response_schema migration)
+        response_schema -> Nullable<Text>,
     }
 }
 
@@ -97,6 +102,32 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    mcp_oauth_states (id) {
+        id -> Text,
+        user_id -> Text,
+        server_id -> Text,
+        code_verifier -> Text,
+        created_at -> Timestamp,
+        auth_config -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    mcp_oauth_tokens (id) {
+        id -> Text,
+        user_id -> Text,
+        server_id -> Text,
+        access_token -> Text,
+        refresh_token -> Nullable<Text>,
+        scope -> Nullable<Text>,
+        expires_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        auth_config -> Nullable<Text>,
+    }
+}
+
 diesel::table! {
     mcp_servers (id) {
         id -> Text,
@@ -111,6 +142,37 @@ diesel::table! {
         user_id -> Nullable<Text>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        // added by ALTER TABLE (2026-10-17 mcp_stdio migration)
+        command -> Nullable<Text>,
+        args -> Nullable<Text>,
+        env -> Nullable<Text>,
+        // added by ALTER TABLE (2026-10-17 mcp_resources_prompts migration)
+        resources -> Nullable<Text>,
+        resource_templates -> Nullable<Text>,
+        prompts -> Nullable<Text>,
+        // added by ALTER TABLE (2026-10-17 tool_call_approvals migration)
+        requires_approval -> Bool,
+        approval_tools -> Nullable<Text>,
+        // added by ALTER TABLE (2026-10-17 tool_timeouts migration)
+        tool_timeout_ms -> Nullable<Integer>,
+        tool_timeouts -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    message_usage (id) {
+        id -> Text,
+        message_id -> Text,
+        chat_id -> Text,
+        user_id -> Text,
+        model_id -> Text,
+        input_tokens -> Integer,
+        output_tokens -> Integer,
+        cache_read_input_tokens -> Integer,
+        cache_write_input_tokens -> Integer,
+        cost -> Nullable<Double>,
+        price_version -> Nullable<Integer>,
+        created_at -> Timestamp,
     }
 }
 
@@ -157,6 +219,71 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    model_prices (id) {
+        id -> Text,
+        model_id -> Text,
+        version -> Integer,
+        input_price -> Double,
+        output_price -> Double,
+        cache_read_price -> Nullable<Double>,
+        cache_write_price -> Nullable<Double>,
+        created_by -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    quota_limits (id) {
+        id -> Text,
+        scope -> Text,
+        subject -> Text,
+        metric -> Text,
+        period -> Text,
+        limit_value -> Double,
+        created_by -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    quota_usage (id) {
+        id -> Text,
+        user_id -> Text,
+        metric -> Text,
+        amount -> Double,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    quota_resets (id) {
+        id -> Text,
+        user_id -> Text,
+        metric -> Text,
+        created_by -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    tool_call_approvals (id) {
+        id -> Text,
+        user_id -> Text,
+        chat_id -> Text,
+        message_id -> Text,
+        tool_call_id -> Text,
+        tool_name -> Text,
+        server_id -> Nullable<Text>,
+        arguments -> Text,
+        status -> Text,
+        reason -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Text,
@@ -207,8 +334,16 @@ diesel::allow_tables_to_appear_in_same_query!(
     chats,
     document_chunks,
     documents,
+    mcp_oauth_states,
+    mcp_oauth_tokens,
     mcp_servers,
+    message_usage,
     messages,
+    model_prices,
     models,
+    quota_limits,
+    quota_resets,
+    quota_usage,
+    tool_call_approvals,
     users,
 );
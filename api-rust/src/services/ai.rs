@@ -5,12 +5,16 @@ use std::collections::HashMap;
 use std::fmt;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
 use tracing::{debug, error, info, instrument};
 
 use crate::config::AppConfig;
-use crate::models::Model;
+use crate::models::{ContextTrim, Model};
+use crate::services::anthropic::AnthropicService;
 use crate::services::bedrock::BedrockService;
 use crate::services::custom::CustomService;
+use crate::services::gemini::GeminiService;
 use crate::services::openai::OpenAIService;
 use crate::services::yandex::YandexService;
 use crate::utils::errors::AppError;
@@ -26,6 +30,14 @@ pub enum ApiProvider {
     YandexAi,
     #[serde(rename = "CUSTOM_REST_API")]
     CustomRestApi,
+    #[serde(rename = "ANTHROPIC")]
+    Anthropic,
+    #[serde(rename = "GEMINI")]
+    Gemini,
+    /// User-defined alias over an ordered list of real models
+    /// (see `services::fallback`).
+    #[serde(rename = "VIRTUAL")]
+    Virtual,
 }
 
 impl ApiProvider {
@@ -35,6 +47,9 @@ impl ApiProvider {
             ApiProvider::OpenAi => "OPEN_AI",
             ApiProvider::YandexAi => "YANDEX_AI",
             ApiProvider::CustomRestApi => "CUSTOM_REST_API",
+            ApiProvider::Anthropic => "ANTHROPIC",
+            ApiProvider::Gemini => "GEMINI",
+            ApiProvider::Virtual => "VIRTUAL",
         }
     }
 }
@@ -48,6 +63,9 @@ impl TryFrom<&str> for ApiProvider {
             "OPEN_AI" => Ok(ApiProvider::OpenAi),
             "YANDEX_AI" => Ok(ApiProvider::YandexAi),
             "CUSTOM_REST_API" => Ok(ApiProvider::CustomRestApi),
+            "ANTHROPIC" => Ok(ApiProvider::Anthropic),
+            "GEMINI" => Ok(ApiProvider::Gemini),
+            "VIRTUAL" => Ok(ApiProvider::Virtual),
             other => Err(AppError::BadRequest(format!(
                 "Unsupported API provider: {}",
                 other
@@ -131,13 +149,24 @@ pub const TOOL_CYCLES_LIMIT: usize = 100;
 /// Server-side execution target for a tool exposed to the model.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ToolBackend {
+    /// Searches the web (see `search_backend::SearchBackend`)
     WebSearch {
-        api_key: String,
-        folder_id: String,
-        api_url: Option<String>,
+        backend: crate::services::search_backend::SearchBackend,
+    },
+    /// Fetches a public URL (see `web_search::fetch_url`)
+    UrlFetch,
+    /// Searches the chunks of the chat's documents (see
+    /// `rag::search_documents`)
+    DocumentSearch {
+        user_id: String,
+        document_ids: Vec<String>,
+        #[serde(skip)]
+        context: Option<Box<crate::services::rag::DocumentSearchContext>>,
     },
     Mcp {
         server: Box<crate::models::McpServer>,
+        /// The session pool key, with the server
+        user_id: String,
         tool_name: String,
         auth_token: Option<String>,
     },
@@ -149,6 +178,13 @@ pub enum ToolBackend {
 pub struct ExecutableTool {
     pub spec: ToolSpec,
     pub backend: ToolBackend,
+    /// Calls wait for the user's approval (see `services::tool_approval`)
+    #[serde(default)]
+    pub requires_approval: bool,
+    /// Deadline of a call; the session's `ToolExecutionPolicy` default
+    /// otherwise
+    #[serde(default)]
+    pub timeout: Option<std::time::Duration>,
 }
 
 /// A tool call executed during a session cycle; recorded into the assistant
@@ -159,6 +195,14 @@ pub struct ExecutedToolCall {
     pub name: String,
     pub args_json: String,
     pub content: String,
+    #[serde(default)]
+    pub duration_ms: u64,
+    /// Why the call failed, timed out or was refused
+    #[serde(default)]
+    pub error: Option<String>,
+    /// The result was cut to the policy's `max_output_chars`
+    #[serde(default)]
+    pub truncated: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -171,6 +215,31 @@ pub struct InvokeModelRequest {
     pub system_prompt: Option<String>,
     #[serde(default)]
     pub tools: Option<Vec<ExecutableTool>>,
+    /// Extended thinking budget in tokens; `None` leaves reasoning at the
+    /// provider default (off where it can be switched off).
+    #[serde(default)]
+    pub thinking_budget: Option<i32>,
+    /// JSON Schema the answer must conform to (structured output).
+    /// Providers enforce it natively where they can (`response_format`, a
+    /// forced tool call); callers still validate the result.
+    #[serde(default)]
+    pub response_schema: Option<serde_json::Value>,
+    /// Asks the user about calls of tools that require approval; without
+    /// one such calls are refused.
+    #[serde(skip)]
+    pub tool_approver: Option<crate::services::tool_approval::ToolApprover>,
+    /// Concurrency, deadline and output cap of the tool calls
+    #[serde(skip)]
+    pub tool_execution: crate::services::tools::ToolExecutionPolicy,
+    /// Set once the session ran a tool call: tools may have side effects,
+    /// so a fallback chain no longer fails the request over to another
+    /// model
+    #[serde(skip)]
+    pub tool_calls_ran: Arc<AtomicBool>,
+    /// The tool calls the session ran, shared with the caller: a stopped
+    /// generation still records the calls made before
+    #[serde(skip)]
+    pub executed_tools: Arc<Mutex<Vec<ExecutedToolCall>>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -181,6 +250,13 @@ pub struct ModelResponse {
     pub finish_reason: Option<String>,
     #[serde(default)]
     pub tool_calls: Vec<ToolCallRequest>,
+    /// Reasoning summary / thinking text, kept apart from `content`.
+    #[serde(default)]
+    pub reasoning: Option<String>,
+    /// Tool calls the session ran before this answer, to be persisted like
+    /// the streaming paths' (`invoke_model_stream`)
+    #[serde(default)]
+    pub executed_tool_calls: Vec<ExecutedToolCall>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -188,6 +264,54 @@ pub struct Usage {
     pub input_tokens: Option<i32>,
     pub output_tokens: Option<i32>,
     pub total_tokens: Option<i32>,
+    /// Input tokens read from the provider's prompt cache.
+    #[serde(default)]
+    pub cache_read_input_tokens: Option<i32>,
+    /// Input tokens written to the provider's prompt cache.
+    #[serde(default)]
+    pub cache_write_input_tokens: Option<i32>,
+}
+
+impl Usage {
+    /// Add another call's counts (tool cycles, streamed chunks); a count
+    /// missing on both sides stays `None`.
+    pub fn add(&mut self, other: &Usage) {
+        fn sum(a: Option<i32>, b: Option<i32>) -> Option<i32> {
+            match (a, b) {
+                (None, None) => None,
+                (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+            }
+        }
+        self.input_tokens = sum(self.input_tokens, other.input_tokens);
+        self.output_tokens = sum(self.output_tokens, other.output_tokens);
+        self.total_tokens = sum(self.total_tokens, other.total_tokens);
+        self.cache_read_input_tokens =
+            sum(self.cache_read_input_tokens, other.cache_read_input_tokens);
+        self.cache_write_input_tokens = sum(
+            self.cache_write_input_tokens,
+            other.cache_write_input_tokens,
+        );
+    }
+}
+
+/// Token usage of a streamed completion, summed over its model calls (each
+/// tool cycle is a call of its own). Clones share the same totals.
+#[derive(Debug, Clone, Default)]
+pub struct UsageTracker(Arc<Mutex<Option<Usage>>>);
+
+impl UsageTracker {
+    pub fn record(&self, usage: &Usage) {
+        if let Ok(mut total) = self.0.lock() {
+            match total.as_mut() {
+                Some(total) => total.add(usage),
+                None => *total = Some(usage.clone()),
+            }
+        }
+    }
+
+    pub fn total(&self) -> Option<Usage> {
+        self.0.lock().ok().and_then(|total| total.clone())
+    }
 }
 
 /// A generated image as raw bytes + mime type, produced by an
@@ -248,16 +372,443 @@ pub struct Amount {
     pub currency: String,
 }
 
+/// Bounds of the per-chat extended thinking budget (also advertised to the
+/// client through `appConfig`).
+pub const REASONING_MIN_TOKEN_BUDGET: i32 = 1024;
+pub const REASONING_MAX_TOKEN_BUDGET: i32 = 16_000;
+
+/// Thinking budget to request for a chat: `None` when thinking is off,
+/// otherwise the chat's budget clamped to the supported range.
+pub fn thinking_budget(thinking: Option<bool>, budget: Option<i32>) -> Option<i32> {
+    thinking.unwrap_or(false).then(|| {
+        budget
+            .unwrap_or(REASONING_MIN_TOKEN_BUDGET)
+            .clamp(REASONING_MIN_TOKEN_BUDGET, REASONING_MAX_TOKEN_BUDGET)
+    })
+}
+
+/// OpenAI-style `reasoning_effort` for a token budget (Node parity: the
+/// budget's share of the max budget picks the effort level).
+pub fn reasoning_effort(budget: i32) -> &'static str {
+    let max = REASONING_MAX_TOKEN_BUDGET as f32;
+    match budget as f32 {
+        b if b < max * 0.1 => "minimal",
+        b if b < max * 0.25 => "low",
+        b if b < max * 0.75 => "medium",
+        _ => "high",
+    }
+}
+
+const THINK_OPEN: &str = "<think>";
+const THINK_CLOSE: &str = "</think>";
+
+/// Splits DeepSeek-style `<think>…</think>` spans out of streamed content
+/// so reasoning from custom models lands in its own channel. Tags split
+/// across deltas are held back until they can be told apart from text.
+#[derive(Debug, Default)]
+pub struct ThinkTagSplitter {
+    in_think: bool,
+    trim_answer: bool,
+    pending: String,
+}
+
+impl ThinkTagSplitter {
+    /// Feed a content delta; returns the `(answer, reasoning)` text that is
+    /// ready to emit.
+    pub fn push(&mut self, delta: &str) -> (String, String) {
+        self.pending.push_str(delta);
+        let mut answer = String::new();
+        let mut reasoning = String::new();
+        loop {
+            let tag = if self.in_think {
+                THINK_CLOSE
+            } else {
+                THINK_OPEN
+            };
+            if let Some(pos) = self.pending.find(tag) {
+                let text: String = self.pending.drain(..pos + tag.len()).collect();
+                self.emit(&text[..pos], &mut answer, &mut reasoning);
+                self.in_think = !self.in_think;
+                self.trim_answer = !self.in_think;
+                continue;
+            }
+            // Hold back a trailing partial tag
+            let keep = (1..tag.len().min(self.pending.len() + 1))
+                .rev()
+                .find(|&k| {
+                    let at = self.pending.len() - k;
+                    self.pending.is_char_boundary(at) && tag.starts_with(&self.pending[at..])
+                })
+                .unwrap_or(0);
+            let text: String = self.pending.drain(..self.pending.len() - keep).collect();
+            self.emit(&text, &mut answer, &mut reasoning);
+            return (answer, reasoning);
+        }
+    }
+
+    /// Flush whatever is still held back at the end of the stream.
+    pub fn finish(&mut self) -> (String, String) {
+        let text = std::mem::take(&mut self.pending);
+        let mut answer = String::new();
+        let mut reasoning = String::new();
+        self.emit(&text, &mut answer, &mut reasoning);
+        (answer, reasoning)
+    }
+
+    fn emit(&mut self, text: &str, answer: &mut String, reasoning: &mut String) {
+        if self.in_think {
+            reasoning.push_str(text);
+            return;
+        }
+        let text = if self.trim_answer {
+            text.trim_start()
+        } else {
+            text
+        };
+        if !text.is_empty() {
+            self.trim_answer = false;
+            answer.push_str(text);
+        }
+    }
+}
+
+/// Split a complete response into answer and `<think>` reasoning.
+pub fn split_think_tags(content: &str) -> (String, Option<String>) {
+    let mut splitter = ThinkTagSplitter::default();
+    let (mut answer, mut reasoning) = splitter.push(content);
+    let (rest_answer, rest_reasoning) = splitter.finish();
+    answer.push_str(&rest_answer);
+    reasoning.push_str(&rest_reasoning);
+    let reasoning = reasoning.trim();
+    (
+        answer,
+        (!reasoning.is_empty()).then(|| reasoning.to_string()),
+    )
+}
+
+/// Tokens reserved for the answer when the request sets no `max_tokens`.
+pub const DEFAULT_RESPONSE_TOKENS: i32 = 4096;
+/// Flat estimate for an image part (a ~1000px image on Claude / GPT-4o).
+const IMAGE_TOKENS: usize = 1600;
+/// Role markers and separators around every message.
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+/// An old turn is trimmed rather than dropped only if at least this much
+/// of it still fits.
+const MIN_TRIMMED_TOKENS: usize = 64;
+
+/// Headroom of the character-based estimates: they are averages, so a
+/// text can take more tokens than estimated.
+const ESTIMATE_MARGIN: f32 = 1.1;
+
+/// Token counter of a provider family. OpenAI models are counted exactly
+/// with their BPE vocabulary (`o200k_base`, `cl100k_base` for older
+/// models). The other families' vocabularies are not public or not
+/// bundled: their counts are estimates from the family's measured average
+/// characters per token for Latin text (other scripts count a token per
+/// character) plus `ESTIMATE_MARGIN`, so budgets keep some headroom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tokenizer {
+    OpenAi,
+    /// GPT-4 / GPT-3.5 and older embeddings models
+    OpenAiCl100k,
+    Anthropic,
+    Gemini,
+    Llama,
+    Generic,
+}
+
+impl Tokenizer {
+    pub fn for_model(api_provider: &str, model_id: &str) -> Self {
+        let model_id = model_id.to_lowercase();
+        let openai = || match tiktoken_rs::tokenizer::get_tokenizer(&model_id) {
+            Some(tiktoken_rs::tokenizer::Tokenizer::Cl100kBase) => Tokenizer::OpenAiCl100k,
+            _ => Tokenizer::OpenAi,
+        };
+        match ApiProvider::from(api_provider.to_string()) {
+            ApiProvider::OpenAi => openai(),
+            ApiProvider::Anthropic => Tokenizer::Anthropic,
+            ApiProvider::Gemini => Tokenizer::Gemini,
+            _ if model_id.contains("claude") || model_id.contains("anthropic.") => {
+                Tokenizer::Anthropic
+            }
+            _ if model_id.contains("gpt")
+                || model_id.starts_with("o1")
+                || model_id.starts_with("o3") =>
+            {
+                openai()
+            }
+            _ if model_id.contains("gemini") || model_id.contains("gemma") => Tokenizer::Gemini,
+            _ if ["llama", "mistral", "qwen", "deepseek"]
+                .iter()
+                .any(|family| model_id.contains(family)) =>
+            {
+                Tokenizer::Llama
+            }
+            _ => Tokenizer::Generic,
+        }
+    }
+
+    /// The BPE vocabulary of an exactly counted family
+    fn bpe(self) -> Option<&'static tiktoken_rs::CoreBPE> {
+        match self {
+            Tokenizer::OpenAi => Some(tiktoken_rs::o200k_base_singleton()),
+            Tokenizer::OpenAiCl100k => Some(tiktoken_rs::cl100k_base_singleton()),
+            _ => None,
+        }
+    }
+
+    fn chars_per_token(self) -> f32 {
+        match self {
+            Tokenizer::OpenAi | Tokenizer::OpenAiCl100k | Tokenizer::Gemini => 4.0,
+            Tokenizer::Llama => 3.7,
+            Tokenizer::Anthropic | Tokenizer::Generic => 3.5,
+        }
+    }
+
+    /// Estimated tokens of a character, margin included.
+    fn char_tokens(self, c: char) -> f32 {
+        let tokens = if c.is_ascii() {
+            1.0 / self.chars_per_token()
+        } else {
+            1.0
+        };
+        tokens * ESTIMATE_MARGIN
+    }
+
+    /// Token count of a text: exact for OpenAI, an estimate otherwise.
+    pub fn count(self, text: &str) -> usize {
+        match self.bpe() {
+            Some(bpe) => bpe.encode_ordinary(text).len(),
+            None => text
+                .chars()
+                .map(|c| self.char_tokens(c))
+                .sum::<f32>()
+                .ceil() as usize,
+        }
+    }
+
+    /// Token count of a message: text and image parts of a structured
+    /// body, replayed tool calls and framing.
+    pub fn count_message(self, message: &ModelMessage) -> usize {
+        let content = match body_parts(&message.content) {
+            Some(parts) => parts
+                .iter()
+                .map(
+                    |part| match part.get("contentType").and_then(|t| t.as_str()) {
+                        Some("image") => IMAGE_TOKENS,
+                        _ => part
+                            .get("content")
+                            .and_then(|c| c.as_str())
+                            .map(|c| self.count(c))
+                            .unwrap_or_default(),
+                    },
+                )
+                .sum(),
+            None => self.count(&message.content),
+        };
+        let tool_calls = message
+            .tool_calls
+            .as_ref()
+            .map(|calls| self.count(&calls.to_string()))
+            .unwrap_or_default();
+        content + tool_calls + MESSAGE_OVERHEAD_TOKENS
+    }
+
+    /// The tail of `text` that fits in `tokens`, marked as cut.
+    fn keep_tail(self, text: &str, tokens: usize) -> String {
+        let starts: Vec<usize> = text.char_indices().map(|(ndx, _)| ndx).collect();
+        let start = match self.bpe() {
+            // the earliest start whose tail fits
+            Some(_) => {
+                let fits = |start: usize| self.count(&text[start..]) <= tokens;
+                let (mut low, mut high) = (0, starts.len());
+                while low < high {
+                    let mid = (low + high) / 2;
+                    if fits(starts[mid]) {
+                        high = mid;
+                    } else {
+                        low = mid + 1;
+                    }
+                }
+                starts.get(low).copied().unwrap_or(text.len())
+            }
+            None => {
+                let mut start = starts.len();
+                let mut used = 0.0f32;
+                for c in text.chars().rev() {
+                    let cost = self.char_tokens(c);
+                    if used + cost > tokens as f32 {
+                        break;
+                    }
+                    used += cost;
+                    start -= 1;
+                }
+                starts.get(start).copied().unwrap_or(text.len())
+            }
+        };
+        format!("…{}", &text[start..])
+    }
+}
+
+/// Parts of a structured message body (a JSON array of `{contentType,
+/// content}` parts, images as `data:` URLs).
+fn body_parts(content: &str) -> Option<Vec<serde_json::Value>> {
+    serde_json::from_str::<serde_json::Value>(content)
+        .ok()
+        .and_then(|parsed| parsed.as_array().cloned())
+        .filter(|parts| parts.iter().all(|part| part.get("contentType").is_some()))
+}
+
+/// Input tokens a request may use in a `max_input_tokens` window once
+/// room is reserved for the answer (`max_tokens` plus any thinking budget).
+pub fn input_budget(request: &InvokeModelRequest, max_input_tokens: i32) -> usize {
+    let reserved = request.max_tokens.unwrap_or(DEFAULT_RESPONSE_TOKENS)
+        + request.thinking_budget.unwrap_or(0);
+    (max_input_tokens - reserved).max(0) as usize
+}
+
+/// Input tokens outside the history: system prompt, tool definitions and
+/// response schema.
+fn fixed_input_tokens(request: &InvokeModelRequest, tokenizer: Tokenizer) -> usize {
+    let tools = request
+        .tools
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .map(|tool| {
+            tokenizer.count(&tool.spec.name)
+                + tokenizer.count(&tool.spec.description)
+                + tokenizer.count(&tool.spec.input_schema.to_string())
+        })
+        .sum::<usize>();
+    request
+        .system_prompt
+        .as_deref()
+        .map(|prompt| tokenizer.count(prompt))
+        .unwrap_or_default()
+        + request
+            .response_schema
+            .as_ref()
+            .map(|schema| tokenizer.count(&schema.to_string()))
+            .unwrap_or_default()
+        + tools
+}
+
+/// Estimated input tokens of a whole request.
+pub fn estimate_input_tokens(request: &InvokeModelRequest, tokenizer: Tokenizer) -> usize {
+    fixed_input_tokens(request, tokenizer)
+        + request
+            .messages
+            .iter()
+            .map(|m| tokenizer.count_message(m))
+            .sum::<usize>()
+}
+
+/// Fit the request's history into `max_input_tokens`, leaving room for the
+/// answer, the system prompt and tool definitions. The oldest turns are dropped first; the oldest kept
+/// one is trimmed when a useful part of it still fits. An assistant
+/// tool-call turn and its tool results are kept or dropped together, and
+/// system messages are always kept. Returns what was cut, if anything.
+pub fn fit_context(
+    request: &mut InvokeModelRequest,
+    max_input_tokens: i32,
+    tokenizer: Tokenizer,
+) -> Option<ContextTrim> {
+    let budget = input_budget(request, max_input_tokens);
+    let fixed = fixed_input_tokens(request, tokenizer);
+    if estimate_input_tokens(request, tokenizer) <= budget {
+        return None;
+    }
+
+    // System messages are kept as they are; the rest is split into turn
+    // groups, a tool result belonging to the assistant turn that
+    // requested it
+    let mut system = Vec::new();
+    let mut groups: Vec<Vec<ModelMessage>> = Vec::new();
+    for message in std::mem::take(&mut request.messages) {
+        match (&message.role, groups.last_mut()) {
+            (MessageRole::System, _) => system.push(message),
+            (MessageRole::Tool, Some(group)) => group.push(message),
+            _ => groups.push(vec![message]),
+        }
+    }
+    let group_tokens = |group: &[ModelMessage]| -> usize {
+        group.iter().map(|m| tokenizer.count_message(m)).sum()
+    };
+    let fixed = fixed + group_tokens(&system);
+
+    let mut trim = ContextTrim {
+        input_tokens_budget: budget as i32,
+        ..Default::default()
+    };
+    let mut available = budget.saturating_sub(fixed);
+    let mut kept: Vec<Vec<ModelMessage>> = Vec::new();
+    let mut groups = groups.into_iter().rev();
+    for mut group in groups.by_ref() {
+        let tokens = group_tokens(&group);
+        if tokens <= available {
+            available -= tokens;
+            kept.push(group);
+            continue;
+        }
+
+        let latest = kept.is_empty();
+        let trimmable = group.len() == 1
+            && group[0].tool_calls.is_none()
+            && body_parts(&group[0].content).is_none();
+        // The latest turn is always sent, trimmed if it can be
+        if trimmable && (latest || available >= MIN_TRIMMED_TOKENS + MESSAGE_OVERHEAD_TOKENS) {
+            let message = &mut group[0];
+            message.content = tokenizer.keep_tail(
+                &message.content,
+                available.saturating_sub(MESSAGE_OVERHEAD_TOKENS + 1),
+            );
+            trim.trimmed_messages += 1;
+            trim.dropped_tokens += tokens.saturating_sub(group_tokens(&group)) as i32;
+            kept.push(group);
+        } else if latest {
+            kept.push(group);
+        } else {
+            trim.dropped_messages += group.len() as i32;
+            trim.dropped_tokens += tokens as i32;
+        }
+        break;
+    }
+    for group in groups {
+        trim.dropped_messages += group.len() as i32;
+        trim.dropped_tokens += group_tokens(&group) as i32;
+    }
+    kept.reverse();
+
+    // The history must still open with a user turn
+    while kept.len() > 1 && kept[0][0].role != MessageRole::User {
+        let group = kept.remove(0);
+        trim.dropped_messages += group.len() as i32;
+        trim.dropped_tokens += group_tokens(&group) as i32;
+    }
+
+    trim.input_tokens = (fixed + kept.iter().map(|g| group_tokens(g)).sum::<usize>()) as i32;
+    request.messages = system;
+    request.messages.extend(kept.into_iter().flatten());
+    Some(trim)
+}
+
 #[allow(dead_code)]
-pub struct StreamCallbacks<F, C, E>
+pub struct StreamCallbacks<F, C, E, R>
 where
     F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
     C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
     E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+    R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
 {
     pub on_token: F,
     pub on_complete: C,
     pub on_error: E,
+    /// Extended thinking / reasoning deltas, streamed apart from the answer
+    /// tokens.
+    pub on_reasoning: R,
+    /// Collects the token usage the provider reports along the stream.
+    pub usage: UsageTracker,
 }
 
 #[async_trait]
@@ -267,15 +818,16 @@ pub trait AIProviderService: Send + Sync {
 
     /// Stream a chat completion. Returns the tool calls executed along the
     /// way (empty when the response needed no tools).
-    async fn invoke_model_stream<F, C, E>(
+    async fn invoke_model_stream<F, C, E, R>(
         &self,
         request: InvokeModelRequest,
-        callbacks: StreamCallbacks<F, C, E>,
+        callbacks: StreamCallbacks<F, C, E, R>,
     ) -> Result<Vec<ExecutedToolCall>, AppError>
     where
         F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
         C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
-        E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync;
+        E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync;
 
     async fn get_models(&self) -> Result<HashMap<String, AIModelInfo>, AppError>;
     async fn get_info(&self, test_connection: bool) -> Result<ProviderInfo, AppError>;
@@ -311,6 +863,8 @@ pub enum AIProviderWrapper {
     OpenAi(OpenAIService),
     Yandex(YandexService),
     Custom(CustomService),
+    Anthropic(AnthropicService),
+    Gemini(GeminiService),
 }
 
 #[async_trait]
@@ -321,18 +875,21 @@ impl AIProviderService for AIProviderWrapper {
             AIProviderWrapper::OpenAi(service) => service.invoke_model(request).await,
             AIProviderWrapper::Yandex(service) => service.invoke_model(request).await,
             AIProviderWrapper::Custom(service) => service.invoke_model(request).await,
+            AIProviderWrapper::Anthropic(service) => service.invoke_model(request).await,
+            AIProviderWrapper::Gemini(service) => service.invoke_model(request).await,
         }
     }
 
-    async fn invoke_model_stream<F, C, E>(
+    async fn invoke_model_stream<F, C, E, R>(
         &self,
         request: InvokeModelRequest,
-        callbacks: StreamCallbacks<F, C, E>,
+        callbacks: StreamCallbacks<F, C, E, R>,
     ) -> Result<Vec<ExecutedToolCall>, AppError>
     where
         F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
         C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
         E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
     {
         match self {
             AIProviderWrapper::Bedrock(service) => {
@@ -347,6 +904,12 @@ impl AIProviderService for AIProviderWrapper {
             AIProviderWrapper::Custom(service) => {
                 service.invoke_model_stream(request, callbacks).await
             }
+            AIProviderWrapper::Anthropic(service) => {
+                service.invoke_model_stream(request, callbacks).await
+            }
+            AIProviderWrapper::Gemini(service) => {
+                service.invoke_model_stream(request, callbacks).await
+            }
         }
     }
 
@@ -356,6 +919,8 @@ impl AIProviderService for AIProviderWrapper {
             AIProviderWrapper::OpenAi(service) => service.get_models().await,
             AIProviderWrapper::Yandex(service) => service.get_models().await,
             AIProviderWrapper::Custom(service) => service.get_models().await,
+            AIProviderWrapper::Anthropic(service) => service.get_models().await,
+            AIProviderWrapper::Gemini(service) => service.get_models().await,
         }
     }
 
@@ -365,6 +930,8 @@ impl AIProviderService for AIProviderWrapper {
             AIProviderWrapper::OpenAi(service) => service.get_info(test_connection).await,
             AIProviderWrapper::Yandex(service) => service.get_info(test_connection).await,
             AIProviderWrapper::Custom(service) => service.get_info(test_connection).await,
+            AIProviderWrapper::Anthropic(service) => service.get_info(test_connection).await,
+            AIProviderWrapper::Gemini(service) => service.get_info(test_connection).await,
         }
     }
 
@@ -378,6 +945,8 @@ impl AIProviderService for AIProviderWrapper {
             AIProviderWrapper::OpenAi(service) => service.get_costs(start_time, end_time).await,
             AIProviderWrapper::Yandex(service) => service.get_costs(start_time, end_time).await,
             AIProviderWrapper::Custom(service) => service.get_costs(start_time, end_time).await,
+            AIProviderWrapper::Anthropic(service) => service.get_costs(start_time, end_time).await,
+            AIProviderWrapper::Gemini(service) => service.get_costs(start_time, end_time).await,
         }
     }
 
@@ -390,6 +959,8 @@ impl AIProviderService for AIProviderWrapper {
             AIProviderWrapper::OpenAi(service) => service.generate_images(request).await,
             AIProviderWrapper::Yandex(service) => service.generate_images(request).await,
             AIProviderWrapper::Custom(service) => service.generate_images(request).await,
+            AIProviderWrapper::Anthropic(service) => service.generate_images(request).await,
+            AIProviderWrapper::Gemini(service) => service.generate_images(request).await,
         }
     }
 
@@ -399,6 +970,8 @@ impl AIProviderService for AIProviderWrapper {
             AIProviderWrapper::OpenAi(service) => service.get_embeddings(model_id, input).await,
             AIProviderWrapper::Yandex(service) => service.get_embeddings(model_id, input).await,
             AIProviderWrapper::Custom(service) => service.get_embeddings(model_id, input).await,
+            AIProviderWrapper::Anthropic(service) => service.get_embeddings(model_id, input).await,
+            AIProviderWrapper::Gemini(service) => service.get_embeddings(model_id, input).await,
         }
     }
 }
@@ -420,7 +993,7 @@ impl AIService {
     pub fn get_provider(&self, api_provider: ApiProvider) -> Result<AIProviderWrapper, AppError> {
         let provider_str = api_provider.as_str();
 
-        if !self.config.is_provider_enabled(provider_str) {
+        if api_provider != ApiProvider::Virtual && !self.config.is_provider_enabled(provider_str) {
             return Err(AppError::BadRequest(format!(
                 "API provider {} is not enabled",
                 provider_str
@@ -437,10 +1010,20 @@ impl AIService {
             ApiProvider::YandexAi => Ok(AIProviderWrapper::Yandex(YandexService::new(
                 self.config.clone(),
             ))),
+            ApiProvider::Anthropic => Ok(AIProviderWrapper::Anthropic(AnthropicService::new(
+                self.config.clone(),
+            ))),
+            ApiProvider::Gemini => Ok(AIProviderWrapper::Gemini(GeminiService::new(
+                self.config.clone(),
+            ))),
             ApiProvider::CustomRestApi => Err(AppError::BadRequest(
                 "Custom REST API provider requires model settings — use get_provider_for_model"
                     .to_string(),
             )),
+            ApiProvider::Virtual => Err(AppError::BadRequest(
+                "Virtual models have no provider of their own — resolve a fallback chain"
+                    .to_string(),
+            )),
         }
     }
 
@@ -455,7 +1038,9 @@ impl AIService {
                     "API provider CUSTOM_REST_API is not enabled".to_string(),
                 ));
             }
-            return Ok(AIProviderWrapper::Custom(CustomService::for_model(model)?));
+            return Ok(AIProviderWrapper::Custom(
+                CustomService::for_model(model)?.with_retry(self.config.retry.clone()),
+            ));
         }
         self.get_provider(api_provider)
     }
@@ -555,6 +1140,12 @@ impl AIService {
         if self.config.is_provider_enabled("YANDEX_AI") {
             providers.push(ApiProvider::YandexAi);
         }
+        if self.config.is_provider_enabled("ANTHROPIC") {
+            providers.push(ApiProvider::Anthropic);
+        }
+        if self.config.is_provider_enabled("GEMINI") {
+            providers.push(ApiProvider::Gemini);
+        }
 
         providers
     }
@@ -565,6 +1156,9 @@ impl AIService {
             ApiProvider::OpenAi => "OpenAI".to_string(),
             ApiProvider::YandexAi => "Yandex AI".to_string(),
             ApiProvider::CustomRestApi => "Custom REST API".to_string(),
+            ApiProvider::Anthropic => "Anthropic".to_string(),
+            ApiProvider::Gemini => "Google Gemini".to_string(),
+            ApiProvider::Virtual => "Virtual model".to_string(),
         }
     }
 
@@ -579,3 +1173,154 @@ impl AIService {
             .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_think_tags_across_deltas() {
+        let mut splitter = ThinkTagSplitter::default();
+        let mut answer = String::new();
+        let mut reasoning = String::new();
+        for delta in [
+            "<thi",
+            "nk>\nstep one",
+            " step two</th",
+            "ink>\n\nThe answer",
+            " is 42<",
+        ] {
+            let (a, r) = splitter.push(delta);
+            answer.push_str(&a);
+            reasoning.push_str(&r);
+        }
+        let (a, r) = splitter.finish();
+        answer.push_str(&a);
+        reasoning.push_str(&r);
+        assert_eq!(reasoning, "\nstep one step two");
+        assert_eq!(answer, "The answer is 42<");
+
+        assert_eq!(split_think_tags("plain"), ("plain".to_string(), None));
+        assert_eq!(
+            split_think_tags("<think>hmm</think> ok"),
+            ("ok".to_string(), Some("hmm".to_string()))
+        );
+    }
+
+    #[test]
+    fn clamps_thinking_budget() {
+        assert_eq!(thinking_budget(None, Some(3000)), None);
+        assert_eq!(thinking_budget(Some(false), Some(3000)), None);
+        assert_eq!(thinking_budget(Some(true), None), Some(1024));
+        assert_eq!(thinking_budget(Some(true), Some(100_000)), Some(16_000));
+        assert_eq!(reasoning_effort(1024), "minimal");
+        assert_eq!(reasoning_effort(3000), "low");
+        assert_eq!(reasoning_effort(16_000), "high");
+    }
+
+    fn budget_request(messages: Vec<ModelMessage>) -> InvokeModelRequest {
+        InvokeModelRequest {
+            model_id: "gpt-4o".to_string(),
+            messages,
+            temperature: None,
+            max_tokens: Some(100),
+            top_p: None,
+            system_prompt: None,
+            tools: None,
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
+        }
+    }
+
+    /// 100 `o200k_base` tokens + framing
+    fn turn(role: MessageRole) -> ModelMessage {
+        ModelMessage::text(role, " word".repeat(100))
+    }
+
+    #[test]
+    fn estimates_tokens_per_family() {
+        assert_eq!(Tokenizer::for_model("OPEN_AI", "gpt-4o"), Tokenizer::OpenAi);
+        assert_eq!(
+            Tokenizer::for_model("OPEN_AI", "gpt-4-turbo"),
+            Tokenizer::OpenAiCl100k
+        );
+        assert_eq!(
+            Tokenizer::for_model("AWS_BEDROCK", "anthropic.claude-3-haiku-20240307-v1:0"),
+            Tokenizer::Anthropic
+        );
+        assert_eq!(
+            Tokenizer::for_model("CUSTOM_REST_API", "llama3.1:8b"),
+            Tokenizer::Llama
+        );
+        // OpenAI counts are exact
+        assert_eq!(Tokenizer::OpenAi.count("hello world"), 2);
+        assert_eq!(Tokenizer::OpenAi.count("привет"), 2);
+        // estimates keep a margin; non-Latin scripts count a token per
+        // character
+        assert_eq!(Tokenizer::Anthropic.count(&"a".repeat(350)), 110);
+        assert_eq!(Tokenizer::Anthropic.count("привет"), 7);
+
+        let image = ModelMessage::text(
+            MessageRole::User,
+            r#"[{"contentType": "image", "content": "data:image/png;base64,AAAA"}]"#,
+        );
+        assert_eq!(
+            Tokenizer::OpenAi.count_message(&image),
+            IMAGE_TOKENS + MESSAGE_OVERHEAD_TOKENS
+        );
+    }
+
+    #[test]
+    fn leaves_fitting_history_alone() {
+        let mut request = budget_request(vec![turn(MessageRole::User)]);
+        assert_eq!(fit_context(&mut request, 8192, Tokenizer::OpenAi), None);
+        assert_eq!(request.messages.len(), 1);
+    }
+
+    #[test]
+    fn drops_oldest_turns_with_their_tool_results() {
+        let mut tool_call = ModelMessage::text(MessageRole::Assistant, "");
+        tool_call.tool_calls = Some(serde_json::json!([{ "id": "t1" }]));
+        let mut tool_result = turn(MessageRole::Tool);
+        tool_result.tool_call_id = Some("t1".to_string());
+        let mut request = budget_request(vec![
+            ModelMessage::text(MessageRole::System, "be brief"),
+            turn(MessageRole::User),
+            tool_call,
+            tool_result,
+            turn(MessageRole::Assistant),
+            turn(MessageRole::User),
+        ]);
+
+        // 100 reserved for the answer leaves room for two turns
+        let trim = fit_context(&mut request, 350, Tokenizer::OpenAi).unwrap();
+        let roles: Vec<_> = request.messages.iter().map(|m| m.role.clone()).collect();
+        // the tool pair goes as a whole; the history reopens on a user turn
+        assert_eq!(roles, vec![MessageRole::System, MessageRole::User]);
+        assert_eq!(trim.dropped_messages, 4);
+        assert_eq!(trim.trimmed_messages, 0);
+        assert_eq!(trim.input_tokens_budget, 250);
+        assert!(trim.input_tokens <= trim.input_tokens_budget);
+    }
+
+    #[test]
+    fn trims_oldest_kept_turn() {
+        let mut request = budget_request(vec![
+            ModelMessage::text(MessageRole::User, " bye".repeat(1000)),
+            turn(MessageRole::Assistant),
+            turn(MessageRole::User),
+        ]);
+
+        let trim = fit_context(&mut request, 408, Tokenizer::OpenAi).unwrap();
+        assert_eq!(request.messages.len(), 3);
+        assert_eq!(trim.dropped_messages, 0);
+        assert_eq!(trim.trimmed_messages, 1);
+        assert!(request.messages[0].content.starts_with('…'));
+        assert!(request.messages[0].content.len() < 400);
+        assert!(trim.input_tokens <= trim.input_tokens_budget);
+    }
+}
@@ -0,0 +1,577 @@
+//! Anthropic provider: the Messages API called directly with an Anthropic
+//! key (`x-api-key`), SSE streaming, tool use and image input. Request and
+//! response mapping is shared with the Bedrock Anthropic family
+//! (`bedrock/providers/anthropic.rs`) — only transport and auth differ.
+
+use async_trait::async_trait;
+use chrono::DateTime;
+use futures_util::StreamExt;
+use reqwest::Client;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use tracing::{debug, warn};
+
+use crate::config::AppConfig;
+use crate::services::ai::*;
+use crate::services::bedrock::providers::anthropic::AnthropicProvider;
+use crate::services::tools::run_tool_calls;
+use crate::utils::errors::AppError;
+
+const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+pub struct AnthropicService {
+    config: AppConfig,
+    client: Client,
+}
+
+impl AnthropicService {
+    pub fn new(config: AppConfig) -> Self {
+        Self {
+            config,
+            client: Client::new(),
+        }
+    }
+
+    fn api_key(&self) -> Result<&str, AppError> {
+        self.config
+            .anthropic_api_key
+            .as_deref()
+            .ok_or_else(|| AppError::Auth("Anthropic API key not configured".to_string()))
+    }
+
+    fn url(&self, path: &str) -> String {
+        let base = self
+            .config
+            .anthropic_api_url
+            .as_deref()
+            .unwrap_or(ANTHROPIC_API_URL)
+            .trim_end_matches('/');
+        format!("{}{}", base, path)
+    }
+
+    fn post(&self, path: &str) -> Result<reqwest::RequestBuilder, AppError> {
+        Ok(self
+            .client
+            .post(self.url(path))
+            .header("Content-Type", "application/json")
+            .header("x-api-key", self.api_key()?)
+            .header("anthropic-version", ANTHROPIC_VERSION))
+    }
+
+    /// Extract the API error message from an Anthropic error payload
+    /// (`{"type": "error", "error": {"type": …, "message": …}}`).
+    fn api_error(status: reqwest::StatusCode, body: &str) -> AppError {
+        let message = serde_json::from_str::<Value>(body)
+            .ok()
+            .and_then(|v| {
+                v.get("error")
+                    .and_then(|e| e.get("message"))
+                    .and_then(|m| m.as_str())
+                    .map(|s| s.to_string())
+            })
+            .unwrap_or_else(|| body.to_string());
+        AppError::Http(format!("Anthropic API error ({}): {}", status, message))
+    }
+
+    async fn send(&self, body: &Value) -> Result<reqwest::Response, AppError> {
+        let response = self
+            .post("/messages")?
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| AppError::Http(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(Self::api_error(status, &error_text));
+        }
+        Ok(response)
+    }
+
+    /// Decode one SSE stream cycle: text deltas go to `on_token`, thinking
+    /// deltas to `on_reasoning`; tool_use and thinking blocks and the stop
+    /// reason are collected for the tool loop.
+    async fn stream_cycle<F, C, E, R>(
+        &self,
+        response: reqwest::Response,
+        callbacks: &StreamCallbacks<F, C, E, R>,
+        full_response: &mut String,
+        tool_blocks: &mut HashMap<u64, (String, String, String)>,
+        thinking_blocks: &mut HashMap<u64, Value>,
+        stop_reason: &mut Option<String>,
+    ) -> Result<(), AppError>
+    where
+        F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+    {
+        let mut stream = response.bytes_stream();
+        let mut line_buffer = String::new();
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| AppError::Http(format!("Stream error: {}", e)))?;
+            line_buffer.push_str(&String::from_utf8_lossy(&chunk));
+
+            // Only `data:` lines matter — every payload repeats its event
+            // name in `type`. Keep the trailing partial line.
+            while let Some(newline_pos) = line_buffer.find('\n') {
+                let line: String = line_buffer.drain(..=newline_pos).collect();
+                let Some(data) = line.trim().strip_prefix("data:") else {
+                    continue;
+                };
+
+                let event = match serde_json::from_str::<Value>(data.trim()) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        warn!("Anthropic: failed to parse stream data: {} — {}", data, e);
+                        continue;
+                    }
+                };
+
+                if event.get("type").and_then(|t| t.as_str()) == Some("error") {
+                    let message = event
+                        .get("error")
+                        .and_then(|e| e.get("message"))
+                        .and_then(|m| m.as_str())
+                        .unwrap_or("unknown stream error");
+                    return Err(AppError::Http(format!(
+                        "Anthropic stream error: {}",
+                        message
+                    )));
+                }
+
+                if let Some(usage) = AnthropicProvider::parse_stream_usage(&event) {
+                    callbacks.usage.record(&usage);
+                }
+                AnthropicProvider::collect_tool_chunks(&event, tool_blocks, stop_reason);
+                if let Some(thinking) =
+                    AnthropicProvider::collect_thinking_chunks(&event, thinking_blocks)
+                {
+                    (callbacks.on_reasoning)(thinking).await;
+                }
+                if let Some(token) = AnthropicProvider::parse_response_chunk(&event) {
+                    full_response.push_str(&token);
+                    (callbacks.on_token)(token).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `claude-*` ids from the models listing; every current Claude model
+    /// is a streaming chat model with image input.
+    fn model_info(id: &str, display_name: Option<&str>) -> AIModelInfo {
+        let name = display_name.unwrap_or(id).to_string();
+        AIModelInfo {
+            api_provider: ApiProvider::Anthropic,
+            provider: Some("Anthropic".to_string()),
+            description: format!("{} by Anthropic", name),
+            name,
+            type_: "chat".to_string(),
+            streaming: true,
+            image_input: true,
+            max_input_tokens: None,
+        }
+    }
+}
+
+#[async_trait]
+impl AIProviderService for AnthropicService {
+    async fn invoke_model(&self, request: InvokeModelRequest) -> Result<ModelResponse, AppError> {
+        let mut session = request;
+
+        let mut executed: Vec<ExecutedToolCall> = Vec::new();
+        for _cycle in 0..TOOL_CYCLES_LIMIT {
+            let body =
+                AnthropicProvider::format_messages_api_request(&session, &session.model_id, false)?;
+            debug!("Anthropic: message for {}", session.model_id);
+
+            let response_json: Value = self.send(&body).await?.json().await.map_err(|e| {
+                AppError::Internal(format!("Failed to parse Anthropic response: {}", e))
+            })?;
+            let parsed =
+                AnthropicProvider::parse_model_response(response_json.clone(), &session.model_id)?;
+
+            if !parsed.tool_calls.is_empty() {
+                let assistant_content = response_json
+                    .get("content")
+                    .cloned()
+                    .unwrap_or_else(|| Value::Array(vec![]));
+                run_tool_calls(
+                    &mut session,
+                    &mut executed,
+                    assistant_content,
+                    parsed.tool_calls,
+                )
+                .await;
+                continue;
+            }
+
+            return Ok(ModelResponse {
+                executed_tool_calls: executed,
+                ..parsed
+            });
+        }
+
+        Err(AppError::Internal(
+            "Anthropic: tool call cycles limit exceeded".to_string(),
+        ))
+    }
+
+    async fn invoke_model_stream<F, C, E, R>(
+        &self,
+        request: InvokeModelRequest,
+        callbacks: StreamCallbacks<F, C, E, R>,
+    ) -> Result<Vec<ExecutedToolCall>, AppError>
+    where
+        F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+    {
+        let mut session = request;
+        let mut executed: Vec<ExecutedToolCall> = Vec::new();
+        let mut full_response = String::new();
+
+        for _cycle in 0..TOOL_CYCLES_LIMIT {
+            let body =
+                AnthropicProvider::format_messages_api_request(&session, &session.model_id, true)?;
+            debug!("Anthropic: streaming message for {}", session.model_id);
+
+            let mut tool_blocks: HashMap<u64, (String, String, String)> = HashMap::new();
+            let mut thinking_blocks: HashMap<u64, Value> = HashMap::new();
+            let mut stop_reason: Option<String> = None;
+
+            let result = match self.send(&body).await {
+                Ok(response) => {
+                    self.stream_cycle(
+                        response,
+                        &callbacks,
+                        &mut full_response,
+                        &mut tool_blocks,
+                        &mut thinking_blocks,
+                        &mut stop_reason,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(error) = result {
+                (callbacks.on_error)(error.clone()).await;
+                return Err(error);
+            }
+
+            // The cycle repeats ONLY to continue after tool calls; a stream
+            // that simply ends is a completed response.
+            if stop_reason.as_deref() != Some("tool_use") || tool_blocks.is_empty() {
+                (callbacks.on_complete)(full_response).await;
+                return Ok(executed);
+            }
+
+            let (assistant_content, calls) =
+                AnthropicProvider::streamed_tool_calls(&tool_blocks, &thinking_blocks);
+            run_tool_calls(&mut session, &mut executed, assistant_content, calls).await;
+        }
+
+        let error = AppError::Internal("Anthropic: tool call cycles limit exceeded".to_string());
+        (callbacks.on_error)(error.clone()).await;
+        Err(error)
+    }
+
+    async fn get_models(&self) -> Result<HashMap<String, AIModelInfo>, AppError> {
+        let response = self
+            .client
+            .get(self.url("/models?limit=1000"))
+            .header("x-api-key", self.api_key()?)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .send()
+            .await
+            .map_err(|e| AppError::Http(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(Self::api_error(status, &error_text));
+        }
+
+        let response_json: Value = response.json().await.map_err(|e| {
+            AppError::Internal(format!("Failed to parse Anthropic models response: {}", e))
+        })?;
+
+        Ok(response_json
+            .get("data")
+            .and_then(|d| d.as_array())
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|model| {
+                        let id = model.get("id").and_then(|id| id.as_str())?;
+                        let display_name = model.get("display_name").and_then(|n| n.as_str());
+                        Some((id.to_string(), Self::model_info(id, display_name)))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn get_info(&self, test_connection: bool) -> Result<ProviderInfo, AppError> {
+        let mut details = HashMap::new();
+        let is_connected = self.config.anthropic_api_key.is_some();
+
+        details.insert("configured".to_string(), is_connected.to_string());
+
+        if test_connection && is_connected {
+            match self.get_models().await {
+                Ok(_) => {
+                    details.insert("connection_test".to_string(), "success".to_string());
+                }
+                Err(e) => {
+                    details.insert("connection_test".to_string(), "failed".to_string());
+                    details.insert("error".to_string(), e.to_string());
+                }
+            }
+        }
+
+        Ok(ProviderInfo {
+            id: "ANTHROPIC".to_string(),
+            name: "Anthropic".to_string(),
+            is_connected,
+            costs_info_available: false,
+            details,
+        })
+    }
+
+    async fn get_costs(
+        &self,
+        start_time: i64,
+        end_time: Option<i64>,
+    ) -> Result<UsageCostInfo, AppError> {
+        Ok(UsageCostInfo {
+            start: DateTime::from_timestamp(start_time, 0).unwrap_or_default(),
+            end: end_time.and_then(|t| DateTime::from_timestamp(t, 0)),
+            costs: vec![],
+            error: Some("Cost information not available for Anthropic".to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::ai::UsageTracker;
+    use crate::utils::test_server::{StubResponse, StubServer};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    fn service(base_url: &str) -> AnthropicService {
+        let mut config = AppConfig::from_env();
+        config.anthropic_api_key = Some("sk-ant-test".to_string());
+        config.anthropic_api_url = Some(format!("{}/v1", base_url));
+        AnthropicService::new(config)
+    }
+
+    fn request() -> InvokeModelRequest {
+        InvokeModelRequest {
+            model_id: "claude-sonnet-4-5".to_string(),
+            messages: vec![ModelMessage::text(MessageRole::User, "hi")],
+            temperature: None,
+            max_tokens: Some(256),
+            top_p: None,
+            system_prompt: Some("be brief".to_string()),
+            tools: None,
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
+        }
+    }
+
+    fn sse(events: &[Value]) -> String {
+        events
+            .iter()
+            .map(|event| {
+                format!(
+                    "event: {}\ndata: {}\n\n",
+                    event["type"].as_str().unwrap_or_default(),
+                    event
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn invokes_messages_api_with_key_and_version_headers() {
+        let server = StubServer::start(vec![StubResponse::json(json!({
+            "content": [{ "type": "text", "text": "Hello!" }],
+            "stop_reason": "end_turn",
+            "usage": {
+                "input_tokens": 5,
+                "output_tokens": 2,
+                "cache_read_input_tokens": 1200,
+                "cache_creation_input_tokens": 40,
+            },
+        }))])
+        .await;
+
+        let response = service(&server.base_url)
+            .invoke_model(request())
+            .await
+            .unwrap();
+        assert_eq!(response.content, "Hello!");
+        let usage = response.usage.unwrap();
+        assert_eq!(usage.output_tokens, Some(2));
+        assert_eq!(usage.cache_read_input_tokens, Some(1200));
+        assert_eq!(usage.cache_write_input_tokens, Some(40));
+
+        let sent = &server.requests()[0];
+        assert!(sent.request_line.starts_with("POST /v1/messages"));
+        assert_eq!(sent.header("x-api-key"), Some("sk-ant-test"));
+        assert_eq!(sent.header("anthropic-version"), Some(ANTHROPIC_VERSION));
+        let body = sent.json();
+        assert_eq!(body["model"], "claude-sonnet-4-5");
+        assert_eq!(body["system"][0]["text"], "be brief");
+        assert_eq!(body["system"][0]["cache_control"]["type"], "ephemeral");
+        assert!(body.get("anthropic_version").is_none());
+    }
+
+    #[tokio::test]
+    async fn invoke_returns_the_executed_tool_calls() {
+        let server = StubServer::start(vec![
+            StubResponse::json(json!({
+                "content": [{ "type": "tool_use", "id": "t1", "name": "lookup",
+                              "input": { "q": "rust" } }],
+                "stop_reason": "tool_use",
+            })),
+            StubResponse::json(json!({
+                "content": [{ "type": "text", "text": "Done." }],
+                "stop_reason": "end_turn",
+            })),
+        ])
+        .await;
+
+        let response = service(&server.base_url)
+            .invoke_model(request())
+            .await
+            .unwrap();
+        assert_eq!(response.content, "Done.");
+        assert_eq!(response.executed_tool_calls.len(), 1);
+        assert_eq!(response.executed_tool_calls[0].id, "t1");
+        assert_eq!(
+            response.executed_tool_calls[0].args_json,
+            json!({"q": "rust"}).to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_text_and_runs_tool_loop() {
+        let tool_turn = sse(&[
+            json!({"type": "message_start", "message": {"usage": {"input_tokens": 3}}}),
+            json!({"type": "content_block_start", "index": 0,
+                "content_block": {"type": "tool_use", "id": "t1", "name": "lookup"}}),
+            json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "input_json_delta", "partial_json": "{\"q\": "}}),
+            json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "input_json_delta", "partial_json": "\"rust\"}"}}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"}}),
+            json!({"type": "message_stop"}),
+        ]);
+        let answer_turn = sse(&[
+            json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "text_delta", "text": "Done"}}),
+            json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "text_delta", "text": "."}}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"}}),
+        ]);
+        let server = StubServer::start(vec![
+            StubResponse::sse(tool_turn),
+            StubResponse::sse(answer_turn),
+        ])
+        .await;
+
+        let tokens = Arc::new(Mutex::new(Vec::<String>::new()));
+        let completed = Arc::new(Mutex::new(None::<String>));
+        let callbacks = StreamCallbacks {
+            on_token: {
+                let tokens = tokens.clone();
+                move |token: String| {
+                    tokens.lock().unwrap().push(token);
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                }
+            },
+            on_complete: {
+                let completed = completed.clone();
+                move |content: String| {
+                    *completed.lock().unwrap() = Some(content);
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                }
+            },
+            on_error: |_e: AppError| Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>,
+            on_reasoning: |_r: String| {
+                Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+            },
+            usage: UsageTracker::default(),
+        };
+
+        let executed = service(&server.base_url)
+            .invoke_model_stream(request(), callbacks)
+            .await
+            .unwrap();
+
+        // the unknown tool still produces a result turn for the model
+        assert_eq!(executed.len(), 1);
+        assert_eq!(executed[0].name, "lookup");
+        assert_eq!(executed[0].args_json, json!({"q": "rust"}).to_string());
+        assert_eq!(*tokens.lock().unwrap(), vec!["Done", "."]);
+        assert_eq!(completed.lock().unwrap().as_deref(), Some("Done."));
+
+        // second cycle replays the tool_use turn and its tool_result
+        let followup = server.requests()[1].json();
+        assert_eq!(followup["stream"], true);
+        assert_eq!(followup["messages"][1]["content"][0]["type"], "tool_use");
+        assert_eq!(followup["messages"][2]["content"][0]["type"], "tool_result");
+    }
+
+    #[tokio::test]
+    async fn surfaces_api_errors() {
+        let server = StubServer::start(vec![StubResponse::text(
+            401,
+            "application/json",
+            json!({"type": "error",
+                "error": {"type": "authentication_error", "message": "invalid x-api-key"}})
+            .to_string(),
+        )])
+        .await;
+
+        let error = service(&server.base_url)
+            .invoke_model(request())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("invalid x-api-key"));
+    }
+
+    #[test]
+    fn image_parts_become_image_blocks() {
+        let mut req = request();
+        req.messages = vec![ModelMessage::text(
+            MessageRole::User,
+            json!([
+                {"contentType": "text", "content": "what is this?"},
+                {"contentType": "image", "content": "data:image/png;base64,iVBORw0"},
+            ])
+            .to_string(),
+        )];
+        let body = AnthropicProvider::format_messages_api_request(&req, "claude-sonnet-4-5", false)
+            .unwrap();
+        let content = &body["messages"][0]["content"];
+        assert_eq!(content[0]["type"], "text");
+        assert_eq!(content[1]["type"], "image");
+        assert_eq!(content[1]["source"]["media_type"], "image/png");
+        assert_eq!(content[1]["source"]["data"], "iVBORw0");
+    }
+}
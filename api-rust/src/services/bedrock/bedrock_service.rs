@@ -1,9 +1,13 @@
 use async_trait::async_trait;
+use aws_config::retry::RetryConfig;
+use aws_config::timeout::TimeoutConfig;
 use aws_config::{BehaviorVersion, Region};
 use aws_sdk_bedrock::{types::ModelModality, Client as BedrockClient};
+use aws_sdk_bedrockruntime::error::ProvideErrorMetadata;
 use aws_sdk_bedrockruntime::operation::invoke_model_with_response_stream::InvokeModelWithResponseStreamError;
 use aws_sdk_bedrockruntime::{primitives::Blob, Client as BedrockRuntimeClient};
 use aws_smithy_runtime_api::client::result::SdkError;
+use aws_smithy_types::error::display::DisplayErrorContext;
 use chrono::{DateTime, Utc};
 use log::error;
 use serde_json::Value;
@@ -11,14 +15,18 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::future::Future;
 use std::pin::Pin;
+use std::time::Duration;
 use tracing::{debug, warn};
 
 use crate::config::AppConfig;
 use crate::services::ai::*;
+use crate::services::bedrock::converse;
 use crate::services::bedrock::providers::{
     ai21::AI21Provider, amazon::AmazonProvider, anthropic::AnthropicProvider,
     cohere::CohereProvider, meta::MetaProvider, mistral::MistralProvider,
 };
+use crate::services::retry::is_retryable_aws_code;
+use crate::services::tools::run_tool_calls;
 use crate::utils::errors::AppError;
 
 /// Curated Bedrock model list shared with the Node API (single source of
@@ -90,6 +98,40 @@ fn sanitize_sampling_params(mut request: InvokeModelRequest) -> InvokeModelReque
     request
 }
 
+/// How to retry a Converse call the model rejected up front.
+#[derive(Debug, PartialEq)]
+enum ConverseRetry {
+    /// The model has no Converse support: use the per-family formatters.
+    Legacy,
+    /// The model rejects tool use: repeat the session without tools.
+    WithoutTools,
+}
+
+fn converse_retry<E: ProvideErrorMetadata>(
+    error: Option<&E>,
+    cycle: usize,
+    session: &InvokeModelRequest,
+) -> Option<ConverseRetry> {
+    let error = error.filter(|e| e.code() == Some("ValidationException"))?;
+    let message = error.message().unwrap_or_default();
+    if session.tools.is_some() && converse::is_tool_use_unsupported(message) {
+        warn!(
+            "Model {} doesn't support tool use, continuing without tools",
+            session.model_id
+        );
+        return Some(ConverseRetry::WithoutTools);
+    }
+    // Falling back mid-session would lose the Converse-shaped tool turns
+    if cycle == 0 && converse::is_converse_unsupported(message) {
+        debug!(
+            "Model {} doesn't support Converse, using the legacy request format",
+            session.model_id
+        );
+        return Some(ConverseRetry::Legacy);
+    }
+    None
+}
+
 pub struct BedrockService {
     config: AppConfig,
     runtime_client: Option<BedrockRuntimeClient>,
@@ -148,7 +190,23 @@ impl BedrockService {
     }
 
     pub(crate) async fn build_aws_config(&self) -> Result<aws_config::SdkConfig, AppError> {
-        let mut config_builder = aws_config::defaults(BehaviorVersion::v2025_01_17());
+        // Request-level retries (throttling, 5xx, timeouts) run inside the
+        // SDK with the shared policy's attempts and backoff bounds; the
+        // policy's deadline bounds the whole operation, retries included
+        // (for streams: until the response starts)
+        let retry = &self.config.retry;
+        let mut config_builder = aws_config::defaults(BehaviorVersion::v2025_01_17())
+            .retry_config(
+                RetryConfig::standard()
+                    .with_max_attempts(retry.max_attempts)
+                    .with_initial_backoff(Duration::from_millis(retry.base_delay_ms))
+                    .with_max_backoff(Duration::from_millis(retry.max_delay_ms)),
+            )
+            .timeout_config(
+                TimeoutConfig::builder()
+                    .operation_timeout(Duration::from_millis(retry.deadline_ms))
+                    .build(),
+            );
         let region = self
             .config
             .aws_bedrock_region
@@ -227,100 +285,216 @@ impl BedrockService {
             ))),
         }
     }
+}
 
-    /// Collect Anthropic streaming events that describe tool_use blocks:
-    /// content_block_start carries id/name, input_json_delta events carry
-    /// the argument JSON in fragments, message_delta carries stop_reason.
-    fn collect_anthropic_tool_chunks(
-        chunk_data: &Value,
-        tool_blocks: &mut HashMap<u64, (String, String, String)>,
-        stop_reason: &mut Option<String>,
-    ) {
-        let index = chunk_data
-            .get("index")
-            .and_then(|i| i.as_u64())
-            .unwrap_or(0);
-        match chunk_data.get("type").and_then(|t| t.as_str()) {
-            Some("content_block_start") => {
-                let Some(block) = chunk_data.get("content_block") else {
-                    return;
-                };
-                if block.get("type").and_then(|t| t.as_str()) == Some("tool_use") {
-                    let id = block
-                        .get("id")
-                        .and_then(|id| id.as_str())
-                        .unwrap_or("unknown_id")
-                        .to_string();
-                    let name = block
-                        .get("name")
-                        .and_then(|n| n.as_str())
-                        .unwrap_or_default()
-                        .to_string();
-                    tool_blocks.insert(index, (id, name, String::new()));
-                }
+impl BedrockService {
+    /// Converse path shared by every chat family. `Ok(None)` means the
+    /// model cannot be called through Converse and the legacy per-family
+    /// formatters should take over.
+    async fn invoke_converse(
+        &self,
+        request: &InvokeModelRequest,
+    ) -> Result<Option<ModelResponse>, AppError> {
+        let mut service = self.clone();
+        let client = service.get_runtime_client().await?;
+
+        let mut session = request.clone();
+        let mut executed: Vec<ExecutedToolCall> = Vec::new();
+
+        for cycle in 0..TOOL_CYCLES_LIMIT {
+            let result = client
+                .converse()
+                .model_id(&session.model_id)
+                .set_messages(Some(converse::request_messages(&session)?))
+                .set_system(converse::system_blocks(&session))
+                .inference_config(converse::inference_config(&session))
+                .set_additional_model_request_fields(converse::additional_fields(&session))
+                .set_tool_config(converse::tool_config(&session)?)
+                .send()
+                .await;
+
+            let output = match result {
+                Ok(output) => output,
+                Err(e) => match converse_retry(e.as_service_error(), cycle, &session) {
+                    Some(ConverseRetry::Legacy) => return Ok(None),
+                    Some(ConverseRetry::WithoutTools) => {
+                        session.tools = None;
+                        continue;
+                    }
+                    None => {
+                        error!(
+                            "Bedrock Converse failed for model {}: {:?}",
+                            session.model_id, e
+                        );
+                        return Err(AppError::Aws(format!(
+                            "Bedrock Converse failed for model '{}': {}",
+                            session.model_id,
+                            DisplayErrorContext(&e)
+                        )));
+                    }
+                },
+            };
+
+            let (response, assistant_content) = converse::parse_output(
+                output.output(),
+                output.stop_reason().as_str(),
+                output.usage(),
+                &request.model_id,
+            );
+
+            if !response.tool_calls.is_empty() {
+                run_tool_calls(
+                    &mut session,
+                    &mut executed,
+                    assistant_content,
+                    response.tool_calls,
+                )
+                .await;
+                continue;
             }
-            Some("content_block_delta") => {
-                let Some(delta) = chunk_data.get("delta") else {
-                    return;
-                };
-                if delta.get("type").and_then(|t| t.as_str()) == Some("input_json_delta") {
-                    if let (Some(fragment), Some(entry)) = (
-                        delta.get("partial_json").and_then(|p| p.as_str()),
-                        tool_blocks.get_mut(&index),
-                    ) {
-                        entry.2.push_str(fragment);
+
+            return Ok(Some(ModelResponse {
+                executed_tool_calls: executed,
+                ..response
+            }));
+        }
+
+        Err(AppError::Internal(
+            "Bedrock: tool call cycles limit exceeded".to_string(),
+        ))
+    }
+
+    /// ConverseStream path; `Ok(None)` (only before any token was emitted)
+    /// hands the request over to the legacy streaming path.
+    async fn converse_stream<F, C, E, R>(
+        &self,
+        request: &InvokeModelRequest,
+        callbacks: &StreamCallbacks<F, C, E, R>,
+    ) -> Result<Option<Vec<ExecutedToolCall>>, AppError>
+    where
+        F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+    {
+        let mut service = self.clone();
+        let client = service.get_runtime_client().await?;
+
+        let mut session = request.clone();
+        let mut executed: Vec<ExecutedToolCall> = Vec::new();
+        let mut full_response = String::new();
+        let mut reasoned = false;
+        let mut retries = self.config.retry.start();
+
+        'cycle: for cycle in 0..TOOL_CYCLES_LIMIT {
+            let result = client
+                .converse_stream()
+                .model_id(&session.model_id)
+                .set_messages(Some(converse::request_messages(&session)?))
+                .set_system(converse::system_blocks(&session))
+                .inference_config(converse::inference_config(&session))
+                .set_additional_model_request_fields(converse::additional_fields(&session))
+                .set_tool_config(converse::tool_config(&session)?)
+                .send()
+                .await;
+
+            let response = match result {
+                Ok(response) => response,
+                Err(e) => match converse_retry(e.as_service_error(), cycle, &session) {
+                    Some(ConverseRetry::Legacy) => return Ok(None),
+                    Some(ConverseRetry::WithoutTools) => {
+                        session.tools = None;
+                        continue;
+                    }
+                    None => {
+                        error!(
+                            "Bedrock ConverseStream failed for model {}: {:?}",
+                            session.model_id, e
+                        );
+                        let error = AppError::Aws(format!(
+                            "Bedrock streaming error for model '{}': {}",
+                            session.model_id,
+                            DisplayErrorContext(&e)
+                        ));
+                        (callbacks.on_error)(error.clone()).await;
+                        return Err(error);
+                    }
+                },
+            };
+
+            let mut state = converse::ConverseStreamState::default();
+            let mut stream = response.stream;
+            loop {
+                match stream.recv().await {
+                    Ok(Some(event)) => match state.apply(&event) {
+                        Some(converse::StreamDelta::Text(token)) => {
+                            full_response.push_str(&token);
+                            (callbacks.on_token)(token).await;
+                        }
+                        Some(converse::StreamDelta::Reasoning(delta)) => {
+                            reasoned = true;
+                            (callbacks.on_reasoning)(delta).await;
+                        }
+                        None => {}
+                    },
+                    Ok(None) => break,
+                    Err(e) => {
+                        // Throttled mid-stream: safe to repeat the cycle
+                        // only while nothing has been streamed
+                        let code = e.as_service_error().and_then(|se| se.code());
+                        if full_response.is_empty() && !reasoned && is_retryable_aws_code(code) {
+                            if let Some(delay) = retries.next_delay(None) {
+                                warn!(
+                                    "Bedrock stream for {} failed ({}), retrying in {:?}",
+                                    session.model_id,
+                                    code.unwrap_or_default(),
+                                    delay
+                                );
+                                tokio::time::sleep(delay).await;
+                                continue 'cycle;
+                            }
+                        }
+                        let error =
+                            AppError::Aws(format!("Stream error: {}", DisplayErrorContext(&e)));
+                        (callbacks.on_error)(error.clone()).await;
+                        return Err(error);
                     }
                 }
             }
-            Some("message_delta") => {
-                if let Some(reason) = chunk_data
-                    .get("delta")
-                    .and_then(|d| d.get("stop_reason"))
-                    .and_then(|r| r.as_str())
-                {
-                    *stop_reason = Some(reason.to_string());
-                }
+
+            if let Some(usage) = &state.usage {
+                debug!(
+                    "Bedrock ConverseStream usage for {}: {:?}",
+                    session.model_id, usage
+                );
+                callbacks.usage.record(usage);
+            }
+
+            // The cycle repeats ONLY to continue after tool calls.
+            if !state.wants_tools() {
+                (callbacks.on_complete)(full_response).await;
+                return Ok(Some(executed));
             }
-            _ => {}
-        }
-    }
 
-    /// Execute the tool calls of an Anthropic tool_use turn and extend the
-    /// session: the assistant turn replays its raw content blocks, each
-    /// result becomes a Tool-role message (a tool_result block on the next
-    /// format pass).
-    async fn run_anthropic_tool_calls(
-        session: &mut InvokeModelRequest,
-        executed: &mut Vec<ExecutedToolCall>,
-        assistant_content: Value,
-        calls: Vec<ToolCallRequest>,
-    ) {
-        session.messages.push(ModelMessage {
-            role: MessageRole::Assistant,
-            content: String::new(),
-            timestamp: None,
-            tool_calls: Some(assistant_content),
-            tool_call_id: None,
-        });
-
-        let tools = session.tools.clone().unwrap_or_default();
-        for call in calls {
-            let (message, record) = crate::services::tools::execute_tool_call(&tools, &call).await;
-            executed.push(record);
-            session.messages.push(message);
+            let (assistant_content, calls) = state.tool_calls();
+            run_tool_calls(&mut session, &mut executed, assistant_content, calls).await;
         }
+
+        let error = AppError::Internal("Bedrock: tool call cycles limit exceeded".to_string());
+        (callbacks.on_error)(error.clone()).await;
+        Err(error)
     }
-}
 
-#[async_trait]
-impl AIProviderService for BedrockService {
-    async fn invoke_model(&self, request: InvokeModelRequest) -> Result<ModelResponse, AppError> {
+    /// Legacy InvokeModel path with per-family request bodies; used for
+    /// models that Converse rejects.
+    async fn invoke_legacy(&self, request: InvokeModelRequest) -> Result<ModelResponse, AppError> {
         let mut service = self.clone();
         let client = service.get_runtime_client().await?;
 
-        let mut session = sanitize_sampling_params(request);
+        let mut session = request;
         let provider = self.get_model_provider(&session.model_id);
 
+        let mut executed: Vec<ExecutedToolCall> = Vec::new();
         for _cycle in 0..TOOL_CYCLES_LIMIT {
             let body = self.format_request_for_provider(&provider, &session)?;
 
@@ -372,8 +546,7 @@ impl AIProviderService for BedrockService {
                     .get("content")
                     .cloned()
                     .unwrap_or_else(|| Value::Array(vec![]));
-                let mut executed = Vec::new();
-                Self::run_anthropic_tool_calls(
+                run_tool_calls(
                     &mut session,
                     &mut executed,
                     assistant_content,
@@ -383,7 +556,10 @@ impl AIProviderService for BedrockService {
                 continue;
             }
 
-            return Ok(parsed);
+            return Ok(ModelResponse {
+                executed_tool_calls: executed,
+                ..parsed
+            });
         }
 
         Err(AppError::Internal(
@@ -391,20 +567,22 @@ impl AIProviderService for BedrockService {
         ))
     }
 
-    async fn invoke_model_stream<F, C, E>(
+    /// Legacy InvokeModelWithResponseStream path (simulated streaming for
+    /// families without a streaming body format).
+    async fn invoke_stream_legacy<F, C, E, R>(
         &self,
         request: InvokeModelRequest,
-        callbacks: StreamCallbacks<F, C, E>,
+        callbacks: &StreamCallbacks<F, C, E, R>,
     ) -> Result<Vec<ExecutedToolCall>, AppError>
     where
         F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
         C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
         E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
     {
         let mut service = self.clone();
         let client = service.get_runtime_client().await?;
 
-        let request = sanitize_sampling_params(request);
         let provider = self.get_model_provider(&request.model_id);
 
         // Check if model supports streaming (Anthropic, Amazon, Mistral)
@@ -416,8 +594,11 @@ impl AIProviderService for BedrockService {
                 "Model {} doesn't support streaming, simulating",
                 request.model_id
             );
-            match self.invoke_model(request).await {
+            match self.invoke_legacy(request).await {
                 Ok(response) => {
+                    if let Some(usage) = &response.usage {
+                        callbacks.usage.record(usage);
+                    }
                     // Simulate streaming by sending chunks of the response
                     let words: Vec<&str> = response.content.split_whitespace().collect();
                     for word in words {
@@ -438,8 +619,10 @@ impl AIProviderService for BedrockService {
             let mut session = request;
             let mut executed: Vec<ExecutedToolCall> = Vec::new();
             let mut full_response = String::new();
+            let mut reasoned = false;
+            let mut retries = self.config.retry.start();
 
-            for _cycle in 0..TOOL_CYCLES_LIMIT {
+            'cycle: for _cycle in 0..TOOL_CYCLES_LIMIT {
                 let body = self.format_request_for_provider(&provider, &session)?;
 
                 let body_bytes = serde_json::to_vec(&body).map_err(|e| {
@@ -475,6 +658,8 @@ impl AIProviderService for BedrockService {
                 // Anthropic tool_use blocks streamed this cycle, keyed by
                 // content block index: (id, name, accumulated input JSON)
                 let mut tool_blocks: HashMap<u64, (String, String, String)> = HashMap::new();
+                // Anthropic thinking blocks, replayed ahead of tool_use
+                let mut thinking_blocks: HashMap<u64, Value> = HashMap::new();
                 let mut stop_reason: Option<String> = None;
 
                 let mut stream = response.body;
@@ -490,12 +675,25 @@ impl AIProviderService for BedrockService {
                                         Ok(chunk_str) => {
                                             match serde_json::from_str::<Value>(chunk_str) {
                                                 Ok(chunk_data) => {
+                                                    if let Some(usage) =
+                                                        invocation_metrics_usage(&chunk_data)
+                                                    {
+                                                        callbacks.usage.record(&usage);
+                                                    }
                                                     if provider == "anthropic" {
-                                                        Self::collect_anthropic_tool_chunks(
+                                                        AnthropicProvider::collect_tool_chunks(
                                                             &chunk_data,
                                                             &mut tool_blocks,
                                                             &mut stop_reason,
                                                         );
+                                                        if let Some(thinking) = AnthropicProvider::collect_thinking_chunks(
+                                                            &chunk_data,
+                                                            &mut thinking_blocks,
+                                                        ) {
+                                                            reasoned = true;
+                                                            (callbacks.on_reasoning)(thinking)
+                                                                .await;
+                                                        }
                                                     }
 
                                                     let token = match provider.as_str() {
@@ -542,6 +740,20 @@ impl AIProviderService for BedrockService {
                             break;
                         }
                         Err(e) => {
+                            let code = e.as_service_error().and_then(|se| se.code());
+                            if full_response.is_empty() && !reasoned && is_retryable_aws_code(code)
+                            {
+                                if let Some(delay) = retries.next_delay(None) {
+                                    warn!(
+                                        "Bedrock stream for {} failed ({}), retrying in {:?}",
+                                        session.model_id,
+                                        code.unwrap_or_default(),
+                                        delay
+                                    );
+                                    tokio::time::sleep(delay).await;
+                                    continue 'cycle;
+                                }
+                            }
                             let error = AppError::Aws(format!("Stream error: {}", e));
                             (callbacks.on_error)(error.clone()).await;
                             return Err(error);
@@ -556,37 +768,9 @@ impl AIProviderService for BedrockService {
                     return Ok(executed);
                 }
 
-                let mut ordered: Vec<(&u64, &(String, String, String))> =
-                    tool_blocks.iter().collect();
-                ordered.sort_by_key(|(index, _)| **index);
-
-                let mut assistant_content: Vec<Value> = Vec::new();
-                let mut calls: Vec<ToolCallRequest> = Vec::new();
-                for (_, (id, name, input_json)) in ordered {
-                    let arguments: Value =
-                        serde_json::from_str(input_json).unwrap_or_else(|_| serde_json::json!({}));
-                    let block = serde_json::json!({
-                        "type": "tool_use",
-                        "id": id,
-                        "name": name,
-                        "input": arguments,
-                    });
-                    assistant_content.push(block.clone());
-                    calls.push(ToolCallRequest {
-                        id: id.clone(),
-                        name: name.clone(),
-                        arguments,
-                        raw: block,
-                    });
-                }
-
-                Self::run_anthropic_tool_calls(
-                    &mut session,
-                    &mut executed,
-                    Value::Array(assistant_content),
-                    calls,
-                )
-                .await;
+                let (assistant_content, calls) =
+                    AnthropicProvider::streamed_tool_calls(&tool_blocks, &thinking_blocks);
+                run_tool_calls(&mut session, &mut executed, assistant_content, calls).await;
             }
 
             let error = AppError::Internal("Bedrock: tool call cycles limit exceeded".to_string());
@@ -594,6 +778,49 @@ impl AIProviderService for BedrockService {
             Err(error)
         }
     }
+}
+
+/// Token usage Bedrock appends to the last chunk of an
+/// InvokeModelWithResponseStream body, whatever the model family.
+fn invocation_metrics_usage(chunk_data: &Value) -> Option<Usage> {
+    let metrics = chunk_data.get("amazon-bedrock-invocationMetrics")?;
+    let tokens = |key: &str| metrics.get(key).and_then(|t| t.as_i64()).map(|t| t as i32);
+    Some(Usage {
+        input_tokens: tokens("inputTokenCount"),
+        output_tokens: tokens("outputTokenCount"),
+        total_tokens: None,
+        cache_read_input_tokens: tokens("cacheReadInputTokenCount"),
+        cache_write_input_tokens: tokens("cacheWriteInputTokenCount"),
+    })
+}
+
+#[async_trait]
+impl AIProviderService for BedrockService {
+    async fn invoke_model(&self, request: InvokeModelRequest) -> Result<ModelResponse, AppError> {
+        let request = sanitize_sampling_params(request);
+        match self.invoke_converse(&request).await? {
+            Some(response) => Ok(response),
+            None => self.invoke_legacy(request).await,
+        }
+    }
+
+    async fn invoke_model_stream<F, C, E, R>(
+        &self,
+        request: InvokeModelRequest,
+        callbacks: StreamCallbacks<F, C, E, R>,
+    ) -> Result<Vec<ExecutedToolCall>, AppError>
+    where
+        F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+    {
+        let request = sanitize_sampling_params(request);
+        match self.converse_stream(&request, &callbacks).await? {
+            Some(executed) => Ok(executed),
+            None => self.invoke_stream_legacy(request, &callbacks).await,
+        }
+    }
 
     async fn get_models(&self) -> Result<HashMap<String, AIModelInfo>, AppError> {
         let mut service = self.clone();
@@ -744,6 +971,22 @@ impl AIProviderService for BedrockService {
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn sdk_config_carries_the_retry_deadline() {
+        let mut config = AppConfig::from_env();
+        config.retry.deadline_ms = 12_000;
+        let sdk_config = BedrockService::new(config)
+            .build_aws_config()
+            .await
+            .unwrap();
+        assert_eq!(
+            sdk_config
+                .timeout_config()
+                .and_then(|timeouts| timeouts.operation_timeout()),
+            Some(Duration::from_millis(12_000))
+        );
+    }
+
     #[test]
     fn parses_shared_models_config() {
         let configs = bedrock_model_configs();
@@ -772,6 +1015,12 @@ mod tests {
             top_p: Some(0.9),
             system_prompt: None,
             tools: None,
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
         };
         let sanitized = sanitize_sampling_params(request);
         assert_eq!(sanitized.temperature, None);
@@ -0,0 +1,924 @@
+//! Bedrock Converse / ConverseStream mapping: one message format for every
+//! Bedrock chat family (Anthropic, Nova, Llama, Mistral, Cohere, AI21) with
+//! tool use, system prompts, image input and token usage — mirroring the
+//! Node provider's `formatConverseParams` / `parseConverseResponse`.
+//!
+//! Assistant tool turns are replayed as Converse-shaped JSON content
+//! (`[{"reasoningContent": …}, {"text": …}, {"toolUse": {"toolUseId",
+//! "name", "input"}}]`), tool results as `toolResult` blocks inside a user
+//! turn.
+
+use aws_sdk_bedrockruntime::primitives::Blob;
+use aws_sdk_bedrockruntime::types::{
+    CachePointBlock, CachePointType, ContentBlock, ContentBlockDelta, ContentBlockStart,
+    ConversationRole, ConverseOutput, ConverseStreamOutput, ImageBlock, ImageFormat, ImageSource,
+    InferenceConfiguration, Message, ReasoningContentBlock, ReasoningContentBlockDelta,
+    ReasoningTextBlock, SpecificToolChoice, SystemContentBlock, TokenUsage, Tool, ToolChoice,
+    ToolConfiguration, ToolInputSchema, ToolResultBlock, ToolResultContentBlock, ToolSpecification,
+    ToolUseBlock,
+};
+use aws_smithy_types::{Document, Number};
+use base64::Engine;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::services::ai::{
+    InvokeModelRequest, MessageRole, ModelMessage, ModelResponse, ToolCallRequest, ToolSpec, Usage,
+};
+use crate::services::structured::{self, STRUCTURED_OUTPUT_TOOL};
+use crate::utils::errors::AppError;
+
+pub(crate) fn json_to_document(value: &Value) -> Document {
+    match value {
+        Value::Null => Document::Null,
+        Value::Bool(b) => Document::Bool(*b),
+        Value::Number(n) => Document::Number(if let Some(u) = n.as_u64() {
+            Number::PosInt(u)
+        } else if let Some(i) = n.as_i64() {
+            Number::NegInt(i)
+        } else {
+            Number::Float(n.as_f64().unwrap_or_default())
+        }),
+        Value::String(s) => Document::String(s.clone()),
+        Value::Array(items) => Document::Array(items.iter().map(json_to_document).collect()),
+        Value::Object(map) => Document::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), json_to_document(v)))
+                .collect(),
+        ),
+    }
+}
+
+pub(crate) fn document_to_json(document: &Document) -> Value {
+    match document {
+        Document::Null => Value::Null,
+        Document::Bool(b) => json!(b),
+        Document::Number(Number::PosInt(u)) => json!(u),
+        Document::Number(Number::NegInt(i)) => json!(i),
+        Document::Number(Number::Float(f)) => json!(f),
+        Document::String(s) => json!(s),
+        Document::Array(items) => Value::Array(items.iter().map(document_to_json).collect()),
+        Document::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), document_to_json(v)))
+                .collect(),
+        ),
+    }
+}
+
+fn build_error(e: impl std::fmt::Display) -> AppError {
+    AppError::Internal(format!("Failed to build Converse request: {}", e))
+}
+
+fn image_block(data_url: &str) -> Option<ContentBlock> {
+    let (mime, data) = data_url.strip_prefix("data:")?.split_once(";base64,")?;
+    let format = match mime {
+        "image/png" => ImageFormat::Png,
+        "image/jpeg" | "image/jpg" => ImageFormat::Jpeg,
+        "image/gif" => ImageFormat::Gif,
+        "image/webp" => ImageFormat::Webp,
+        _ => return None,
+    };
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .ok()?;
+    ImageBlock::builder()
+        .format(format)
+        .source(ImageSource::Bytes(Blob::new(bytes)))
+        .build()
+        .ok()
+        .map(ContentBlock::Image)
+}
+
+/// Message body → Converse blocks: structured bodies (a JSON array of
+/// `{contentType, content}` parts, images as `data:` URLs) become
+/// text/image blocks, anything else a single text block.
+fn body_blocks(body: &str) -> Vec<ContentBlock> {
+    let parts = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|parsed| parsed.as_array().cloned())
+        .unwrap_or_default();
+
+    let blocks: Vec<ContentBlock> = parts
+        .iter()
+        .filter_map(|part| {
+            let content = part.get("content").and_then(|v| v.as_str())?;
+            match part.get("contentType").and_then(|v| v.as_str())? {
+                "image" => image_block(content),
+                "text" if !content.trim().is_empty() => {
+                    Some(ContentBlock::Text(content.to_string()))
+                }
+                _ => None,
+            }
+        })
+        .collect();
+
+    if !blocks.is_empty() {
+        return blocks;
+    }
+    // Converse rejects blank text blocks
+    if body.trim().is_empty() {
+        return Vec::new();
+    }
+    vec![ContentBlock::Text(body.to_string())]
+}
+
+/// Reasoning block → replay JSON (text with its signature, or the
+/// base64 of redacted content).
+fn reasoning_replay(block: &ReasoningContentBlock) -> Option<Value> {
+    match block {
+        ReasoningContentBlock::ReasoningText(text) => Some(json!({
+            "reasoningContent": { "text": text.text(), "signature": text.signature() }
+        })),
+        ReasoningContentBlock::RedactedContent(data) => Some(json!({
+            "reasoningContent": {
+                "redactedContent": base64::engine::general_purpose::STANDARD.encode(data.as_ref())
+            }
+        })),
+        _ => None,
+    }
+}
+
+/// Replayed reasoning JSON → content block.
+fn replay_reasoning(reasoning: &Value) -> Result<Option<ContentBlock>, AppError> {
+    if let Some(data) = reasoning.get("redactedContent").and_then(|d| d.as_str()) {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(build_error)?;
+        return Ok(Some(ContentBlock::ReasoningContent(
+            ReasoningContentBlock::RedactedContent(Blob::new(bytes)),
+        )));
+    }
+    let Some(text) = reasoning.get("text").and_then(|t| t.as_str()) else {
+        return Ok(None);
+    };
+    let block = ReasoningTextBlock::builder()
+        .text(text)
+        .set_signature(
+            reasoning
+                .get("signature")
+                .and_then(|s| s.as_str())
+                .map(str::to_string),
+        )
+        .build()
+        .map_err(build_error)?;
+    Ok(Some(ContentBlock::ReasoningContent(
+        ReasoningContentBlock::ReasoningText(block),
+    )))
+}
+
+/// Replayed assistant tool turn (Converse-shaped JSON) → content blocks.
+fn replay_blocks(content: &Value) -> Result<Vec<ContentBlock>, AppError> {
+    let mut blocks = Vec::new();
+    for item in content.as_array().into_iter().flatten() {
+        if let Some(reasoning) = item.get("reasoningContent") {
+            blocks.extend(replay_reasoning(reasoning)?);
+        }
+        if let Some(text) = item
+            .get("text")
+            .and_then(|t| t.as_str())
+            .filter(|t| !t.trim().is_empty())
+        {
+            blocks.push(ContentBlock::Text(text.to_string()));
+        }
+        if let Some(tool_use) = item.get("toolUse") {
+            let block = ToolUseBlock::builder()
+                .tool_use_id(tool_use["toolUseId"].as_str().unwrap_or_default())
+                .name(tool_use["name"].as_str().unwrap_or_default())
+                .input(json_to_document(&tool_use["input"]))
+                .build()
+                .map_err(build_error)?;
+            blocks.push(ContentBlock::ToolUse(block));
+        }
+    }
+    Ok(blocks)
+}
+
+/// Session messages → Converse messages. Consecutive turns of the same
+/// role are merged (Converse requires alternating roles), tool results
+/// travel as `toolResult` blocks of a user turn and system-role messages
+/// are left to [`system_blocks`].
+pub(crate) fn format_messages(messages: &[ModelMessage]) -> Result<Vec<Message>, AppError> {
+    let mut turns: Vec<(ConversationRole, Vec<ContentBlock>)> = Vec::new();
+
+    for msg in messages {
+        let (role, blocks) = match msg.role {
+            MessageRole::System => continue,
+            MessageRole::Tool => {
+                let result = ToolResultBlock::builder()
+                    .tool_use_id(msg.tool_call_id.clone().unwrap_or_default())
+                    .content(ToolResultContentBlock::Text(msg.content.clone()))
+                    .build()
+                    .map_err(build_error)?;
+                (
+                    ConversationRole::User,
+                    vec![ContentBlock::ToolResult(result)],
+                )
+            }
+            MessageRole::Assistant => {
+                let mut blocks = Vec::new();
+                match &msg.tool_calls {
+                    Some(tool_calls) => {
+                        if !msg.content.trim().is_empty() {
+                            blocks.push(ContentBlock::Text(msg.content.clone()));
+                        }
+                        blocks.extend(replay_blocks(tool_calls)?);
+                    }
+                    None => blocks.extend(body_blocks(&msg.content)),
+                }
+                (ConversationRole::Assistant, blocks)
+            }
+            MessageRole::User => (ConversationRole::User, body_blocks(&msg.content)),
+        };
+
+        if blocks.is_empty() {
+            continue;
+        }
+        match turns.last_mut() {
+            Some((last_role, last_blocks)) if *last_role == role => last_blocks.extend(blocks),
+            _ => turns.push((role, blocks)),
+        }
+    }
+
+    turns
+        .into_iter()
+        .map(|(role, blocks)| {
+            Message::builder()
+                .role(role)
+                .set_content(Some(blocks))
+                .build()
+                .map_err(build_error)
+        })
+        .collect()
+}
+
+/// Models accepting Converse cache points (prompt caching).
+const CACHE_POINT_MODELS: &[&str] = &[
+    "anthropic.claude-opus-4",
+    "anthropic.claude-sonnet-4",
+    "anthropic.claude-haiku-4",
+    "anthropic.claude-3-7-sonnet",
+    "anthropic.claude-3-5-haiku",
+    "anthropic.claude-3-5-sonnet",
+    "amazon.nova",
+];
+
+fn supports_cache_points(model_id: &str) -> bool {
+    CACHE_POINT_MODELS.iter().any(|m| model_id.contains(m))
+}
+
+fn cache_point() -> Result<CachePointBlock, AppError> {
+    CachePointBlock::builder()
+        .r#type(CachePointType::Default)
+        .build()
+        .map_err(build_error)
+}
+
+/// Session messages for the request; on models with prompt caching a
+/// cache point closes the last message, so the next turn (or tool cycle)
+/// rereads the history up to it from the cache.
+pub(crate) fn request_messages(request: &InvokeModelRequest) -> Result<Vec<Message>, AppError> {
+    let mut messages = format_messages(&request.messages)?;
+    if !supports_cache_points(&request.model_id) {
+        return Ok(messages);
+    }
+    if let Some(last) = messages.pop() {
+        let mut content = last.content().to_vec();
+        content.push(ContentBlock::CachePoint(cache_point()?));
+        messages.push(
+            Message::builder()
+                .role(last.role().clone())
+                .set_content(Some(content))
+                .build()
+                .map_err(build_error)?,
+        );
+    }
+    Ok(messages)
+}
+
+/// System prompt plus any system-role session messages, followed by a
+/// cache point on models with prompt caching.
+pub(crate) fn system_blocks(request: &InvokeModelRequest) -> Option<Vec<SystemContentBlock>> {
+    let mut blocks: Vec<SystemContentBlock> = request
+        .system_prompt
+        .iter()
+        .chain(
+            request
+                .messages
+                .iter()
+                .filter(|m| matches!(m.role, MessageRole::System))
+                .map(|m| &m.content),
+        )
+        .filter(|text| !text.trim().is_empty())
+        .map(|text| SystemContentBlock::Text(text.clone()))
+        .collect();
+    if blocks.is_empty() {
+        return None;
+    }
+    if supports_cache_points(&request.model_id) {
+        blocks.extend(cache_point().ok().map(SystemContentBlock::CachePoint));
+    }
+    Some(blocks)
+}
+
+/// Claude models take an extended thinking budget through
+/// `additionalModelRequestFields`; a forced tool call (structured output)
+/// is incompatible with it.
+fn thinking_budget(request: &InvokeModelRequest) -> Option<i32> {
+    request
+        .thinking_budget
+        .filter(|_| request.model_id.contains("anthropic."))
+        .filter(|_| request.response_schema.is_none())
+}
+
+pub(crate) fn inference_config(request: &InvokeModelRequest) -> InferenceConfiguration {
+    match thinking_budget(request) {
+        // Thinking counts against max tokens and rejects custom sampling
+        Some(budget) => InferenceConfiguration::builder()
+            .max_tokens(request.max_tokens.unwrap_or(4096) + budget)
+            .build(),
+        None => InferenceConfiguration::builder()
+            .set_max_tokens(request.max_tokens)
+            .set_temperature(request.temperature)
+            .set_top_p(request.top_p)
+            .build(),
+    }
+}
+
+pub(crate) fn additional_fields(request: &InvokeModelRequest) -> Option<Document> {
+    thinking_budget(request).map(|budget| {
+        json_to_document(&json!({
+            "thinking": { "type": "enabled", "budget_tokens": budget }
+        }))
+    })
+}
+
+fn tool_specification(spec: &ToolSpec) -> Result<Tool, AppError> {
+    ToolSpecification::builder()
+        .name(&spec.name)
+        .description(&spec.description)
+        .input_schema(ToolInputSchema::Json(json_to_document(&spec.input_schema)))
+        .build()
+        .map(Tool::ToolSpec)
+        .map_err(build_error)
+}
+
+/// Chat tools, or — for structured output — the single output tool the
+/// model is forced to call.
+pub(crate) fn tool_config(
+    request: &InvokeModelRequest,
+) -> Result<Option<ToolConfiguration>, AppError> {
+    if let Some(schema) = &request.response_schema {
+        let choice = SpecificToolChoice::builder()
+            .name(STRUCTURED_OUTPUT_TOOL)
+            .build()
+            .map_err(build_error)?;
+        return ToolConfiguration::builder()
+            .tools(tool_specification(&structured::output_tool(schema))?)
+            .tool_choice(ToolChoice::Tool(choice))
+            .build()
+            .map(Some)
+            .map_err(build_error);
+    }
+
+    let Some(tools) = request.tools.as_deref().filter(|t| !t.is_empty()) else {
+        return Ok(None);
+    };
+
+    let mut specs = tools
+        .iter()
+        .map(|tool| tool_specification(&tool.spec))
+        .collect::<Result<Vec<_>, _>>()?;
+    if supports_cache_points(&request.model_id) {
+        specs.push(Tool::CachePoint(cache_point()?));
+    }
+
+    ToolConfiguration::builder()
+        .set_tools(Some(specs))
+        .build()
+        .map(Some)
+        .map_err(build_error)
+}
+
+pub(crate) fn parse_usage(usage: &TokenUsage) -> Usage {
+    Usage {
+        input_tokens: Some(usage.input_tokens()),
+        output_tokens: Some(usage.output_tokens()),
+        total_tokens: Some(usage.total_tokens()),
+        cache_read_input_tokens: usage.cache_read_input_tokens(),
+        cache_write_input_tokens: usage.cache_write_input_tokens(),
+    }
+}
+
+fn tool_call(id: &str, name: &str, input: Value) -> (Value, ToolCallRequest) {
+    let replay = json!({ "toolUse": { "toolUseId": id, "name": name, "input": input } });
+    let request = ToolCallRequest {
+        id: id.to_string(),
+        name: name.to_string(),
+        arguments: input,
+        raw: replay.clone(),
+    };
+    (replay, request)
+}
+
+/// Converse output → model response plus the assistant content to replay
+/// when the model requested tools.
+pub(crate) fn parse_output(
+    output: Option<&ConverseOutput>,
+    stop_reason: &str,
+    usage: Option<&TokenUsage>,
+    model_id: &str,
+) -> (ModelResponse, Value) {
+    let mut content = String::new();
+    let mut reasoning = String::new();
+    let mut replay: Vec<Value> = Vec::new();
+    let mut tool_calls = Vec::new();
+
+    let blocks = output
+        .and_then(|o| o.as_message().ok())
+        .map(|m| m.content())
+        .unwrap_or_default();
+    for block in blocks {
+        match block {
+            ContentBlock::Text(text) => {
+                content.push_str(text);
+                replay.push(json!({ "text": text }));
+            }
+            // The forced structured-output call carries the answer as its
+            // input
+            ContentBlock::ToolUse(tool_use) if tool_use.name() == STRUCTURED_OUTPUT_TOOL => {
+                content.push_str(&document_to_json(tool_use.input()).to_string());
+            }
+            ContentBlock::ToolUse(tool_use) => {
+                let (item, call) = tool_call(
+                    tool_use.tool_use_id(),
+                    tool_use.name(),
+                    document_to_json(tool_use.input()),
+                );
+                replay.push(item);
+                tool_calls.push(call);
+            }
+            ContentBlock::ReasoningContent(block) => {
+                if let Ok(text) = block.as_reasoning_text() {
+                    reasoning.push_str(text.text());
+                }
+                replay.extend(reasoning_replay(block));
+            }
+            _ => {}
+        }
+    }
+
+    let response = ModelResponse {
+        content,
+        model_id: model_id.to_string(),
+        usage: usage.map(parse_usage),
+        finish_reason: Some(stop_reason.to_string()),
+        tool_calls,
+        reasoning: (!reasoning.is_empty()).then_some(reasoning),
+        executed_tool_calls: Vec::new(),
+    };
+    (response, Value::Array(replay))
+}
+
+/// A delta to hand to the stream callbacks.
+#[derive(Debug, PartialEq)]
+pub(crate) enum StreamDelta {
+    Text(String),
+    Reasoning(String),
+}
+
+/// Accumulates one ConverseStream cycle: text and reasoning deltas are
+/// handed back to the caller, tool_use and reasoning blocks are collected
+/// by content block index.
+#[derive(Default)]
+pub(crate) struct ConverseStreamState {
+    text: String,
+    /// (tool use id, name, accumulated input JSON) by content block index
+    tool_blocks: HashMap<i32, (String, String, String)>,
+    /// Reasoning replay JSON (`text` + `signature` or `redactedContent`)
+    /// by content block index
+    reasoning_blocks: HashMap<i32, Value>,
+    pub stop_reason: Option<String>,
+    pub usage: Option<Usage>,
+}
+
+impl ConverseStreamState {
+    /// Apply a stream event; returns the delta to emit, if any.
+    pub fn apply(&mut self, event: &ConverseStreamOutput) -> Option<StreamDelta> {
+        match event {
+            ConverseStreamOutput::ContentBlockStart(start) => {
+                if let Some(ContentBlockStart::ToolUse(tool_use)) = start.start() {
+                    self.tool_blocks.insert(
+                        start.content_block_index(),
+                        (
+                            tool_use.tool_use_id().to_string(),
+                            tool_use.name().to_string(),
+                            String::new(),
+                        ),
+                    );
+                }
+                None
+            }
+            ConverseStreamOutput::ContentBlockDelta(delta) => match delta.delta() {
+                Some(ContentBlockDelta::Text(text)) if !text.is_empty() => {
+                    self.text.push_str(text);
+                    Some(StreamDelta::Text(text.clone()))
+                }
+                Some(ContentBlockDelta::ReasoningContent(reasoning)) => {
+                    let block = self
+                        .reasoning_blocks
+                        .entry(delta.content_block_index())
+                        .or_insert_with(|| json!({}));
+                    match reasoning {
+                        ReasoningContentBlockDelta::Text(text) => {
+                            let so_far = block.get("text").and_then(|t| t.as_str());
+                            block["text"] = json!(format!("{}{}", so_far.unwrap_or(""), text));
+                            (!text.is_empty()).then(|| StreamDelta::Reasoning(text.clone()))
+                        }
+                        ReasoningContentBlockDelta::Signature(signature) => {
+                            block["signature"] = json!(signature);
+                            None
+                        }
+                        ReasoningContentBlockDelta::RedactedContent(data) => {
+                            block["redactedContent"] = json!(
+                                base64::engine::general_purpose::STANDARD.encode(data.as_ref())
+                            );
+                            None
+                        }
+                        _ => None,
+                    }
+                }
+                Some(ContentBlockDelta::ToolUse(tool_use)) => {
+                    if let Some(block) = self.tool_blocks.get_mut(&delta.content_block_index()) {
+                        block.2.push_str(tool_use.input());
+                    }
+                    None
+                }
+                _ => None,
+            },
+            ConverseStreamOutput::MessageStop(stop) => {
+                self.stop_reason = Some(stop.stop_reason().as_str().to_string());
+                None
+            }
+            ConverseStreamOutput::Metadata(metadata) => {
+                self.usage = metadata.usage().map(parse_usage);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    pub fn wants_tools(&self) -> bool {
+        self.stop_reason.as_deref() == Some("tool_use") && !self.tool_blocks.is_empty()
+    }
+
+    /// Assistant content to replay (this cycle's reasoning, text and
+    /// toolUse blocks) and the tool calls to execute, in block order.
+    pub fn tool_calls(&self) -> (Value, Vec<ToolCallRequest>) {
+        let mut indices: Vec<&i32> = self.tool_blocks.keys().collect();
+        indices.sort();
+        let mut reasoning: Vec<(&i32, &Value)> = self.reasoning_blocks.iter().collect();
+        reasoning.sort_by_key(|(index, _)| **index);
+
+        let mut replay: Vec<Value> = reasoning
+            .into_iter()
+            .map(|(_, block)| json!({ "reasoningContent": block }))
+            .collect();
+        if !self.text.trim().is_empty() {
+            replay.push(json!({ "text": self.text }));
+        }
+        let mut calls = Vec::new();
+        for index in indices {
+            let (id, name, input) = &self.tool_blocks[index];
+            let input = Some(input.as_str())
+                .filter(|i| !i.trim().is_empty())
+                .and_then(|i| serde_json::from_str(i).ok())
+                .unwrap_or_else(|| json!({}));
+            let (item, call) = tool_call(id, name, input);
+            replay.push(item);
+            calls.push(call);
+        }
+        (Value::Array(replay), calls)
+    }
+}
+
+/// Validation messages meaning the model cannot be called through Converse
+/// at all (legacy invoke-model formatters take over).
+pub(crate) fn is_converse_unsupported(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.contains("doesn't support the model")
+        || message.contains("does not support the model")
+        || message.contains("doesn't support converse")
+        || message.contains("does not support converse")
+}
+
+/// Validation messages meaning the model rejects tool use in Converse;
+/// the session is retried without tools.
+pub(crate) fn is_tool_use_unsupported(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.contains("doesn't support tool use") || message.contains("does not support tool use")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_bedrockruntime::types::{
+        ContentBlockDeltaEvent, ContentBlockStartEvent, MessageStopEvent, StopReason,
+        ToolUseBlockDelta, ToolUseBlockStart,
+    };
+
+    fn tool_turn(id: &str, content: &str) -> ModelMessage {
+        ModelMessage {
+            role: MessageRole::Tool,
+            content: content.to_string(),
+            timestamp: None,
+            tool_calls: None,
+            tool_call_id: Some(id.to_string()),
+        }
+    }
+
+    #[test]
+    fn document_round_trip() {
+        let value =
+            json!({"q": "rust", "limit": 3, "offset": -1, "score": 0.5, "tags": [true, null]});
+        assert_eq!(document_to_json(&json_to_document(&value)), value);
+    }
+
+    #[test]
+    fn formats_images_tool_results_and_merges_roles() {
+        let image = json!([
+            {"contentType": "text", "content": "What is this?"},
+            {"contentType": "image", "content": "data:image/png;base64,iVBORw0KGgo="},
+        ])
+        .to_string();
+        let messages = vec![
+            ModelMessage::text(MessageRole::System, "be brief"),
+            ModelMessage::text(MessageRole::User, image),
+            ModelMessage {
+                role: MessageRole::Assistant,
+                content: String::new(),
+                timestamp: None,
+                tool_calls: Some(json!([
+                    {"toolUse": {"toolUseId": "t1", "name": "lookup", "input": {"q": "a"}}},
+                    {"toolUse": {"toolUseId": "t2", "name": "lookup", "input": {"q": "b"}}},
+                ])),
+                tool_call_id: None,
+            },
+            tool_turn("t1", "first"),
+            tool_turn("t2", "second"),
+        ];
+
+        let formatted = format_messages(&messages).unwrap();
+        assert_eq!(formatted.len(), 3);
+        assert_eq!(formatted[0].role(), &ConversationRole::User);
+        assert!(formatted[0].content()[0].is_text());
+        assert!(formatted[0].content()[1].is_image());
+        assert_eq!(formatted[1].content().len(), 2);
+        assert!(formatted[1].content()[0].is_tool_use());
+        // both results share one user turn
+        assert_eq!(formatted[2].role(), &ConversationRole::User);
+        assert_eq!(formatted[2].content().len(), 2);
+        assert!(formatted[2].content()[1].is_tool_result());
+    }
+
+    #[test]
+    fn system_prompt_and_system_messages() {
+        let request = InvokeModelRequest {
+            model_id: "amazon.nova-lite-v1:0".to_string(),
+            messages: vec![ModelMessage::text(MessageRole::System, "extra")],
+            temperature: None,
+            max_tokens: Some(64),
+            top_p: None,
+            system_prompt: Some("base".to_string()),
+            tools: None,
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
+        };
+        let blocks = system_blocks(&request).unwrap();
+        assert_eq!(blocks.len(), 3);
+        assert!(blocks[2].is_cache_point());
+        assert!(tool_config(&request).unwrap().is_none());
+    }
+
+    #[test]
+    fn cache_points_only_on_caching_models() {
+        let mut request = InvokeModelRequest {
+            model_id: "anthropic.claude-sonnet-4-20250514-v1:0".to_string(),
+            messages: vec![
+                ModelMessage::text(MessageRole::User, "hi"),
+                ModelMessage::text(MessageRole::Assistant, "hello"),
+                ModelMessage::text(MessageRole::User, "go on"),
+            ],
+            temperature: None,
+            max_tokens: Some(64),
+            top_p: None,
+            system_prompt: Some("base".to_string()),
+            tools: None,
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
+        };
+        let messages = request_messages(&request).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].content().len(), 1);
+        assert!(messages[2].content()[1].is_cache_point());
+
+        request.model_id = "meta.llama3-70b-instruct-v1:0".to_string();
+        let messages = request_messages(&request).unwrap();
+        assert_eq!(messages[2].content().len(), 1);
+        assert_eq!(system_blocks(&request).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn response_schema_forces_output_tool() {
+        let request = InvokeModelRequest {
+            model_id: "anthropic.claude-3-7-sonnet-20250219-v1:0".to_string(),
+            messages: vec![ModelMessage::text(MessageRole::User, "Extract: Bob")],
+            temperature: None,
+            max_tokens: Some(64),
+            top_p: None,
+            system_prompt: None,
+            tools: None,
+            thinking_budget: Some(2048),
+            response_schema: Some(json!({"type": "object"})),
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
+        };
+        let config = tool_config(&request).unwrap().unwrap();
+        assert_eq!(config.tools().len(), 1);
+        assert!(matches!(
+            config.tool_choice(),
+            Some(ToolChoice::Tool(choice)) if choice.name() == STRUCTURED_OUTPUT_TOOL
+        ));
+        assert!(additional_fields(&request).is_none());
+
+        let output = ConverseOutput::Message(
+            Message::builder()
+                .role(ConversationRole::Assistant)
+                .content(ContentBlock::ToolUse(
+                    ToolUseBlock::builder()
+                        .tool_use_id("t1")
+                        .name(STRUCTURED_OUTPUT_TOOL)
+                        .input(json_to_document(&json!({"name": "Bob"})))
+                        .build()
+                        .unwrap(),
+                ))
+                .build()
+                .unwrap(),
+        );
+        let (response, _) = parse_output(Some(&output), "tool_use", None, "model");
+        assert_eq!(response.content, r#"{"name":"Bob"}"#);
+        assert!(response.tool_calls.is_empty());
+    }
+
+    #[test]
+    fn stream_state_collects_tool_use() {
+        let mut state = ConverseStreamState::default();
+        let events = [
+            ConverseStreamOutput::ContentBlockDelta(
+                ContentBlockDeltaEvent::builder()
+                    .content_block_index(0)
+                    .delta(ContentBlockDelta::Text("Let me check.".to_string()))
+                    .build()
+                    .unwrap(),
+            ),
+            ConverseStreamOutput::ContentBlockStart(
+                ContentBlockStartEvent::builder()
+                    .content_block_index(1)
+                    .start(ContentBlockStart::ToolUse(
+                        ToolUseBlockStart::builder()
+                            .tool_use_id("t1")
+                            .name("lookup")
+                            .build()
+                            .unwrap(),
+                    ))
+                    .build()
+                    .unwrap(),
+            ),
+            ConverseStreamOutput::ContentBlockDelta(
+                ContentBlockDeltaEvent::builder()
+                    .content_block_index(1)
+                    .delta(ContentBlockDelta::ToolUse(
+                        ToolUseBlockDelta::builder()
+                            .input("{\"q\":")
+                            .build()
+                            .unwrap(),
+                    ))
+                    .build()
+                    .unwrap(),
+            ),
+            ConverseStreamOutput::ContentBlockDelta(
+                ContentBlockDeltaEvent::builder()
+                    .content_block_index(1)
+                    .delta(ContentBlockDelta::ToolUse(
+                        ToolUseBlockDelta::builder()
+                            .input("\"rust\"}")
+                            .build()
+                            .unwrap(),
+                    ))
+                    .build()
+                    .unwrap(),
+            ),
+            ConverseStreamOutput::MessageStop(
+                MessageStopEvent::builder()
+                    .stop_reason(StopReason::ToolUse)
+                    .build()
+                    .unwrap(),
+            ),
+        ];
+
+        let tokens: Vec<StreamDelta> = events.iter().filter_map(|e| state.apply(e)).collect();
+        assert_eq!(tokens, vec![StreamDelta::Text("Let me check.".to_string())]);
+        assert!(state.wants_tools());
+
+        let (replay, calls) = state.tool_calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].arguments, json!({"q": "rust"}));
+        assert_eq!(replay[0]["text"], "Let me check.");
+        assert_eq!(replay[1]["toolUse"]["toolUseId"], "t1");
+
+        // the replay round-trips into a Converse assistant turn
+        let blocks = replay_blocks(&replay).unwrap();
+        assert!(blocks[1].is_tool_use());
+    }
+
+    #[test]
+    fn stream_state_separates_reasoning() {
+        let reasoning = |index: i32, delta: ReasoningContentBlockDelta| {
+            ConverseStreamOutput::ContentBlockDelta(
+                ContentBlockDeltaEvent::builder()
+                    .content_block_index(index)
+                    .delta(ContentBlockDelta::ReasoningContent(delta))
+                    .build()
+                    .unwrap(),
+            )
+        };
+        let mut state = ConverseStreamState::default();
+        let deltas: Vec<StreamDelta> = [
+            reasoning(0, ReasoningContentBlockDelta::Text("Think".to_string())),
+            reasoning(0, ReasoningContentBlockDelta::Text("ing".to_string())),
+            reasoning(0, ReasoningContentBlockDelta::Signature("sig".to_string())),
+        ]
+        .iter()
+        .filter_map(|e| state.apply(e))
+        .collect();
+        assert_eq!(
+            deltas,
+            vec![
+                StreamDelta::Reasoning("Think".to_string()),
+                StreamDelta::Reasoning("ing".to_string()),
+            ]
+        );
+
+        // Reasoning (with signature) leads the replayed assistant turn
+        let (replay, _) = state.tool_calls();
+        assert_eq!(replay[0]["reasoningContent"]["text"], "Thinking");
+        let blocks = replay_blocks(&replay).unwrap();
+        let block = blocks[0].as_reasoning_content().unwrap();
+        assert_eq!(block.as_reasoning_text().unwrap().signature(), Some("sig"));
+
+        let mut request = InvokeModelRequest {
+            model_id: "us.anthropic.claude-sonnet-4-5-v1:0".to_string(),
+            messages: Vec::new(),
+            temperature: Some(0.2),
+            max_tokens: Some(1000),
+            top_p: None,
+            system_prompt: None,
+            tools: None,
+            thinking_budget: Some(2048),
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
+        };
+        let config = inference_config(&request);
+        assert_eq!(config.max_tokens(), Some(3048));
+        assert_eq!(config.temperature(), None);
+        assert!(additional_fields(&request).is_some());
+        request.model_id = "amazon.nova-lite-v1:0".to_string();
+        assert!(additional_fields(&request).is_none());
+    }
+
+    #[test]
+    fn classifies_validation_errors() {
+        assert!(is_converse_unsupported(
+            "This action doesn't support the model that you provided. Try again with a supported text or chat model."
+        ));
+        assert!(is_tool_use_unsupported(
+            "This model doesn't support tool use."
+        ));
+        assert!(!is_converse_unsupported(
+            "Input is too long for requested model."
+        ));
+    }
+}
@@ -1,5 +1,6 @@
 pub mod bedrock_service;
 pub mod bedrock_service_costs;
+pub mod converse;
 pub mod providers;
 
 pub use bedrock_service::*;
@@ -187,12 +187,15 @@ impl AI21Provider {
                 .get("totalTokens")
                 .and_then(|t| t.as_i64())
                 .map(|t| t as i32),
+            cache_read_input_tokens: None,
+            cache_write_input_tokens: None,
         });
 
         Ok(ModelResponse {
             content,
             model_id: model_id.to_string(),
             tool_calls: Vec::new(),
+            executed_tool_calls: Vec::new(),
             usage,
             finish_reason: response
                 .get("choices")
@@ -201,6 +204,7 @@ impl AI21Provider {
                 .and_then(|choice| choice.get("finishReason"))
                 .and_then(|r| r.as_str())
                 .map(|s| s.to_string()),
+            reasoning: None,
         })
     }
 }
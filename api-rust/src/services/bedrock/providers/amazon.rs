@@ -372,8 +372,10 @@ impl AmazonProvider {
             content,
             model_id: model_id.to_string(),
             tool_calls: Vec::new(),
+            executed_tool_calls: Vec::new(),
             usage: None,
             finish_reason: None,
+            reasoning: None,
         })
     }
 
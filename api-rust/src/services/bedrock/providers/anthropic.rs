@@ -2,9 +2,11 @@ use crate::models::message::{Message, MessageRole};
 use crate::services::ai::{
     InvokeModelRequest, MessageRole as AIMessageRole, ModelResponse, ToolCallRequest, Usage,
 };
+use crate::services::structured::{self, STRUCTURED_OUTPUT_TOOL};
 use crate::utils::errors::AppError;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::collections::HashMap;
 
 #[derive(Debug, Serialize)]
 pub struct AnthropicRequestMessage {
@@ -38,6 +40,8 @@ pub struct AnthropicContentBlock {
 pub struct AnthropicUsage {
     pub input_tokens: Option<u32>,
     pub output_tokens: Option<u32>,
+    pub cache_read_input_tokens: Option<u32>,
+    pub cache_creation_input_tokens: Option<u32>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -63,82 +67,55 @@ impl AnthropicProvider {
                     _ => "user",
                 };
 
-                // Handle both string and structured content
-                let content =
-                    if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(msg.get_body()) {
-                        if parsed.is_array() {
-                            // Handle structured content with images/text
-                            let content_parts: Vec<serde_json::Value> = parsed
-                                .as_array()
-                                .unwrap_or(&vec![])
-                                .iter()
-                                .filter_map(|part| {
-                                    if let Some(obj) = part.as_object() {
-                                        if let Some(content_type) =
-                                            obj.get("contentType").and_then(|v| v.as_str())
-                                        {
-                                            match content_type {
-                                                "image" => {
-                                                    if let Some(content) =
-                                                        obj.get("content").and_then(|v| v.as_str())
-                                                    {
-                                                        // Parse data URL format: data:image/type;base64,data
-                                                        if let Some(captures) = regex::Regex::new(
-                                                            r"^data:(image/[^;]+);base64,(.*)$",
-                                                        )
-                                                        .unwrap()
-                                                        .captures(content)
-                                                        {
-                                                            let media_type =
-                                                                captures.get(1).unwrap().as_str();
-                                                            let base64_data =
-                                                                captures.get(2).unwrap().as_str();
-
-                                                            return Some(json!({
-                                                                "type": "image",
-                                                                "source": {
-                                                                    "type": "base64",
-                                                                    "media_type": media_type,
-                                                                    "data": base64_data
-                                                                }
-                                                            }));
-                                                        }
-                                                    }
-                                                }
-                                                "text" => {
-                                                    if let Some(content) =
-                                                        obj.get("content").and_then(|v| v.as_str())
-                                                    {
-                                                        return Some(json!({
-                                                            "type": "text",
-                                                            "text": content
-                                                        }));
-                                                    }
-                                                }
-                                                _ => {}
-                                            }
-                                        }
-                                    }
-                                    None
-                                })
-                                .collect();
-
-                            json!(content_parts)
-                        } else {
-                            json!(msg.get_body())
-                        }
-                    } else {
-                        json!(msg.get_body())
-                    };
-
                 AnthropicRequestMessage {
                     role: role.to_string(),
-                    content,
+                    content: Self::format_content(msg.get_body()),
                 }
             })
             .collect()
     }
 
+    /// Message body → Anthropic content: structured bodies (a JSON array of
+    /// `{contentType, content}` parts, images as `data:` URLs) become
+    /// text/image blocks, anything else stays a plain string.
+    pub fn format_content(body: &str) -> Value {
+        let Some(parts) = serde_json::from_str::<Value>(body)
+            .ok()
+            .and_then(|parsed| parsed.as_array().cloned())
+        else {
+            return json!(body);
+        };
+
+        let data_url = regex::Regex::new(r"^data:(image/[^;]+);base64,(.*)$").unwrap();
+        let blocks: Vec<Value> = parts
+            .iter()
+            .filter_map(|part| {
+                let content = part.get("content").and_then(|v| v.as_str())?;
+                match part.get("contentType").and_then(|v| v.as_str())? {
+                    "image" => {
+                        // data URL format: data:image/type;base64,data
+                        let captures = data_url.captures(content)?;
+                        Some(json!({
+                            "type": "image",
+                            "source": {
+                                "type": "base64",
+                                "media_type": captures.get(1)?.as_str(),
+                                "data": captures.get(2)?.as_str(),
+                            }
+                        }))
+                    }
+                    "text" => Some(json!({ "type": "text", "text": content })),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        if blocks.is_empty() {
+            return json!(body);
+        }
+        json!(blocks)
+    }
+
     pub fn create_request_body(
         messages: Vec<AnthropicRequestMessage>,
         system_prompt: Option<String>,
@@ -224,7 +201,7 @@ impl AnthropicProvider {
                 AIMessageRole::User => {
                     messages.push(serde_json::json!({
                         "role": "user",
-                        "content": msg.content,
+                        "content": Self::format_content(&msg.content),
                     }));
                 }
             }
@@ -236,19 +213,41 @@ impl AnthropicProvider {
             "anthropic_version": "bedrock-2023-05-31"
         });
 
-        if let Some(temp) = request.temperature {
-            body["temperature"] = temp.into();
-        }
+        // A forced tool call (structured output) is incompatible with
+        // extended thinking
+        if let Some(budget) = request
+            .thinking_budget
+            .filter(|_| request.response_schema.is_none())
+        {
+            // Extended thinking counts against max_tokens and rejects
+            // custom sampling
+            body["thinking"] = json!({ "type": "enabled", "budget_tokens": budget });
+            body["max_tokens"] = json!(request.max_tokens.unwrap_or(4096) + budget);
+        } else {
+            if let Some(temp) = request.temperature {
+                body["temperature"] = temp.into();
+            }
 
-        if let Some(top_p) = request.top_p {
-            body["top_p"] = top_p.into();
+            if let Some(top_p) = request.top_p {
+                body["top_p"] = top_p.into();
+            }
         }
 
         if let Some(system) = system_message {
-            body["system"] = system.into();
+            body["system"] = json!([{ "type": "text", "text": system }]);
         }
 
-        if let Some(tools) = request.tools.as_deref().filter(|t| !t.is_empty()) {
+        // Structured output is emulated with a forced call of a tool whose
+        // input schema is the response schema
+        if let Some(schema) = &request.response_schema {
+            let tool = structured::output_tool(schema);
+            body["tools"] = json!([{
+                "name": tool.name,
+                "description": tool.description,
+                "input_schema": tool.input_schema,
+            }]);
+            body["tool_choice"] = json!({ "type": "tool", "name": STRUCTURED_OUTPUT_TOOL });
+        } else if let Some(tools) = request.tools.as_deref().filter(|t| !t.is_empty()) {
             body["tools"] = serde_json::json!(tools
                 .iter()
                 .map(|tool| {
@@ -261,9 +260,221 @@ impl AnthropicProvider {
                 .collect::<Vec<_>>());
         }
 
+        Self::add_cache_breakpoints(&mut body);
         Ok(body)
     }
 
+    /// Prompt caching: `cache_control` breakpoints after the tool
+    /// definitions, the system prompt and the last message cache the
+    /// request prefix up to each of them, so the next turn (or tool cycle)
+    /// rereads the unchanged tools, system prompt and history from the
+    /// cache. Prefixes below the model's minimum cacheable length are
+    /// simply not cached.
+    fn add_cache_breakpoints(body: &mut Value) {
+        let cache_control = json!({ "type": "ephemeral" });
+
+        if let Some(tool) = body["tools"].as_array_mut().and_then(|t| t.last_mut()) {
+            tool["cache_control"] = cache_control.clone();
+        }
+        if let Some(block) = body["system"].as_array_mut().and_then(|b| b.last_mut()) {
+            block["cache_control"] = cache_control.clone();
+        }
+
+        let Some(message) = body["messages"].as_array_mut().and_then(|m| m.last_mut()) else {
+            return;
+        };
+        if let Some(text) = message["content"].as_str() {
+            if text.is_empty() {
+                return;
+            }
+            message["content"] = json!([{ "type": "text", "text": text }]);
+        }
+        if let Some(block) = message["content"].as_array_mut().and_then(|b| b.last_mut()) {
+            block["cache_control"] = cache_control;
+        }
+    }
+
+    /// Request body for the Anthropic Messages API called directly: the
+    /// Bedrock body with `model` (and `stream`) in place of the
+    /// `anthropic_version` field, which the direct API takes as a header.
+    pub fn format_messages_api_request(
+        request: &InvokeModelRequest,
+        model_id: &str,
+        stream: bool,
+    ) -> Result<Value, AppError> {
+        let mut body = Self::format_request(request)?;
+        if let Some(obj) = body.as_object_mut() {
+            obj.remove("anthropic_version");
+        }
+        body["model"] = json!(model_id);
+        if stream {
+            body["stream"] = json!(true);
+        }
+        Ok(body)
+    }
+
+    /// Collect streaming events of thinking blocks, which are replayed
+    /// (with their signature) ahead of tool_use blocks on the next cycle.
+    /// Returns the thinking text delta, if any.
+    pub fn collect_thinking_chunks(
+        chunk_data: &Value,
+        thinking_blocks: &mut HashMap<u64, Value>,
+    ) -> Option<String> {
+        let index = chunk_data.get("index").and_then(|i| i.as_u64())?;
+        match chunk_data.get("type").and_then(|t| t.as_str()) {
+            Some("content_block_start") => {
+                let block = chunk_data.get("content_block")?;
+                if matches!(
+                    block.get("type").and_then(|t| t.as_str()),
+                    Some("thinking" | "redacted_thinking")
+                ) {
+                    thinking_blocks.insert(index, block.clone());
+                }
+                None
+            }
+            Some("content_block_delta") => {
+                let delta = chunk_data.get("delta")?;
+                let block = thinking_blocks.get_mut(&index)?;
+                match delta.get("type").and_then(|t| t.as_str()) {
+                    Some("thinking_delta") => {
+                        let text = delta.get("thinking").and_then(|t| t.as_str())?;
+                        let thinking = block.get("thinking").and_then(|t| t.as_str());
+                        block["thinking"] = json!(format!("{}{}", thinking.unwrap_or(""), text));
+                        Some(text.to_string()).filter(|t| !t.is_empty())
+                    }
+                    Some("signature_delta") => {
+                        block["signature"] = delta.get("signature").cloned()?;
+                        None
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Collect streaming events that describe tool_use blocks:
+    /// content_block_start carries id/name, input_json_delta events carry
+    /// the argument JSON in fragments, message_delta carries stop_reason.
+    pub fn collect_tool_chunks(
+        chunk_data: &Value,
+        tool_blocks: &mut HashMap<u64, (String, String, String)>,
+        stop_reason: &mut Option<String>,
+    ) {
+        let index = chunk_data
+            .get("index")
+            .and_then(|i| i.as_u64())
+            .unwrap_or(0);
+        match chunk_data.get("type").and_then(|t| t.as_str()) {
+            Some("content_block_start") => {
+                let Some(block) = chunk_data.get("content_block") else {
+                    return;
+                };
+                if block.get("type").and_then(|t| t.as_str()) == Some("tool_use") {
+                    let id = block
+                        .get("id")
+                        .and_then(|id| id.as_str())
+                        .unwrap_or("unknown_id")
+                        .to_string();
+                    let name = block
+                        .get("name")
+                        .and_then(|n| n.as_str())
+                        .unwrap_or_default()
+                        .to_string();
+                    tool_blocks.insert(index, (id, name, String::new()));
+                }
+            }
+            Some("content_block_delta") => {
+                let Some(delta) = chunk_data.get("delta") else {
+                    return;
+                };
+                if delta.get("type").and_then(|t| t.as_str()) == Some("input_json_delta") {
+                    if let (Some(fragment), Some(entry)) = (
+                        delta.get("partial_json").and_then(|p| p.as_str()),
+                        tool_blocks.get_mut(&index),
+                    ) {
+                        entry.2.push_str(fragment);
+                    }
+                }
+            }
+            Some("message_delta") => {
+                if let Some(reason) = chunk_data
+                    .get("delta")
+                    .and_then(|d| d.get("stop_reason"))
+                    .and_then(|r| r.as_str())
+                {
+                    *stop_reason = Some(reason.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Assemble the streamed tool_use blocks (in content block order) into
+    /// the assistant content replayed next cycle and the calls to execute.
+    /// Thinking blocks lead the replayed content, as the API requires.
+    pub fn streamed_tool_calls(
+        tool_blocks: &HashMap<u64, (String, String, String)>,
+        thinking_blocks: &HashMap<u64, Value>,
+    ) -> (Value, Vec<ToolCallRequest>) {
+        let mut ordered: Vec<(&u64, &(String, String, String))> = tool_blocks.iter().collect();
+        ordered.sort_by_key(|(index, _)| **index);
+        let mut thinking: Vec<(&u64, &Value)> = thinking_blocks.iter().collect();
+        thinking.sort_by_key(|(index, _)| **index);
+
+        let mut assistant_content: Vec<Value> = thinking
+            .into_iter()
+            .map(|(_, block)| block.clone())
+            .collect();
+        let mut calls: Vec<ToolCallRequest> = Vec::new();
+        for (_, (id, name, input_json)) in ordered {
+            let arguments: Value = serde_json::from_str(input_json).unwrap_or_else(|_| json!({}));
+            let block = json!({
+                "type": "tool_use",
+                "id": id,
+                "name": name,
+                "input": arguments,
+            });
+            assistant_content.push(block.clone());
+            calls.push(ToolCallRequest {
+                id: id.clone(),
+                name: name.clone(),
+                arguments,
+                raw: block,
+            });
+        }
+        (Value::Array(assistant_content), calls)
+    }
+
+    fn parse_usage(usage: &Value) -> Usage {
+        let tokens = |key: &str| usage.get(key).and_then(|t| t.as_i64()).map(|t| t as i32);
+        Usage {
+            input_tokens: tokens("input_tokens"),
+            output_tokens: tokens("output_tokens"),
+            total_tokens: None,
+            cache_read_input_tokens: tokens("cache_read_input_tokens"),
+            cache_write_input_tokens: tokens("cache_creation_input_tokens"),
+        }
+    }
+
+    /// Token usage of a stream event: `message_start` carries the input
+    /// (and cache) counts, `message_delta` the output count.
+    pub fn parse_stream_usage(chunk_data: &Value) -> Option<Usage> {
+        let usage = match chunk_data.get("type").and_then(|t| t.as_str()) {
+            Some("message_start") => chunk_data.pointer("/message/usage"),
+            Some("message_delta") => chunk_data.get("usage"),
+            _ => None,
+        }?;
+        let mut usage = Self::parse_usage(usage);
+        // message_delta repeats the input counts of message_start
+        if chunk_data.get("type").and_then(|t| t.as_str()) == Some("message_delta") {
+            usage.input_tokens = None;
+            usage.cache_read_input_tokens = None;
+            usage.cache_write_input_tokens = None;
+        }
+        Some(usage)
+    }
+
     pub fn parse_model_response(
         response: Value,
         model_id: &str,
@@ -274,15 +485,31 @@ impl AnthropicProvider {
             .cloned()
             .unwrap_or_default();
 
-        let content = blocks
+        let is_tool_use =
+            |block: &&Value| block.get("type").and_then(|t| t.as_str()) == Some("tool_use");
+        let is_structured_output = |block: &&Value| {
+            block.get("name").and_then(|n| n.as_str()) == Some(STRUCTURED_OUTPUT_TOOL)
+        };
+
+        // The forced structured-output call carries the answer as its input
+        let content = match blocks.iter().filter(is_tool_use).find(is_structured_output) {
+            Some(block) => block.get("input").cloned().unwrap_or_default().to_string(),
+            None => blocks
+                .iter()
+                .filter_map(|block| block.get("text").and_then(|t| t.as_str()))
+                .collect::<Vec<_>>()
+                .join(""),
+        };
+        let reasoning = blocks
             .iter()
-            .filter_map(|block| block.get("text").and_then(|t| t.as_str()))
+            .filter_map(|block| block.get("thinking").and_then(|t| t.as_str()))
             .collect::<Vec<_>>()
-            .join("");
+            .join("\n\n");
 
         let tool_calls = blocks
             .iter()
-            .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("tool_use"))
+            .filter(is_tool_use)
+            .filter(|block| !is_structured_output(block))
             .map(|block| ToolCallRequest {
                 id: block
                     .get("id")
@@ -302,27 +529,19 @@ impl AnthropicProvider {
             })
             .collect();
 
-        let usage = response.get("usage").map(|u| Usage {
-            input_tokens: u
-                .get("input_tokens")
-                .and_then(|t| t.as_i64())
-                .map(|t| t as i32),
-            output_tokens: u
-                .get("output_tokens")
-                .and_then(|t| t.as_i64())
-                .map(|t| t as i32),
-            total_tokens: None,
-        });
+        let usage = response.get("usage").map(Self::parse_usage);
 
         Ok(ModelResponse {
             content,
             model_id: model_id.to_string(),
             tool_calls,
+            executed_tool_calls: Vec::new(),
             usage,
             finish_reason: response
                 .get("stop_reason")
                 .and_then(|r| r.as_str())
                 .map(|s| s.to_string()),
+            reasoning: (!reasoning.is_empty()).then_some(reasoning),
         })
     }
 }
@@ -364,11 +583,21 @@ mod tests {
                     input_schema: json!({"type": "object"}),
                 },
                 backend: ToolBackend::WebSearch {
-                    api_key: "k".to_string(),
-                    folder_id: "f".to_string(),
-                    api_url: None,
+                    backend: crate::services::search_backend::SearchBackend::Yandex {
+                        api_key: "k".to_string(),
+                        folder_id: "f".to_string(),
+                        api_url: None,
+                    },
                 },
+                requires_approval: false,
+                timeout: None,
             }]),
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
         }
     }
 
@@ -384,6 +613,28 @@ mod tests {
         assert_eq!(body["messages"][2]["content"][0]["tool_use_id"], "t1");
     }
 
+    #[test]
+    fn cache_breakpoints_close_tools_system_and_history() {
+        let mut request = request_with_tools();
+        request.system_prompt = Some("be brief".to_string());
+        let body = AnthropicProvider::format_request(&request).unwrap();
+        assert_eq!(body["tools"][0]["cache_control"]["type"], "ephemeral");
+        assert_eq!(body["system"][0]["cache_control"]["type"], "ephemeral");
+        assert_eq!(
+            body["messages"][2]["content"][0]["cache_control"]["type"],
+            "ephemeral"
+        );
+        assert!(body["messages"][0].get("cache_control").is_none());
+
+        request.messages = vec![ModelMessage::text(AIMessageRole::User, "hi")];
+        let body = AnthropicProvider::format_request(&request).unwrap();
+        assert_eq!(body["messages"][0]["content"][0]["text"], "hi");
+        assert_eq!(
+            body["messages"][0]["content"][0]["cache_control"]["type"],
+            "ephemeral"
+        );
+    }
+
     #[test]
     fn parses_tool_use_response() {
         let response = json!({
@@ -401,4 +652,72 @@ mod tests {
         assert_eq!(parsed.tool_calls[0].arguments["query"], "rust");
         assert_eq!(parsed.finish_reason.as_deref(), Some("tool_use"));
     }
+
+    #[test]
+    fn response_schema_forces_output_tool() {
+        let mut request = request_with_tools();
+        request.response_schema = Some(json!({"type": "object"}));
+        request.thinking_budget = Some(2048);
+        let body = AnthropicProvider::format_request(&request).unwrap();
+        assert_eq!(body["tools"].as_array().unwrap().len(), 1);
+        assert_eq!(body["tools"][0]["name"], STRUCTURED_OUTPUT_TOOL);
+        assert_eq!(body["tool_choice"]["name"], STRUCTURED_OUTPUT_TOOL);
+        assert!(body.get("thinking").is_none());
+
+        let response = json!({
+            "content": [{ "type": "tool_use", "id": "t9", "name": STRUCTURED_OUTPUT_TOOL,
+                          "input": { "name": "Bob" } }],
+            "stop_reason": "tool_use",
+        });
+        let parsed = AnthropicProvider::parse_model_response(response, "model").unwrap();
+        assert_eq!(parsed.content, r#"{"name":"Bob"}"#);
+        assert!(parsed.tool_calls.is_empty());
+    }
+
+    #[test]
+    fn thinking_streams_apart_and_replays_before_tool_use() {
+        let mut request = request_with_tools();
+        request.temperature = Some(0.3);
+        request.thinking_budget = Some(2048);
+        let body = AnthropicProvider::format_request(&request).unwrap();
+        assert_eq!(body["thinking"]["budget_tokens"], 2048);
+        assert_eq!(body["max_tokens"], 512 + 2048);
+        assert!(body.get("temperature").is_none());
+
+        let events = [
+            json!({"type": "content_block_start", "index": 0,
+                "content_block": {"type": "thinking", "thinking": ""}}),
+            json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "thinking_delta", "thinking": "Need to search"}}),
+            json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "signature_delta", "signature": "sig"}}),
+            json!({"type": "content_block_start", "index": 1,
+                "content_block": {"type": "tool_use", "id": "t3", "name": "internal_web_search"}}),
+            json!({"type": "content_block_delta", "index": 1,
+                "delta": {"type": "input_json_delta", "partial_json": "{}"}}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"}}),
+        ];
+        let mut tool_blocks = HashMap::new();
+        let mut thinking_blocks = HashMap::new();
+        let mut stop_reason = None;
+        let mut reasoning = String::new();
+        for event in &events {
+            AnthropicProvider::collect_tool_chunks(event, &mut tool_blocks, &mut stop_reason);
+            if let Some(delta) =
+                AnthropicProvider::collect_thinking_chunks(event, &mut thinking_blocks)
+            {
+                reasoning.push_str(&delta);
+            }
+            assert!(AnthropicProvider::parse_response_chunk(event).is_none());
+        }
+        assert_eq!(reasoning, "Need to search");
+
+        let (content, calls) =
+            AnthropicProvider::streamed_tool_calls(&tool_blocks, &thinking_blocks);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(content[0]["type"], "thinking");
+        assert_eq!(content[0]["thinking"], "Need to search");
+        assert_eq!(content[0]["signature"], "sig");
+        assert_eq!(content[1]["type"], "tool_use");
+    }
 }
@@ -180,17 +180,21 @@ impl CohereProvider {
                 .and_then(|t| t.as_i64())
                 .map(|t| t as i32),
             total_tokens: None,
+            cache_read_input_tokens: None,
+            cache_write_input_tokens: None,
         });
 
         Ok(ModelResponse {
             content,
             model_id: model_id.to_string(),
             tool_calls: Vec::new(),
+            executed_tool_calls: Vec::new(),
             usage,
             finish_reason: response
                 .get("finishReason")
                 .and_then(|r| r.as_str())
                 .map(|s| s.to_string()),
+            reasoning: None,
         })
     }
 }
@@ -182,6 +182,7 @@ impl MetaProvider {
             content,
             model_id: model_id.to_string(),
             tool_calls: Vec::new(),
+            executed_tool_calls: Vec::new(),
             usage: Some(Usage {
                 input_tokens: response
                     .get("prompt_token_count")
@@ -192,11 +193,14 @@ impl MetaProvider {
                     .and_then(|t| t.as_i64())
                     .map(|t| t as i32),
                 total_tokens: None,
+                cache_read_input_tokens: None,
+                cache_write_input_tokens: None,
             }),
             finish_reason: response
                 .get("stop_reason")
                 .and_then(|r| r.as_str())
                 .map(|s| s.to_string()),
+            reasoning: None,
         })
     }
 }
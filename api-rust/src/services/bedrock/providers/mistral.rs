@@ -181,16 +181,20 @@ impl MistralProvider {
             input_tokens: u.prompt_tokens,
             output_tokens: u.completion_tokens,
             total_tokens: u.total_tokens,
+            cache_read_input_tokens: None,
+            cache_write_input_tokens: None,
         });
 
         Ok(ModelResponse {
             content,
             model_id: model_id.to_string(),
             tool_calls: Vec::new(),
+            executed_tool_calls: Vec::new(),
             usage,
             finish_reason: choice
                 .and_then(|c| c.finish_reason.clone())
                 .map(|s| s.to_string()),
+            reasoning: None,
         })
     }
 }
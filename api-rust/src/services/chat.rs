@@ -98,7 +98,10 @@ impl<'a> ChatService<'a> {
                 c.is_pinned,
                 c.folder_id,
                 c.created_at,
-                c.updated_at
+                c.updated_at,
+                c.thinking,
+                c.thinking_budget,
+                c.response_schema
             FROM chats c
             LEFT JOIN (
                 SELECT
@@ -0,0 +1,372 @@
+//! Conversation compaction with rolling summaries.
+//!
+//! When a chat's history nears the model's input window, the older turns
+//! are summarized with the user's summarization model (the one used for
+//! document summaries). The summary is stored as a `system` message whose
+//! `metadata.summarizedMessageIds` lists the turns it replaces; the next
+//! completions send the summary in place of them. Each new summary folds
+//! in the previous one, so a chat of any length stays within the window.
+
+use chrono::Utc;
+use diesel::prelude::*;
+use tracing::{debug, info, warn};
+
+use crate::database::DbConnection;
+use crate::models::{Message, MessageMetadata, MessageRole, MessageUsage, Model, User};
+use crate::schema::{messages, models};
+use crate::services::ai::{
+    self, AIProviderService, AIService, InvokeModelRequest, MessageRole as AIMessageRole,
+    ModelMessage, Tokenizer, Usage,
+};
+use crate::services::usage;
+use crate::utils::errors::AppError;
+
+/// Share of the input budget at which the history gets compacted.
+const COMPACTION_THRESHOLD: f32 = 0.8;
+/// Most recent messages always sent as they are.
+const KEEP_RECENT_MESSAGES: usize = 10;
+const SUMMARY_OUTPUT_TOKENS: i32 = 2000;
+const SUMMARY_TEMPERATURE: f32 = 0.25;
+const DEFAULT_SUMMARY_MAX_INPUT_TOKENS: i32 = 8192;
+
+const SUMMARY_PROMPT: &str = "You maintain a running summary of a long conversation between \
+a user and an AI assistant. Summarize the conversation you are given in up to 1024 words, \
+keeping the facts, decisions, open questions, names, numbers and code identifiers the \
+assistant needs to continue it. Return only the summary, without any additional commentaries.";
+
+fn metadata(message: &Message) -> Option<MessageMetadata> {
+    message
+        .metadata
+        .as_deref()
+        .and_then(|m| serde_json::from_str::<MessageMetadata>(m).ok())
+}
+
+/// Message ids a summary message replaces; `None` for other messages.
+pub fn summarized_ids(message: &Message) -> Option<Vec<String>> {
+    if message.get_role() != MessageRole::System {
+        return None;
+    }
+    metadata(message).and_then(|m| m.summarized_message_ids)
+}
+
+/// The chat's latest summary, if it has been compacted.
+pub fn latest_summary(conn: &mut DbConnection, chat_id: &str) -> Result<Option<Message>, AppError> {
+    let system_messages: Vec<Message> = messages::table
+        .filter(messages::chat_id.eq(chat_id))
+        .filter(messages::role.eq(String::from(MessageRole::System)))
+        .order(messages::created_at.desc())
+        .load(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    Ok(system_messages
+        .into_iter()
+        .find(|m| summarized_ids(m).is_some()))
+}
+
+/// Context messages without the turns the summary replaces and without
+/// summary messages themselves (the summary is sent separately, see
+/// [`summary_message`]).
+pub fn without_summarized(messages: Vec<Message>, summary: Option<&Message>) -> Vec<Message> {
+    let summarized = summary.and_then(summarized_ids).unwrap_or_default();
+    messages
+        .into_iter()
+        .filter(|m| !summarized.contains(&m.id) && summarized_ids(m).is_none())
+        .collect()
+}
+
+/// The summary as it is sent to the model, ahead of the remaining turns.
+pub fn summary_message(summary: &Message) -> ModelMessage {
+    ModelMessage::text(
+        AIMessageRole::System,
+        format!(
+            "Summary of the earlier part of this conversation:\n\n{}",
+            summary.content
+        ),
+    )
+}
+
+/// Summaries are billed to the chat owner like answers; a failed ledger
+/// write leaves the summary unpriced.
+fn record_usage(
+    conn: &mut DbConnection,
+    summary: &Message,
+    user: &User,
+    model: &Model,
+    usage: Option<&Usage>,
+) -> Option<MessageUsage> {
+    usage::record(conn, &summary.id, &summary.chat_id, &user.id, model, usage?)
+        .map_err(|e| warn!("Failed to record usage of summary {}: {}", summary.id, e))
+        .ok()
+}
+
+/// The user's summarization model.
+fn summarization_model(conn: &mut DbConnection, user: &User) -> Result<Option<Model>, AppError> {
+    let Some(model_id) = user
+        .settings
+        .as_ref()
+        .and_then(|s| s.document_summarization_model_id.clone())
+        .or_else(|| user.document_summarization_model_id.clone())
+    else {
+        return Ok(None);
+    };
+    models::table
+        .filter(models::model_id.eq(&model_id))
+        .filter(models::user_id.eq(&user.id))
+        .first(conn)
+        .optional()
+        .map_err(|e| AppError::Database(e.to_string()))
+}
+
+/// Plain-text transcript of the turns; images and other non-text parts of
+/// structured bodies are left out.
+fn transcript(turns: &[Message]) -> String {
+    turns
+        .iter()
+        .filter_map(|m| {
+            let speaker = match m.get_role() {
+                MessageRole::User => "User",
+                MessageRole::Assistant => "Assistant",
+                _ => return None,
+            };
+            let text = match serde_json::from_str::<serde_json::Value>(&m.content)
+                .ok()
+                .and_then(|v| v.as_array().cloned())
+            {
+                Some(parts) => parts
+                    .iter()
+                    .filter(|p| p.get("contentType").and_then(|t| t.as_str()) == Some("text"))
+                    .filter_map(|p| p.get("content").and_then(|c| c.as_str()))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                None => m.content.clone(),
+            };
+            (!text.trim().is_empty()).then(|| format!("{}: {}", speaker, text.trim()))
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+async fn summarize(
+    ai_service: &AIService,
+    model: &Model,
+    previous: Option<&str>,
+    turns: &[Message],
+) -> Result<(String, Option<Usage>), AppError> {
+    let system_prompt = match previous {
+        Some(previous) => format!(
+            "{}\n\nSummary of the conversation before these turns, to be folded in:\n\n{}",
+            SUMMARY_PROMPT, previous
+        ),
+        None => SUMMARY_PROMPT.to_string(),
+    };
+    let mut request = InvokeModelRequest {
+        model_id: model.model_id.clone(),
+        messages: vec![ModelMessage::text(AIMessageRole::User, transcript(turns))],
+        temperature: Some(SUMMARY_TEMPERATURE),
+        max_tokens: Some(SUMMARY_OUTPUT_TOKENS),
+        top_p: None,
+        system_prompt: Some(system_prompt),
+        tools: None,
+        thinking_budget: None,
+        response_schema: None,
+        tool_approver: None,
+        tool_execution: Default::default(),
+        tool_calls_ran: Default::default(),
+        executed_tools: Default::default(),
+    };
+    // A transcript beyond the summarization model's window keeps its end
+    ai::fit_context(
+        &mut request,
+        model
+            .max_input_tokens
+            .unwrap_or(DEFAULT_SUMMARY_MAX_INPUT_TOKENS),
+        Tokenizer::for_model(&model.api_provider, &model.model_id),
+    );
+
+    let response = ai_service
+        .get_provider_for_model(model)?
+        .invoke_model(request)
+        .await?;
+    let summary = response.content.trim().to_string();
+    if summary.is_empty() {
+        return Err(AppError::Internal(
+            "Summarization model returned an empty summary".to_string(),
+        ));
+    }
+    Ok((summary, response.usage))
+}
+
+/// Compact the chat when `request` nears the `max_input_tokens` window:
+/// everything in `context` (chronological, already without summarized
+/// turns) but the most recent messages is summarized together with the
+/// previous summary into a new summary message. Returns the new summary,
+/// or `None` when no compaction was needed or possible.
+#[allow(clippy::too_many_arguments)]
+pub async fn compact_if_needed(
+    conn: &mut DbConnection,
+    ai_service: &AIService,
+    user: &User,
+    chat_id: &str,
+    request: &InvokeModelRequest,
+    (max_input_tokens, tokenizer): (i32, Tokenizer),
+    context: &[Message],
+    summary: Option<&Message>,
+) -> Result<Option<Message>, AppError> {
+    let budget = ai::input_budget(request, max_input_tokens);
+    if (ai::estimate_input_tokens(request, tokenizer) as f32) < budget as f32 * COMPACTION_THRESHOLD
+    {
+        return Ok(None);
+    }
+    if context.len() <= KEEP_RECENT_MESSAGES {
+        return Ok(None);
+    }
+    let Some(model) = summarization_model(conn, user)? else {
+        debug!(
+            "Chat {} nears the context window, but no summarization model is configured",
+            chat_id
+        );
+        return Ok(None);
+    };
+
+    let turns = &context[..context.len() - KEEP_RECENT_MESSAGES];
+    let (content, usage) = summarize(
+        ai_service,
+        &model,
+        summary.map(|s| s.content.as_str()),
+        turns,
+    )
+    .await?;
+
+    // The new summary replaces the previous one and everything it covered
+    let mut summarized_message_ids = summary.and_then(summarized_ids).unwrap_or_default();
+    summarized_message_ids.extend(summary.map(|s| s.id.clone()));
+    summarized_message_ids.extend(turns.iter().map(|m| m.id.clone()));
+
+    let mut message = Message::new(
+        chat_id.to_string(),
+        None,
+        content,
+        String::from(MessageRole::System),
+        model.model_id.clone(),
+        Some(model.name.clone()),
+    );
+    message.metadata = serde_json::to_string(&MessageMetadata {
+        usage: record_usage(conn, &message, user, &model, usage.as_ref()),
+        summarized_message_ids: Some(summarized_message_ids),
+        ..Default::default()
+    })
+    .ok();
+    let message: Message = diesel::insert_into(messages::table)
+        .values(&message)
+        .get_result(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+
+    info!(
+        "Compacted chat {}: {} message(s) summarized with {}",
+        chat_id,
+        turns.len(),
+        model.model_id
+    );
+    Ok(Some(message))
+}
+
+/// Summarize the turns a summary replaces again, with the user's current
+/// summarization model.
+pub async fn regenerate(
+    conn: &mut DbConnection,
+    ai_service: &AIService,
+    user: &User,
+    summary: Message,
+) -> Result<Message, AppError> {
+    let ids = summarized_ids(&summary)
+        .ok_or_else(|| AppError::Validation("Message is not a chat summary".to_string()))?;
+    let model = summarization_model(conn, user)?
+        .ok_or_else(|| AppError::Validation("No summarization model is configured".to_string()))?;
+
+    let turns: Vec<Message> = messages::table
+        .filter(messages::chat_id.eq(&summary.chat_id))
+        .filter(messages::id.eq_any(&ids))
+        .order(messages::created_at.asc())
+        .load(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    let turns = without_summarized(turns, None);
+    let (content, usage) = summarize(ai_service, &model, None, &turns).await?;
+
+    let mut metadata = metadata(&summary).unwrap_or_default();
+    metadata.usage = record_usage(conn, &summary, user, &model, usage.as_ref());
+
+    diesel::update(messages::table.filter(messages::id.eq(&summary.id)))
+        .set((
+            messages::content.eq(content),
+            messages::metadata.eq(serde_json::to_string(&metadata).ok()),
+            messages::model_id.eq(&model.model_id),
+            messages::model_name.eq(&model.name),
+            messages::updated_at.eq(Utc::now().naive_utc()),
+        ))
+        .get_result(conn)
+        .map_err(|e| AppError::Database(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, role: MessageRole, content: &str) -> Message {
+        let mut message = Message::new(
+            "chat".to_string(),
+            None,
+            content.to_string(),
+            String::from(role),
+            "model".to_string(),
+            None,
+        );
+        message.id = id.to_string();
+        message
+    }
+
+    fn summary(id: &str, ids: &[&str]) -> Message {
+        let mut summary = message(id, MessageRole::System, "they talked");
+        summary.metadata = serde_json::to_string(&MessageMetadata {
+            summarized_message_ids: Some(ids.iter().map(|id| id.to_string()).collect()),
+            ..Default::default()
+        })
+        .ok();
+        summary
+    }
+
+    #[test]
+    fn summary_replaces_its_turns() {
+        let old_summary = summary("s1", &["m1"]);
+        let new_summary = summary("s2", &["m1", "s1", "m2"]);
+        let context = vec![
+            message("m1", MessageRole::User, "hi"),
+            old_summary,
+            message("m2", MessageRole::Assistant, "hello"),
+            message("m3", MessageRole::User, "go on"),
+            new_summary.clone(),
+        ];
+
+        let kept = without_summarized(context, Some(&new_summary));
+        assert_eq!(
+            kept.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(),
+            vec!["m3"]
+        );
+        assert!(summary_message(&new_summary)
+            .content
+            .ends_with("they talked"));
+        assert_eq!(summarized_ids(&message("m4", MessageRole::User, "x")), None);
+    }
+
+    #[test]
+    fn transcript_keeps_text_turns() {
+        let turns = vec![
+            message(
+                "m1",
+                MessageRole::User,
+                r#"[{"contentType": "text", "content": "look"}, {"contentType": "image", "content": "data:image/png;base64,AAAA"}]"#,
+            ),
+            message("m2", MessageRole::Assistant, "A cat."),
+            message("m3", MessageRole::Error, "timeout"),
+        ];
+        assert_eq!(transcript(&turns), "User: look\n\nAssistant: A cat.");
+    }
+}
@@ -1,7 +1,8 @@
 //! Custom REST API provider: user-defined models speaking the OpenAI
-//! chat-completions protocol against an arbitrary endpoint (Ollama, DeepSeek,
-//! vLLM, …). Endpoint, API key and the provider-side model name live in the
-//! model row's `custom_settings` JSON — mirroring the Node API's
+//! chat-completions or Responses protocol, or Ollama's native API, against
+//! an arbitrary endpoint (Ollama, DeepSeek, vLLM, …). Endpoint, API key
+//! and the provider-side model name live in the model row's
+//! `custom_settings` JSON — mirroring the Node API's
 //! `CustomRestApiProvider`.
 
 use async_trait::async_trait;
@@ -12,15 +13,26 @@ use std::pin::Pin;
 
 use crate::models::model::{CustomModelSettings, Model};
 use crate::services::ai::*;
+use crate::services::ollama_protocol::OllamaProtocol;
 use crate::services::openai_protocol::OpenAIProtocol;
+use crate::services::openai_responses_protocol::OpenAIResponsesProtocol;
+use crate::services::retry::RetryPolicy;
 use crate::utils::errors::AppError;
 
 /// Custom model protocols (subset of the Node API's `CustomModelProtocol`;
-/// the Responses API and Bedrock-custom variants are not ported yet).
+/// the Bedrock-custom variant is not ported yet).
 pub const PROTOCOL_OPENAI_CHAT_COMPLETIONS: &str = "OPENAI_CHAT_COMPLETIONS";
+pub const PROTOCOL_OPENAI_RESPONSES: &str = "OPENAI_RESPONSES";
+pub const PROTOCOL_OLLAMA: &str = "OLLAMA";
+
+enum CustomProtocol {
+    ChatCompletions(OpenAIProtocol),
+    Responses(OpenAIResponsesProtocol),
+    Ollama(OllamaProtocol),
+}
 
 pub struct CustomService {
-    protocol: OpenAIProtocol,
+    protocol: CustomProtocol,
 }
 
 impl CustomService {
@@ -47,48 +59,126 @@ impl CustomService {
                 AppError::BadRequest("Endpoint URL is required for a custom model".to_string())
             })?;
 
-        if let Some(protocol) = settings.protocol.as_deref() {
-            if protocol != PROTOCOL_OPENAI_CHAT_COMPLETIONS {
+        let model_name = settings.model_name.clone().filter(|m| !m.is_empty());
+        let base = || {
+            OpenAIProtocol::new(
+                endpoint.clone(),
+                settings.api_key.clone().filter(|k| !k.is_empty()),
+                model_name.clone(),
+                "Custom model",
+            )
+        };
+
+        let protocol = match settings.protocol.as_deref() {
+            None | Some(PROTOCOL_OPENAI_CHAT_COMPLETIONS) => {
+                CustomProtocol::ChatCompletions(base())
+            }
+            Some(PROTOCOL_OPENAI_RESPONSES) => {
+                CustomProtocol::Responses(OpenAIResponsesProtocol::new(base()))
+            }
+            Some(PROTOCOL_OLLAMA) => CustomProtocol::Ollama(OllamaProtocol::new(
+                endpoint.clone(),
+                model_name.clone(),
+                settings.keep_alive.clone().filter(|k| !k.is_empty()),
+                settings.num_ctx.filter(|n| *n > 0),
+            )),
+            Some(other) => {
                 return Err(AppError::BadRequest(format!(
-                    "Unsupported custom model protocol: {} (only {} is supported)",
-                    protocol, PROTOCOL_OPENAI_CHAT_COMPLETIONS
+                    "Unsupported custom model protocol: {} (expected {}, {} or {})",
+                    other,
+                    PROTOCOL_OPENAI_CHAT_COMPLETIONS,
+                    PROTOCOL_OPENAI_RESPONSES,
+                    PROTOCOL_OLLAMA
                 )));
             }
-        }
+        };
 
-        Ok(Self {
-            protocol: OpenAIProtocol::new(
-                endpoint,
-                settings.api_key.clone().filter(|k| !k.is_empty()),
-                settings.model_name.clone().filter(|m| !m.is_empty()),
-                "Custom model",
-            ),
-        })
+        Ok(Self { protocol })
+    }
+
+    /// Apply the transient-failure retry policy to whichever protocol
+    /// client the model uses.
+    pub fn with_retry(self, retry: RetryPolicy) -> Self {
+        let protocol = match self.protocol {
+            CustomProtocol::ChatCompletions(protocol) => {
+                CustomProtocol::ChatCompletions(protocol.with_retry(retry))
+            }
+            CustomProtocol::Responses(protocol) => {
+                CustomProtocol::Responses(protocol.with_retry(retry))
+            }
+            CustomProtocol::Ollama(protocol) => CustomProtocol::Ollama(protocol.with_retry(retry)),
+        };
+        Self { protocol }
+    }
+
+    /// Chat-completions client used for images and embeddings by the
+    /// OpenAI protocols; `None` for Ollama.
+    fn base(&self) -> Option<&OpenAIProtocol> {
+        match &self.protocol {
+            CustomProtocol::ChatCompletions(protocol) => Some(protocol),
+            CustomProtocol::Responses(protocol) => Some(protocol.base()),
+            CustomProtocol::Ollama(_) => None,
+        }
     }
 }
 
 #[async_trait]
 impl AIProviderService for CustomService {
     async fn invoke_model(&self, request: InvokeModelRequest) -> Result<ModelResponse, AppError> {
-        self.protocol.invoke(&request).await
+        match &self.protocol {
+            CustomProtocol::ChatCompletions(protocol) => protocol.invoke(&request).await,
+            CustomProtocol::Responses(protocol) => protocol.invoke(&request).await,
+            CustomProtocol::Ollama(protocol) => protocol.invoke(&request).await,
+        }
     }
 
-    async fn invoke_model_stream<F, C, E>(
+    async fn invoke_model_stream<F, C, E, R>(
         &self,
         request: InvokeModelRequest,
-        callbacks: StreamCallbacks<F, C, E>,
+        callbacks: StreamCallbacks<F, C, E, R>,
     ) -> Result<Vec<ExecutedToolCall>, AppError>
     where
         F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
         C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
         E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
     {
-        self.protocol.invoke_stream(&request, &callbacks).await
+        match &self.protocol {
+            CustomProtocol::ChatCompletions(protocol) => {
+                protocol.invoke_stream(&request, &callbacks).await
+            }
+            CustomProtocol::Responses(protocol) => {
+                protocol.invoke_stream(&request, &callbacks).await
+            }
+            CustomProtocol::Ollama(protocol) => protocol.invoke_stream(&request, &callbacks).await,
+        }
     }
 
-    // Custom models are user-created DB rows — nothing to fetch dynamically.
+    // Custom models are user-created DB rows; only an Ollama server can
+    // list what else it serves (`/api/tags`) for auto-discovery.
     async fn get_models(&self) -> Result<HashMap<String, AIModelInfo>, AppError> {
-        Ok(HashMap::new())
+        let CustomProtocol::Ollama(protocol) = &self.protocol else {
+            return Ok(HashMap::new());
+        };
+
+        Ok(protocol
+            .list_models()
+            .await?
+            .into_iter()
+            .map(|model| {
+                let info = AIModelInfo {
+                    api_provider: ApiProvider::CustomRestApi,
+                    provider: Some("Ollama".to_string()),
+                    name: model.name.clone(),
+                    description: format!("Ollama {}", model.name),
+                    type_: if model.embedding { "embedding" } else { "chat" }.to_string(),
+                    streaming: !model.embedding,
+                    image_input: model.image_input,
+                    max_input_tokens: None,
+                };
+                (model.name, info)
+            })
+            .collect())
     }
 
     async fn get_info(&self, _test_connection: bool) -> Result<ProviderInfo, AppError> {
@@ -118,13 +208,24 @@ impl AIProviderService for CustomService {
         &self,
         request: GenerateImagesRequest,
     ) -> Result<Vec<GeneratedImage>, AppError> {
-        self.protocol
-            .generate_images(&request.model_id, &request.prompt, request.count)
+        let Some(base) = self.base() else {
+            return Err(AppError::BadRequest(
+                "Images generation is not supported by the Ollama protocol".to_string(),
+            ));
+        };
+        base.generate_images(&request.model_id, &request.prompt, request.count)
             .await
     }
 
     async fn get_embeddings(&self, model_id: &str, input: &str) -> Result<Vec<f32>, AppError> {
-        let (embedding, _) = self.protocol.get_embeddings(model_id, input).await?;
+        let base = match &self.protocol {
+            CustomProtocol::Ollama(protocol) => {
+                return protocol.get_embeddings(model_id, input).await
+            }
+            CustomProtocol::ChatCompletions(protocol) => protocol,
+            CustomProtocol::Responses(protocol) => protocol.base(),
+        };
+        let (embedding, _) = base.get_embeddings(model_id, input).await?;
         Ok(embedding)
     }
 }
@@ -139,6 +240,7 @@ mod tests {
             api_key: Some("sk-test".to_string()),
             model_name: Some("llama3".to_string()),
             protocol: protocol.map(|s| s.to_string()),
+            ..Default::default()
         }
     }
 
@@ -157,9 +259,30 @@ mod tests {
         assert!(CustomService::from_settings(&s).is_ok());
     }
 
+    #[test]
+    fn accepts_responses_protocol() {
+        let s = settings(
+            Some("http://localhost:11434/v1"),
+            Some(PROTOCOL_OPENAI_RESPONSES),
+        );
+        let service = CustomService::from_settings(&s).unwrap();
+        assert!(matches!(service.protocol, CustomProtocol::Responses(_)));
+    }
+
+    #[test]
+    fn accepts_ollama_protocol() {
+        let s = settings(Some("http://localhost:11434"), Some(PROTOCOL_OLLAMA));
+        let service = CustomService::from_settings(&s).unwrap();
+        assert!(matches!(service.protocol, CustomProtocol::Ollama(_)));
+        assert!(service.base().is_none());
+    }
+
     #[test]
     fn rejects_unknown_protocol() {
-        let s = settings(Some("http://localhost:11434/v1"), Some("OPENAI_RESPONSES"));
+        let s = settings(
+            Some("http://localhost:11434/v1"),
+            Some("AWS_BEDROCK_CUSTOM"),
+        );
         assert!(CustomService::from_settings(&s).is_err());
     }
 }
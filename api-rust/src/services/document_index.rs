@@ -338,6 +338,12 @@ async fn index_document(
                         top_p: None,
                         system_prompt: None,
                         tools: None,
+                        thinking_budget: None,
+                        response_schema: None,
+                        tool_approver: None,
+                        tool_execution: Default::default(),
+                        tool_calls_ran: Default::default(),
+                        executed_tools: Default::default(),
                     })
                     .await?;
 
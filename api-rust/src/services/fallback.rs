@@ -0,0 +1,550 @@
+//! Virtual models and provider fallback chains.
+//!
+//! A virtual model is a `models` row with `api_provider = "VIRTUAL"` whose
+//! `custom_settings.fallbackModels` lists real `model_id`s in priority
+//! order. A request goes to the first target; when it fails with a
+//! transient error (429, 5xx, timeout / throttling) the next one is tried.
+//! Streaming requests only fail over while nothing has been streamed yet,
+//! so the client never sees two partial answers glued together, and no
+//! request fails over once it ran a tool call, which must not run twice.
+
+use diesel::prelude::*;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+use crate::database::DbConnection;
+use crate::models::model::CustomModelSettings;
+use crate::models::{ContextTrim, Model};
+use crate::schema::models;
+use crate::services::ai::{
+    self, AIProviderService, AIProviderWrapper, AIService, ExecutedToolCall, InvokeModelRequest,
+    ModelResponse, StreamCallbacks, Tokenizer,
+};
+use crate::utils::errors::AppError;
+
+pub const API_PROVIDER_VIRTUAL: &str = "VIRTUAL";
+
+/// Whether a provider error is worth retrying on the next model of the
+/// chain: rate limiting, server-side failures and timeouts. Provider errors
+/// only carry a message, so the status is read back from it — every client
+/// formats HTTP failures as `... (<status> <reason>) ...`.
+pub fn is_failover_error(error: &AppError) -> bool {
+    let message = match error {
+        AppError::Http(message) | AppError::Aws(message) => message,
+        _ => return false,
+    };
+
+    let bytes = message.as_bytes();
+    for (ndx, _) in message.match_indices('(') {
+        let code = &bytes[ndx + 1..];
+        if code.len() >= 4
+            && code[..3].iter().all(u8::is_ascii_digit)
+            && matches!(code[3], b' ' | b')')
+        {
+            let status: u16 = message[ndx + 1..ndx + 4].parse().unwrap_or_default();
+            if status == 429 || (500..600).contains(&status) {
+                return true;
+            }
+        }
+    }
+
+    let lower = message.to_lowercase();
+    [
+        "timed out",
+        "timeout",
+        "throttling",
+        "too many requests",
+        "rate limit",
+        "serviceunavailable",
+        "service unavailable",
+        "internalserverexception",
+        "modelnotready",
+        "overloaded",
+    ]
+    .iter()
+    .any(|needle| lower.contains(needle))
+}
+
+/// Ordered target `model_id`s of a virtual model.
+pub fn fallback_model_ids(model: &Model) -> Vec<String> {
+    model
+        .custom_settings
+        .as_deref()
+        .and_then(|s| serde_json::from_str::<CustomModelSettings>(s).ok())
+        .and_then(|settings| settings.fallback_models)
+        .unwrap_or_default()
+}
+
+/// Validate the target list of a virtual model being created or updated:
+/// every target must be one of the user's chat models and not virtual
+/// itself. Returns the targets in the given order.
+pub fn load_virtual_targets(
+    conn: &mut DbConnection,
+    user_id: &str,
+    target_ids: &[String],
+) -> Result<Vec<Model>, AppError> {
+    if target_ids.is_empty() {
+        return Err(AppError::Validation(
+            "A virtual model needs at least one target model".to_string(),
+        ));
+    }
+
+    let targets: Vec<Model> = models::table
+        .filter(models::user_id.eq(user_id))
+        .filter(models::model_id.eq_any(target_ids))
+        .load(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+
+    target_ids
+        .iter()
+        .map(|target_id| {
+            let target = targets
+                .iter()
+                .find(|m| &m.model_id == target_id)
+                .ok_or_else(|| {
+                    AppError::Validation(format!("Target model {} not found", target_id))
+                })?;
+            if target.api_provider == API_PROVIDER_VIRTUAL {
+                return Err(AppError::Validation(format!(
+                    "Target model {} is itself a virtual model",
+                    target_id
+                )));
+            }
+            if target.type_ != "chat" {
+                return Err(AppError::Validation(format!(
+                    "Target model {} is not a chat model",
+                    target_id
+                )));
+            }
+            Ok(target.clone())
+        })
+        .collect()
+}
+
+pub struct FallbackCandidate {
+    pub model: Model,
+    pub provider: AIProviderWrapper,
+}
+
+/// The models a request may be served by, in the order they are tried.
+/// A regular model resolves to a chain of one.
+pub struct FallbackChain {
+    candidates: Vec<FallbackCandidate>,
+    is_virtual: bool,
+}
+
+impl FallbackChain {
+    pub fn resolve(
+        conn: &mut DbConnection,
+        ai_service: &AIService,
+        user_id: &str,
+        model: &Model,
+    ) -> Result<Self, AppError> {
+        if model.api_provider != API_PROVIDER_VIRTUAL {
+            let provider = ai_service.get_provider_for_model(model)?;
+            return Ok(Self {
+                candidates: vec![FallbackCandidate {
+                    model: model.clone(),
+                    provider,
+                }],
+                is_virtual: false,
+            });
+        }
+
+        let target_ids = fallback_model_ids(model);
+        let targets: Vec<Model> = models::table
+            .filter(models::user_id.eq(user_id))
+            .filter(models::model_id.eq_any(&target_ids))
+            .load(conn)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let mut candidates = Vec::new();
+        for target_id in &target_ids {
+            let Some(target) = targets.iter().find(|m| &m.model_id == target_id) else {
+                warn!(
+                    "Virtual model {}: target {} not found",
+                    model.model_id, target_id
+                );
+                continue;
+            };
+            // Chains are one level deep; inactive targets are skipped
+            if !target.is_active || target.api_provider == API_PROVIDER_VIRTUAL {
+                continue;
+            }
+            match ai_service.get_provider_for_model(target) {
+                Ok(provider) => candidates.push(FallbackCandidate {
+                    model: target.clone(),
+                    provider,
+                }),
+                Err(e) => warn!(
+                    "Virtual model {}: target {} unavailable: {}",
+                    model.model_id, target_id, e
+                ),
+            }
+        }
+
+        if candidates.is_empty() {
+            return Err(AppError::Validation(format!(
+                "Virtual model {} has no available target models",
+                model.model_id
+            )));
+        }
+        Ok(Self {
+            candidates,
+            is_virtual: true,
+        })
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        self.is_virtual
+    }
+
+    /// The first (preferred) target, used by flows without failover.
+    pub fn primary(&self) -> &FallbackCandidate {
+        &self.candidates[0]
+    }
+
+    /// The smallest known input window of the chain and its tokenizer:
+    /// history is budgeted against it, so that any target can take the
+    /// request after a failover.
+    pub fn input_window(&self) -> Option<(i32, Tokenizer)> {
+        self.candidates
+            .iter()
+            .map(|candidate| &candidate.model)
+            .filter(|model| model.max_input_tokens.is_some_and(|tokens| tokens > 0))
+            .min_by_key(|model| model.max_input_tokens)
+            .map(|model| {
+                (
+                    model.max_input_tokens.unwrap_or_default(),
+                    Tokenizer::for_model(&model.api_provider, &model.model_id),
+                )
+            })
+    }
+
+    /// Fit the request's history into the chain's input window.
+    pub fn fit_context(&self, request: &mut InvokeModelRequest) -> Option<ContextTrim> {
+        let (max_input_tokens, tokenizer) = self.input_window()?;
+        ai::fit_context(request, max_input_tokens, tokenizer)
+    }
+
+    /// Non-streaming completion; returns the response together with the
+    /// model that produced it.
+    pub async fn invoke_model(
+        &self,
+        request: InvokeModelRequest,
+    ) -> Result<(ModelResponse, &Model), AppError> {
+        let last = self.candidates.len() - 1;
+        for (ndx, candidate) in self.candidates.iter().enumerate() {
+            let mut attempt = request.clone();
+            attempt.model_id = candidate.model.model_id.clone();
+            let tool_calls_ran = Arc::new(AtomicBool::new(false));
+            attempt.tool_calls_ran = tool_calls_ran.clone();
+            match candidate.provider.invoke_model(attempt).await {
+                Ok(response) => return Ok((response, &candidate.model)),
+                Err(e)
+                    if ndx < last
+                        && !tool_calls_ran.load(Ordering::SeqCst)
+                        && is_failover_error(&e) =>
+                {
+                    warn!(
+                        "Model {} failed ({}), failing over to {}",
+                        candidate.model.model_id,
+                        e,
+                        self.candidates[ndx + 1].model.model_id
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(AppError::Internal("Fallback chain is empty".to_string()))
+    }
+
+    /// Streaming completion with failover. Errors of an attempt that may
+    /// still fail over are held back from `callbacks.on_error`; once a token
+    /// (answer or reasoning) has been streamed or a tool call ran the
+    /// attempt's outcome is final.
+    pub async fn invoke_model_stream<F, C, E, R>(
+        &self,
+        request: InvokeModelRequest,
+        callbacks: StreamCallbacks<F, C, E, R>,
+    ) -> Result<(Vec<ExecutedToolCall>, &Model), AppError>
+    where
+        F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+    {
+        let last = self.candidates.len() - 1;
+        for (ndx, candidate) in self.candidates.iter().enumerate() {
+            let streamed = AtomicBool::new(false);
+            let reported: Mutex<Option<AppError>> = Mutex::new(None);
+
+            let mut attempt = request.clone();
+            attempt.model_id = candidate.model.model_id.clone();
+            let tool_calls_ran = Arc::new(AtomicBool::new(false));
+            attempt.tool_calls_ran = tool_calls_ran.clone();
+            let attempt_callbacks = StreamCallbacks {
+                on_token: |token: String| {
+                    streamed.store(true, Ordering::SeqCst);
+                    (callbacks.on_token)(token)
+                },
+                on_complete: |content: String| (callbacks.on_complete)(content),
+                on_error: |error: AppError| {
+                    if let Ok(mut reported) = reported.lock() {
+                        *reported = Some(error);
+                    }
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                },
+                on_reasoning: |delta: String| {
+                    streamed.store(true, Ordering::SeqCst);
+                    (callbacks.on_reasoning)(delta)
+                },
+                usage: callbacks.usage.clone(),
+            };
+
+            let result = candidate
+                .provider
+                .invoke_model_stream(attempt, attempt_callbacks)
+                .await;
+            let reported = reported.into_inner().ok().flatten();
+            match result {
+                Ok(executed) => return Ok((executed, &candidate.model)),
+                Err(e)
+                    if ndx < last
+                        && !streamed.load(Ordering::SeqCst)
+                        && !tool_calls_ran.load(Ordering::SeqCst)
+                        && is_failover_error(&e) =>
+                {
+                    warn!(
+                        "Model {} failed ({}), failing over to {}",
+                        candidate.model.model_id,
+                        e,
+                        self.candidates[ndx + 1].model.model_id
+                    );
+                }
+                Err(e) => {
+                    if let Some(reported) = reported {
+                        (callbacks.on_error)(reported).await;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Err(AppError::Internal("Fallback chain is empty".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::model::CustomModelSettings;
+    use crate::services::ai::{MessageRole, ModelMessage, UsageTracker};
+    use crate::services::custom::{CustomService, PROTOCOL_OPENAI_CHAT_COMPLETIONS};
+    use crate::services::retry::RetryPolicy;
+    use crate::utils::test_server::{StubResponse, StubServer};
+    use std::sync::Arc;
+
+    fn custom_model(model_id: &str, endpoint: &str) -> Model {
+        let mut model = Model::new(
+            model_id.to_string(),
+            None,
+            model_id.to_string(),
+            "CUSTOM_REST_API".to_string(),
+            Some("user".to_string()),
+        );
+        model.is_custom = true;
+        model.custom_settings = serde_json::to_string(&CustomModelSettings {
+            endpoint: Some(endpoint.to_string()),
+            model_name: Some(model_id.to_string()),
+            protocol: Some(PROTOCOL_OPENAI_CHAT_COMPLETIONS.to_string()),
+            ..Default::default()
+        })
+        .ok();
+        model
+    }
+
+    fn chain(models: Vec<Model>) -> FallbackChain {
+        FallbackChain {
+            candidates: models
+                .into_iter()
+                .map(|model| FallbackCandidate {
+                    provider: AIProviderWrapper::Custom(
+                        CustomService::for_model(&model)
+                            .unwrap()
+                            .with_retry(RetryPolicy::none()),
+                    ),
+                    model,
+                })
+                .collect(),
+            is_virtual: true,
+        }
+    }
+
+    fn request() -> InvokeModelRequest {
+        InvokeModelRequest {
+            model_id: "virtual".to_string(),
+            messages: vec![ModelMessage::text(MessageRole::User, "hi".to_string())],
+            temperature: None,
+            max_tokens: None,
+            top_p: None,
+            system_prompt: None,
+            tools: None,
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
+        }
+    }
+
+    fn status(code: u16) -> StubResponse {
+        StubResponse::text(
+            code,
+            "application/json",
+            r#"{"error":{"message":"try later"}}"#,
+        )
+    }
+
+    #[test]
+    fn classifies_failover_errors() {
+        for message in [
+            "Custom model API error (429 Too Many Requests): slow down",
+            "Anthropic API error (529 <unknown status code>): overloaded",
+            "Gemini API error (503 Service Unavailable): busy",
+            "error sending request: operation timed out",
+        ] {
+            assert!(
+                is_failover_error(&AppError::Http(message.to_string())),
+                "{message}"
+            );
+        }
+        assert!(is_failover_error(&AppError::Aws(
+            "ThrottlingException: Too many tokens, please wait".to_string()
+        )));
+        assert!(!is_failover_error(&AppError::Http(
+            "Custom model API error (400 Bad Request): invalid messages".to_string()
+        )));
+        assert!(!is_failover_error(&AppError::Http(
+            "OpenAI API error (401 Unauthorized): bad key (5000 tokens)".to_string()
+        )));
+        assert!(!is_failover_error(&AppError::Validation(
+            "Service unavailable".to_string()
+        )));
+    }
+
+    #[tokio::test]
+    async fn stream_fails_over_on_rate_limit() {
+        let first = StubServer::start(vec![status(429)]).await;
+        let second = StubServer::start(vec![StubResponse::sse(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"4\"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n\
+             data: [DONE]\n\n",
+        )])
+        .await;
+        let chain = chain(vec![
+            custom_model("primary", &first.base_url),
+            custom_model("backup", &second.base_url),
+        ]);
+
+        let tokens = Arc::new(Mutex::new(String::new()));
+        let errors = Arc::new(Mutex::new(Vec::<String>::new()));
+        let callbacks = StreamCallbacks {
+            on_token: |token: String| {
+                tokens.lock().unwrap().push_str(&token);
+                Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+            },
+            on_complete: |_c: String| {
+                Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+            },
+            on_error: |e: AppError| {
+                errors.lock().unwrap().push(e.to_string());
+                Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+            },
+            on_reasoning: |_r: String| {
+                Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+            },
+            usage: UsageTracker::default(),
+        };
+
+        let (_, answered_by) = chain
+            .invoke_model_stream(request(), callbacks)
+            .await
+            .unwrap();
+        assert_eq!(answered_by.model_id, "backup");
+        assert_eq!(*tokens.lock().unwrap(), "4");
+        assert!(errors.lock().unwrap().is_empty());
+        assert_eq!(first.requests()[0].json()["model"], "primary");
+        assert_eq!(second.requests()[0].json()["model"], "backup");
+    }
+
+    #[tokio::test]
+    async fn client_errors_do_not_fail_over() {
+        let first = StubServer::start(vec![status(400)]).await;
+        let second = StubServer::start(vec![StubResponse::json(serde_json::json!({
+            "choices": [{"message": {"role": "assistant", "content": "4"}}]
+        }))])
+        .await;
+        let chain = chain(vec![
+            custom_model("primary", &first.base_url),
+            custom_model("backup", &second.base_url),
+        ]);
+
+        assert!(chain.invoke_model(request()).await.is_err());
+        assert!(second.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn no_failover_once_a_tool_call_ran() {
+        let first = StubServer::start(vec![
+            StubResponse::json(serde_json::json!({
+                "choices": [{
+                    "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{
+                            "id": "call-1",
+                            "type": "function",
+                            "function": {"name": "search", "arguments": "{\"query\":\"x\"}"}
+                        }]
+                    },
+                    "finish_reason": "tool_calls"
+                }]
+            })),
+            status(503),
+        ])
+        .await;
+        let second = StubServer::start(vec![StubResponse::json(serde_json::json!({
+            "choices": [{"message": {"role": "assistant", "content": "4"}}]
+        }))])
+        .await;
+        let chain = chain(vec![
+            custom_model("primary", &first.base_url),
+            custom_model("backup", &second.base_url),
+        ]);
+        let mut request = request();
+        request.tools = Some(vec![crate::services::ai::ExecutableTool {
+            spec: crate::services::ai::ToolSpec {
+                name: "search".to_string(),
+                description: "Search the web".to_string(),
+                input_schema: serde_json::json!({ "type": "object" }),
+            },
+            backend: crate::services::ai::ToolBackend::WebSearch {
+                backend: crate::services::search_backend::SearchBackend::Searxng {
+                    api_url: "http://127.0.0.1:9".to_string(),
+                    api_key: None,
+                    public_only: false,
+                },
+            },
+            requires_approval: false,
+            timeout: None,
+        }]);
+
+        assert!(chain.invoke_model(request).await.is_err());
+        assert_eq!(first.requests().len(), 2);
+        assert!(second.requests().is_empty());
+    }
+}
@@ -0,0 +1,868 @@
+//! Google Gemini provider: the Generative Language REST API
+//! (`generateContent` / `streamGenerateContent?alt=sse`) with function
+//! calling, image input, `embedContent` embeddings and Imagen `predict`
+//! images generation. Auth is the `x-goog-api-key` header.
+//!
+//! Model turns that requested functions are replayed verbatim (their raw
+//! `parts`, including thought signatures); tool results go back as
+//! `functionResponse` parts of a user turn.
+
+use async_trait::async_trait;
+use base64::Engine;
+use chrono::DateTime;
+use futures_util::StreamExt;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use tracing::{debug, warn};
+
+use crate::config::AppConfig;
+use crate::services::ai::*;
+use crate::services::tools::run_tool_calls;
+use crate::utils::errors::AppError;
+
+const GEMINI_API_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+pub struct GeminiService {
+    config: AppConfig,
+    client: Client,
+}
+
+impl GeminiService {
+    pub fn new(config: AppConfig) -> Self {
+        Self {
+            config,
+            client: Client::new(),
+        }
+    }
+
+    fn api_key(&self) -> Result<&str, AppError> {
+        self.config
+            .gemini_api_key
+            .as_deref()
+            .ok_or_else(|| AppError::Auth("Gemini API key not configured".to_string()))
+    }
+
+    fn url(&self, path: &str) -> String {
+        let base = self
+            .config
+            .gemini_api_url
+            .as_deref()
+            .unwrap_or(GEMINI_API_URL)
+            .trim_end_matches('/');
+        format!("{}{}", base, path)
+    }
+
+    /// `models/{id}:{method}` — listed ids are stored without the
+    /// `models/` prefix.
+    fn model_url(&self, model_id: &str, method: &str) -> String {
+        let id = model_id.trim_start_matches("models/");
+        self.url(&format!("/models/{}:{}", id, method))
+    }
+
+    /// Extract the API error message from a Google error payload
+    /// (`{"error": {"code": …, "message": …, "status": …}}`).
+    fn api_error(status: reqwest::StatusCode, body: &str) -> AppError {
+        let message = serde_json::from_str::<Value>(body)
+            .ok()
+            .and_then(|v| {
+                v.get("error")
+                    .and_then(|e| e.get("message"))
+                    .and_then(|m| m.as_str())
+                    .map(|s| s.to_string())
+            })
+            .unwrap_or_else(|| body.to_string());
+        AppError::Http(format!("Gemini API error ({}): {}", status, message))
+    }
+
+    async fn post(&self, url: String, body: &Value) -> Result<reqwest::Response, AppError> {
+        let response = self
+            .client
+            .post(url)
+            .header("x-goog-api-key", self.api_key()?)
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| AppError::Http(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(Self::api_error(status, &error_text));
+        }
+        Ok(response)
+    }
+
+    /// Message body → Gemini parts: structured bodies (a JSON array of
+    /// `{contentType, content}` parts, images as `data:` URLs) become
+    /// text/inlineData parts, anything else a single text part.
+    pub fn format_parts(body: &str) -> Vec<Value> {
+        let parts = serde_json::from_str::<Value>(body)
+            .ok()
+            .and_then(|parsed| parsed.as_array().cloned())
+            .unwrap_or_default();
+
+        let formatted: Vec<Value> = parts
+            .iter()
+            .filter_map(|part| {
+                let content = part.get("content").and_then(|v| v.as_str())?;
+                match part.get("contentType").and_then(|v| v.as_str())? {
+                    "image" => {
+                        let (mime, data) = content.strip_prefix("data:")?.split_once(";base64,")?;
+                        Some(json!({ "inlineData": { "mimeType": mime, "data": data } }))
+                    }
+                    "text" => Some(json!({ "text": content })),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        if formatted.is_empty() {
+            return vec![json!({ "text": body })];
+        }
+        formatted
+    }
+
+    /// Function names by call id, from the replayed model turns — Gemini
+    /// matches a `functionResponse` by name, the session only keeps the id.
+    fn function_names(messages: &[ModelMessage]) -> HashMap<String, String> {
+        messages
+            .iter()
+            .filter_map(|m| m.tool_calls.as_ref()?.as_array())
+            .flatten()
+            .filter_map(|part| {
+                let call = part.get("functionCall")?;
+                let name = call.get("name")?.as_str()?.to_string();
+                let id = call
+                    .get("id")
+                    .and_then(|id| id.as_str())
+                    .map(|id| id.to_string())
+                    .unwrap_or_else(|| name.clone());
+                Some((id, name))
+            })
+            .collect()
+    }
+
+    pub fn build_request(request: &InvokeModelRequest) -> Value {
+        let names = Self::function_names(&request.messages);
+        let mut contents: Vec<(String, Vec<Value>)> = Vec::new();
+        let mut system_texts: Vec<String> = request.system_prompt.iter().cloned().collect();
+
+        for msg in &request.messages {
+            let (role, parts) = match msg.role {
+                MessageRole::System => {
+                    system_texts.push(msg.content.clone());
+                    continue;
+                }
+                MessageRole::Tool => {
+                    let id = msg.tool_call_id.clone().unwrap_or_default();
+                    let name = names.get(&id).cloned().unwrap_or_else(|| id.clone());
+                    let mut response = json!({
+                        "name": name,
+                        "response": { "content": msg.content },
+                    });
+                    if names.contains_key(&id) && id != name {
+                        response["id"] = json!(id);
+                    }
+                    ("user", vec![json!({ "functionResponse": response })])
+                }
+                MessageRole::Assistant => {
+                    match msg.tool_calls.as_ref().and_then(|c| c.as_array()) {
+                        Some(parts) => ("model", parts.clone()),
+                        None => ("model", vec![json!({ "text": msg.content })]),
+                    }
+                }
+                MessageRole::User => ("user", Self::format_parts(&msg.content)),
+            };
+
+            // Consecutive turns of one role are merged (parallel function
+            // responses share a single user turn).
+            match contents.last_mut() {
+                Some((last_role, last_parts)) if last_role == role => last_parts.extend(parts),
+                _ => contents.push((role.to_string(), parts)),
+            }
+        }
+
+        let mut body = json!({
+            "contents": contents
+                .into_iter()
+                .map(|(role, parts)| json!({ "role": role, "parts": parts }))
+                .collect::<Vec<_>>(),
+        });
+
+        let system_texts: Vec<&String> = system_texts
+            .iter()
+            .filter(|t| !t.trim().is_empty())
+            .collect();
+        if !system_texts.is_empty() {
+            body["systemInstruction"] = json!({
+                "parts": system_texts.iter().map(|t| json!({ "text": t })).collect::<Vec<_>>(),
+            });
+        }
+
+        let mut generation_config = json!({});
+        if let Some(max_tokens) = request.max_tokens {
+            generation_config["maxOutputTokens"] = json!(max_tokens);
+        }
+        if let Some(temperature) = request.temperature {
+            generation_config["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = request.top_p {
+            generation_config["topP"] = json!(top_p);
+        }
+        if let Some(budget) = request.thinking_budget {
+            generation_config["thinkingConfig"] =
+                json!({ "thinkingBudget": budget, "includeThoughts": true });
+        }
+        if let Some(schema) = &request.response_schema {
+            generation_config["responseMimeType"] = json!("application/json");
+            generation_config["responseJsonSchema"] = schema.clone();
+        }
+        body["generationConfig"] = generation_config;
+
+        if let Some(tools) = request.tools.as_deref().filter(|t| !t.is_empty()) {
+            body["tools"] = json!([{
+                "functionDeclarations": tools
+                    .iter()
+                    .map(|tool| json!({
+                        "name": tool.spec.name,
+                        "description": tool.spec.description,
+                        "parameters": tool.spec.input_schema,
+                    }))
+                    .collect::<Vec<_>>(),
+            }]);
+        }
+
+        body
+    }
+
+    fn candidate_parts(response: &Value) -> Vec<Value> {
+        response
+            .pointer("/candidates/0/content/parts")
+            .and_then(|p| p.as_array())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// `functionCall` parts → tool call requests (id falls back to the
+    /// function name when the API does not assign call ids).
+    fn function_calls(parts: &[Value]) -> Vec<ToolCallRequest> {
+        parts
+            .iter()
+            .filter_map(|part| {
+                let call = part.get("functionCall")?;
+                let name = call.get("name")?.as_str()?.to_string();
+                Some(ToolCallRequest {
+                    id: call
+                        .get("id")
+                        .and_then(|id| id.as_str())
+                        .map(|id| id.to_string())
+                        .unwrap_or_else(|| name.clone()),
+                    name,
+                    arguments: call.get("args").cloned().unwrap_or_else(|| json!({})),
+                    raw: part.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Visible text of the parts; `thought` parts are reasoning.
+    fn split_text(parts: &[Value]) -> (String, String) {
+        let mut text = String::new();
+        let mut thoughts = String::new();
+        for part in parts {
+            let Some(chunk) = part.get("text").and_then(|t| t.as_str()) else {
+                continue;
+            };
+            if part.get("thought").and_then(|t| t.as_bool()) == Some(true) {
+                thoughts.push_str(chunk);
+            } else {
+                text.push_str(chunk);
+            }
+        }
+        (text, thoughts)
+    }
+
+    fn parse_usage(response: &Value) -> Option<Usage> {
+        let usage = response.get("usageMetadata")?;
+        let tokens = |key: &str| usage.get(key).and_then(|t| t.as_i64()).map(|t| t as i32);
+        Some(Usage {
+            input_tokens: tokens("promptTokenCount"),
+            output_tokens: tokens("candidatesTokenCount"),
+            total_tokens: tokens("totalTokenCount"),
+            cache_read_input_tokens: tokens("cachedContentTokenCount"),
+            cache_write_input_tokens: None,
+        })
+    }
+
+    fn finish_reason(response: &Value) -> Option<String> {
+        response
+            .pointer("/candidates/0/finishReason")
+            .and_then(|r| r.as_str())
+            .map(|r| r.to_string())
+    }
+
+    /// Decode one SSE stream cycle: text parts go to `on_token`, thought
+    /// parts to `on_reasoning`; the model turn's parts are collected for a
+    /// possible function-call replay.
+    async fn stream_cycle<F, C, E, R>(
+        &self,
+        response: reqwest::Response,
+        callbacks: &StreamCallbacks<F, C, E, R>,
+        full_response: &mut String,
+        turn_parts: &mut Vec<Value>,
+    ) -> Result<(), AppError>
+    where
+        F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+    {
+        let mut stream = response.bytes_stream();
+        let mut line_buffer = String::new();
+        // usageMetadata is cumulative; the last chunk's counts are the cycle's
+        let mut usage = None;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| AppError::Http(format!("Stream error: {}", e)))?;
+            line_buffer.push_str(&String::from_utf8_lossy(&chunk));
+
+            // Keep the trailing partial line.
+            while let Some(newline_pos) = line_buffer.find('\n') {
+                let line: String = line_buffer.drain(..=newline_pos).collect();
+                let Some(data) = line.trim().strip_prefix("data:") else {
+                    continue;
+                };
+
+                let event = match serde_json::from_str::<Value>(data.trim()) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        warn!("Gemini: failed to parse stream data: {} — {}", data, e);
+                        continue;
+                    }
+                };
+
+                if let Some(message) = event.pointer("/error/message").and_then(|m| m.as_str()) {
+                    return Err(AppError::Http(format!("Gemini stream error: {}", message)));
+                }
+
+                for part in Self::candidate_parts(&event) {
+                    let is_thought = part.get("thought").and_then(|t| t.as_bool()) == Some(true);
+                    if let Some(text) = part
+                        .get("text")
+                        .and_then(|t| t.as_str())
+                        .filter(|t| !t.is_empty())
+                    {
+                        if is_thought {
+                            (callbacks.on_reasoning)(text.to_string()).await;
+                        } else {
+                            full_response.push_str(text);
+                            (callbacks.on_token)(text.to_string()).await;
+                        }
+                    }
+                    turn_parts.push(part);
+                }
+                usage = Self::parse_usage(&event).or(usage);
+            }
+        }
+        if let Some(usage) = &usage {
+            callbacks.usage.record(usage);
+        }
+        Ok(())
+    }
+
+    /// Classify a listed model by its supported generation methods →
+    /// (type, streaming, image_input); `None` for models kept out of the
+    /// list (audio/live variants, attributed QA, …).
+    pub fn classify_model(id: &str, methods: &[&str]) -> Option<(&'static str, bool, bool)> {
+        if ["tts", "native-audio", "live", "aqa"]
+            .iter()
+            .any(|skip| id.contains(skip))
+        {
+            return None;
+        }
+        if methods.contains(&"embedContent") {
+            return Some(("embedding", false, false));
+        }
+        if methods.contains(&"predict") && id.starts_with("imagen") {
+            return Some(("image_generation", false, false));
+        }
+        if methods.contains(&"generateContent") && !id.contains("image-generation") {
+            return Some(("chat", true, id.starts_with("gemini")));
+        }
+        None
+    }
+}
+
+#[async_trait]
+impl AIProviderService for GeminiService {
+    async fn invoke_model(&self, request: InvokeModelRequest) -> Result<ModelResponse, AppError> {
+        let mut session = request;
+
+        let mut executed: Vec<ExecutedToolCall> = Vec::new();
+        for _cycle in 0..TOOL_CYCLES_LIMIT {
+            let body = Self::build_request(&session);
+            debug!("Gemini: generateContent for {}", session.model_id);
+
+            let response_json: Value = self
+                .post(self.model_url(&session.model_id, "generateContent"), &body)
+                .await?
+                .json()
+                .await
+                .map_err(|e| {
+                    AppError::Internal(format!("Failed to parse Gemini response: {}", e))
+                })?;
+
+            let parts = Self::candidate_parts(&response_json);
+            let calls = Self::function_calls(&parts);
+            if !calls.is_empty() {
+                run_tool_calls(&mut session, &mut executed, Value::Array(parts), calls).await;
+                continue;
+            }
+
+            let (content, thoughts) = Self::split_text(&parts);
+            return Ok(ModelResponse {
+                content,
+                model_id: session.model_id.clone(),
+                usage: Self::parse_usage(&response_json),
+                finish_reason: Self::finish_reason(&response_json),
+                tool_calls: Vec::new(),
+                reasoning: (!thoughts.is_empty()).then_some(thoughts),
+                executed_tool_calls: executed,
+            });
+        }
+
+        Err(AppError::Internal(
+            "Gemini: tool call cycles limit exceeded".to_string(),
+        ))
+    }
+
+    async fn invoke_model_stream<F, C, E, R>(
+        &self,
+        request: InvokeModelRequest,
+        callbacks: StreamCallbacks<F, C, E, R>,
+    ) -> Result<Vec<ExecutedToolCall>, AppError>
+    where
+        F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+    {
+        let mut session = request;
+        let mut executed: Vec<ExecutedToolCall> = Vec::new();
+        let mut full_response = String::new();
+
+        for _cycle in 0..TOOL_CYCLES_LIMIT {
+            let body = Self::build_request(&session);
+            debug!("Gemini: streamGenerateContent for {}", session.model_id);
+
+            let mut turn_parts: Vec<Value> = Vec::new();
+            let url = format!(
+                "{}?alt=sse",
+                self.model_url(&session.model_id, "streamGenerateContent")
+            );
+            let result = match self.post(url, &body).await {
+                Ok(response) => {
+                    self.stream_cycle(response, &callbacks, &mut full_response, &mut turn_parts)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(error) = result {
+                (callbacks.on_error)(error.clone()).await;
+                return Err(error);
+            }
+
+            // The cycle repeats ONLY to continue after function calls.
+            let calls = Self::function_calls(&turn_parts);
+            if calls.is_empty() {
+                (callbacks.on_complete)(full_response).await;
+                return Ok(executed);
+            }
+
+            run_tool_calls(&mut session, &mut executed, Value::Array(turn_parts), calls).await;
+        }
+
+        let error = AppError::Internal("Gemini: tool call cycles limit exceeded".to_string());
+        (callbacks.on_error)(error.clone()).await;
+        Err(error)
+    }
+
+    async fn get_models(&self) -> Result<HashMap<String, AIModelInfo>, AppError> {
+        let response = self
+            .client
+            .get(self.url("/models?pageSize=1000"))
+            .header("x-goog-api-key", self.api_key()?)
+            .send()
+            .await
+            .map_err(|e| AppError::Http(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(Self::api_error(status, &error_text));
+        }
+
+        let response_json: Value = response.json().await.map_err(|e| {
+            AppError::Internal(format!("Failed to parse Gemini models response: {}", e))
+        })?;
+
+        let mut models = HashMap::new();
+        for model in response_json
+            .get("models")
+            .and_then(|m| m.as_array())
+            .into_iter()
+            .flatten()
+        {
+            let Some(id) = model
+                .get("name")
+                .and_then(|n| n.as_str())
+                .map(|n| n.trim_start_matches("models/"))
+            else {
+                continue;
+            };
+            let methods: Vec<&str> = model
+                .get("supportedGenerationMethods")
+                .and_then(|m| m.as_array())
+                .map(|m| m.iter().filter_map(|v| v.as_str()).collect())
+                .unwrap_or_default();
+            let Some((type_, streaming, image_input)) = Self::classify_model(id, &methods) else {
+                continue;
+            };
+
+            let name = model
+                .get("displayName")
+                .and_then(|n| n.as_str())
+                .unwrap_or(id)
+                .to_string();
+            models.insert(
+                id.to_string(),
+                AIModelInfo {
+                    api_provider: ApiProvider::Gemini,
+                    provider: Some("Google".to_string()),
+                    description: model
+                        .get("description")
+                        .and_then(|d| d.as_str())
+                        .map(|d| d.to_string())
+                        .unwrap_or_else(|| format!("{} by Google", name)),
+                    name,
+                    type_: type_.to_string(),
+                    streaming,
+                    image_input,
+                    max_input_tokens: model
+                        .get("inputTokenLimit")
+                        .and_then(|t| t.as_i64())
+                        .map(|t| t as i32),
+                },
+            );
+        }
+
+        Ok(models)
+    }
+
+    async fn get_info(&self, test_connection: bool) -> Result<ProviderInfo, AppError> {
+        let mut details = HashMap::new();
+        let is_connected = self.config.gemini_api_key.is_some();
+
+        details.insert("configured".to_string(), is_connected.to_string());
+
+        if test_connection && is_connected {
+            match self.get_models().await {
+                Ok(_) => {
+                    details.insert("connection_test".to_string(), "success".to_string());
+                }
+                Err(e) => {
+                    details.insert("connection_test".to_string(), "failed".to_string());
+                    details.insert("error".to_string(), e.to_string());
+                }
+            }
+        }
+
+        Ok(ProviderInfo {
+            id: "GEMINI".to_string(),
+            name: "Google Gemini".to_string(),
+            is_connected,
+            costs_info_available: false,
+            details,
+        })
+    }
+
+    async fn get_costs(
+        &self,
+        start_time: i64,
+        end_time: Option<i64>,
+    ) -> Result<UsageCostInfo, AppError> {
+        Ok(UsageCostInfo {
+            start: DateTime::from_timestamp(start_time, 0).unwrap_or_default(),
+            end: end_time.and_then(|t| DateTime::from_timestamp(t, 0)),
+            costs: vec![],
+            error: Some("Cost information not available for Google Gemini".to_string()),
+        })
+    }
+
+    /// Imagen `predict`: one prediction per requested image.
+    async fn generate_images(
+        &self,
+        request: GenerateImagesRequest,
+    ) -> Result<Vec<GeneratedImage>, AppError> {
+        let body = json!({
+            "instances": [{ "prompt": request.prompt }],
+            "parameters": { "sampleCount": request.count.max(1) },
+        });
+        let response_json: Value = self
+            .post(self.model_url(&request.model_id, "predict"), &body)
+            .await?
+            .json()
+            .await
+            .map_err(|e| {
+                AppError::Internal(format!("Failed to parse Gemini predict response: {}", e))
+            })?;
+
+        let images: Vec<GeneratedImage> = response_json
+            .get("predictions")
+            .and_then(|p| p.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|prediction| {
+                let data = prediction.get("bytesBase64Encoded")?.as_str()?;
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(data)
+                    .ok()?;
+                Some(GeneratedImage {
+                    bytes,
+                    mime: prediction
+                        .get("mimeType")
+                        .and_then(|m| m.as_str())
+                        .unwrap_or("image/png")
+                        .to_string(),
+                })
+            })
+            .collect();
+
+        if images.is_empty() {
+            return Err(AppError::Internal(
+                "Gemini returned no images (the prompt may have been filtered)".to_string(),
+            ));
+        }
+        Ok(images)
+    }
+
+    async fn get_embeddings(&self, model_id: &str, input: &str) -> Result<Vec<f32>, AppError> {
+        let body = json!({ "content": { "parts": [{ "text": input }] } });
+        let response_json: Value = self
+            .post(self.model_url(model_id, "embedContent"), &body)
+            .await?
+            .json()
+            .await
+            .map_err(|e| {
+                AppError::Internal(format!("Failed to parse Gemini embeddings response: {}", e))
+            })?;
+
+        response_json
+            .pointer("/embedding/values")
+            .and_then(|v| v.as_array())
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|v| v.as_f64())
+                    .map(|v| v as f32)
+                    .collect()
+            })
+            .ok_or_else(|| AppError::Internal("Gemini returned no embedding".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::ai::UsageTracker;
+    use crate::utils::test_server::{StubResponse, StubServer};
+    use std::sync::{Arc, Mutex};
+
+    fn service(base_url: &str) -> GeminiService {
+        let mut config = AppConfig::from_env();
+        config.gemini_api_key = Some("gm-test".to_string());
+        config.gemini_api_url = Some(format!("{}/v1beta", base_url));
+        GeminiService::new(config)
+    }
+
+    fn request() -> InvokeModelRequest {
+        InvokeModelRequest {
+            model_id: "gemini-2.5-flash".to_string(),
+            messages: vec![ModelMessage::text(MessageRole::User, "hi")],
+            temperature: Some(0.2),
+            max_tokens: Some(256),
+            top_p: None,
+            system_prompt: Some("be brief".to_string()),
+            tools: None,
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn invokes_generate_content_and_runs_function_calls() {
+        let server = StubServer::start(vec![
+            StubResponse::json(json!({
+                "candidates": [{ "content": { "role": "model", "parts": [
+                    { "functionCall": { "name": "lookup", "args": { "q": "rust" } },
+                      "thoughtSignature": "sig" },
+                ]}, "finishReason": "STOP" }],
+            })),
+            StubResponse::json(json!({
+                "candidates": [{ "content": { "role": "model", "parts": [
+                    { "text": "Checking sources.", "thought": true },
+                    { "text": "Hello!" },
+                ]}, "finishReason": "STOP" }],
+                "usageMetadata": { "promptTokenCount": 5, "candidatesTokenCount": 2, "totalTokenCount": 7 },
+            })),
+        ])
+        .await;
+
+        let response = service(&server.base_url)
+            .invoke_model(request())
+            .await
+            .unwrap();
+        assert_eq!(response.content, "Hello!");
+        assert_eq!(response.reasoning.as_deref(), Some("Checking sources."));
+        assert_eq!(response.usage.unwrap().total_tokens, Some(7));
+
+        let requests = server.requests();
+        let sent = &requests[0];
+        assert!(sent
+            .request_line
+            .starts_with("POST /v1beta/models/gemini-2.5-flash:generateContent"));
+        assert_eq!(sent.header("x-goog-api-key"), Some("gm-test"));
+        let body = sent.json();
+        assert_eq!(body["systemInstruction"]["parts"][0]["text"], "be brief");
+        assert_eq!(body["generationConfig"]["maxOutputTokens"], 256);
+
+        // the model turn is replayed verbatim, the result as functionResponse
+        let followup = requests[1].json();
+        assert_eq!(followup["contents"][1]["role"], "model");
+        assert_eq!(
+            followup["contents"][1]["parts"][0]["thoughtSignature"],
+            "sig"
+        );
+        let result = &followup["contents"][2]["parts"][0]["functionResponse"];
+        assert_eq!(result["name"], "lookup");
+    }
+
+    #[tokio::test]
+    async fn streams_sse_text_parts() {
+        let events = [
+            json!({"candidates": [{"content": {"role": "model",
+                "parts": [{"text": "Planning", "thought": true}]}}]}),
+            json!({"candidates": [{"content": {"role": "model", "parts": [{"text": "Hel"}]}}]}),
+            json!({"candidates": [{"content": {"role": "model", "parts": [{"text": "lo"}]},
+                "finishReason": "STOP"}]}),
+        ];
+        let body: String = events
+            .iter()
+            .map(|e| format!("data: {}\r\n\r\n", e))
+            .collect();
+        let server = StubServer::start(vec![StubResponse::sse(body)]).await;
+
+        let tokens = Arc::new(Mutex::new(Vec::<String>::new()));
+        let completed = Arc::new(Mutex::new(None::<String>));
+        let thoughts = Arc::new(Mutex::new(String::new()));
+        let callbacks = StreamCallbacks {
+            on_token: {
+                let tokens = tokens.clone();
+                move |token: String| {
+                    tokens.lock().unwrap().push(token);
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                }
+            },
+            on_complete: {
+                let completed = completed.clone();
+                move |content: String| {
+                    *completed.lock().unwrap() = Some(content);
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                }
+            },
+            on_error: |_e: AppError| Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>,
+            on_reasoning: {
+                let thoughts = thoughts.clone();
+                move |delta: String| {
+                    thoughts.lock().unwrap().push_str(&delta);
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                }
+            },
+            usage: UsageTracker::default(),
+        };
+
+        let mut request = request();
+        request.thinking_budget = Some(1024);
+        service(&server.base_url)
+            .invoke_model_stream(request, callbacks)
+            .await
+            .unwrap();
+        assert_eq!(*thoughts.lock().unwrap(), "Planning");
+        assert_eq!(*tokens.lock().unwrap(), vec!["Hel", "lo"]);
+        assert_eq!(
+            server.requests()[0].json()["generationConfig"]["thinkingConfig"]["thinkingBudget"],
+            1024
+        );
+        assert_eq!(completed.lock().unwrap().as_deref(), Some("Hello"));
+        assert!(server.requests()[0]
+            .request_line
+            .contains(":streamGenerateContent?alt=sse"));
+    }
+
+    #[tokio::test]
+    async fn embeds_content() {
+        let server = StubServer::start(vec![StubResponse::json(json!({
+            "embedding": { "values": [0.5, -0.25] },
+        }))])
+        .await;
+
+        let embedding = service(&server.base_url)
+            .get_embeddings("gemini-embedding-001", "hello")
+            .await
+            .unwrap();
+        assert_eq!(embedding, vec![0.5, -0.25]);
+        let sent = server.requests()[0].json();
+        assert_eq!(sent["content"]["parts"][0]["text"], "hello");
+    }
+
+    #[test]
+    fn classifies_listed_models() {
+        assert_eq!(
+            GeminiService::classify_model("gemini-2.5-pro", &["generateContent", "countTokens"]),
+            Some(("chat", true, true))
+        );
+        assert_eq!(
+            GeminiService::classify_model("gemini-embedding-001", &["embedContent"]),
+            Some(("embedding", false, false))
+        );
+        assert_eq!(
+            GeminiService::classify_model("imagen-4.0-generate-001", &["predict"]),
+            Some(("image_generation", false, false))
+        );
+        assert_eq!(
+            GeminiService::classify_model("gemini-2.5-flash-preview-tts", &["generateContent"]),
+            None
+        );
+    }
+
+    #[test]
+    fn image_parts_become_inline_data() {
+        let parts = GeminiService::format_parts(
+            &json!([
+                {"contentType": "text", "content": "what is this?"},
+                {"contentType": "image", "content": "data:image/png;base64,iVBORw0"},
+            ])
+            .to_string(),
+        );
+        assert_eq!(parts[0]["text"], "what is this?");
+        assert_eq!(parts[1]["inlineData"]["mimeType"], "image/png");
+        assert_eq!(parts[1]["inlineData"]["data"], "iVBORw0");
+    }
+}
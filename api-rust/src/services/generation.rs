@@ -0,0 +1,106 @@
+//! Streaming generations in flight, by assistant message id, so that
+//! `stopMessage` (another request) can cancel one. Stopping drops the
+//! stream future: the provider's HTTP response or Bedrock event stream is
+//! closed and the tool loop ends with it. The registry is per process: with
+//! several API instances `stopMessage` only reaches the generations of the
+//! instance it lands on, and returns false for the others.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+struct Generation {
+    user_id: String,
+    token: CancellationToken,
+}
+
+lazy_static::lazy_static! {
+    static ref GENERATIONS: Mutex<HashMap<String, Generation>> = Mutex::new(HashMap::new());
+}
+
+/// A registered generation; unregisters when dropped.
+pub struct GenerationHandle {
+    message_id: String,
+    token: CancellationToken,
+}
+
+impl GenerationHandle {
+    /// Run `future` to completion, or `None` once the generation is
+    /// stopped (the future is dropped).
+    pub async fn run<F: Future>(&self, future: F) -> Option<F::Output> {
+        self.token.run_until_cancelled(future).await
+    }
+}
+
+impl Drop for GenerationHandle {
+    fn drop(&mut self) {
+        if let Ok(mut generations) = GENERATIONS.lock() {
+            generations.remove(&self.message_id);
+        }
+    }
+}
+
+/// Register the generation of an assistant message of `user_id`.
+pub fn register(message_id: &str, user_id: &str) -> GenerationHandle {
+    let token = CancellationToken::new();
+    if let Ok(mut generations) = GENERATIONS.lock() {
+        generations.insert(
+            message_id.to_string(),
+            Generation {
+                user_id: user_id.to_string(),
+                token: token.clone(),
+            },
+        );
+    }
+    GenerationHandle {
+        message_id: message_id.to_string(),
+        token,
+    }
+}
+
+/// Stop the user's generation of a message; false when none is running.
+pub fn stop(message_id: &str, user_id: &str) -> bool {
+    let Ok(generations) = GENERATIONS.lock() else {
+        return false;
+    };
+    match generations.get(message_id) {
+        Some(generation) if generation.user_id == user_id => {
+            info!("Stopping generation of message {}", message_id);
+            generation.token.cancel();
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn stop_cancels_the_owners_generation_only() {
+        let handle = register("message-1", "user-1");
+
+        assert!(!stop("message-1", "user-2"));
+        assert!(!stop("message-2", "user-1"));
+        assert!(stop("message-1", "user-1"));
+
+        let result = handle
+            .run(tokio::time::sleep(Duration::from_secs(60)))
+            .await;
+        assert!(result.is_none());
+
+        // unregistered once the handle is gone
+        drop(handle);
+        assert!(!stop("message-1", "user-1"));
+    }
+
+    #[tokio::test]
+    async fn run_returns_the_output_when_not_stopped() {
+        let handle = register("message-3", "user-1");
+        assert_eq!(handle.run(async { 42 }).await, Some(42));
+    }
+}
@@ -1,26 +1,149 @@
 //! Minimal MCP (Model Context Protocol) client over Streamable HTTP:
-//! JSON-RPC `initialize` → `tools/list` / `tools/call`. Responses may come
+//! JSON-RPC `initialize` → `tools/list` / `tools/call`, resources
+//! (`resources/list`, `resources/templates/list`, `resources/read`) and
+//! prompts (`prompts/list`, `prompts/get`). Responses may come
 //! back as plain JSON or as an SSE stream — both are handled. Mirrors the
-//! Node API's mcp.service (which uses the official SDK client).
+//! Node API's mcp.service (which uses the official SDK client). STDIO
+//! servers go through the process pool in `services/mcp_stdio.rs`.
+//!
+//! Sessions are pooled per (user, server) and re-initialized when the
+//! server expires them; `notifications/tools/list_changed` marks the stored
+//! tools stale. Pool, stale flags and health are per API process.
 
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{json, Value};
+use tracing::debug;
 
-use crate::models::{GqlMcpAuthConfig, McpServer};
+use crate::models::{
+    GqlMcpAuthConfig, GqlMcpServerHealth, McpServer, MessageMcpResource, MCP_HEALTH_HEALTHY,
+    MCP_HEALTH_UNHEALTHY,
+};
+use crate::services::mcp_stdio::{self, StdioConfig};
 use crate::utils::errors::AppError;
 
-const MCP_PROTOCOL_VERSION: &str = "2025-03-26";
+pub(crate) const MCP_PROTOCOL_VERSION: &str = "2025-03-26";
+
+/// Listings follow `nextCursor` up to this many pages
+const MAX_LIST_PAGES: usize = 20;
+
+/// Pooled sessions unused for this long are dropped (servers expire idle
+/// sessions too, answering 404)
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// An initialized session; `id` is the `Mcp-Session-Id`, none for
+/// stateless servers
+struct PooledSession {
+    id: Option<String>,
+    last_used: Instant,
+}
+
+lazy_static::lazy_static! {
+    /// Sessions by (user id, server id), so that calls skip `initialize`
+    static ref SESSIONS: Mutex<HashMap<(String, String), PooledSession>> =
+        Mutex::new(HashMap::new());
+    /// Servers that sent `notifications/tools/list_changed` since their
+    /// tools were last listed
+    static ref TOOLS_CHANGED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    /// Outcome of the latest requests per server
+    static ref HEALTH: Mutex<HashMap<String, GqlMcpServerHealth>> = Mutex::new(HashMap::new());
+}
+
+pub(crate) fn mark_tools_changed(server_id: &str) {
+    debug!("MCP server {} tools changed", server_id);
+    TOOLS_CHANGED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(server_id.to_string());
+}
+
+/// Whether the server's tools changed since they were last listed; clears
+/// the flag.
+pub fn take_tools_changed(server_id: &str) -> bool {
+    TOOLS_CHANGED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(server_id)
+}
+
+/// Health of a server from the requests this API process made to it;
+/// `None` before the first one.
+pub fn health(server_id: &str) -> Option<GqlMcpServerHealth> {
+    HEALTH
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(server_id)
+        .cloned()
+}
+
+fn record_health(server_id: &str, started: Instant, error: Option<&AppError>) {
+    let now = Utc::now().naive_utc();
+    let mut health = HEALTH.lock().unwrap_or_else(|e| e.into_inner());
+    let entry = health
+        .entry(server_id.to_string())
+        .or_insert_with(|| GqlMcpServerHealth {
+            status: MCP_HEALTH_HEALTHY.to_string(),
+            last_checked_at: now,
+            last_success_at: None,
+            last_error: None,
+            consecutive_failures: 0,
+            latency_ms: None,
+        });
+    entry.last_checked_at = now;
+    entry.latency_ms = Some(started.elapsed().as_millis().min(i32::MAX as u128) as i32);
+    match error {
+        None => {
+            entry.status = MCP_HEALTH_HEALTHY.to_string();
+            entry.last_success_at = Some(now);
+            entry.consecutive_failures = 0;
+        }
+        Some(e) => {
+            entry.status = MCP_HEALTH_UNHEALTHY.to_string();
+            entry.last_error = Some(e.to_string());
+            entry.consecutive_failures += 1;
+        }
+    }
+}
+
+/// A JSON-RPC error answered by the server: the server works, the request
+/// failed
+fn rpc_error(error: &Value) -> AppError {
+    let message = error
+        .get("message")
+        .and_then(|m| m.as_str())
+        .unwrap_or("unknown error");
+    AppError::Http(format!("MCP error: {}", message))
+}
+
+enum Posted {
+    Answered(Value),
+    /// 404 on a session: the server dropped it
+    SessionExpired,
+}
 
 pub struct McpClient {
     client: reqwest::Client,
+    server_id: String,
     url: String,
     auth_header: Option<(String, String)>,
     session_id: Option<String>,
+    /// (user id, server id) of the pooled session
+    session_key: (String, String),
+    initialized: bool,
+    next_id: u64,
+    /// STDIO server: its id (the process pool key) and command
+    stdio: Option<(String, StdioConfig)>,
 }
 
 impl McpClient {
-    /// Build a client for a stored server; `auth_token` is the per-request
-    /// OAuth/Bearer token supplied by the web client (mcpTokens).
-    pub fn for_server(server: &McpServer, auth_token: Option<&str>) -> Self {
+    /// Build a client for a stored server, on the user's pooled session;
+    /// `auth_token` is the OAuth/Bearer token (the web client's mcpTokens,
+    /// else the stored OAuth token).
+    pub fn for_server(server: &McpServer, user_id: &str, auth_token: Option<&str>) -> Self {
         let auth_config: Option<GqlMcpAuthConfig> = server
             .auth_config
             .as_ref()
@@ -48,20 +171,68 @@ impl McpClient {
 
         Self {
             client: reqwest::Client::new(),
+            server_id: server.id.clone(),
             url: server.url.clone(),
             auth_header,
             session_id: None,
+            session_key: (user_id.to_string(), server.id.clone()),
+            initialized: false,
+            next_id: 1,
+            stdio: server
+                .is_stdio()
+                .then(|| (server.id.clone(), StdioConfig::for_server(server))),
         }
     }
 
     async fn rpc(&mut self, method: &str, params: Value) -> Result<Value, AppError> {
+        let started = Instant::now();
+        let result = self.request(method, params).await;
+        // JSON-RPC errors come from a working server
+        let failure = match &result {
+            Err(AppError::Http(message)) if message.starts_with("MCP error:") => None,
+            Err(e) => Some(e),
+            Ok(_) => None,
+        };
+        record_health(&self.server_id, started, failure);
+        result
+    }
+
+    async fn request(&mut self, method: &str, params: Value) -> Result<Value, AppError> {
+        if let Some((server_id, config)) = &self.stdio {
+            return mcp_stdio::request(server_id, config, method, params).await;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
         let body = json!({
             "jsonrpc": "2.0",
-            "id": 1,
+            "id": id,
             "method": method,
             "params": params,
         });
+        match self.post(&body).await? {
+            Posted::Answered(value) => Ok(value),
+            Posted::SessionExpired if method != "initialize" => {
+                debug!(
+                    "MCP session of server {} expired, reconnecting",
+                    self.server_id
+                );
+                self.forget_session();
+                Box::pin(self.initialize()).await?;
+                match self.post(&body).await? {
+                    Posted::Answered(value) => Ok(value),
+                    Posted::SessionExpired => Err(AppError::Http(
+                        "MCP server rejected the new session".to_string(),
+                    )),
+                }
+            }
+            Posted::SessionExpired => Err(AppError::Http(
+                "MCP server rejected the session".to_string(),
+            )),
+        }
+    }
 
+    fn http_request(&self) -> reqwest::RequestBuilder {
         let mut request = self
             .client
             .post(&self.url)
@@ -73,13 +244,22 @@ impl McpClient {
         if let Some(session) = &self.session_id {
             request = request.header("Mcp-Session-Id", session.as_str());
         }
+        request
+    }
 
-        let response = request
-            .json(&body)
+    /// POST a request and return the response with its id; notifications
+    /// in an SSE answer are handled along the way.
+    async fn post(&mut self, body: &Value) -> Result<Posted, AppError> {
+        let response = self
+            .http_request()
+            .json(body)
             .send()
             .await
             .map_err(|e| AppError::Http(format!("MCP request failed: {}", e)))?;
 
+        if response.status() == reqwest::StatusCode::NOT_FOUND && self.session_id.is_some() {
+            return Ok(Posted::SessionExpired);
+        }
         if let Some(session) = response
             .headers()
             .get("mcp-session-id")
@@ -108,36 +288,66 @@ impl McpClient {
             )));
         }
 
-        // Streamable HTTP may answer as an SSE stream — take the first
-        // data: payload carrying our JSON-RPC response.
-        let payload = if content_type.contains("text/event-stream") {
+        // Streamable HTTP may answer as an SSE stream: notifications, then
+        // the JSON-RPC response carrying our id
+        let messages: Vec<Value> = if content_type.contains("text/event-stream") {
             text.lines()
                 .filter_map(|line| line.trim().strip_prefix("data:"))
-                .map(str::trim)
-                .find(|data| data.contains("\"jsonrpc\""))
-                .map(|s| s.to_string())
-                .ok_or_else(|| {
-                    AppError::Internal("MCP SSE response contained no JSON-RPC payload".to_string())
-                })?
+                .filter_map(|data| serde_json::from_str(data.trim()).ok())
+                .collect()
         } else {
-            text
+            vec![serde_json::from_str(&text)
+                .map_err(|e| AppError::Internal(format!("Invalid MCP response JSON: {}", e)))?]
         };
 
-        let value: Value = serde_json::from_str(&payload)
-            .map_err(|e| AppError::Internal(format!("Invalid MCP response JSON: {}", e)))?;
+        let mut answer = None;
+        for message in messages {
+            if message.get("method").and_then(|m| m.as_str())
+                == Some("notifications/tools/list_changed")
+            {
+                mark_tools_changed(&self.server_id);
+            } else if message.get("id") == body.get("id") {
+                answer = Some(message);
+            }
+        }
+        let value = answer.ok_or_else(|| {
+            AppError::Internal("MCP response contained no JSON-RPC payload".to_string())
+        })?;
 
         if let Some(error) = value.get("error") {
-            let message = error
-                .get("message")
-                .and_then(|m| m.as_str())
-                .unwrap_or("unknown error");
-            return Err(AppError::Http(format!("MCP error: {}", message)));
+            return Err(rpc_error(error));
         }
+        Ok(Posted::Answered(
+            value.get("result").cloned().unwrap_or(Value::Null),
+        ))
+    }
 
-        Ok(value.get("result").cloned().unwrap_or(Value::Null))
+    fn forget_session(&mut self) {
+        self.session_id = None;
+        self.initialized = false;
+        SESSIONS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.session_key);
     }
 
+    /// Join the user's pooled session, else initialize a new one.
     async fn initialize(&mut self) -> Result<(), AppError> {
+        // STDIO processes are initialized when started
+        if self.stdio.is_some() || self.initialized {
+            return Ok(());
+        }
+        {
+            let mut sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+            sessions.retain(|_, session| session.last_used.elapsed() < SESSION_IDLE_TIMEOUT);
+            if let Some(session) = sessions.get_mut(&self.session_key) {
+                session.last_used = Instant::now();
+                self.session_id = session.id.clone();
+                self.initialized = true;
+                return Ok(());
+            }
+        }
+
         self.rpc(
             "initialize",
             json!({
@@ -147,20 +357,154 @@ impl McpClient {
             }),
         )
         .await?;
+        let notification = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+        if let Err(e) = self.http_request().json(&notification).send().await {
+            debug!("MCP initialized notification failed: {}", e);
+        }
+
+        self.initialized = true;
+        SESSIONS.lock().unwrap_or_else(|e| e.into_inner()).insert(
+            self.session_key.clone(),
+            PooledSession {
+                id: self.session_id.clone(),
+                last_used: Instant::now(),
+            },
+        );
         Ok(())
     }
 
+    /// Items of a paginated listing (`tools`, `resources`...).
+    async fn list(&mut self, method: &str, key: &str) -> Result<Vec<Value>, AppError> {
+        let mut items = Vec::new();
+        let mut cursor: Option<String> = None;
+        for _ in 0..MAX_LIST_PAGES {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.rpc(method, params).await?;
+            items.extend(
+                result
+                    .get(key)
+                    .and_then(|t| t.as_array())
+                    .cloned()
+                    .unwrap_or_default(),
+            );
+            cursor = result
+                .get("nextCursor")
+                .and_then(|c| c.as_str())
+                .map(str::to_string);
+            if cursor.is_none() {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
     /// List the server's tools (initialize + tools/list).
     pub async fn list_tools(&mut self) -> Result<Vec<Value>, AppError> {
         self.initialize().await?;
-        let result = self.rpc("tools/list", json!({})).await?;
+        self.list("tools/list", "tools").await
+    }
+
+    /// List the server's resources, resource templates and prompts. Servers
+    /// without these capabilities answer with an error: an empty list.
+    pub async fn list_resources_and_prompts(&mut self) -> (Vec<Value>, Vec<Value>, Vec<Value>) {
+        if let Err(e) = self.initialize().await {
+            debug!("MCP initialize failed: {}", e);
+            return Default::default();
+        }
+        let mut listings = Vec::new();
+        for (method, key) in [
+            ("resources/list", "resources"),
+            ("resources/templates/list", "resourceTemplates"),
+            ("prompts/list", "prompts"),
+        ] {
+            listings.push(self.list(method, key).await.unwrap_or_else(|e| {
+                debug!("MCP {} failed: {}", method, e);
+                Vec::new()
+            }));
+        }
+        let prompts = listings.pop().unwrap_or_default();
+        let templates = listings.pop().unwrap_or_default();
+        let resources = listings.pop().unwrap_or_default();
+        (resources, templates, prompts)
+    }
+
+    /// Read a resource; binary contents are left out.
+    pub async fn read_resource(
+        &mut self,
+        server_id: &str,
+        uri: &str,
+    ) -> Result<Vec<MessageMcpResource>, AppError> {
+        self.initialize().await?;
+        let result = self.rpc("resources/read", json!({ "uri": uri })).await?;
         Ok(result
-            .get("tools")
-            .and_then(|t| t.as_array())
-            .cloned()
+            .get("contents")
+            .and_then(|c| c.as_array())
+            .map(|contents| {
+                contents
+                    .iter()
+                    .filter_map(|content| {
+                        Some(MessageMcpResource {
+                            server_id: server_id.to_string(),
+                            uri: content
+                                .get("uri")
+                                .and_then(|u| u.as_str())
+                                .unwrap_or(uri)
+                                .to_string(),
+                            mime_type: content
+                                .get("mimeType")
+                                .and_then(|m| m.as_str())
+                                .map(str::to_string),
+                            text: content.get("text")?.as_str()?.to_string(),
+                        })
+                    })
+                    .collect()
+            })
             .unwrap_or_default())
     }
 
+    /// Render a prompt: the text of its messages (text content and
+    /// embedded text resources).
+    pub async fn get_prompt(
+        &mut self,
+        name: &str,
+        arguments: &serde_json::Map<String, Value>,
+    ) -> Result<String, AppError> {
+        self.initialize().await?;
+        let result = self
+            .rpc(
+                "prompts/get",
+                json!({ "name": name, "arguments": arguments }),
+            )
+            .await?;
+        let text = result
+            .get("messages")
+            .and_then(|m| m.as_array())
+            .map(|messages| {
+                messages
+                    .iter()
+                    .filter_map(|m| {
+                        let content = m.get("content")?;
+                        content
+                            .get("text")
+                            .or_else(|| content.get("resource")?.get("text"))?
+                            .as_str()
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            })
+            .unwrap_or_default();
+        if text.is_empty() {
+            return Err(AppError::Validation(format!(
+                "MCP prompt {} has no text content",
+                name
+            )));
+        }
+        Ok(text)
+    }
+
     /// Call a tool and return the result content as text.
     pub async fn call_tool(&mut self, name: &str, args: Value) -> Result<String, AppError> {
         self.initialize().await?;
@@ -184,6 +528,17 @@ impl McpClient {
     }
 }
 
+/// Stored/exposed shape of raw MCP descriptors (resources, resource
+/// templates, prompts): `T` keeps the known fields, invalid items are left
+/// out.
+pub fn descriptors_to_stored_json<T: DeserializeOwned + Serialize>(items: &[Value]) -> String {
+    let mapped: Vec<T> = items
+        .iter()
+        .filter_map(|item| serde_json::from_value(item.clone()).ok())
+        .collect();
+    serde_json::to_string(&mapped).unwrap_or_else(|_| "[]".to_string())
+}
+
 /// Map raw MCP tool descriptors to the stored/exposed shape
 /// (name/description/inputSchema/outputSchema as JSON strings).
 pub fn tools_to_stored_json(tools: &[Value]) -> String {
@@ -200,3 +555,224 @@ pub fn tools_to_stored_json(tools: &[Value]) -> String {
         .collect();
     serde_json::to_string(&mapped).unwrap_or_else(|_| "[]".to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{GqlMcpPrompt, GqlMcpResource};
+    use crate::utils::test_server::{StubResponse, StubServer};
+
+    fn server(id: &str, url: &str) -> McpServer {
+        let now = chrono::Utc::now().naive_utc();
+        McpServer {
+            id: id.to_string(),
+            name: "fake".to_string(),
+            url: url.to_string(),
+            description: None,
+            transport_type: crate::models::MCP_TRANSPORT_STREAMABLE_HTTP.to_string(),
+            auth_type: "NONE".to_string(),
+            auth_config: None,
+            tools: None,
+            is_active: true,
+            user_id: None,
+            created_at: now,
+            updated_at: now,
+            command: None,
+            args: None,
+            env: None,
+            resources: None,
+            resource_templates: None,
+            prompts: None,
+            requires_approval: false,
+            approval_tools: None,
+            tool_timeout_ms: None,
+            tool_timeouts: None,
+        }
+    }
+
+    fn answer(id: u64, result: Value) -> StubResponse {
+        StubResponse::json(json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+    }
+
+    fn accepted() -> StubResponse {
+        StubResponse::text(202, "application/json", "")
+    }
+
+    fn method(request: &crate::utils::test_server::StubRequest) -> String {
+        request.json()["method"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn reuses_the_users_session() {
+        let stub = StubServer::start(vec![
+            answer(1, json!({})).with_header("Mcp-Session-Id", "session-1"),
+            accepted(),
+            answer(2, json!({ "tools": [{ "name": "search" }] })),
+            answer(1, json!({ "tools": [{ "name": "search" }] })),
+        ])
+        .await;
+        let server = server("mcp-test-pool", &stub.base_url);
+
+        let first = McpClient::for_server(&server, "user-1", None)
+            .list_tools()
+            .await
+            .unwrap();
+        let second = McpClient::for_server(&server, "user-1", None)
+            .list_tools()
+            .await
+            .unwrap();
+        assert_eq!(first, second);
+
+        let requests = stub.requests();
+        let methods: Vec<String> = requests.iter().map(method).collect();
+        assert_eq!(
+            methods,
+            vec![
+                "initialize",
+                "notifications/initialized",
+                "tools/list",
+                "tools/list"
+            ]
+        );
+        assert_eq!(requests[3].header("Mcp-Session-Id"), Some("session-1"));
+        assert_eq!(
+            health(&server.id).map(|h| h.status),
+            Some(MCP_HEALTH_HEALTHY.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn reconnects_when_the_session_expired() {
+        let stub = StubServer::start(vec![
+            answer(1, json!({})).with_header("Mcp-Session-Id", "session-1"),
+            accepted(),
+            answer(2, json!({ "tools": [] })),
+            StubResponse::text(404, "application/json", ""),
+            answer(2, json!({})).with_header("Mcp-Session-Id", "session-2"),
+            accepted(),
+            answer(1, json!({ "tools": [{ "name": "search" }] })),
+        ])
+        .await;
+        let server = server("mcp-test-expiry", &stub.base_url);
+
+        McpClient::for_server(&server, "user-1", None)
+            .list_tools()
+            .await
+            .unwrap();
+        let tools = McpClient::for_server(&server, "user-1", None)
+            .list_tools()
+            .await
+            .unwrap();
+        assert_eq!(tools[0]["name"], "search");
+
+        let requests = stub.requests();
+        assert_eq!(method(&requests[4]), "initialize");
+        assert_eq!(requests[6].header("Mcp-Session-Id"), Some("session-2"));
+    }
+
+    #[tokio::test]
+    async fn list_changed_notifications_mark_the_tools_stale() {
+        let stub = StubServer::start(vec![
+            answer(1, json!({})),
+            accepted(),
+            StubResponse::sse(concat!(
+                "event: message\n",
+                "data: {\"jsonrpc\":\"2.0\",\"method\":\"notifications/tools/list_changed\"}\n\n",
+                "event: message\n",
+                "data: {\"jsonrpc\":\"2.0\",\"id\":2,\"result\":{\"content\":[{\"type\":\"text\",\"text\":\"done\"}]}}\n\n",
+            )),
+        ])
+        .await;
+        let server = server("mcp-test-list-changed", &stub.base_url);
+
+        let result = McpClient::for_server(&server, "user-1", None)
+            .call_tool("search", json!({}))
+            .await
+            .unwrap();
+        assert_eq!(result, "done");
+        assert!(take_tools_changed(&server.id));
+        assert!(!take_tools_changed(&server.id));
+    }
+
+    #[tokio::test]
+    async fn failed_requests_make_the_server_unhealthy() {
+        let stub =
+            StubServer::start(vec![StubResponse::text(502, "text/plain", "bad gateway")]).await;
+        let server = server("mcp-test-health", &stub.base_url);
+
+        assert!(McpClient::for_server(&server, "user-1", None)
+            .list_tools()
+            .await
+            .is_err());
+        let health = health(&server.id).unwrap();
+        assert_eq!(health.status, MCP_HEALTH_UNHEALTHY);
+        assert_eq!(health.consecutive_failures, 1);
+        assert!(health.last_error.unwrap().contains("502"));
+    }
+
+    #[test]
+    fn descriptors_keep_the_known_fields() {
+        let resources = [
+            json!({ "uri": "file:///notes.md", "name": "notes", "mimeType": "text/markdown", "size": 12 }),
+            json!("not a resource"),
+        ];
+        let stored: Vec<GqlMcpResource> =
+            serde_json::from_str(&descriptors_to_stored_json::<GqlMcpResource>(&resources))
+                .unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].uri, "file:///notes.md");
+        assert_eq!(stored[0].mime_type.as_deref(), Some("text/markdown"));
+
+        let prompts = [json!({
+            "name": "review",
+            "arguments": [{ "name": "file", "required": true }],
+        })];
+        let stored: Vec<GqlMcpPrompt> =
+            serde_json::from_str(&descriptors_to_stored_json::<GqlMcpPrompt>(&prompts)).unwrap();
+        let arguments = stored[0].arguments.as_ref().unwrap();
+        assert_eq!(arguments[0].name, "file");
+        assert_eq!(arguments[0].required, Some(true));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reads_resources_and_renders_prompts_over_stdio() {
+        // answers resources/read and prompts/get with canned results
+        const FAKE_SERVER: &str = r#"
+while IFS= read -r line; do
+  id=$(printf '%s' "$line" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+  [ -z "$id" ] && continue
+  case "$line" in
+    *'"method":"resources/read"'*) printf '{"jsonrpc":"2.0","id":%s,"result":{"contents":[{"uri":"file:///a.txt","mimeType":"text/plain","text":"hello"},{"uri":"file:///a.png","blob":"AAAA"}]}}\n' "$id" ;;
+    *'"method":"prompts/get"'*) printf '{"jsonrpc":"2.0","id":%s,"result":{"messages":[{"role":"user","content":{"type":"text","text":"Review"}},{"role":"user","content":{"type":"resource","resource":{"uri":"file:///a.txt","text":"hello"}}}]}}\n' "$id" ;;
+    *) printf '{"jsonrpc":"2.0","id":%s,"result":{}}\n' "$id" ;;
+  esac
+done
+"#;
+        let server = McpServer {
+            transport_type: crate::models::MCP_TRANSPORT_STDIO.to_string(),
+            command: Some("/bin/sh".to_string()),
+            args: serde_json::to_string(&["-c", FAKE_SERVER]).ok(),
+            ..server("mcp-test-resources", "")
+        };
+        let mut client = McpClient::for_server(&server, "user", None);
+
+        let contents = client
+            .read_resource(&server.id, "file:///a.txt")
+            .await
+            .unwrap();
+        assert_eq!(contents.len(), 1);
+        assert_eq!(contents[0].text, "hello");
+        assert_eq!(contents[0].mime_type.as_deref(), Some("text/plain"));
+
+        let prompt = client
+            .get_prompt("review", &serde_json::Map::new())
+            .await
+            .unwrap();
+        assert_eq!(prompt, "Review\n\nhello");
+        mcp_stdio::shutdown(&server.id);
+    }
+}
@@ -0,0 +1,904 @@
+//! Server-side OAuth for MCP servers (`auth_type` OAUTH2). Endpoints come
+//! from the server's auth config, else are discovered: protected-resource
+//! metadata (RFC 9728) names the authorization server, whose metadata
+//! (RFC 8414 / OpenID) gives the endpoints; a server without a client id
+//! gets one by dynamic client registration (RFC 7591). Discovered endpoints
+//! and registered clients are saved back to the auth config when the user
+//! may change the server; otherwise (a shared server for a non-admin) they
+//! are kept with the user's authorization.
+//!
+//! The authorization code grant uses PKCE: `start` returns the URL to open,
+//! `complete` handles the callback. The client credentials grant needs no
+//! user interaction. Tokens are stored per user and server and refreshed
+//! when expired (a rotated refresh token replaces the old one), so chats and
+//! background tool calls authenticate without the web client's `mcpTokens`.
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use oauth2::basic::{BasicClient, BasicErrorResponseType, BasicTokenResponse};
+use oauth2::reqwest::async_http_client;
+use oauth2::{
+    AuthType, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, RefreshToken, RequestTokenError, Scope, TokenResponse, TokenUrl,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::database::DbConnection;
+use crate::models::{GqlMcpAuthConfig, McpOAuthState, McpOAuthToken, McpServer};
+use crate::schema::{mcp_oauth_states, mcp_oauth_tokens, mcp_servers};
+use crate::utils::errors::AppError;
+
+pub const GRANT_AUTHORIZATION_CODE: &str = "authorization_code";
+pub const GRANT_CLIENT_CREDENTIALS: &str = "client_credentials";
+
+/// Pending authorizations expire after this long
+const STATE_TTL_MINUTES: i64 = 10;
+/// Tokens are refreshed this long before they expire
+const EXPIRY_MARGIN_SECONDS: i64 = 60;
+
+/// Endpoints found by discovery
+#[derive(Debug, Default, PartialEq)]
+pub struct Discovery {
+    pub authorization_url: Option<String>,
+    pub token_url: Option<String>,
+    pub registration_url: Option<String>,
+    pub scope: Option<String>,
+    /// Set when the server publishes protected-resource metadata
+    pub resource: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResourceMetadata {
+    resource: Option<String>,
+    #[serde(default)]
+    authorization_servers: Vec<String>,
+    #[serde(default)]
+    scopes_supported: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthServerMetadata {
+    authorization_endpoint: Option<String>,
+    token_endpoint: Option<String>,
+    registration_endpoint: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RegisteredClient {
+    client_id: String,
+    client_secret: Option<String>,
+}
+
+/// How `start` went
+pub enum OAuthStart {
+    /// Open this URL (authorization code grant)
+    Authorize(String),
+    /// A token was obtained right away (client credentials grant)
+    Connected,
+}
+
+/// `{callback_url_base}/auth/mcp/callback`
+pub fn redirect_uri(config: &AppConfig) -> Result<String, AppError> {
+    let base = config
+        .callback_url_base
+        .as_ref()
+        .ok_or_else(|| AppError::Validation("Callback URL base not configured".to_string()))?;
+    Ok(format!("{}/auth/mcp/callback", base.trim_end_matches('/')))
+}
+
+fn parse_auth_config(json: Option<&str>) -> Option<GqlMcpAuthConfig> {
+    json.and_then(|s| serde_json::from_str(s).ok())
+}
+
+fn auth_config(server: &McpServer) -> GqlMcpAuthConfig {
+    parse_auth_config(server.auth_config.as_deref()).unwrap_or_default()
+}
+
+/// The auth config of `user_id` for a server: the one registered for the
+/// user alone, else the server's.
+fn user_auth_config(server: &McpServer, user_config: Option<&str>) -> GqlMcpAuthConfig {
+    parse_auth_config(user_config).unwrap_or_else(|| auth_config(server))
+}
+
+/// Whether the user may save to the server's auth config: their own
+/// server, or a shared one for admins.
+fn can_change(server: &McpServer, user_id: &str, is_admin: bool) -> bool {
+    match server.user_id.as_deref() {
+        Some(owner) => owner == user_id,
+        None => is_admin,
+    }
+}
+
+fn grant_type(auth: &GqlMcpAuthConfig) -> &str {
+    auth.grant_type
+        .as_deref()
+        .unwrap_or(GRANT_AUTHORIZATION_CODE)
+}
+
+/// Well-known metadata URLs for `url`, path-specific first (RFC 8414 §3,
+/// RFC 9728 §3).
+fn well_known_urls(url: &str, suffixes: &[&str]) -> Vec<String> {
+    let Ok(parsed) = url::Url::parse(url) else {
+        return Vec::new();
+    };
+    let origin = parsed.origin().ascii_serialization();
+    let path = parsed.path().trim_end_matches('/');
+    let mut urls = Vec::new();
+    for suffix in suffixes {
+        if !path.is_empty() {
+            urls.push(format!("{}/.well-known/{}{}", origin, suffix, path));
+        }
+        urls.push(format!("{}/.well-known/{}", origin, suffix));
+    }
+    // OpenID issuers with a path also publish under the path
+    if !path.is_empty() && suffixes.contains(&"openid-configuration") {
+        urls.push(format!(
+            "{}{}/.well-known/openid-configuration",
+            origin, path
+        ));
+    }
+    urls
+}
+
+async fn fetch_metadata<T: serde::de::DeserializeOwned>(
+    http: &reqwest::Client,
+    urls: &[String],
+) -> Option<T> {
+    for url in urls {
+        let response = match http
+            .get(url)
+            .header("Accept", "application/json")
+            .header("MCP-Protocol-Version", super::mcp::MCP_PROTOCOL_VERSION)
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                debug!("OAuth metadata {}: {}", url, response.status());
+                continue;
+            }
+            Err(e) => {
+                debug!("OAuth metadata {} failed: {}", url, e);
+                continue;
+            }
+        };
+        match response.json::<T>().await {
+            Ok(metadata) => return Some(metadata),
+            Err(e) => debug!("Invalid OAuth metadata at {}: {}", url, e),
+        }
+    }
+    None
+}
+
+/// Discover the authorization server of an MCP server URL. Without
+/// metadata the server's origin is the authorization server, with the
+/// default `/authorize`, `/token` and `/register` endpoints.
+pub async fn discover(server_url: &str) -> Result<Discovery, AppError> {
+    let http = reqwest::Client::new();
+    let resource: Option<ResourceMetadata> = fetch_metadata(
+        &http,
+        &well_known_urls(server_url, &["oauth-protected-resource"]),
+    )
+    .await;
+
+    let origin = url::Url::parse(server_url)
+        .map_err(|e| AppError::Validation(format!("Invalid MCP server URL: {}", e)))?
+        .origin()
+        .ascii_serialization();
+    let issuer = resource
+        .as_ref()
+        .and_then(|r| r.authorization_servers.first().cloned())
+        .unwrap_or_else(|| origin.clone());
+
+    let metadata: Option<AuthServerMetadata> = fetch_metadata(
+        &http,
+        &well_known_urls(
+            &issuer,
+            &["oauth-authorization-server", "openid-configuration"],
+        ),
+    )
+    .await;
+
+    let mut discovery = Discovery {
+        scope: resource
+            .as_ref()
+            .filter(|r| !r.scopes_supported.is_empty())
+            .map(|r| r.scopes_supported.join(" ")),
+        resource: resource
+            .as_ref()
+            .map(|r| r.resource.clone().unwrap_or_else(|| server_url.to_string())),
+        ..Default::default()
+    };
+    match metadata {
+        Some(metadata) => {
+            discovery.authorization_url = metadata.authorization_endpoint;
+            discovery.token_url = metadata.token_endpoint;
+            discovery.registration_url = metadata.registration_endpoint;
+        }
+        None => {
+            let base = issuer.trim_end_matches('/');
+            discovery.authorization_url = Some(format!("{}/authorize", base));
+            discovery.token_url = Some(format!("{}/token", base));
+            discovery.registration_url = Some(format!("{}/register", base));
+        }
+    }
+    Ok(discovery)
+}
+
+/// Register a client for kate-chat (RFC 7591).
+async fn register_client(
+    registration_url: &str,
+    redirect_uri: &str,
+    grant_type: &str,
+) -> Result<RegisteredClient, AppError> {
+    let grant_types = if grant_type == GRANT_CLIENT_CREDENTIALS {
+        json!([GRANT_CLIENT_CREDENTIALS])
+    } else {
+        json!([GRANT_AUTHORIZATION_CODE, "refresh_token"])
+    };
+    let response = reqwest::Client::new()
+        .post(registration_url)
+        .json(&json!({
+            "client_name": "KateChat",
+            "redirect_uris": [redirect_uri],
+            "grant_types": grant_types,
+            "response_types": ["code"],
+            "token_endpoint_auth_method": if grant_type == GRANT_CLIENT_CREDENTIALS {
+                "client_secret_post"
+            } else {
+                "none"
+            },
+        }))
+        .send()
+        .await
+        .map_err(|e| AppError::Http(format!("Client registration failed: {}", e)))?;
+    let status = response.status();
+    if !status.is_success() {
+        let text = response.text().await.unwrap_or_default();
+        return Err(AppError::Http(format!(
+            "Client registration failed ({}): {}",
+            status,
+            text.chars().take(300).collect::<String>()
+        )));
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| AppError::Http(format!("Invalid client registration response: {}", e)))
+}
+
+/// The auth config to authorize with, its endpoints and client filled in
+/// by discovering and registering what is missing. Saved to the server
+/// when `shared` is false; otherwise the second value is the config
+/// (JSON) to keep for the user, the server left unchanged.
+async fn prepared_auth_config(
+    conn: &mut DbConnection,
+    server: &McpServer,
+    mut auth: GqlMcpAuthConfig,
+    redirect_uri: &str,
+    shared: bool,
+) -> Result<(GqlMcpAuthConfig, Option<String>), AppError> {
+    let grant = grant_type(&auth).to_string();
+    let needs_authorization_url =
+        grant == GRANT_AUTHORIZATION_CODE && auth.authorization_url.is_none();
+    if auth.token_url.is_some() && auth.client_id.is_some() && !needs_authorization_url {
+        // the server's own config needs no copy
+        let user_config = (shared && auth != auth_config(server))
+            .then(|| serde_json::to_string(&auth).unwrap_or_default());
+        return Ok((auth, user_config));
+    }
+
+    let discovery = discover(&server.url).await?;
+    auth.authorization_url = auth.authorization_url.or(discovery.authorization_url);
+    auth.token_url = auth.token_url.or(discovery.token_url);
+    auth.scope = auth.scope.or(discovery.scope);
+    auth.resource = auth.resource.or(discovery.resource);
+    if auth.client_id.is_none() {
+        let registration_url = discovery.registration_url.ok_or_else(|| {
+            AppError::Validation(
+                "MCP server has no client id and does not support client registration".to_string(),
+            )
+        })?;
+        let client = register_client(&registration_url, redirect_uri, &grant).await?;
+        info!(
+            "Registered OAuth client {} for MCP server {}",
+            client.client_id, server.id
+        );
+        auth.client_id = Some(client.client_id);
+        auth.client_secret = client.client_secret;
+    }
+
+    let json = serde_json::to_string(&auth).unwrap_or_default();
+    if shared {
+        return Ok((auth, Some(json)));
+    }
+    diesel::update(mcp_servers::table.filter(mcp_servers::id.eq(&server.id)))
+        .set(mcp_servers::auth_config.eq(json))
+        .execute(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    Ok((auth, None))
+}
+
+fn oauth_client(
+    auth: &GqlMcpAuthConfig,
+    redirect_uri: Option<&str>,
+) -> Result<BasicClient, AppError> {
+    let token_url = auth
+        .token_url
+        .clone()
+        .ok_or_else(|| AppError::Validation("MCP server OAuth token URL missing".to_string()))?;
+    let client_id = auth
+        .client_id
+        .clone()
+        .ok_or_else(|| AppError::Validation("MCP server OAuth client id missing".to_string()))?;
+    // the client credentials grant has no authorization endpoint
+    let authorization_url = auth.authorization_url.clone().unwrap_or(token_url.clone());
+
+    let mut client = BasicClient::new(
+        ClientId::new(client_id),
+        auth.client_secret.clone().map(ClientSecret::new),
+        AuthUrl::new(authorization_url)
+            .map_err(|e| AppError::Validation(format!("Invalid authorization URL: {}", e)))?,
+        Some(
+            TokenUrl::new(token_url)
+                .map_err(|e| AppError::Validation(format!("Invalid token URL: {}", e)))?,
+        ),
+    )
+    .set_auth_type(AuthType::RequestBody);
+    if let Some(redirect_uri) = redirect_uri {
+        client = client.set_redirect_uri(
+            RedirectUrl::new(redirect_uri.to_string())
+                .map_err(|e| AppError::Internal(format!("Invalid redirect URL: {}", e)))?,
+        );
+    }
+    Ok(client)
+}
+
+fn scopes(auth: &GqlMcpAuthConfig) -> Vec<Scope> {
+    auth.scope
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|s| Scope::new(s.to_string()))
+        .collect()
+}
+
+/// Token endpoint failure; `Rejected` is an invalid or revoked grant.
+enum TokenFailure {
+    Rejected(String),
+    Failed(AppError),
+}
+
+fn token_failure<RE: std::error::Error + 'static>(
+    e: RequestTokenError<RE, oauth2::StandardErrorResponse<BasicErrorResponseType>>,
+) -> TokenFailure {
+    match &e {
+        RequestTokenError::ServerResponse(response)
+            if matches!(
+                response.error(),
+                BasicErrorResponseType::InvalidGrant | BasicErrorResponseType::UnauthorizedClient
+            ) =>
+        {
+            TokenFailure::Rejected(e.to_string())
+        }
+        _ => TokenFailure::Failed(AppError::Http(format!("OAuth token request failed: {}", e))),
+    }
+}
+
+/// Store the tokens of a user for a server, with the auth config
+/// registered for the user alone; a response without a refresh token keeps
+/// the stored one.
+fn save_token(
+    conn: &mut DbConnection,
+    user_id: &str,
+    server_id: &str,
+    response: &BasicTokenResponse,
+    auth_config: Option<String>,
+) -> Result<McpOAuthToken, AppError> {
+    let now = Utc::now().naive_utc();
+    let existing: Option<McpOAuthToken> = mcp_oauth_tokens::table
+        .filter(mcp_oauth_tokens::user_id.eq(user_id))
+        .filter(mcp_oauth_tokens::server_id.eq(server_id))
+        .first(conn)
+        .optional()
+        .map_err(|e| AppError::Database(e.to_string()))?;
+
+    let token = McpOAuthToken {
+        id: existing
+            .as_ref()
+            .map(|t| t.id.clone())
+            .unwrap_or_else(|| Uuid::new_v4().to_string()),
+        user_id: user_id.to_string(),
+        server_id: server_id.to_string(),
+        access_token: response.access_token().secret().clone(),
+        refresh_token: response
+            .refresh_token()
+            .map(|t| t.secret().clone())
+            .or_else(|| existing.as_ref().and_then(|t| t.refresh_token.clone())),
+        scope: response.scopes().map(|scopes| {
+            scopes
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        }),
+        expires_at: response
+            .expires_in()
+            .and_then(|d| Duration::from_std(d).ok())
+            .map(|d| now + d),
+        created_at: existing.as_ref().map(|t| t.created_at).unwrap_or(now),
+        updated_at: now,
+        auth_config,
+    };
+
+    if existing.is_some() {
+        diesel::update(mcp_oauth_tokens::table.filter(mcp_oauth_tokens::id.eq(&token.id)))
+            .set((
+                mcp_oauth_tokens::access_token.eq(&token.access_token),
+                mcp_oauth_tokens::refresh_token.eq(&token.refresh_token),
+                mcp_oauth_tokens::scope.eq(&token.scope),
+                mcp_oauth_tokens::expires_at.eq(token.expires_at),
+                mcp_oauth_tokens::updated_at.eq(now),
+                mcp_oauth_tokens::auth_config.eq(&token.auth_config),
+            ))
+            .execute(conn)
+    } else {
+        diesel::insert_into(mcp_oauth_tokens::table)
+            .values(&token)
+            .execute(conn)
+    }
+    .map_err(|e| AppError::Database(e.to_string()))?;
+    Ok(token)
+}
+
+async fn client_credentials_token(
+    auth: &GqlMcpAuthConfig,
+) -> Result<BasicTokenResponse, TokenFailure> {
+    let client = oauth_client(auth, None).map_err(TokenFailure::Failed)?;
+    let mut request = client
+        .exchange_client_credentials()
+        .add_scopes(scopes(auth));
+    if let Some(resource) = &auth.resource {
+        request = request.add_extra_param("resource", resource.clone());
+    }
+    request
+        .request_async(async_http_client)
+        .await
+        .map_err(token_failure)
+}
+
+/// Start authorizing the user for a server.
+pub async fn start(
+    conn: &mut DbConnection,
+    config: &AppConfig,
+    user_id: &str,
+    is_admin: bool,
+    server: &McpServer,
+) -> Result<OAuthStart, AppError> {
+    if server.auth_type != "OAUTH2" {
+        return Err(AppError::Validation(
+            "MCP server does not use OAuth".to_string(),
+        ));
+    }
+    let redirect_uri = redirect_uri(config)?;
+    let shared = !can_change(server, user_id, is_admin);
+    let auth = if shared {
+        // a client registered for the user before is reused
+        let stored: Option<String> = mcp_oauth_tokens::table
+            .filter(mcp_oauth_tokens::user_id.eq(user_id))
+            .filter(mcp_oauth_tokens::server_id.eq(&server.id))
+            .select(mcp_oauth_tokens::auth_config)
+            .first(conn)
+            .optional()
+            .map_err(|e| AppError::Database(e.to_string()))?
+            .flatten();
+        user_auth_config(server, stored.as_deref())
+    } else {
+        auth_config(server)
+    };
+    let (auth, user_config) =
+        prepared_auth_config(conn, server, auth, &redirect_uri, shared).await?;
+
+    if grant_type(&auth) == GRANT_CLIENT_CREDENTIALS {
+        let response = client_credentials_token(&auth)
+            .await
+            .map_err(|failure| match failure {
+                TokenFailure::Rejected(reason) => AppError::Auth(reason),
+                TokenFailure::Failed(e) => e,
+            })?;
+        save_token(conn, user_id, &server.id, &response, user_config)?;
+        return Ok(OAuthStart::Connected);
+    }
+
+    let cutoff = Utc::now().naive_utc() - Duration::minutes(STATE_TTL_MINUTES);
+    diesel::delete(mcp_oauth_states::table.filter(mcp_oauth_states::created_at.lt(cutoff)))
+        .execute(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+
+    let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+    let state = McpOAuthState {
+        id: CsrfToken::new_random().secret().clone(),
+        user_id: user_id.to_string(),
+        server_id: server.id.clone(),
+        code_verifier: verifier.secret().clone(),
+        created_at: Utc::now().naive_utc(),
+        auth_config: user_config,
+    };
+    diesel::insert_into(mcp_oauth_states::table)
+        .values(&state)
+        .execute(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+
+    let client = oauth_client(&auth, Some(&redirect_uri))?;
+    let state_id = state.id.clone();
+    let mut request = client
+        .authorize_url(move || CsrfToken::new(state_id))
+        .add_scopes(scopes(&auth))
+        .set_pkce_challenge(challenge);
+    if let Some(resource) = &auth.resource {
+        request = request.add_extra_param("resource", resource.clone());
+    }
+    let (url, _) = request.url();
+    Ok(OAuthStart::Authorize(url.to_string()))
+}
+
+/// Handle the authorization callback: exchange the code and store the
+/// tokens. Returns the server.
+pub async fn complete(
+    conn: &mut DbConnection,
+    config: &AppConfig,
+    state: &str,
+    code: &str,
+) -> Result<McpServer, AppError> {
+    let pending: McpOAuthState = mcp_oauth_states::table
+        .filter(mcp_oauth_states::id.eq(state))
+        .first(conn)
+        .map_err(|_| AppError::Auth("Unknown or expired authorization state".to_string()))?;
+    // single use
+    diesel::delete(mcp_oauth_states::table.filter(mcp_oauth_states::id.eq(&pending.id)))
+        .execute(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    if pending.created_at < Utc::now().naive_utc() - Duration::minutes(STATE_TTL_MINUTES) {
+        return Err(AppError::Auth("Authorization request expired".to_string()));
+    }
+
+    let server: McpServer = mcp_servers::table
+        .filter(mcp_servers::id.eq(&pending.server_id))
+        .first(conn)
+        .map_err(|_| AppError::NotFound("MCP server not found".to_string()))?;
+    let auth = user_auth_config(&server, pending.auth_config.as_deref());
+    let client = oauth_client(&auth, Some(&redirect_uri(config)?))?;
+    let mut request = client
+        .exchange_code(AuthorizationCode::new(code.to_string()))
+        .set_pkce_verifier(PkceCodeVerifier::new(pending.code_verifier));
+    if let Some(resource) = &auth.resource {
+        request = request.add_extra_param("resource", resource.clone());
+    }
+    let response = request
+        .request_async(async_http_client)
+        .await
+        .map_err(|e| AppError::Auth(format!("Failed to exchange code for token: {}", e)))?;
+    save_token(
+        conn,
+        &pending.user_id,
+        &server.id,
+        &response,
+        pending.auth_config,
+    )?;
+    info!(
+        "MCP server {} authorized for user {}",
+        server.id, pending.user_id
+    );
+    Ok(server)
+}
+
+fn is_fresh(expires_at: Option<NaiveDateTime>) -> bool {
+    expires_at
+        .is_none_or(|at| at > Utc::now().naive_utc() + Duration::seconds(EXPIRY_MARGIN_SECONDS))
+}
+
+/// The stored access token of a user for an OAUTH2 server, refreshed (or
+/// for client credentials, obtained) when needed. `None` when the user has
+/// to authorize.
+pub async fn access_token(
+    conn: &mut DbConnection,
+    user_id: &str,
+    server: &McpServer,
+) -> Result<Option<String>, AppError> {
+    if server.auth_type != "OAUTH2" {
+        return Ok(None);
+    }
+    let stored: Option<McpOAuthToken> = mcp_oauth_tokens::table
+        .filter(mcp_oauth_tokens::user_id.eq(user_id))
+        .filter(mcp_oauth_tokens::server_id.eq(&server.id))
+        .first(conn)
+        .optional()
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    if let Some(token) = &stored {
+        if is_fresh(token.expires_at) {
+            return Ok(Some(token.access_token.clone()));
+        }
+    }
+
+    let user_config = stored.as_ref().and_then(|t| t.auth_config.clone());
+    let auth = user_auth_config(server, user_config.as_deref());
+    let result = match stored.as_ref().and_then(|t| t.refresh_token.clone()) {
+        Some(refresh_token) => {
+            let client = oauth_client(&auth, None)?;
+            let refresh_token = RefreshToken::new(refresh_token);
+            let mut request = client.exchange_refresh_token(&refresh_token);
+            if let Some(resource) = &auth.resource {
+                request = request.add_extra_param("resource", resource.clone());
+            }
+            request
+                .request_async(async_http_client)
+                .await
+                .map_err(token_failure)
+        }
+        None if grant_type(&auth) == GRANT_CLIENT_CREDENTIALS => {
+            client_credentials_token(&auth).await
+        }
+        None => return Ok(None),
+    };
+
+    match result {
+        Ok(response) => Ok(Some(
+            save_token(conn, user_id, &server.id, &response, user_config)?.access_token,
+        )),
+        Err(TokenFailure::Rejected(reason)) => {
+            // dead refresh token: the user has to authorize again
+            warn!(
+                "MCP OAuth token of user {} for server {} rejected: {}",
+                user_id, server.id, reason
+            );
+            disconnect(conn, user_id, &server.id)?;
+            Ok(None)
+        }
+        Err(TokenFailure::Failed(e)) => Err(e),
+    }
+}
+
+/// The token to call `server` with: the client's, else the stored one.
+pub async fn resolve_token(
+    conn: &mut DbConnection,
+    user_id: &str,
+    server: &McpServer,
+    client_token: Option<String>,
+) -> Option<String> {
+    if client_token.is_some() {
+        return client_token;
+    }
+    access_token(conn, user_id, server)
+        .await
+        .unwrap_or_else(|e| {
+            warn!(
+                "MCP OAuth token for server {} unavailable: {}",
+                server.id, e
+            );
+            None
+        })
+}
+
+/// Forget the user's tokens for a server.
+pub fn disconnect(
+    conn: &mut DbConnection,
+    user_id: &str,
+    server_id: &str,
+) -> Result<bool, AppError> {
+    let deleted = diesel::delete(
+        mcp_oauth_tokens::table
+            .filter(mcp_oauth_tokens::user_id.eq(user_id))
+            .filter(mcp_oauth_tokens::server_id.eq(server_id)),
+    )
+    .execute(conn)
+    .map_err(|e| AppError::Database(e.to_string()))?;
+    Ok(deleted > 0)
+}
+
+/// The servers the user has authorized.
+pub fn connections(conn: &mut DbConnection, user_id: &str) -> Result<Vec<McpOAuthToken>, AppError> {
+    mcp_oauth_tokens::table
+        .filter(mcp_oauth_tokens::user_id.eq(user_id))
+        .load(conn)
+        .map_err(|e| AppError::Database(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{StubResponse, StubServer};
+
+    #[test]
+    fn well_known_urls_try_the_path_first() {
+        assert_eq!(
+            well_known_urls(
+                "https://mcp.example.com/github/mcp",
+                &["oauth-protected-resource"]
+            ),
+            vec![
+                "https://mcp.example.com/.well-known/oauth-protected-resource/github/mcp",
+                "https://mcp.example.com/.well-known/oauth-protected-resource",
+            ]
+        );
+        assert_eq!(
+            well_known_urls(
+                "https://auth.example.com",
+                &["oauth-authorization-server", "openid-configuration"]
+            ),
+            vec![
+                "https://auth.example.com/.well-known/oauth-authorization-server",
+                "https://auth.example.com/.well-known/openid-configuration",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn discovers_the_authorization_server_of_a_protected_resource() {
+        let auth_server = StubServer::start(vec![StubResponse::json(json!({
+            "issuer": "https://auth.example.com",
+            "authorization_endpoint": "https://auth.example.com/oauth/authorize",
+            "token_endpoint": "https://auth.example.com/oauth/token",
+            "registration_endpoint": "https://auth.example.com/oauth/register",
+        }))])
+        .await;
+        let resource = StubServer::start(vec![StubResponse::json(json!({
+            "resource": "https://mcp.example.com/mcp",
+            "authorization_servers": [auth_server.base_url],
+            "scopes_supported": ["repo", "read:user"],
+        }))])
+        .await;
+
+        let discovery = discover(&format!("{}/mcp", resource.base_url))
+            .await
+            .unwrap();
+        assert_eq!(
+            discovery,
+            Discovery {
+                authorization_url: Some("https://auth.example.com/oauth/authorize".to_string()),
+                token_url: Some("https://auth.example.com/oauth/token".to_string()),
+                registration_url: Some("https://auth.example.com/oauth/register".to_string()),
+                scope: Some("repo read:user".to_string()),
+                resource: Some("https://mcp.example.com/mcp".to_string()),
+            }
+        );
+        assert_eq!(
+            resource.requests()[0].request_line,
+            "GET /.well-known/oauth-protected-resource/mcp HTTP/1.1"
+        );
+        assert_eq!(
+            auth_server.requests()[0].request_line,
+            "GET /.well-known/oauth-authorization-server HTTP/1.1"
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_default_endpoints_without_metadata() {
+        let server = StubServer::start(vec![StubResponse::text(404, "text/plain", "")]).await;
+        let discovery = discover(&format!("{}/mcp", server.base_url)).await.unwrap();
+        assert_eq!(
+            discovery.token_url,
+            Some(format!("{}/token", server.base_url))
+        );
+        assert_eq!(discovery.resource, None);
+    }
+
+    #[tokio::test]
+    async fn registers_a_public_client() {
+        let server = StubServer::start(vec![StubResponse::json(json!({
+            "client_id": "registered-id",
+        }))])
+        .await;
+        let client = register_client(
+            &format!("{}/register", server.base_url),
+            "https://api.example.com/auth/mcp/callback",
+            GRANT_AUTHORIZATION_CODE,
+        )
+        .await
+        .unwrap();
+        assert_eq!(client.client_id, "registered-id");
+        assert!(client.client_secret.is_none());
+
+        let body = server.requests()[0].json();
+        assert_eq!(body["token_endpoint_auth_method"], "none");
+        assert_eq!(
+            body["redirect_uris"][0],
+            "https://api.example.com/auth/mcp/callback"
+        );
+    }
+
+    #[tokio::test]
+    async fn client_credentials_request_the_configured_scope_and_resource() {
+        let server = StubServer::start(vec![StubResponse::json(json!({
+            "access_token": "cc-token",
+            "token_type": "Bearer",
+            "expires_in": 3600,
+        }))])
+        .await;
+        let auth = GqlMcpAuthConfig {
+            client_id: Some("client".to_string()),
+            client_secret: Some("secret".to_string()),
+            token_url: Some(format!("{}/token", server.base_url)),
+            scope: Some("tools".to_string()),
+            grant_type: Some(GRANT_CLIENT_CREDENTIALS.to_string()),
+            resource: Some("https://mcp.example.com/mcp".to_string()),
+            ..Default::default()
+        };
+        let response = client_credentials_token(&auth).await.ok().unwrap();
+        assert_eq!(response.access_token().secret(), "cc-token");
+
+        let body = &server.requests()[0].body;
+        assert!(body.contains("grant_type=client_credentials"));
+        assert!(body.contains("client_secret=secret"));
+        assert!(body.contains("scope=tools"));
+        assert!(body.contains("resource=https%3A%2F%2Fmcp.example.com%2Fmcp"));
+    }
+
+    fn oauth_server(user_id: Option<&str>, auth: &GqlMcpAuthConfig) -> McpServer {
+        let now = Utc::now().naive_utc();
+        McpServer {
+            id: "server".to_string(),
+            name: "docs".to_string(),
+            url: "https://mcp.example.com".to_string(),
+            description: None,
+            transport_type: crate::models::MCP_TRANSPORT_STREAMABLE_HTTP.to_string(),
+            auth_type: "OAUTH2".to_string(),
+            auth_config: Some(serde_json::to_string(auth).unwrap()),
+            tools: None,
+            is_active: true,
+            user_id: user_id.map(str::to_string),
+            created_at: now,
+            updated_at: now,
+            command: None,
+            args: None,
+            env: None,
+            resources: None,
+            resource_templates: None,
+            prompts: None,
+            requires_approval: false,
+            approval_tools: None,
+            tool_timeout_ms: None,
+            tool_timeouts: None,
+        }
+    }
+
+    #[test]
+    fn only_owners_and_admins_change_a_server() {
+        let shared = oauth_server(None, &GqlMcpAuthConfig::default());
+        assert!(can_change(&shared, "admin", true));
+        assert!(!can_change(&shared, "user", false));
+
+        let private = oauth_server(Some("user"), &GqlMcpAuthConfig::default());
+        assert!(can_change(&private, "user", false));
+        assert!(!can_change(&private, "other", false));
+    }
+
+    #[test]
+    fn a_user_registration_overrides_the_server_config() {
+        let shared = GqlMcpAuthConfig {
+            client_id: Some("shared".to_string()),
+            ..Default::default()
+        };
+        let server = oauth_server(None, &shared);
+        assert_eq!(user_auth_config(&server, None), shared);
+
+        let mine = GqlMcpAuthConfig {
+            client_id: Some("mine".to_string()),
+            client_secret: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let user_config = serde_json::to_string(&mine).unwrap();
+        assert_eq!(user_auth_config(&server, Some(&user_config)), mine);
+    }
+
+    #[test]
+    fn tokens_are_refreshed_shortly_before_they_expire() {
+        let now = Utc::now().naive_utc();
+        assert!(is_fresh(None));
+        assert!(is_fresh(Some(now + Duration::minutes(10))));
+        assert!(!is_fresh(Some(now + Duration::seconds(30))));
+    }
+}
@@ -0,0 +1,451 @@
+//! STDIO MCP servers: an admin-registered command spawned by the API,
+//! speaking newline-delimited JSON-RPC over its stdin/stdout. Processes are
+//! started on demand (with the `initialize` handshake), stopped after
+//! `IDLE_TIMEOUT` without requests, and restarted when they crash — a
+//! request that finds its process gone is retried once on a fresh one.
+//! Requests to a server are serialized. The pool is per API process, like
+//! the chat pubsub.
+
+use std::collections::{BTreeMap, HashMap};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, Once};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tracing::{debug, info, warn};
+
+use crate::models::McpServer;
+use crate::utils::errors::AppError;
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Variables passed through from the API's environment; the rest of it
+/// (database URL, provider keys...) is not the server's business.
+const INHERITED_ENV: [&str; 6] = ["HOME", "LOGNAME", "PATH", "SHELL", "TERM", "USER"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StdioConfig {
+    pub command: String,
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+}
+
+impl StdioConfig {
+    pub fn for_server(server: &McpServer) -> Self {
+        Self {
+            command: server.command.clone().unwrap_or_default(),
+            args: server.stdio_args(),
+            env: server.stdio_env(),
+        }
+    }
+}
+
+struct Process {
+    server_id: String,
+    config: StdioConfig,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    next_id: u64,
+    last_used: Instant,
+}
+
+/// Why a request failed: `Unsent` and `Exited` mean the process is gone
+/// (restart it) before or after the request reached it, `Failed` is the
+/// server's own error.
+enum Failure {
+    Unsent(String),
+    Exited(String),
+    Failed(AppError),
+}
+
+/// Methods without side effects, sent again when the process dies before
+/// answering. Anything else (`tools/call`) may have run already.
+fn is_repeatable(method: &str) -> bool {
+    matches!(method, "initialize" | "resources/read" | "prompts/get") || method.ends_with("/list")
+}
+
+type Slot = Arc<tokio::sync::Mutex<Option<Process>>>;
+
+lazy_static::lazy_static! {
+    static ref PROCESSES: Mutex<HashMap<String, Slot>> = Mutex::new(HashMap::new());
+}
+
+static REAPER: Once = Once::new();
+
+fn slot(server_id: &str) -> Slot {
+    let mut processes = PROCESSES.lock().unwrap_or_else(|e| e.into_inner());
+    processes.entry(server_id.to_string()).or_default().clone()
+}
+
+/// Send a JSON-RPC request to the server's process, starting it if needed;
+/// returns the `result`.
+pub async fn request(
+    server_id: &str,
+    config: &StdioConfig,
+    method: &str,
+    params: Value,
+) -> Result<Value, AppError> {
+    if config.command.trim().is_empty() {
+        return Err(AppError::Validation(
+            "STDIO MCP server has no command".to_string(),
+        ));
+    }
+    REAPER.call_once(|| {
+        tokio::spawn(reap_idle());
+    });
+
+    let slot = slot(server_id);
+    let mut process = slot.lock().await;
+    for attempt in 0..2 {
+        let running = match process.as_mut() {
+            Some(p) => p.config == *config && matches!(p.child.try_wait(), Ok(None)),
+            None => false,
+        };
+        if !running {
+            if process.take().is_some() {
+                info!("Restarting STDIO MCP server {}", server_id);
+            }
+            *process = Some(start(server_id, config).await?);
+        }
+        let Some(current) = process.as_mut() else {
+            break;
+        };
+
+        let outcome =
+            tokio::time::timeout(REQUEST_TIMEOUT, send(current, method, params.clone())).await;
+        // only a request the process never received is always safe to resend
+        let unsent = matches!(outcome, Ok(Err(Failure::Unsent(_))));
+        match outcome {
+            Ok(Ok(result)) => {
+                current.last_used = Instant::now();
+                return Ok(result);
+            }
+            Ok(Err(Failure::Failed(e))) => {
+                current.last_used = Instant::now();
+                return Err(e);
+            }
+            Ok(Err(Failure::Unsent(reason) | Failure::Exited(reason))) => {
+                warn!("STDIO MCP server {} exited: {}", server_id, reason);
+                *process = None;
+                if attempt > 0 || (!unsent && !is_repeatable(method)) {
+                    return Err(AppError::Internal(format!(
+                        "MCP server process exited: {}",
+                        reason
+                    )));
+                }
+            }
+            Err(_) => {
+                // the process may be stuck: don't reuse it
+                *process = None;
+                return Err(AppError::Http(format!(
+                    "MCP server did not answer {} within {}s",
+                    method,
+                    REQUEST_TIMEOUT.as_secs()
+                )));
+            }
+        }
+    }
+    Err(AppError::Internal(
+        "MCP server process could not be started".to_string(),
+    ))
+}
+
+/// Stop the server's process (deleted or reconfigured server).
+pub fn shutdown(server_id: &str) {
+    let removed = PROCESSES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(server_id);
+    // the child is killed when its `Process` is dropped
+    if let Some(slot) = removed {
+        if let Ok(mut process) = slot.try_lock() {
+            *process = None;
+        }
+    }
+}
+
+async fn start(server_id: &str, config: &StdioConfig) -> Result<Process, AppError> {
+    info!(
+        "Starting STDIO MCP server {}: {}",
+        server_id, config.command
+    );
+    let mut command = Command::new(&config.command);
+    command
+        .args(&config.args)
+        .env_clear()
+        .envs(INHERITED_ENV.iter().filter_map(|name| {
+            std::env::var(name)
+                .ok()
+                .map(|value| (name.to_string(), value))
+        }))
+        .envs(&config.env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = command.spawn().map_err(|e| {
+        AppError::Internal(format!(
+            "Failed to start MCP server '{}': {}",
+            config.command, e
+        ))
+    })?;
+
+    let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        return Err(AppError::Internal(
+            "MCP server process has no stdio".to_string(),
+        ));
+    };
+    if let Some(stderr) = child.stderr.take() {
+        let server_id = server_id.to_string();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                debug!("MCP server {} stderr: {}", server_id, line);
+            }
+        });
+    }
+
+    let mut process = Process {
+        server_id: server_id.to_string(),
+        config: config.clone(),
+        child,
+        stdin,
+        stdout: BufReader::new(stdout).lines(),
+        next_id: 1,
+        last_used: Instant::now(),
+    };
+
+    let initialize = send(
+        &mut process,
+        "initialize",
+        json!({
+            "protocolVersion": super::mcp::MCP_PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": { "name": "kate-chat", "version": env!("CARGO_PKG_VERSION") },
+        }),
+    );
+    match tokio::time::timeout(REQUEST_TIMEOUT, initialize).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(Failure::Failed(e))) => return Err(e),
+        Ok(Err(Failure::Unsent(reason) | Failure::Exited(reason))) => {
+            return Err(AppError::Internal(format!(
+                "MCP server exited during initialization: {}",
+                reason
+            )))
+        }
+        Err(_) => {
+            return Err(AppError::Http(
+                "MCP server did not answer initialize".to_string(),
+            ))
+        }
+    }
+    notify(&mut process, "notifications/initialized")
+        .await
+        .map_err(|reason| AppError::Internal(format!("MCP server exited: {}", reason)))?;
+    Ok(process)
+}
+
+async fn write_line(process: &mut Process, message: &Value) -> Result<(), String> {
+    let mut line = message.to_string();
+    line.push('\n');
+    process
+        .stdin
+        .write_all(line.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    process.stdin.flush().await.map_err(|e| e.to_string())
+}
+
+async fn notify(process: &mut Process, method: &str) -> Result<(), String> {
+    write_line(process, &json!({ "jsonrpc": "2.0", "method": method })).await
+}
+
+async fn send(process: &mut Process, method: &str, params: Value) -> Result<Value, Failure> {
+    let id = process.next_id;
+    process.next_id += 1;
+    write_line(
+        process,
+        &json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }),
+    )
+    .await
+    .map_err(Failure::Unsent)?;
+
+    loop {
+        let line = match process.stdout.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return Err(Failure::Exited("stdout closed".to_string())),
+            Err(e) => return Err(Failure::Exited(e.to_string())),
+        };
+        // notifications, requests from the server and stray output are
+        // skipped
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        if message.get("method").and_then(|m| m.as_str())
+            == Some("notifications/tools/list_changed")
+        {
+            super::mcp::mark_tools_changed(&process.server_id);
+            continue;
+        }
+        if message.get("id").and_then(Value::as_u64) != Some(id) || message.get("method").is_some()
+        {
+            continue;
+        }
+
+        if let Some(error) = message.get("error") {
+            let text = error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("unknown error");
+            return Err(Failure::Failed(AppError::Http(format!(
+                "MCP error: {}",
+                text
+            ))));
+        }
+        return Ok(message.get("result").cloned().unwrap_or(Value::Null));
+    }
+}
+
+async fn reap_idle() {
+    let mut interval = tokio::time::interval(REAP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let slots: Vec<(String, Slot)> = PROCESSES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(id, slot)| (id.clone(), slot.clone()))
+            .collect();
+        for (server_id, slot) in slots {
+            // busy slots are in use, not idle
+            let Ok(mut process) = slot.try_lock() else {
+                continue;
+            };
+            if process
+                .as_ref()
+                .is_some_and(|p| p.last_used.elapsed() >= IDLE_TIMEOUT)
+            {
+                info!("Stopping idle STDIO MCP server {}", server_id);
+                *process = None;
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// A fake MCP server: answers each request line with its id; a request
+    /// with `"crash": true` params is logged to `$CRASHES` and exits, `boom`
+    /// is a JSON-RPC error.
+    const FAKE_SERVER: &str = r#"
+while IFS= read -r line; do
+  id=$(printf '%s' "$line" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+  [ -z "$id" ] && continue
+  case "$line" in
+    *'"crash":true'*) echo "$line" >> "$CRASHES"; exit 1 ;;
+    *'"method":"boom"'*) printf '{"jsonrpc":"2.0","id":%s,"error":{"code":-1,"message":"boom"}}\n' "$id" ;;
+    *) echo "log line"; printf '{"jsonrpc":"2.0","id":%s,"result":{"pid":%s,"name":"%s"}}\n' "$id" "$$" "$NAME" ;;
+  esac
+done
+"#;
+
+    fn config(name: &str) -> StdioConfig {
+        StdioConfig {
+            command: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), FAKE_SERVER.to_string()],
+            env: BTreeMap::from([
+                ("NAME".to_string(), name.to_string()),
+                (
+                    "CRASHES".to_string(),
+                    crashes(name).to_string_lossy().into_owned(),
+                ),
+            ]),
+        }
+    }
+
+    fn crashes(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("mcp-stdio-test-{}-{}", std::process::id(), name))
+    }
+
+    fn crash_count(name: &str) -> usize {
+        let count = std::fs::read_to_string(crashes(name))
+            .map(|log| log.lines().count())
+            .unwrap_or(0);
+        let _ = std::fs::remove_file(crashes(name));
+        count
+    }
+
+    #[tokio::test]
+    async fn starts_on_demand_and_reuses_the_process() {
+        let config = config("fs");
+        let first = request("stdio-test-1", &config, "tools/list", json!({}))
+            .await
+            .unwrap();
+        let second = request("stdio-test-1", &config, "tools/list", json!({}))
+            .await
+            .unwrap();
+        assert_eq!(first["name"], "fs");
+        assert_eq!(first["pid"], second["pid"]);
+
+        let error = request("stdio-test-1", &config, "boom", json!({})).await;
+        assert!(matches!(error, Err(AppError::Http(message)) if message == "MCP error: boom"));
+        shutdown("stdio-test-1");
+    }
+
+    #[tokio::test]
+    async fn restarts_a_crashed_or_reconfigured_process() {
+        let before = request("stdio-test-2", &config("a"), "tools/list", json!({}))
+            .await
+            .unwrap();
+
+        // a listing is retried once, on a fresh process that crashes too
+        assert!(request(
+            "stdio-test-2",
+            &config("a"),
+            "tools/list",
+            json!({ "crash": true })
+        )
+        .await
+        .is_err());
+        assert_eq!(crash_count("a"), 2);
+        let after = request("stdio-test-2", &config("a"), "tools/list", json!({}))
+            .await
+            .unwrap();
+        assert_ne!(before["pid"], after["pid"]);
+
+        let reconfigured = request("stdio-test-2", &config("b"), "tools/list", json!({}))
+            .await
+            .unwrap();
+        assert_eq!(reconfigured["name"], "b");
+        assert_ne!(after["pid"], reconfigured["pid"]);
+        shutdown("stdio-test-2");
+    }
+
+    #[tokio::test]
+    async fn does_not_repeat_a_tool_call_whose_process_died() {
+        let config = config("c");
+        let result = request(
+            "stdio-test-3",
+            &config,
+            "tools/call",
+            json!({ "name": "write", "crash": true }),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::Internal(_))));
+        assert_eq!(crash_count("c"), 1);
+
+        // the next request gets a fresh process
+        let after = request("stdio-test-3", &config, "tools/list", json!({}))
+            .await
+            .unwrap();
+        assert_eq!(after["name"], "c");
+        shutdown("stdio-test-3");
+    }
+}
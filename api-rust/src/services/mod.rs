@@ -1,17 +1,33 @@
 pub mod ai;
+pub mod anthropic;
 pub mod bedrock;
 pub mod chat;
+pub mod compaction;
 pub mod custom;
 pub mod document_index;
 pub mod document_status_redis;
+pub mod fallback;
+pub mod gemini;
+pub mod generation;
 pub mod mcp;
+pub mod mcp_oauth;
+pub mod mcp_stdio;
 pub mod model;
+pub mod ollama_protocol;
 pub mod openai;
 pub mod openai_protocol;
+pub mod openai_responses_protocol;
 pub mod pubsub;
+pub mod quota;
 pub mod rag;
+pub mod retry;
 pub mod s3;
+pub mod search_backend;
+pub mod side_by_side;
 pub mod sqs;
+pub mod structured;
+pub mod tool_approval;
 pub mod tools;
+pub mod usage;
 pub mod web_search;
 pub mod yandex;
@@ -1,9 +1,13 @@
 use diesel::prelude::*;
+use std::collections::{HashMap, HashSet};
+use tracing::warn;
 
-use crate::database::DbPool;
+use crate::database::{DbConnection, DbPool};
+use crate::models::model::CustomModelSettings;
 use crate::models::{GqlModel, Model, NewModel, User};
 use crate::schema::models;
-use crate::services::ai::AIService;
+use crate::services::ai::{AIProviderService, AIService};
+use crate::services::custom::{CustomService, PROTOCOL_OLLAMA};
 use crate::utils::errors::AppError;
 
 pub struct ModelService<'a> {
@@ -36,7 +40,7 @@ impl<'a> ModelService<'a> {
             .load(&mut conn)
             .map_err(|e| AppError::Database(e.to_string()))?;
 
-        let enabled_map: std::collections::HashMap<String, bool> = existing_models
+        let enabled_map: HashMap<String, bool> = existing_models
             .iter()
             .map(|m| (m.model_id.clone(), m.is_active))
             .collect();
@@ -54,14 +58,17 @@ impl<'a> ModelService<'a> {
 
         let mut gql_models = Vec::new();
 
-        // Chat models can drive in-chat tools: web search (when Yandex
-        // Search credentials are configured) and MCP servers. The client
-        // shows the per-chat toggles only for tools advertised here.
+        // Chat models can drive in-chat tools: web search (when a search
+        // backend is configured), URL fetch, document search and MCP
+        // servers. The client shows the per-chat toggles only for
+        // tools advertised here.
         let chat_tools = {
             let mut tools = Vec::new();
             if crate::services::web_search::web_search_available(self.ai_service.config()) {
                 tools.push("web_search".to_string());
             }
+            tools.push("url_fetch".to_string());
+            tools.push("search_documents".to_string());
             tools.push("mcp".to_string());
             serde_json::to_string(&tools).ok()
         };
@@ -106,6 +113,105 @@ impl<'a> ModelService<'a> {
             gql_models.push(GqlModel::from_model(&saved_model, user.clone()));
         }
 
+        gql_models.extend(
+            self.discover_ollama_models(&mut conn, user, &existing_models, &chat_tools)
+                .await?,
+        );
+
         Ok(gql_models)
     }
+
+    /// Auto-discovery for the OLLAMA custom protocol: every model served by
+    /// an Ollama endpoint the user already has a custom model for
+    /// (`/api/tags`) is registered as a custom model with the same endpoint
+    /// and runtime options. Unreachable servers are skipped.
+    async fn discover_ollama_models(
+        &self,
+        conn: &mut DbConnection,
+        user: &User,
+        existing_models: &[Model],
+        chat_tools: &Option<String>,
+    ) -> Result<Vec<GqlModel>, AppError> {
+        let mut known_ids: HashSet<String> =
+            existing_models.iter().map(|m| m.model_id.clone()).collect();
+        // endpoint → (settings of the first model, provider-side names in use)
+        let mut endpoints: HashMap<String, (CustomModelSettings, HashSet<String>)> = HashMap::new();
+        for model in existing_models.iter().filter(|m| m.is_custom) {
+            let Some(settings) = model
+                .custom_settings
+                .as_deref()
+                .and_then(|s| serde_json::from_str::<CustomModelSettings>(s).ok())
+            else {
+                continue;
+            };
+            let Some(endpoint) = settings.endpoint.clone() else {
+                continue;
+            };
+            if settings.protocol.as_deref() != Some(PROTOCOL_OLLAMA) {
+                continue;
+            }
+            let model_name = settings.model_name.clone().unwrap_or_default();
+            endpoints
+                .entry(endpoint)
+                .or_insert_with(|| (settings, HashSet::new()))
+                .1
+                .insert(model_name);
+        }
+
+        let mut discovered_models = Vec::new();
+        for (endpoint, (settings, served_names)) in endpoints {
+            let discovered = match CustomService::from_settings(&settings)?.get_models().await {
+                Ok(discovered) => discovered,
+                Err(e) => {
+                    warn!("Ollama model discovery failed for {}: {}", endpoint, e);
+                    continue;
+                }
+            };
+
+            for (name, info) in discovered {
+                if served_names.contains(&name) || !known_ids.insert(name.clone()) {
+                    continue;
+                }
+
+                let model_settings = CustomModelSettings {
+                    model_name: Some(name.clone()),
+                    ..settings.clone()
+                };
+                let now = chrono::Utc::now().naive_utc();
+                let new_model = NewModel {
+                    id: uuid::Uuid::new_v4().to_string(),
+                    name: info.name.clone(),
+                    model_id: name,
+                    description: Some(info.description.clone()),
+                    user_id: Some(user.id.clone()),
+                    api_provider: info.api_provider.to_string(),
+                    provider: info.provider.clone(),
+                    type_: info.type_.clone(),
+                    streaming: info.streaming,
+                    image_input: info.image_input,
+                    max_input_tokens: settings.num_ctx,
+                    tools: (info.type_ == "chat").then(|| chat_tools.clone()).flatten(),
+                    features: None,
+                    custom_settings: serde_json::to_string(&model_settings).ok(),
+                    is_active: true,
+                    is_custom: true,
+                    created_at: now,
+                    updated_at: now,
+                };
+
+                diesel::insert_into(models::table)
+                    .values(&new_model)
+                    .execute(conn)
+                    .map_err(|e| AppError::Database(e.to_string()))?;
+
+                let saved_model: Model = models::table
+                    .filter(models::id.eq(&new_model.id))
+                    .first(conn)
+                    .map_err(|e| AppError::Database(e.to_string()))?;
+                discovered_models.push(GqlModel::from_model(&saved_model, user.clone()));
+            }
+        }
+
+        Ok(discovered_models)
+    }
 }
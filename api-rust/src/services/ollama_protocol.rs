@@ -0,0 +1,734 @@
+//! Ollama native protocol client: `/api/chat` (NDJSON streaming, function
+//! calling, images), `/api/embed` and `/api/tags`. Used by custom models
+//! with the `OLLAMA` protocol — unlike Ollama's OpenAI-compatible shim it
+//! passes `keep_alive` and `options.num_ctx` through to the server.
+
+use futures_util::StreamExt;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use tracing::{debug, warn};
+
+use crate::services::ai::{
+    ExecutedToolCall, InvokeModelRequest, MessageRole, ModelMessage, ModelResponse,
+    StreamCallbacks, ToolCallRequest, Usage, TOOL_CYCLES_LIMIT,
+};
+use crate::services::retry::{send_with_retry, RetryPolicy};
+use crate::services::tools::run_tool_calls;
+use crate::utils::errors::AppError;
+
+/// A model listed by `/api/tags`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OllamaModel {
+    pub name: String,
+    pub embedding: bool,
+    pub image_input: bool,
+}
+
+pub struct OllamaProtocol {
+    client: Client,
+    /// Server root (`http://host:11434`), without `/api` or `/v1`.
+    base_url: String,
+    model_override: Option<String>,
+    keep_alive: Option<String>,
+    num_ctx: Option<i32>,
+    retry: RetryPolicy,
+}
+
+impl OllamaProtocol {
+    pub fn new(
+        endpoint: impl Into<String>,
+        model_override: Option<String>,
+        keep_alive: Option<String>,
+        num_ctx: Option<i32>,
+    ) -> Self {
+        // Accept the server root as well as the `/api` or OpenAI-shim
+        // `/v1` URLs users tend to paste.
+        let mut base_url = endpoint.into();
+        while base_url.ends_with('/') {
+            base_url.pop();
+        }
+        for suffix in ["/v1", "/api"] {
+            if let Some(stripped) = base_url.strip_suffix(suffix) {
+                base_url = stripped.to_string();
+            }
+        }
+        Self {
+            client: Client::new(),
+            base_url,
+            model_override,
+            keep_alive,
+            num_ctx,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn effective_model_id(&self, requested: &str) -> String {
+        self.model_override
+            .clone()
+            .unwrap_or_else(|| requested.to_string())
+    }
+
+    fn api_error(status: reqwest::StatusCode, body: &str) -> AppError {
+        let message = serde_json::from_str::<Value>(body)
+            .ok()
+            .and_then(|v| v.get("error")?.as_str().map(|s| s.to_string()))
+            .unwrap_or_else(|| body.to_string());
+        AppError::Http(format!("Ollama API error ({}): {}", status, message))
+    }
+
+    /// POST a JSON body; transient failures are retried when `retry` is
+    /// set (streams pass `false` once tokens went out).
+    async fn post(
+        &self,
+        path: &str,
+        body: &Value,
+        retry: bool,
+    ) -> Result<reqwest::Response, AppError> {
+        let policy = if retry {
+            self.retry.clone()
+        } else {
+            RetryPolicy::none()
+        };
+        let url = format!("{}{}", self.base_url, path);
+        let response = send_with_retry(&policy, "Ollama", || self.client.post(&url).json(body))
+            .await
+            .map_err(|e| AppError::Http(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(Self::api_error(status, &error_text));
+        }
+        Ok(response)
+    }
+
+    /// Request `options` and `keep_alive` shared by chat and embeddings.
+    fn apply_runtime_options(&self, body: &mut Value) {
+        if let Some(num_ctx) = self.num_ctx {
+            body["options"]["num_ctx"] = json!(num_ctx);
+        }
+        if let Some(keep_alive) = &self.keep_alive {
+            body["keep_alive"] = json!(keep_alive);
+        }
+    }
+
+    /// Message body → `(content, images)`: structured bodies (a JSON array
+    /// of `{contentType, content}` parts) are split into their text and the
+    /// raw base64 of their `data:` URL images.
+    fn format_content(body: &str) -> (String, Vec<String>) {
+        let Some(parts) = serde_json::from_str::<Value>(body)
+            .ok()
+            .and_then(|parsed| parsed.as_array().cloned())
+        else {
+            return (body.to_string(), Vec::new());
+        };
+
+        let mut texts = Vec::new();
+        let mut images = Vec::new();
+        for part in &parts {
+            let Some(content) = part.get("content").and_then(|v| v.as_str()) else {
+                continue;
+            };
+            match part.get("contentType").and_then(|v| v.as_str()) {
+                Some("image") => {
+                    if let Some((_, data)) = content.split_once(";base64,") {
+                        images.push(data.to_string());
+                    }
+                }
+                Some("text") => texts.push(content.to_string()),
+                _ => {}
+            }
+        }
+
+        if texts.is_empty() && images.is_empty() {
+            return (body.to_string(), Vec::new());
+        }
+        (texts.join("\n"), images)
+    }
+
+    /// Call id → function name from the replayed assistant turns (tool
+    /// result messages are matched by `tool_name`).
+    fn function_names(messages: &[ModelMessage]) -> HashMap<String, String> {
+        messages
+            .iter()
+            .filter_map(|m| m.tool_calls.as_ref()?.as_array())
+            .flatten()
+            .enumerate()
+            .filter_map(|(ndx, call)| {
+                let name = call.pointer("/function/name")?.as_str()?.to_string();
+                Some((Self::call_id(call, ndx), name))
+            })
+            .collect()
+    }
+
+    /// Ollama only assigns call ids in newer versions; older ones get a
+    /// positional id.
+    fn call_id(call: &Value, ndx: usize) -> String {
+        call.get("id")
+            .and_then(|id| id.as_str())
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| format!("call_{}", ndx))
+    }
+
+    pub fn build_chat_body(&self, request: &InvokeModelRequest, stream: bool) -> Value {
+        let names = Self::function_names(&request.messages);
+        let mut messages: Vec<Value> = Vec::new();
+
+        if let Some(system_prompt) = &request.system_prompt {
+            messages.push(json!({ "role": "system", "content": system_prompt }));
+        }
+
+        for msg in &request.messages {
+            match msg.role {
+                MessageRole::Tool => {
+                    let id = msg.tool_call_id.clone().unwrap_or_default();
+                    messages.push(json!({
+                        "role": "tool",
+                        "content": msg.content,
+                        "tool_name": names.get(&id).cloned().unwrap_or(id),
+                    }));
+                }
+                MessageRole::Assistant => {
+                    let mut message = json!({ "role": "assistant", "content": msg.content });
+                    if let Some(tool_calls) = &msg.tool_calls {
+                        message["tool_calls"] = tool_calls.clone();
+                    }
+                    messages.push(message);
+                }
+                MessageRole::System => {
+                    messages.push(json!({ "role": "system", "content": msg.content }));
+                }
+                MessageRole::User => {
+                    let (content, images) = Self::format_content(&msg.content);
+                    let mut message = json!({ "role": "user", "content": content });
+                    if !images.is_empty() {
+                        message["images"] = json!(images);
+                    }
+                    messages.push(message);
+                }
+            }
+        }
+
+        let mut body = json!({
+            "model": self.effective_model_id(&request.model_id),
+            "messages": messages,
+            "stream": stream,
+            "options": {},
+        });
+        if let Some(temperature) = request.temperature {
+            body["options"]["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = request.top_p {
+            body["options"]["top_p"] = json!(top_p);
+        }
+        if let Some(max_tokens) = request.max_tokens {
+            body["options"]["num_predict"] = json!(max_tokens);
+        }
+        // Ollama has no thinking budget, only a switch; thinking comes back
+        // in `message.thinking`
+        if request.thinking_budget.is_some() {
+            body["think"] = json!(true);
+        }
+        // Ollama constrains the output to a JSON Schema given as `format`
+        if let Some(schema) = &request.response_schema {
+            body["format"] = schema.clone();
+        }
+        self.apply_runtime_options(&mut body);
+
+        if let Some(tools) = request.tools.as_deref().filter(|t| !t.is_empty()) {
+            body["tools"] = json!(tools
+                .iter()
+                .map(|tool| json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.spec.name,
+                        "description": tool.spec.description,
+                        "parameters": tool.spec.input_schema,
+                    },
+                }))
+                .collect::<Vec<_>>());
+        }
+
+        body
+    }
+
+    /// `message.tool_calls` → tool call requests; `offset` keeps positional
+    /// ids unique across the calls of one turn.
+    fn parse_tool_calls(raw_calls: &[Value], offset: usize) -> Vec<ToolCallRequest> {
+        raw_calls
+            .iter()
+            .enumerate()
+            .filter_map(|(ndx, call)| {
+                let function = call.get("function")?;
+                let name = function.get("name")?.as_str()?.to_string();
+                let arguments = match function.get("arguments") {
+                    // some models return the arguments JSON-encoded
+                    Some(Value::String(s)) => serde_json::from_str(s).unwrap_or_else(|_| json!({})),
+                    Some(args) => args.clone(),
+                    None => json!({}),
+                };
+                Some(ToolCallRequest {
+                    id: Self::call_id(call, offset + ndx),
+                    name,
+                    arguments,
+                    raw: call.clone(),
+                })
+            })
+            .collect()
+    }
+
+    fn parse_usage(response: &Value) -> Option<Usage> {
+        let input = response.get("prompt_eval_count").and_then(|t| t.as_i64());
+        let output = response.get("eval_count").and_then(|t| t.as_i64());
+        if input.is_none() && output.is_none() {
+            return None;
+        }
+        Some(Usage {
+            input_tokens: input.map(|t| t as i32),
+            output_tokens: output.map(|t| t as i32),
+            total_tokens: Some((input.unwrap_or(0) + output.unwrap_or(0)) as i32),
+            cache_read_input_tokens: None,
+            cache_write_input_tokens: None,
+        })
+    }
+
+    /// Tool calls already requested in this session (positional id offset).
+    fn session_calls(session: &InvokeModelRequest) -> usize {
+        session
+            .messages
+            .iter()
+            .filter_map(|m| m.tool_calls.as_ref()?.as_array().map(|a| a.len()))
+            .sum()
+    }
+
+    /// POST /api/chat (non-streaming), repeated while the model keeps
+    /// requesting tools.
+    pub async fn invoke(&self, request: &InvokeModelRequest) -> Result<ModelResponse, AppError> {
+        let mut session = request.clone();
+        let mut executed: Vec<ExecutedToolCall> = Vec::new();
+
+        for _cycle in 0..TOOL_CYCLES_LIMIT {
+            let body = self.build_chat_body(&session, false);
+            debug!("Ollama: chat for {}", body["model"]);
+
+            let response_json: Value = self
+                .post("/api/chat", &body, true)
+                .await?
+                .json()
+                .await
+                .map_err(|e| {
+                    AppError::Internal(format!("Failed to parse Ollama response: {}", e))
+                })?;
+
+            let raw_calls = response_json
+                .pointer("/message/tool_calls")
+                .and_then(|c| c.as_array())
+                .cloned()
+                .unwrap_or_default();
+            let calls = Self::parse_tool_calls(&raw_calls, Self::session_calls(&session));
+            if !calls.is_empty() {
+                run_tool_calls(&mut session, &mut executed, Value::Array(raw_calls), calls).await;
+                continue;
+            }
+
+            let message = response_json.get("message");
+            let text = |key: &str| {
+                message
+                    .and_then(|m| m.get(key))
+                    .and_then(|c| c.as_str())
+                    .unwrap_or("")
+                    .to_string()
+            };
+            let thinking = text("thinking");
+            return Ok(ModelResponse {
+                content: text("content"),
+                model_id: request.model_id.clone(),
+                usage: Self::parse_usage(&response_json),
+                finish_reason: response_json
+                    .get("done_reason")
+                    .and_then(|r| r.as_str())
+                    .map(|r| r.to_string()),
+                tool_calls: Vec::new(),
+                reasoning: (!thinking.is_empty()).then_some(thinking),
+                executed_tool_calls: executed,
+            });
+        }
+
+        Err(AppError::Internal(
+            "Ollama: tool call cycles limit exceeded".to_string(),
+        ))
+    }
+
+    /// POST /api/chat with `stream: true`: one JSON object per line, the
+    /// last one carrying `done: true` and the token counts.
+    pub async fn invoke_stream<F, C, E, R>(
+        &self,
+        request: &InvokeModelRequest,
+        callbacks: &StreamCallbacks<F, C, E, R>,
+    ) -> Result<Vec<ExecutedToolCall>, AppError>
+    where
+        F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+    {
+        let mut session = request.clone();
+        let mut executed: Vec<ExecutedToolCall> = Vec::new();
+        let mut full_response = String::new();
+        let mut reasoned = false;
+
+        for _cycle in 0..TOOL_CYCLES_LIMIT {
+            let body = self.build_chat_body(&session, true);
+            debug!("Ollama: streaming chat for {}", body["model"]);
+
+            let mut raw_calls: Vec<Value> = Vec::new();
+            let result = match self
+                .post("/api/chat", &body, full_response.is_empty() && !reasoned)
+                .await
+            {
+                Ok(response) => {
+                    self.stream_cycle(
+                        response,
+                        callbacks,
+                        &mut full_response,
+                        &mut reasoned,
+                        &mut raw_calls,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(error) = result {
+                (callbacks.on_error)(error.clone()).await;
+                return Err(error);
+            }
+
+            // The cycle repeats ONLY to continue after tool calls.
+            let calls = Self::parse_tool_calls(&raw_calls, Self::session_calls(&session));
+            if calls.is_empty() {
+                (callbacks.on_complete)(full_response).await;
+                return Ok(executed);
+            }
+
+            run_tool_calls(&mut session, &mut executed, Value::Array(raw_calls), calls).await;
+        }
+
+        let error = AppError::Internal("Ollama: tool call cycles limit exceeded".to_string());
+        (callbacks.on_error)(error.clone()).await;
+        Err(error)
+    }
+
+    async fn stream_cycle<F, C, E, R>(
+        &self,
+        response: reqwest::Response,
+        callbacks: &StreamCallbacks<F, C, E, R>,
+        full_response: &mut String,
+        reasoned: &mut bool,
+        raw_calls: &mut Vec<Value>,
+    ) -> Result<(), AppError>
+    where
+        F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+    {
+        let mut stream = response.bytes_stream();
+        let mut line_buffer = String::new();
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| AppError::Http(format!("Stream error: {}", e)))?;
+            line_buffer.push_str(&String::from_utf8_lossy(&chunk));
+
+            // NDJSON: keep the trailing partial line.
+            while let Some(newline_pos) = line_buffer.find('\n') {
+                let line: String = line_buffer.drain(..=newline_pos).collect();
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+
+                let event = match serde_json::from_str::<Value>(line) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        warn!("Ollama: failed to parse stream line: {} — {}", line, e);
+                        continue;
+                    }
+                };
+
+                if let Some(message) = event.get("error").and_then(|m| m.as_str()) {
+                    return Err(AppError::Http(format!("Ollama stream error: {}", message)));
+                }
+
+                if let Some(thinking) = event
+                    .pointer("/message/thinking")
+                    .and_then(|c| c.as_str())
+                    .filter(|c| !c.is_empty())
+                {
+                    *reasoned = true;
+                    (callbacks.on_reasoning)(thinking.to_string()).await;
+                }
+                if let Some(token) = event
+                    .pointer("/message/content")
+                    .and_then(|c| c.as_str())
+                    .filter(|c| !c.is_empty())
+                {
+                    full_response.push_str(token);
+                    (callbacks.on_token)(token.to_string()).await;
+                }
+                if let Some(calls) = event
+                    .pointer("/message/tool_calls")
+                    .and_then(|c| c.as_array())
+                {
+                    raw_calls.extend(calls.iter().cloned());
+                }
+                if event.get("done").and_then(|d| d.as_bool()) == Some(true) {
+                    if let Some(usage) = Self::parse_usage(&event) {
+                        debug!("Ollama: stream usage {:?}", usage);
+                        callbacks.usage.record(&usage);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// POST /api/embed — returns the embedding of the single input.
+    pub async fn get_embeddings(&self, model_id: &str, input: &str) -> Result<Vec<f32>, AppError> {
+        let mut body = json!({
+            "model": self.effective_model_id(model_id),
+            "input": input,
+        });
+        self.apply_runtime_options(&mut body);
+
+        let response_json: Value = self
+            .post("/api/embed", &body, true)
+            .await?
+            .json()
+            .await
+            .map_err(|e| {
+                AppError::Internal(format!("Failed to parse Ollama embeddings response: {}", e))
+            })?;
+
+        response_json
+            .pointer("/embeddings/0")
+            .and_then(|v| v.as_array())
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|v| v.as_f64())
+                    .map(|v| v as f32)
+                    .collect()
+            })
+            .ok_or_else(|| AppError::Internal("Ollama returned no embedding".to_string()))
+    }
+
+    /// GET /api/tags — locally available models, classified by family
+    /// (BERT-family models are embedding models, CLIP/mllama-family ones
+    /// accept images).
+    pub async fn list_models(&self) -> Result<Vec<OllamaModel>, AppError> {
+        let url = format!("{}/api/tags", self.base_url);
+        let response = send_with_retry(&self.retry, "Ollama", || self.client.get(&url))
+            .await
+            .map_err(|e| AppError::Http(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(Self::api_error(status, &error_text));
+        }
+
+        let response_json: Value = response.json().await.map_err(|e| {
+            AppError::Internal(format!("Failed to parse Ollama tags response: {}", e))
+        })?;
+
+        Ok(response_json
+            .get("models")
+            .and_then(|m| m.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|model| {
+                let name = model.get("name").and_then(|n| n.as_str())?.to_string();
+                let families: Vec<&str> = model
+                    .pointer("/details/families")
+                    .and_then(|f| f.as_array())
+                    .map(|f| f.iter().filter_map(|v| v.as_str()).collect())
+                    .unwrap_or_default();
+                let embedding =
+                    name.contains("embed") || families.iter().any(|f| f.contains("bert"));
+                let image_input = !embedding
+                    && families
+                        .iter()
+                        .any(|f| matches!(*f, "clip" | "mllama") || f.contains("vision"));
+                Some(OllamaModel {
+                    name,
+                    embedding,
+                    image_input,
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::ai::UsageTracker;
+    use crate::utils::test_server::{StubResponse, StubServer};
+    use std::sync::{Arc, Mutex};
+
+    fn protocol(base_url: &str) -> OllamaProtocol {
+        OllamaProtocol::new(
+            format!("{}/v1/", base_url),
+            Some("llama3.2".to_string()),
+            Some("10m".to_string()),
+            Some(8192),
+        )
+    }
+
+    fn request() -> InvokeModelRequest {
+        InvokeModelRequest {
+            model_id: "my-llama".to_string(),
+            messages: vec![ModelMessage::text(MessageRole::User, "hi")],
+            temperature: Some(0.3),
+            max_tokens: Some(128),
+            top_p: None,
+            system_prompt: Some("be brief".to_string()),
+            tools: None,
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn chat_passes_context_size_and_keep_alive() {
+        let server = StubServer::start(vec![
+            StubResponse::json(json!({
+                "message": { "role": "assistant", "content": "",
+                    "tool_calls": [{ "function": { "name": "lookup", "arguments": { "q": "rust" } } }] },
+                "done": true,
+            })),
+            StubResponse::json(json!({
+                "message": { "role": "assistant", "content": "Hello!" },
+                "done": true, "done_reason": "stop",
+                "prompt_eval_count": 12, "eval_count": 3,
+            })),
+        ])
+        .await;
+
+        let response = protocol(&server.base_url).invoke(&request()).await.unwrap();
+        assert_eq!(response.content, "Hello!");
+        assert_eq!(response.usage.unwrap().total_tokens, Some(15));
+
+        let requests = server.requests();
+        assert!(requests[0].request_line.starts_with("POST /api/chat"));
+        let body = requests[0].json();
+        assert_eq!(body["model"], "llama3.2");
+        assert_eq!(body["options"]["num_ctx"], 8192);
+        assert_eq!(body["options"]["num_predict"], 128);
+        assert_eq!(body["keep_alive"], "10m");
+        assert_eq!(body["messages"][0]["role"], "system");
+
+        let followup = requests[1].json();
+        assert_eq!(
+            followup["messages"][2]["tool_calls"][0]["function"]["name"],
+            "lookup"
+        );
+        assert_eq!(followup["messages"][3]["role"], "tool");
+        assert_eq!(followup["messages"][3]["tool_name"], "lookup");
+    }
+
+    #[tokio::test]
+    async fn streams_ndjson_lines() {
+        let lines = [
+            json!({"message": {"role": "assistant", "content": "", "thinking": "Hmm"}, "done": false}),
+            json!({"message": {"role": "assistant", "content": "Hel"}, "done": false}),
+            json!({"message": {"role": "assistant", "content": "lo"}, "done": false}),
+            json!({"message": {"role": "assistant", "content": ""}, "done": true,
+                "prompt_eval_count": 4, "eval_count": 2}),
+        ];
+        let body: String = lines.iter().map(|l| format!("{}\n", l)).collect();
+        let server =
+            StubServer::start(vec![StubResponse::text(200, "application/x-ndjson", body)]).await;
+
+        let tokens = Arc::new(Mutex::new(Vec::<String>::new()));
+        let completed = Arc::new(Mutex::new(None::<String>));
+        let thinking = Arc::new(Mutex::new(String::new()));
+        let callbacks = StreamCallbacks {
+            on_token: {
+                let tokens = tokens.clone();
+                move |token: String| {
+                    tokens.lock().unwrap().push(token);
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                }
+            },
+            on_complete: {
+                let completed = completed.clone();
+                move |content: String| {
+                    *completed.lock().unwrap() = Some(content);
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                }
+            },
+            on_error: |_e: AppError| Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>,
+            on_reasoning: {
+                let thinking = thinking.clone();
+                move |delta: String| {
+                    thinking.lock().unwrap().push_str(&delta);
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                }
+            },
+            usage: UsageTracker::default(),
+        };
+
+        let mut request = request();
+        request.thinking_budget = Some(2048);
+        protocol(&server.base_url)
+            .invoke_stream(&request, &callbacks)
+            .await
+            .unwrap();
+        assert_eq!(*tokens.lock().unwrap(), vec!["Hel", "lo"]);
+        assert_eq!(completed.lock().unwrap().as_deref(), Some("Hello"));
+        assert_eq!(*thinking.lock().unwrap(), "Hmm");
+        assert_eq!(server.requests()[0].json()["stream"], true);
+        assert_eq!(server.requests()[0].json()["think"], true);
+    }
+
+    #[tokio::test]
+    async fn embeds_and_lists_tags() {
+        let server = StubServer::start(vec![
+            StubResponse::json(json!({ "embeddings": [[0.25, 0.5]] })),
+            StubResponse::json(json!({ "models": [
+                { "name": "llama3.2:latest", "details": { "families": ["llama"] } },
+                { "name": "nomic-embed-text:latest", "details": { "families": ["nomic-bert"] } },
+                { "name": "llava:7b", "details": { "families": ["llama", "clip"] } },
+            ]})),
+        ])
+        .await;
+
+        let protocol = protocol(&server.base_url);
+        let embedding = protocol.get_embeddings("ignored", "hello").await.unwrap();
+        assert_eq!(embedding, vec![0.25, 0.5]);
+        assert_eq!(server.requests()[0].json()["options"]["num_ctx"], 8192);
+
+        let models = protocol.list_models().await.unwrap();
+        assert_eq!(models.len(), 3);
+        assert!(!models[0].embedding && !models[0].image_input);
+        assert!(models[1].embedding);
+        assert!(models[2].image_input);
+        assert!(server.requests()[1]
+            .request_line
+            .starts_with("GET /api/tags"));
+    }
+}
@@ -35,12 +35,10 @@ impl OpenAIService {
             .openai_api_key
             .clone()
             .ok_or_else(|| AppError::Auth("OpenAI API key not configured".to_string()))?;
-        Ok(OpenAIProtocol::new(
-            OPENAI_API_URL,
-            Some(api_key),
-            None,
-            "OpenAI",
-        ))
+        Ok(
+            OpenAIProtocol::new(OPENAI_API_URL, Some(api_key), None, "OpenAI")
+                .with_retry(self.config.retry.clone()),
+        )
     }
 
     pub fn classify_model(model_id: &str) -> Option<(&'static str, bool, bool)> {
@@ -82,15 +80,16 @@ impl AIProviderService for OpenAIService {
         self.protocol()?.invoke(&request).await
     }
 
-    async fn invoke_model_stream<F, C, E>(
+    async fn invoke_model_stream<F, C, E, R>(
         &self,
         request: InvokeModelRequest,
-        callbacks: StreamCallbacks<F, C, E>,
+        callbacks: StreamCallbacks<F, C, E, R>,
     ) -> Result<Vec<ExecutedToolCall>, AppError>
     where
         F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
         C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
         E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
     {
         self.protocol()?.invoke_stream(&request, &callbacks).await
     }
@@ -12,12 +12,23 @@ use std::pin::Pin;
 use tracing::{debug, warn};
 
 use crate::services::ai::{
-    ExecutedToolCall, GeneratedImage, InvokeModelRequest, MessageRole, ModelMessage, ModelResponse,
-    StreamCallbacks, ToolCallRequest, Usage, TOOL_CYCLES_LIMIT,
+    reasoning_effort, split_think_tags, ExecutedToolCall, GeneratedImage, InvokeModelRequest,
+    MessageRole, ModelResponse, StreamCallbacks, ThinkTagSplitter, ToolCallRequest, Usage,
+    UsageTracker, TOOL_CYCLES_LIMIT,
 };
-use crate::services::tools::execute_tool_call;
+use crate::services::retry::{send_with_retry, RetryPolicy};
+use crate::services::structured::STRUCTURED_OUTPUT_TOOL;
+use crate::services::tools::run_tool_calls;
 use crate::utils::errors::AppError;
 
+/// OpenAI reasoning models (o-series, gpt-5): no sampling params, reasoning
+/// effort instead.
+pub(crate) fn is_reasoning_model(model_id: &str) -> bool {
+    ["o1", "o3", "o4", "gpt-5"]
+        .iter()
+        .any(|p| model_id.starts_with(p))
+}
+
 pub struct OpenAIProtocol {
     client: Client,
     /// e.g. `https://api.openai.com/v1`, `https://ai.api.cloud.yandex.net/v1`
@@ -31,6 +42,7 @@ pub struct OpenAIProtocol {
     model_override: Option<String>,
     /// Human-readable provider label used in error messages.
     label: String,
+    retry: RetryPolicy,
 }
 
 impl OpenAIProtocol {
@@ -50,9 +62,15 @@ impl OpenAIProtocol {
             auth_header: api_key.map(|key| format!("Bearer {}", key)),
             model_override,
             label: label.into(),
+            retry: RetryPolicy::default(),
         }
     }
 
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
     /// Replace the `Authorization` header value entirely (provider-specific
     /// auth schemes).
     pub fn with_auth_header(mut self, value: impl Into<String>) -> Self {
@@ -66,11 +84,15 @@ impl OpenAIProtocol {
             .unwrap_or_else(|| requested.to_string())
     }
 
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
     fn url(&self, path: &str) -> String {
         format!("{}{}", self.base_url, path)
     }
 
-    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+    pub(crate) fn post(&self, path: &str) -> reqwest::RequestBuilder {
         let mut builder = self
             .client
             .post(self.url(path))
@@ -81,6 +103,25 @@ impl OpenAIProtocol {
         builder
     }
 
+    /// POST a JSON body, retrying transient failures per the retry policy
+    /// when `retry` is set (streams pass `false` once tokens went out). The
+    /// last response is returned even when its status is an error.
+    pub(crate) async fn send(
+        &self,
+        path: &str,
+        body: &Value,
+        retry: bool,
+    ) -> Result<reqwest::Response, AppError> {
+        let policy = if retry {
+            self.retry.clone()
+        } else {
+            RetryPolicy::none()
+        };
+        send_with_retry(&policy, &self.label, || self.post(path).json(body))
+            .await
+            .map_err(|e| AppError::Http(e.to_string()))
+    }
+
     fn get(&self, path: &str) -> reqwest::RequestBuilder {
         let mut builder = self.client.get(self.url(path));
         if let Some(auth) = &self.auth_header {
@@ -90,7 +131,7 @@ impl OpenAIProtocol {
     }
 
     /// Extract the API error message from an OpenAI-style error payload.
-    fn api_error(&self, status: reqwest::StatusCode, body: &str) -> AppError {
+    pub(crate) fn api_error(&self, status: reqwest::StatusCode, body: &str) -> AppError {
         let message = serde_json::from_str::<Value>(body)
             .ok()
             .and_then(|v| {
@@ -151,14 +192,14 @@ impl OpenAIProtocol {
         // OpenAI reasoning models reject sampling params and renamed the
         // token cap to max_completion_tokens (the Node API handles this in
         // its per-provider params processor).
-        let reasoning_model = ["o1", "o3", "o4", "gpt-5"]
-            .iter()
-            .any(|p| model_id.starts_with(p));
+        let reasoning_model = is_reasoning_model(&model_id);
 
         let mut body = json!({ "model": model_id, "messages": messages });
 
         if stream {
             body["stream"] = json!(true);
+            // the final chunk then reports the token usage
+            body["stream_options"] = json!({ "include_usage": true });
         }
         if let Some(max_tokens) = request.max_tokens {
             if reasoning_model {
@@ -175,6 +216,16 @@ impl OpenAIProtocol {
                 body["top_p"] = json!(top_p);
             }
         }
+        if let Some(budget) = request.thinking_budget.filter(|_| reasoning_model) {
+            body["reasoning_effort"] = json!(reasoning_effort(budget));
+        }
+
+        if let Some(schema) = &request.response_schema {
+            body["response_format"] = json!({
+                "type": "json_schema",
+                "json_schema": { "name": STRUCTURED_OUTPUT_TOOL, "schema": schema },
+            });
+        }
 
         if let Some(tools) = request.tools.as_deref().filter(|t| !t.is_empty()) {
             body["tools"] = json!(tools
@@ -233,20 +284,7 @@ impl OpenAIProtocol {
         raw_calls: Vec<Value>,
     ) {
         let calls = Self::parse_tool_calls(&raw_calls);
-        session.messages.push(ModelMessage {
-            role: MessageRole::Assistant,
-            content: String::new(),
-            timestamp: None,
-            tool_calls: Some(Value::Array(raw_calls)),
-            tool_call_id: None,
-        });
-
-        let tools = session.tools.clone().unwrap_or_default();
-        for call in calls {
-            let (message, record) = execute_tool_call(&tools, &call).await;
-            executed.push(record);
-            session.messages.push(message);
-        }
+        run_tool_calls(session, executed, Value::Array(raw_calls), calls).await;
     }
 
     fn parse_usage(usage: &Value) -> Usage {
@@ -263,6 +301,11 @@ impl OpenAIProtocol {
                 .get("total_tokens")
                 .and_then(|t| t.as_i64())
                 .map(|t| t as i32),
+            cache_read_input_tokens: usage
+                .pointer("/prompt_tokens_details/cached_tokens")
+                .and_then(|t| t.as_i64())
+                .map(|t| t as i32),
+            cache_write_input_tokens: None,
         }
     }
 
@@ -271,17 +314,13 @@ impl OpenAIProtocol {
     pub async fn invoke(&self, request: &InvokeModelRequest) -> Result<ModelResponse, AppError> {
         let mut session = request.clone();
         let mut executed: Vec<ExecutedToolCall> = Vec::new();
+        let usage = UsageTracker::default();
 
         for _cycle in 0..TOOL_CYCLES_LIMIT {
             let body = self.build_completion_body(&session, false);
             debug!("{}: chat completion for {}", self.label, body["model"]);
 
-            let response = self
-                .post("/chat/completions")
-                .json(&body)
-                .send()
-                .await
-                .map_err(|e| AppError::Http(e.to_string()))?;
+            let response = self.send("/chat/completions", &body, true).await?;
 
             if !response.status().is_success() {
                 let status = response.status();
@@ -292,6 +331,9 @@ impl OpenAIProtocol {
             let response_json: Value = response.json().await.map_err(|e| {
                 AppError::Internal(format!("Failed to parse {} response: {}", self.label, e))
             })?;
+            if let Some(cycle_usage) = response_json.get("usage").filter(|u| u.is_object()) {
+                usage.record(&Self::parse_usage(cycle_usage));
+            }
 
             let first_choice = response_json
                 .get("choices")
@@ -310,11 +352,19 @@ impl OpenAIProtocol {
                 continue;
             }
 
-            let content = message
-                .and_then(|msg| msg.get("content"))
-                .and_then(|content| content.as_str())
-                .unwrap_or("")
-                .to_string();
+            // Reasoning arrives either as a separate field (OpenAI-compatible
+            // reasoning models, DeepSeek) or inline in `<think>` tags
+            let (content, inline_reasoning) = split_think_tags(
+                message
+                    .and_then(|msg| msg.get("content"))
+                    .and_then(|content| content.as_str())
+                    .unwrap_or(""),
+            );
+            let reasoning = message
+                .and_then(Self::reasoning_text)
+                .filter(|r| !r.is_empty())
+                .map(str::to_string)
+                .or(inline_reasoning);
 
             let finish_reason = first_choice
                 .and_then(|choice| choice.get("finish_reason"))
@@ -324,9 +374,11 @@ impl OpenAIProtocol {
             return Ok(ModelResponse {
                 content,
                 model_id: request.model_id.clone(),
-                usage: response_json.get("usage").map(Self::parse_usage),
+                usage: usage.total(),
                 finish_reason,
                 tool_calls: Vec::new(),
+                reasoning,
+                executed_tool_calls: executed,
             });
         }
 
@@ -342,19 +394,22 @@ impl OpenAIProtocol {
     /// a delta may be split between two network reads. When the model
     /// finishes a cycle with tool calls, they are executed and the session
     /// re-invoked (Node's streamChatCompletionLegacy loop).
-    pub async fn invoke_stream<F, C, E>(
+    pub async fn invoke_stream<F, C, E, R>(
         &self,
         request: &InvokeModelRequest,
-        callbacks: &StreamCallbacks<F, C, E>,
+        callbacks: &StreamCallbacks<F, C, E, R>,
     ) -> Result<Vec<ExecutedToolCall>, AppError>
     where
         F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
         C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
         E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
     {
         let mut session = request.clone();
         let mut executed: Vec<ExecutedToolCall> = Vec::new();
         let mut full_response = String::new();
+        let mut streamed_reasoning = false;
+        let mut think_tags = ThinkTagSplitter::default();
 
         for _cycle in 0..TOOL_CYCLES_LIMIT {
             let body = self.build_completion_body(&session, true);
@@ -363,12 +418,14 @@ impl OpenAIProtocol {
                 self.label, body["model"]
             );
 
+            // Retrying is safe only while nothing has been streamed
             let response = self
-                .post("/chat/completions")
-                .json(&body)
-                .send()
-                .await
-                .map_err(|e| AppError::Http(e.to_string()))?;
+                .send(
+                    "/chat/completions",
+                    &body,
+                    full_response.is_empty() && !streamed_reasoning,
+                )
+                .await?;
 
             if !response.status().is_success() {
                 let status = response.status();
@@ -420,12 +477,24 @@ impl OpenAIProtocol {
                         }
                     };
 
+                    if let Some(usage) = json_data.get("usage").filter(|u| u.is_object()) {
+                        callbacks.usage.record(&Self::parse_usage(usage));
+                    }
+
                     let choice = json_data
                         .get("choices")
                         .and_then(|c| c.as_array())
                         .and_then(|arr| arr.first());
                     let delta = choice.and_then(|choice| choice.get("delta"));
 
+                    if let Some(reasoning) = delta
+                        .and_then(Self::reasoning_text)
+                        .filter(|r| !r.is_empty())
+                    {
+                        streamed_reasoning = true;
+                        (callbacks.on_reasoning)(reasoning.to_string()).await;
+                    }
+
                     if let Some(calls) = delta
                         .and_then(|delta| delta.get("tool_calls"))
                         .and_then(|calls| calls.as_array())
@@ -435,18 +504,23 @@ impl OpenAIProtocol {
                         .and_then(|delta| delta.get("content"))
                         .and_then(|content| content.as_str())
                     {
+                        let (token, reasoning) = think_tags.push(token);
+                        if !reasoning.is_empty() {
+                            streamed_reasoning = true;
+                            (callbacks.on_reasoning)(reasoning).await;
+                        }
                         if !token.is_empty() {
-                            full_response.push_str(token);
-                            (callbacks.on_token)(token.to_string()).await;
+                            full_response.push_str(&token);
+                            (callbacks.on_token)(token).await;
                         }
                     }
 
                     let finish_reason = choice
                         .and_then(|choice| choice.get("finish_reason"))
                         .and_then(|reason| reason.as_str());
+                    // read on to the usage chunk that closes the stream
                     if finish_reason == Some("tool_calls") && !streamed_tool_calls.is_empty() {
                         tools_requested = true;
-                        break 'outer;
                     }
                 }
             }
@@ -454,6 +528,14 @@ impl OpenAIProtocol {
             // The cycle repeats ONLY to continue after tool calls; a stream
             // that simply ends is a completed response.
             if !tools_requested {
+                let (token, reasoning) = think_tags.finish();
+                if !reasoning.is_empty() {
+                    (callbacks.on_reasoning)(reasoning).await;
+                }
+                if !token.is_empty() {
+                    full_response.push_str(&token);
+                    (callbacks.on_token)(token).await;
+                }
                 (callbacks.on_complete)(full_response).await;
                 return Ok(executed);
             }
@@ -466,6 +548,15 @@ impl OpenAIProtocol {
         Err(error)
     }
 
+    /// `reasoning_content` (DeepSeek, vLLM) or `reasoning` (OpenRouter,
+    /// Ollama's OpenAI endpoint) of a message or stream delta.
+    fn reasoning_text(message: &Value) -> Option<&str> {
+        message
+            .get("reasoning_content")
+            .or_else(|| message.get("reasoning"))
+            .and_then(|r| r.as_str())
+    }
+
     /// Merge a chunk's `delta.tool_calls` entries into the accumulated list:
     /// the first delta for an index carries id/name, later deltas append
     /// argument fragments.
@@ -525,12 +616,7 @@ impl OpenAIProtocol {
             self.label, n, model_id
         );
 
-        let response = self
-            .post("/images/generations")
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| AppError::Http(e.to_string()))?;
+        let response = self.send("/images/generations", &body, true).await?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -581,12 +667,7 @@ impl OpenAIProtocol {
             "encoding_format": "float",
         });
 
-        let response = self
-            .post("/embeddings")
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| AppError::Http(e.to_string()))?;
+        let response = self.send("/embeddings", &body, true).await?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -617,9 +698,7 @@ impl OpenAIProtocol {
 
     /// GET /models → list of model ids.
     pub async fn list_model_ids(&self) -> Result<Vec<String>, AppError> {
-        let response = self
-            .get("/models")
-            .send()
+        let response = send_with_retry(&self.retry, &self.label, || self.get("/models"))
             .await
             .map_err(|e| AppError::Http(e.to_string()))?;
 
@@ -652,6 +731,7 @@ impl OpenAIProtocol {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::services::ai::ModelMessage;
 
     fn request() -> InvokeModelRequest {
         InvokeModelRequest {
@@ -662,6 +742,12 @@ mod tests {
             top_p: None,
             system_prompt: Some("be brief".to_string()),
             tools: None,
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
         }
     }
 
@@ -677,6 +763,19 @@ mod tests {
         assert!(body.get("stream").is_none());
     }
 
+    #[test]
+    fn response_schema_becomes_json_schema_format() {
+        let protocol = OpenAIProtocol::new("https://api.openai.com/v1", None, None, "OpenAI");
+        let mut request = request();
+        request.response_schema = Some(json!({"type": "object"}));
+        let body = protocol.build_completion_body(&request, false);
+        assert_eq!(body["response_format"]["type"], "json_schema");
+        assert_eq!(
+            body["response_format"]["json_schema"]["schema"]["type"],
+            "object"
+        );
+    }
+
     #[test]
     fn streaming_body_sets_stream_flag() {
         let protocol = OpenAIProtocol::new("https://api.openai.com/v1", None, None, "OpenAI");
@@ -722,10 +821,14 @@ mod tests {
                 input_schema: json!({"type": "object"}),
             },
             backend: ToolBackend::WebSearch {
-                api_key: "k".to_string(),
-                folder_id: "f".to_string(),
-                api_url: None,
+                backend: crate::services::search_backend::SearchBackend::Yandex {
+                    api_key: "k".to_string(),
+                    folder_id: "f".to_string(),
+                    api_url: None,
+                },
             },
+            requires_approval: false,
+            timeout: None,
         }]);
         // assistant tool_calls turn + tool result turn
         req.messages.push(ModelMessage {
@@ -781,6 +884,69 @@ mod tests {
         assert_eq!(calls[0].arguments, json!({}));
     }
 
+    #[test]
+    fn reasoning_budget_maps_to_effort() {
+        let protocol = OpenAIProtocol::new("https://api.openai.com/v1", None, None, "OpenAI");
+        let mut req = request();
+        req.thinking_budget = Some(8_000);
+        assert!(protocol
+            .build_completion_body(&req, false)
+            .get("reasoning_effort")
+            .is_none());
+        req.model_id = "o4-mini".to_string();
+        let body = protocol.build_completion_body(&req, false);
+        assert_eq!(body["reasoning_effort"], "medium");
+    }
+
+    #[tokio::test]
+    async fn streams_reasoning_apart_from_content() {
+        use crate::utils::test_server::{StubResponse, StubServer};
+        use std::sync::{Arc, Mutex};
+
+        let deltas = [
+            json!({"choices": [{"delta": {"reasoning_content": "Weighing "}}]}),
+            json!({"choices": [{"delta": {"reasoning_content": "options."}}]}),
+            json!({"choices": [{"delta": {"content": "<think>inline</th"}}]}),
+            json!({"choices": [{"delta": {"content": "ink>\n\nDone"}, "finish_reason": "stop"}]}),
+        ];
+        let body: String = deltas
+            .iter()
+            .map(|d| format!("data: {}\n\n", d))
+            .chain(std::iter::once("data: [DONE]\n\n".to_string()))
+            .collect();
+        let server = StubServer::start(vec![StubResponse::sse(body)]).await;
+
+        let reasoning = Arc::new(Mutex::new(String::new()));
+        let completed = Arc::new(Mutex::new(None::<String>));
+        let callbacks = StreamCallbacks {
+            on_token: |_t: String| Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>,
+            on_complete: {
+                let completed = completed.clone();
+                move |content: String| {
+                    *completed.lock().unwrap() = Some(content);
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                }
+            },
+            on_error: |_e: AppError| Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>,
+            on_reasoning: {
+                let reasoning = reasoning.clone();
+                move |delta: String| {
+                    reasoning.lock().unwrap().push_str(&delta);
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                }
+            },
+            usage: UsageTracker::default(),
+        };
+
+        OpenAIProtocol::new(&server.base_url, None, None, "Custom")
+            .with_retry(RetryPolicy::none())
+            .invoke_stream(&request(), &callbacks)
+            .await
+            .unwrap();
+        assert_eq!(*reasoning.lock().unwrap(), "Weighing options.inline");
+        assert_eq!(completed.lock().unwrap().as_deref(), Some("Done"));
+    }
+
     #[test]
     fn trailing_slash_is_trimmed() {
         let protocol = OpenAIProtocol::new("http://host/v1///", None, None, "X");
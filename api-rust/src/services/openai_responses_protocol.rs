@@ -0,0 +1,665 @@
+//! OpenAI Responses API protocol client (`POST /responses`): typed stream
+//! events, function calling over `function_call` / `function_call_output`
+//! items and reasoning summaries. Used by custom models whose gateway only
+//! exposes `/v1/responses` (Node's `OpenAIResponsesProtocol`); embeddings,
+//! images and model listing stay on the shared chat-completions client.
+//!
+//! The session is replayed statelessly on every tool cycle (no
+//! `previous_response_id`), so gateways that do not store responses work.
+
+use futures_util::StreamExt;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::pin::Pin;
+use tracing::{debug, warn};
+
+use crate::services::ai::{
+    reasoning_effort, ExecutedToolCall, InvokeModelRequest, MessageRole, ModelResponse,
+    StreamCallbacks, ToolCallRequest, Usage, UsageTracker, TOOL_CYCLES_LIMIT,
+};
+use crate::services::openai_protocol::{is_reasoning_model, OpenAIProtocol};
+use crate::services::retry::RetryPolicy;
+use crate::services::structured::STRUCTURED_OUTPUT_TOOL;
+use crate::services::tools::run_tool_calls;
+use crate::utils::errors::AppError;
+
+/// The Responses API rejects `max_output_tokens` below 16.
+const MIN_OUTPUT_TOKENS: i32 = 16;
+
+pub struct OpenAIResponsesProtocol {
+    /// Transport (base URL, auth, model override, label) shared with the
+    /// chat-completions client.
+    base: OpenAIProtocol,
+}
+
+impl OpenAIResponsesProtocol {
+    pub fn new(base: OpenAIProtocol) -> Self {
+        Self { base }
+    }
+
+    pub fn with_retry(self, retry: RetryPolicy) -> Self {
+        Self {
+            base: self.base.with_retry(retry),
+        }
+    }
+
+    /// The underlying chat-completions client (embeddings, images, models).
+    pub fn base(&self) -> &OpenAIProtocol {
+        &self.base
+    }
+
+    pub fn build_responses_body(&self, request: &InvokeModelRequest, stream: bool) -> Value {
+        let model_id = self.base.effective_model_id(&request.model_id);
+        let mut input: Vec<Value> = Vec::new();
+
+        for msg in &request.messages {
+            match msg.role {
+                MessageRole::Tool => {
+                    input.push(json!({
+                        "type": "function_call_output",
+                        "call_id": msg.tool_call_id.clone().unwrap_or_default(),
+                        "output": msg.content,
+                    }));
+                }
+                // An assistant turn that requested tools replays its
+                // function_call items verbatim.
+                MessageRole::Assistant if msg.tool_calls.is_some() => {
+                    if !msg.content.is_empty() {
+                        input.push(json!({ "role": "assistant", "content": msg.content }));
+                    }
+                    if let Some(items) = msg.tool_calls.as_ref().and_then(|c| c.as_array()) {
+                        input.extend(items.iter().cloned());
+                    }
+                }
+                _ => {
+                    let role = match msg.role {
+                        MessageRole::Assistant => "assistant",
+                        MessageRole::System => "developer",
+                        _ => "user",
+                    };
+                    input.push(json!({ "role": role, "content": msg.content }));
+                }
+            }
+        }
+
+        let reasoning_model = is_reasoning_model(&model_id);
+        let mut body = json!({ "model": model_id, "input": input });
+
+        if let Some(system_prompt) = &request.system_prompt {
+            body["instructions"] = json!(system_prompt);
+        }
+        if stream {
+            body["stream"] = json!(true);
+        }
+        if let Some(max_tokens) = request.max_tokens {
+            body["max_output_tokens"] = json!(max_tokens.max(MIN_OUTPUT_TOKENS));
+        }
+        if reasoning_model {
+            body["reasoning"] = json!({ "summary": "auto" });
+            if let Some(budget) = request.thinking_budget {
+                body["reasoning"]["effort"] = json!(reasoning_effort(budget));
+            }
+        } else {
+            if let Some(temperature) = request.temperature {
+                body["temperature"] = json!(temperature);
+            }
+            if let Some(top_p) = request.top_p {
+                body["top_p"] = json!(top_p);
+            }
+        }
+
+        if let Some(schema) = &request.response_schema {
+            body["text"] = json!({
+                "format": {
+                    "type": "json_schema",
+                    "name": STRUCTURED_OUTPUT_TOOL,
+                    "schema": schema,
+                    "strict": false,
+                },
+            });
+        }
+
+        if let Some(tools) = request.tools.as_deref().filter(|t| !t.is_empty()) {
+            body["tools"] = json!(tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "name": tool.spec.name,
+                        "description": tool.spec.description,
+                        "parameters": tool.spec.input_schema,
+                        "strict": false,
+                    })
+                })
+                .collect::<Vec<_>>());
+        }
+
+        body
+    }
+
+    /// `function_call` output items → tool call requests. The replayed item
+    /// drops the server-side `id`/`status` so stateless replays are valid.
+    fn parse_function_calls(items: &[Value]) -> Vec<ToolCallRequest> {
+        items
+            .iter()
+            .filter(|item| item.get("type").and_then(|t| t.as_str()) == Some("function_call"))
+            .filter_map(|item| {
+                let name = item.get("name")?.as_str()?.to_string();
+                let call_id = item
+                    .get("call_id")
+                    .and_then(|id| id.as_str())
+                    .unwrap_or("unknown_id")
+                    .to_string();
+                let raw_arguments = item
+                    .get("arguments")
+                    .and_then(|a| a.as_str())
+                    .unwrap_or("")
+                    .to_string();
+                let arguments = Some(raw_arguments.as_str())
+                    .filter(|a| !a.trim().is_empty())
+                    .and_then(|a| serde_json::from_str(a).ok())
+                    .unwrap_or_else(|| json!({}));
+                Some(ToolCallRequest {
+                    raw: json!({
+                        "type": "function_call",
+                        "call_id": call_id,
+                        "name": name,
+                        "arguments": raw_arguments,
+                    }),
+                    id: call_id,
+                    name,
+                    arguments,
+                })
+            })
+            .collect()
+    }
+
+    /// Concatenated `output_text` parts of the `message` output items.
+    fn output_text(output: &[Value]) -> String {
+        output
+            .iter()
+            .filter(|item| item.get("type").and_then(|t| t.as_str()) == Some("message"))
+            .filter_map(|item| item.get("content").and_then(|c| c.as_array()))
+            .flatten()
+            .filter(|part| part.get("type").and_then(|t| t.as_str()) == Some("output_text"))
+            .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+            .collect()
+    }
+
+    /// Reasoning summaries of the `reasoning` output items, one paragraph
+    /// per summary part.
+    fn reasoning_summary(output: &[Value]) -> Option<String> {
+        let parts: Vec<&str> = output
+            .iter()
+            .filter(|item| item.get("type").and_then(|t| t.as_str()) == Some("reasoning"))
+            .filter_map(|item| item.get("summary").and_then(|s| s.as_array()))
+            .flatten()
+            .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+            .filter(|text| !text.is_empty())
+            .collect();
+        (!parts.is_empty()).then(|| parts.join("\n\n"))
+    }
+
+    fn parse_usage(usage: &Value) -> Usage {
+        let tokens = |key: &str| usage.get(key).and_then(|t| t.as_i64()).map(|t| t as i32);
+        Usage {
+            input_tokens: tokens("input_tokens"),
+            output_tokens: tokens("output_tokens"),
+            total_tokens: tokens("total_tokens"),
+            cache_read_input_tokens: usage
+                .pointer("/input_tokens_details/cached_tokens")
+                .and_then(|t| t.as_i64())
+                .map(|t| t as i32),
+            cache_write_input_tokens: None,
+        }
+    }
+
+    /// Error message of a `response.failed` / `error` stream event.
+    fn event_error(&self, event: &Value) -> AppError {
+        let message = event
+            .get("message")
+            .or_else(|| {
+                event
+                    .get("response")
+                    .and_then(|r| r.get("error")?.get("message"))
+            })
+            .and_then(|m| m.as_str())
+            .unwrap_or("unknown error");
+        AppError::Http(format!("{} API error: {}", self.base.label(), message))
+    }
+
+    async fn send(&self, body: &Value, retry: bool) -> Result<reqwest::Response, AppError> {
+        let response = self.base.send("/responses", body, retry).await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(self.base.api_error(status, &error_text));
+        }
+        Ok(response)
+    }
+
+    /// POST /responses (non-streaming). Repeats the session cycle while
+    /// the model keeps requesting function calls.
+    pub async fn invoke(&self, request: &InvokeModelRequest) -> Result<ModelResponse, AppError> {
+        let mut session = request.clone();
+        let mut executed: Vec<ExecutedToolCall> = Vec::new();
+        let usage = UsageTracker::default();
+
+        for _cycle in 0..TOOL_CYCLES_LIMIT {
+            let body = self.build_responses_body(&session, false);
+            debug!("{}: response for {}", self.base.label(), body["model"]);
+
+            let response_json: Value = self.send(&body, true).await?.json().await.map_err(|e| {
+                AppError::Internal(format!(
+                    "Failed to parse {} response: {}",
+                    self.base.label(),
+                    e
+                ))
+            })?;
+            if let Some(cycle_usage) = response_json.get("usage").filter(|u| u.is_object()) {
+                usage.record(&Self::parse_usage(cycle_usage));
+            }
+
+            let output = response_json
+                .get("output")
+                .and_then(|o| o.as_array())
+                .cloned()
+                .unwrap_or_default();
+
+            let calls = Self::parse_function_calls(&output);
+            if !calls.is_empty() {
+                let replay = Value::Array(calls.iter().map(|c| c.raw.clone()).collect());
+                run_tool_calls(&mut session, &mut executed, replay, calls).await;
+                continue;
+            }
+
+            let finish_reason = response_json
+                .get("incomplete_details")
+                .and_then(|d| d.get("reason"))
+                .or_else(|| response_json.get("status"))
+                .and_then(|r| r.as_str())
+                .map(|s| s.to_string());
+
+            return Ok(ModelResponse {
+                content: Self::output_text(&output),
+                model_id: request.model_id.clone(),
+                usage: usage.total(),
+                finish_reason,
+                tool_calls: Vec::new(),
+                reasoning: Self::reasoning_summary(&output),
+                executed_tool_calls: executed,
+            });
+        }
+
+        Err(AppError::Internal(format!(
+            "{}: tool call cycles limit exceeded",
+            self.base.label()
+        )))
+    }
+
+    /// POST /responses with `stream: true`. Text arrives as
+    /// `response.output_text.delta` events; function calls are complete
+    /// once their `response.output_item.done` event arrives, after which
+    /// the tools run and the session is re-invoked.
+    pub async fn invoke_stream<F, C, E, R>(
+        &self,
+        request: &InvokeModelRequest,
+        callbacks: &StreamCallbacks<F, C, E, R>,
+    ) -> Result<Vec<ExecutedToolCall>, AppError>
+    where
+        F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+    {
+        let mut session = request.clone();
+        let mut executed: Vec<ExecutedToolCall> = Vec::new();
+        let mut full_response = String::new();
+        let mut reasoned = false;
+
+        for _cycle in 0..TOOL_CYCLES_LIMIT {
+            let body = self.build_responses_body(&session, true);
+            debug!(
+                "{}: streaming response for {}",
+                self.base.label(),
+                body["model"]
+            );
+
+            let function_items = match self
+                .stream_cycle(&body, callbacks, &mut full_response, &mut reasoned)
+                .await
+            {
+                Ok(items) => items,
+                Err(error) => {
+                    (callbacks.on_error)(error.clone()).await;
+                    return Err(error);
+                }
+            };
+
+            let calls = Self::parse_function_calls(&function_items);
+            // The cycle repeats ONLY to continue after function calls.
+            if calls.is_empty() {
+                (callbacks.on_complete)(full_response).await;
+                return Ok(executed);
+            }
+
+            let replay = Value::Array(calls.iter().map(|c| c.raw.clone()).collect());
+            run_tool_calls(&mut session, &mut executed, replay, calls).await;
+        }
+
+        let error = AppError::Internal(format!(
+            "{}: tool call cycles limit exceeded",
+            self.base.label()
+        ));
+        (callbacks.on_error)(error.clone()).await;
+        Err(error)
+    }
+
+    /// Run one streaming request; returns the completed function_call items.
+    /// `reasoned` is set once a reasoning summary delta went out.
+    async fn stream_cycle<F, C, E, R>(
+        &self,
+        body: &Value,
+        callbacks: &StreamCallbacks<F, C, E, R>,
+        full_response: &mut String,
+        reasoned: &mut bool,
+    ) -> Result<Vec<Value>, AppError>
+    where
+        F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+    {
+        // Retrying is safe only while nothing has been streamed
+        let mut stream = self
+            .send(body, full_response.is_empty() && !*reasoned)
+            .await?
+            .bytes_stream();
+        let mut line_buffer = String::new();
+        let mut function_items: Vec<Value> = Vec::new();
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| AppError::Http(format!("Stream error: {}", e)))?;
+            line_buffer.push_str(&String::from_utf8_lossy(&chunk));
+
+            // Only `data:` lines matter — every payload repeats its event
+            // name in `type`. Keep the trailing partial line.
+            while let Some(newline_pos) = line_buffer.find('\n') {
+                let line: String = line_buffer.drain(..=newline_pos).collect();
+                let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+                    continue;
+                };
+                if data == "[DONE]" {
+                    return Ok(function_items);
+                }
+
+                let event = match serde_json::from_str::<Value>(data) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        warn!(
+                            "{}: failed to parse stream data: {} — {}",
+                            self.base.label(),
+                            data,
+                            e
+                        );
+                        continue;
+                    }
+                };
+
+                match event.get("type").and_then(|t| t.as_str()) {
+                    Some("response.output_text.delta") => {
+                        if let Some(delta) = event
+                            .get("delta")
+                            .and_then(|d| d.as_str())
+                            .filter(|d| !d.is_empty())
+                        {
+                            full_response.push_str(delta);
+                            (callbacks.on_token)(delta.to_string()).await;
+                        }
+                    }
+                    Some("response.output_item.done") => {
+                        if let Some(item) = event.get("item").filter(|item| {
+                            item.get("type").and_then(|t| t.as_str()) == Some("function_call")
+                        }) {
+                            function_items.push(item.clone());
+                        }
+                    }
+                    Some("response.reasoning_summary_part.added") if *reasoned => {
+                        // Summary parts read as paragraphs
+                        (callbacks.on_reasoning)("\n\n".to_string()).await;
+                    }
+                    Some("response.reasoning_summary_text.delta") => {
+                        if let Some(delta) = event
+                            .get("delta")
+                            .and_then(|d| d.as_str())
+                            .filter(|d| !d.is_empty())
+                        {
+                            *reasoned = true;
+                            (callbacks.on_reasoning)(delta.to_string()).await;
+                        }
+                    }
+                    Some("response.failed") | Some("error") => {
+                        return Err(self.event_error(&event));
+                    }
+                    Some("response.completed") | Some("response.incomplete") => {
+                        if let Some(usage) =
+                            event.pointer("/response/usage").filter(|u| u.is_object())
+                        {
+                            callbacks.usage.record(&Self::parse_usage(usage));
+                        }
+                        return Ok(function_items);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(function_items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::ai::{ExecutableTool, ModelMessage, ToolBackend, ToolSpec};
+    use crate::utils::test_server::{StubResponse, StubServer};
+    use std::sync::{Arc, Mutex};
+
+    fn protocol(base_url: &str) -> OpenAIResponsesProtocol {
+        OpenAIResponsesProtocol::new(OpenAIProtocol::new(
+            base_url,
+            Some("sk-test".to_string()),
+            None,
+            "Custom model",
+        ))
+    }
+
+    fn request(model_id: &str) -> InvokeModelRequest {
+        InvokeModelRequest {
+            model_id: model_id.to_string(),
+            messages: vec![ModelMessage::text(MessageRole::User, "hi")],
+            temperature: Some(0.5),
+            max_tokens: Some(8),
+            top_p: None,
+            system_prompt: Some("be brief".to_string()),
+            tools: Some(vec![ExecutableTool {
+                spec: ToolSpec {
+                    name: "lookup".to_string(),
+                    description: "Look things up".to_string(),
+                    input_schema: json!({"type": "object"}),
+                },
+                backend: ToolBackend::WebSearch {
+                    backend: crate::services::search_backend::SearchBackend::Yandex {
+                        api_key: "k".to_string(),
+                        folder_id: "f".to_string(),
+                        api_url: None,
+                    },
+                },
+                requires_approval: false,
+                timeout: None,
+            }]),
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
+        }
+    }
+
+    fn sse(events: &[Value]) -> String {
+        events
+            .iter()
+            .map(|event| {
+                format!(
+                    "event: {}\ndata: {}\n\n",
+                    event["type"].as_str().unwrap_or_default(),
+                    event
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn builds_responses_body() {
+        let body = protocol("http://gw/v1").build_responses_body(&request("llama3"), true);
+        assert_eq!(body["model"], "llama3");
+        assert_eq!(body["instructions"], "be brief");
+        assert_eq!(body["input"][0]["role"], "user");
+        assert_eq!(body["max_output_tokens"], MIN_OUTPUT_TOKENS);
+        assert_eq!(body["temperature"], 0.5);
+        assert_eq!(body["stream"], true);
+        // flat function tool shape, not chat-completions' nested `function`
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["name"], "lookup");
+        assert!(body.get("reasoning").is_none());
+    }
+
+    #[test]
+    fn reasoning_models_request_summaries() {
+        let body = protocol("http://gw/v1").build_responses_body(&request("gpt-5-mini"), false);
+        assert_eq!(body["reasoning"]["summary"], "auto");
+        assert!(body.get("temperature").is_none());
+    }
+
+    #[tokio::test]
+    async fn invoke_runs_function_calls_and_returns_reasoning() {
+        let server = StubServer::start(vec![
+            StubResponse::json(json!({
+                "id": "resp_1",
+                "status": "completed",
+                "output": [{ "type": "function_call", "id": "fc_1", "status": "completed",
+                    "call_id": "call_1", "name": "lookup", "arguments": "{\"q\":\"rust\"}" }],
+            })),
+            StubResponse::json(json!({
+                "id": "resp_2",
+                "status": "completed",
+                "output": [
+                    { "type": "reasoning", "summary": [{ "type": "summary_text", "text": "Thought." }] },
+                    { "type": "message", "role": "assistant",
+                      "content": [{ "type": "output_text", "text": "Answer" }] },
+                ],
+                "usage": { "input_tokens": 10, "output_tokens": 3, "total_tokens": 13 },
+            })),
+        ])
+        .await;
+
+        let mut req = request("gpt-5-mini");
+        // the stub web search tool has no reachable backend — only the
+        // replay shape matters here
+        req.tools = None;
+        let response = protocol(&server.base_url).invoke(&req).await.unwrap();
+        assert_eq!(response.content, "Answer");
+        assert_eq!(response.reasoning.as_deref(), Some("Thought."));
+        assert_eq!(response.usage.unwrap().total_tokens, Some(13));
+
+        let requests = server.requests();
+        assert!(requests[0].request_line.starts_with("POST /responses"));
+        let followup = requests[1].json();
+        assert_eq!(followup["input"][1]["type"], "function_call");
+        assert_eq!(followup["input"][1]["call_id"], "call_1");
+        assert!(followup["input"][1].get("id").is_none());
+        assert_eq!(followup["input"][2]["type"], "function_call_output");
+        assert_eq!(followup["input"][2]["call_id"], "call_1");
+    }
+
+    #[tokio::test]
+    async fn streams_output_text_deltas() {
+        let server = StubServer::start(vec![StubResponse::sse(sse(&[
+            json!({"type": "response.created", "response": {"id": "resp_1"}}),
+            json!({"type": "response.reasoning_summary_part.added", "summary_index": 0}),
+            json!({"type": "response.reasoning_summary_text.delta", "delta": "Greeting"}),
+            json!({"type": "response.reasoning_summary_part.added", "summary_index": 1}),
+            json!({"type": "response.reasoning_summary_text.delta", "delta": "Be brief"}),
+            json!({"type": "response.output_text.delta", "delta": "Hel"}),
+            json!({"type": "response.output_text.delta", "delta": "lo"}),
+            json!({"type": "response.completed", "response": {"id": "resp_1"}}),
+        ]))])
+        .await;
+
+        let tokens = Arc::new(Mutex::new(Vec::<String>::new()));
+        let completed = Arc::new(Mutex::new(None::<String>));
+        let reasoning = Arc::new(Mutex::new(String::new()));
+        let callbacks = StreamCallbacks {
+            on_token: {
+                let tokens = tokens.clone();
+                move |token: String| {
+                    tokens.lock().unwrap().push(token);
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                }
+            },
+            on_complete: {
+                let completed = completed.clone();
+                move |content: String| {
+                    *completed.lock().unwrap() = Some(content);
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                }
+            },
+            on_error: |_e: AppError| Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>,
+            on_reasoning: {
+                let reasoning = reasoning.clone();
+                move |delta: String| {
+                    reasoning.lock().unwrap().push_str(&delta);
+                    Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+                }
+            },
+            usage: UsageTracker::default(),
+        };
+
+        let executed = protocol(&server.base_url)
+            .invoke_stream(&request("llama3"), &callbacks)
+            .await
+            .unwrap();
+        assert!(executed.is_empty());
+        assert_eq!(*reasoning.lock().unwrap(), "Greeting\n\nBe brief");
+        assert_eq!(*tokens.lock().unwrap(), vec!["Hel", "lo"]);
+        assert_eq!(completed.lock().unwrap().as_deref(), Some("Hello"));
+    }
+
+    #[tokio::test]
+    async fn failed_stream_surfaces_error() {
+        let server = StubServer::start(vec![StubResponse::sse(sse(&[json!({
+            "type": "response.failed",
+            "response": {"error": {"message": "model overloaded"}},
+        })]))])
+        .await;
+
+        let callbacks = StreamCallbacks {
+            on_token: |_t: String| Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>,
+            on_complete: |_c: String| {
+                Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+            },
+            on_error: |_e: AppError| Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>,
+            on_reasoning: |_r: String| {
+                Box::pin(async {}) as Pin<Box<dyn Future<Output = ()> + Send>>
+            },
+            usage: UsageTracker::default(),
+        };
+        let error = protocol(&server.base_url)
+            .invoke_stream(&request("llama3"), &callbacks)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("model overloaded"));
+    }
+}
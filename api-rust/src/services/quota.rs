@@ -0,0 +1,520 @@
+//! Per-user quotas. Limits come from the `DEMO_MAX_*` environment values
+//! (users with the `user` role), from `quota_limits` rows of the user's
+//! role and from rows of the user; the most specific one wins for each
+//! metric and period. Usage is counted from append-only ledgers — the
+//! token usage ledger and `quota_usage`, recorded when chats, messages,
+//! images and documents are created, so deleting them does not give the
+//! quota back — from the start of the period or the latest admin reset,
+//! whichever is later. Creations are checked and recorded by `reserve` in
+//! one transaction holding the user's row, so concurrent requests of a
+//! user cannot all pass the check.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::database::DbConnection;
+use crate::models::{
+    QuotaLimit, QuotaMetric, QuotaPeriod, QuotaReset, QuotaScope, QuotaSource, QuotaStatus,
+    QuotaUsage, User, ROLE_USER,
+};
+use crate::schema::{
+    documents, message_usage, messages, quota_limits, quota_resets, quota_usage, users,
+};
+use crate::utils::errors::AppError;
+
+/// A limit in force for a user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EffectiveLimit {
+    pub metric: QuotaMetric,
+    pub period: QuotaPeriod,
+    pub limit: f64,
+    pub source: QuotaSource,
+}
+
+/// `DEMO_MAX_CHATS` / `DEMO_MAX_IMAGES` limits; they apply to the `user`
+/// role only, admins are left unlimited unless a role limit says otherwise.
+pub fn config_limits(config: &AppConfig, role: &str) -> Vec<EffectiveLimit> {
+    if role != ROLE_USER {
+        return Vec::new();
+    }
+    [
+        (QuotaMetric::Chats, config.demo_max_chats),
+        (QuotaMetric::Images, config.demo_max_images),
+    ]
+    .into_iter()
+    .filter_map(|(metric, limit)| {
+        limit.filter(|l| *l >= 0).map(|limit| EffectiveLimit {
+            metric,
+            period: QuotaPeriod::Total,
+            limit: limit as f64,
+            source: QuotaSource::Config,
+        })
+    })
+    .collect()
+}
+
+/// Merge limits by metric and period: user rows override role rows, which
+/// override the environment defaults. A negative limit lifts the limit.
+pub fn merge_limits(
+    defaults: Vec<EffectiveLimit>,
+    rows: &[QuotaLimit],
+    user: &User,
+) -> Vec<EffectiveLimit> {
+    let mut merged: BTreeMap<(QuotaMetric, QuotaPeriod), EffectiveLimit> = defaults
+        .into_iter()
+        .map(|limit| ((limit.metric, limit.period), limit))
+        .collect();
+
+    let mut overrides: Vec<EffectiveLimit> = rows
+        .iter()
+        .filter_map(|row| {
+            let source = match QuotaScope::parse(&row.scope)? {
+                QuotaScope::Role if row.subject == user.role => QuotaSource::Role,
+                QuotaScope::User if row.subject == user.id => QuotaSource::User,
+                _ => return None,
+            };
+            Some(EffectiveLimit {
+                metric: QuotaMetric::parse(&row.metric)?,
+                period: QuotaPeriod::parse(&row.period)?,
+                limit: row.limit_value,
+                source,
+            })
+        })
+        .collect();
+    overrides.sort_by_key(|limit| limit.source);
+
+    for limit in overrides {
+        if limit.limit < 0.0 {
+            merged.remove(&(limit.metric, limit.period));
+        } else {
+            merged.insert((limit.metric, limit.period), limit);
+        }
+    }
+    merged.into_values().collect()
+}
+
+pub fn effective_limits(
+    conn: &mut DbConnection,
+    config: &AppConfig,
+    user: &User,
+) -> Result<Vec<EffectiveLimit>, AppError> {
+    let rows: Vec<QuotaLimit> = quota_limits::table
+        .filter(
+            quota_limits::scope
+                .eq(QuotaScope::Role.as_str())
+                .and(quota_limits::subject.eq(&user.role))
+                .or(quota_limits::scope
+                    .eq(QuotaScope::User.as_str())
+                    .and(quota_limits::subject.eq(&user.id))),
+        )
+        .load(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    Ok(merge_limits(config_limits(config, &user.role), &rows, user))
+}
+
+/// Start and end of the period `now` falls in (UTC); `Total` has neither.
+pub fn period_bounds(
+    period: QuotaPeriod,
+    now: NaiveDateTime,
+) -> (Option<NaiveDateTime>, Option<NaiveDateTime>) {
+    let today = now.date();
+    let (start, end) = match period {
+        QuotaPeriod::Total => return (None, None),
+        QuotaPeriod::Day => (today, today + Duration::days(1)),
+        QuotaPeriod::Month => {
+            let start = NaiveDate::from_ymd_opt(today.year(), today.month(), 1);
+            let end = match today.month() {
+                12 => NaiveDate::from_ymd_opt(today.year() + 1, 1, 1),
+                month => NaiveDate::from_ymd_opt(today.year(), month + 1, 1),
+            };
+            match (start, end) {
+                (Some(start), Some(end)) => (start, end),
+                _ => return (None, None),
+            }
+        }
+    };
+    (start.and_hms_opt(0, 0, 0), end.and_hms_opt(0, 0, 0))
+}
+
+/// Whether a request adding `amount` goes over `limit`. Token and spend
+/// checks pass 0 as the cost of a request is only known once answered:
+/// they fail once the limit is reached.
+pub fn exceeds(used: f64, amount: f64, limit: f64) -> bool {
+    if amount > 0.0 {
+        used + amount > limit
+    } else {
+        used >= limit
+    }
+}
+
+fn latest_resets(
+    conn: &mut DbConnection,
+    user_id: &str,
+) -> Result<HashMap<String, NaiveDateTime>, AppError> {
+    let rows: Vec<(String, NaiveDateTime)> = quota_resets::table
+        .filter(quota_resets::user_id.eq(user_id))
+        .select((quota_resets::metric, quota_resets::created_at))
+        .load(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    let mut latest: HashMap<String, NaiveDateTime> = HashMap::new();
+    for (metric, at) in rows {
+        let entry = latest.entry(metric).or_insert(at);
+        *entry = (*entry).max(at);
+    }
+    Ok(latest)
+}
+
+/// Usage of a metric since `since` (all of it when `None`). Storage is
+/// what the user stores now, whatever the period.
+pub fn usage_since(
+    conn: &mut DbConnection,
+    user_id: &str,
+    metric: QuotaMetric,
+    since: Option<NaiveDateTime>,
+) -> Result<f64, AppError> {
+    let since = since.unwrap_or(DateTime::UNIX_EPOCH.naive_utc());
+    let db_error = |e: diesel::result::Error| AppError::Database(e.to_string());
+    let used = match metric {
+        QuotaMetric::Tokens => message_usage::table
+            .filter(message_usage::user_id.eq(user_id))
+            .filter(message_usage::created_at.ge(since))
+            .select((message_usage::input_tokens, message_usage::output_tokens))
+            .load::<(i32, i32)>(conn)
+            .map_err(db_error)?
+            .into_iter()
+            .map(|(input, output)| input as f64 + output as f64)
+            .sum(),
+        QuotaMetric::Spend => message_usage::table
+            .filter(message_usage::user_id.eq(user_id))
+            .filter(message_usage::created_at.ge(since))
+            .select(message_usage::cost)
+            .load::<Option<f64>>(conn)
+            .map_err(db_error)?
+            .into_iter()
+            .flatten()
+            .sum(),
+        QuotaMetric::Chats
+        | QuotaMetric::Messages
+        | QuotaMetric::Images
+        | QuotaMetric::Documents => quota_usage::table
+            .filter(quota_usage::user_id.eq(user_id))
+            .filter(quota_usage::metric.eq(metric.as_str()))
+            .filter(quota_usage::created_at.ge(since))
+            .select(quota_usage::amount)
+            .load::<f64>(conn)
+            .map_err(db_error)?
+            .into_iter()
+            .sum(),
+        QuotaMetric::StorageBytes => documents::table
+            .filter(documents::owner_id.eq(user_id))
+            .select(documents::file_size)
+            .load::<i64>(conn)
+            .map_err(db_error)?
+            .into_iter()
+            .map(|size| size as f64)
+            .sum(),
+    };
+    Ok(used)
+}
+
+/// Count a creation (`amount` chats, messages, images or documents)
+/// against the user's quotas; a negative amount gives back what was
+/// reserved but not created.
+pub fn record(
+    conn: &mut DbConnection,
+    user_id: &str,
+    metric: QuotaMetric,
+    amount: f64,
+) -> Result<(), AppError> {
+    diesel::insert_into(quota_usage::table)
+        .values(&QuotaUsage {
+            id: Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            metric: metric.as_str().to_string(),
+            amount,
+            created_at: Utc::now().naive_utc(),
+        })
+        .execute(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    Ok(())
+}
+
+/// Every limit in force for the user with its usage.
+pub fn status(
+    conn: &mut DbConnection,
+    config: &AppConfig,
+    user: &User,
+) -> Result<Vec<QuotaStatus>, AppError> {
+    let limits = effective_limits(conn, config, user)?;
+    statuses(conn, user, &limits)
+}
+
+fn statuses(
+    conn: &mut DbConnection,
+    user: &User,
+    limits: &[EffectiveLimit],
+) -> Result<Vec<QuotaStatus>, AppError> {
+    let now = Utc::now().naive_utc();
+    let resets = latest_resets(conn, &user.id)?;
+    let mut result = Vec::with_capacity(limits.len());
+    for limit in limits {
+        let (start, end) = period_bounds(limit.period, now);
+        let counted_since = match limit.metric {
+            QuotaMetric::StorageBytes => None,
+            metric => start.max(resets.get(metric.as_str()).copied()),
+        };
+        let used = usage_since(conn, &user.id, limit.metric, counted_since)?;
+        result.push(QuotaStatus {
+            metric: limit.metric,
+            period: limit.period,
+            source: limit.source,
+            limit: limit.limit,
+            used,
+            remaining: (limit.limit - used).max(0.0),
+            counted_since,
+            resets_at: end,
+        });
+    }
+    Ok(result)
+}
+
+/// Reject a request that would go over one of the user's limits;
+/// `amounts` are what the request adds per metric.
+pub fn check(
+    conn: &mut DbConnection,
+    config: &AppConfig,
+    user: &User,
+    amounts: &[(QuotaMetric, f64)],
+) -> Result<(), AppError> {
+    let limits: Vec<EffectiveLimit> = effective_limits(conn, config, user)?
+        .into_iter()
+        .filter(|limit| amounts.iter().any(|(metric, _)| *metric == limit.metric))
+        .collect();
+    if limits.is_empty() {
+        return Ok(());
+    }
+
+    for status in statuses(conn, user, &limits)? {
+        let amount = amounts
+            .iter()
+            .filter(|(metric, _)| *metric == status.metric)
+            .map(|(_, amount)| amount)
+            .sum();
+        if exceeds(status.used, amount, status.limit) {
+            info!(
+                "Quota exceeded for user {}: {:?} {:?} used {} of {}",
+                user.id, status.metric, status.period, status.used, status.limit
+            );
+            return Err(AppError::QuotaExceeded(format!(
+                "{} limit per {} reached ({} of {})",
+                status.metric.as_str().to_lowercase().replace('_', " "),
+                status.period.as_str().to_lowercase(),
+                status.used,
+                status.limit
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Check `amounts` like `check`, run `create` and record the chats,
+/// messages, images and documents among them, all in one transaction.
+/// The user's row is written first: concurrent reservations of the user
+/// wait for this one to commit and then count what it recorded.
+pub fn reserve<T>(
+    conn: &mut DbConnection,
+    config: &AppConfig,
+    user: &User,
+    amounts: &[(QuotaMetric, f64)],
+    create: impl FnOnce(&mut DbConnection) -> Result<T, AppError>,
+) -> Result<T, AppError> {
+    conn.transaction(|conn| {
+        diesel::update(users::table.filter(users::id.eq(&user.id)))
+            .set(users::updated_at.eq(users::updated_at))
+            .execute(conn)?;
+        check(conn, config, user, amounts)?;
+        let created = create(conn)?;
+        for (metric, amount) in amounts {
+            if is_counted_creation(*metric) && *amount > 0.0 {
+                record(conn, &user.id, *metric, *amount)?;
+            }
+        }
+        Ok(created)
+    })
+}
+
+/// Metrics counted from the `quota_usage` ledger
+fn is_counted_creation(metric: QuotaMetric) -> bool {
+    matches!(
+        metric,
+        QuotaMetric::Chats | QuotaMetric::Messages | QuotaMetric::Images | QuotaMetric::Documents
+    )
+}
+
+/// `DEMO_MAX_CHAT_MESSAGES`: messages one chat can hold (`user` role).
+pub fn check_chat_messages(
+    conn: &mut DbConnection,
+    config: &AppConfig,
+    user: &User,
+    chat_id: &str,
+) -> Result<(), AppError> {
+    let Some(max) = config.demo_max_chat_messages.filter(|max| *max >= 0) else {
+        return Ok(());
+    };
+    if user.role != ROLE_USER {
+        return Ok(());
+    }
+    let count: i64 = messages::table
+        .filter(messages::chat_id.eq(chat_id))
+        .count()
+        .get_result(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    if count >= max as i64 {
+        return Err(AppError::QuotaExceeded(format!(
+            "chat messages limit reached ({} of {})",
+            count, max
+        )));
+    }
+    Ok(())
+}
+
+/// Start counting the user's usage of `metric` (every metric but storage
+/// when `None`) from now.
+pub fn reset(
+    conn: &mut DbConnection,
+    user_id: &str,
+    metric: Option<QuotaMetric>,
+    reset_by: &str,
+) -> Result<(), AppError> {
+    let now = Utc::now().naive_utc();
+    let metrics = QuotaMetric::ALL
+        .into_iter()
+        .filter(|m| metric.map_or(*m != QuotaMetric::StorageBytes, |metric| metric == *m));
+    // one row at a time: batch inserts are not supported across backends
+    for metric in metrics {
+        diesel::insert_into(quota_resets::table)
+            .values(&QuotaReset {
+                id: Uuid::new_v4().to_string(),
+                user_id: user_id.to_string(),
+                metric: metric.as_str().to_string(),
+                created_by: Some(reset_by.to_string()),
+                created_at: now,
+            })
+            .execute(conn)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(role: &str) -> User {
+        let now = Utc::now().naive_utc();
+        User {
+            id: "user-1".to_string(),
+            email: "user@example.com".to_string(),
+            password: None,
+            first_name: String::new(),
+            last_name: String::new(),
+            role: role.to_string(),
+            default_model_id: None,
+            default_system_prompt: None,
+            avatar_url: None,
+            google_id: None,
+            github_id: None,
+            microsoft_id: None,
+            auth_provider: None,
+            settings: None,
+            models_count: None,
+            documents_embeddings_model_id: None,
+            document_summarization_model_id: None,
+            chats_count: None,
+            default_temperature: None,
+            default_max_tokens: None,
+            default_top_p: None,
+            default_images_count: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn row(scope: QuotaScope, subject: &str, metric: QuotaMetric, limit: f64) -> QuotaLimit {
+        let now = Utc::now().naive_utc();
+        QuotaLimit {
+            id: Uuid::new_v4().to_string(),
+            scope: scope.as_str().to_string(),
+            subject: subject.to_string(),
+            metric: metric.as_str().to_string(),
+            period: QuotaPeriod::Total.as_str().to_string(),
+            limit_value: limit,
+            created_by: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[test]
+    fn user_limits_override_role_and_config() {
+        let mut config = AppConfig::from_env();
+        config.demo_max_chats = Some(5);
+        config.demo_max_images = Some(10);
+        let user = user(ROLE_USER);
+        let rows = vec![
+            // the user row wins regardless of order
+            row(QuotaScope::User, "user-1", QuotaMetric::Chats, 20.0),
+            row(QuotaScope::Role, ROLE_USER, QuotaMetric::Chats, 8.0),
+            // negative lifts the config limit
+            row(QuotaScope::Role, ROLE_USER, QuotaMetric::Images, -1.0),
+            row(QuotaScope::Role, "admin", QuotaMetric::Messages, 1.0),
+            row(QuotaScope::User, "user-2", QuotaMetric::Tokens, 1.0),
+        ];
+
+        let limits = merge_limits(config_limits(&config, &user.role), &rows, &user);
+        assert_eq!(limits.len(), 1);
+        assert_eq!(limits[0].metric, QuotaMetric::Chats);
+        assert_eq!(limits[0].limit, 20.0);
+        assert_eq!(limits[0].source, QuotaSource::User);
+
+        // config defaults leave admins alone
+        assert!(config_limits(&config, "admin").is_empty());
+    }
+
+    #[test]
+    fn period_bounds_are_utc_calendar_periods() {
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        let now = at("2026-12-17 15:30:00");
+
+        assert_eq!(
+            period_bounds(QuotaPeriod::Day, now),
+            (
+                Some(at("2026-12-17 00:00:00")),
+                Some(at("2026-12-18 00:00:00"))
+            )
+        );
+        assert_eq!(
+            period_bounds(QuotaPeriod::Month, now),
+            (
+                Some(at("2026-12-01 00:00:00")),
+                Some(at("2027-01-01 00:00:00"))
+            )
+        );
+        assert_eq!(period_bounds(QuotaPeriod::Total, now), (None, None));
+    }
+
+    #[test]
+    fn exceeds_counts_the_request_or_the_reached_limit() {
+        assert!(!exceeds(4.0, 1.0, 5.0));
+        assert!(exceeds(5.0, 1.0, 5.0));
+        assert!(exceeds(3.0, 4.0, 5.0));
+        // tokens / spend: blocked once reached
+        assert!(!exceeds(4.99, 0.0, 5.0));
+        assert!(exceeds(5.0, 0.0, 5.0));
+    }
+}
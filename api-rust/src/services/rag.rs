@@ -2,20 +2,33 @@
 //! cosine similarity (embeddings are stored as JSON arrays for every
 //! backend) and build the structured-answer prompt. Mirrors the Node
 //! API's EmbeddingsService.findChunks + RAG_REQUEST.
+//!
+//! The same retrieval is also a model-callable tool (`search_documents`),
+//! so that a chat model can search the chat's documents several times,
+//! with refined queries, within its normal tool loop.
 
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::collections::HashMap;
+use std::fmt;
 use tracing::{debug, warn};
 
-use crate::database::DbConnection;
+use crate::config::AppConfig;
+use crate::database::{DbConnection, DbPool};
 use crate::models::document::{Document, DocumentChunk};
-use crate::models::Model;
+use crate::models::{MessageRelevantChunk, Model};
 use crate::schema::{document_chunks, documents, models};
-use crate::services::ai::{AIProviderService, AIService};
+use crate::services::ai::{AIProviderService, AIService, ExecutableTool, ToolBackend, ToolSpec};
+use crate::services::structured;
 use crate::utils::errors::AppError;
 
 pub const RAG_QUERY_CHUNKS_LIMIT: usize = 10;
 
+pub const SEARCH_DOCUMENTS_TOOL_NAME: &str = "search_documents";
+/// Most chunks one document search returns
+const SEARCH_DOCUMENTS_MAX_LIMIT: usize = 30;
+
 /// A chunk selected for the RAG context.
 #[derive(Debug, Clone)]
 pub struct RankedChunk {
@@ -140,6 +153,193 @@ fn cosine_similarity(a: &[f32], b: &[f32]) -> Option<f32> {
     (denominator > 0.0).then(|| dot / denominator)
 }
 
+/// What the document search tool needs to run: the database and the
+/// user's effective config (for the embeddings models).
+#[derive(Clone)]
+pub struct DocumentSearchContext {
+    pub db_pool: DbPool,
+    pub config: AppConfig,
+}
+
+impl fmt::Debug for DocumentSearchContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DocumentSearchContext")
+            .finish_non_exhaustive()
+    }
+}
+
+/// The document search tool over the user's `documents` (id, file name).
+pub fn search_documents_tool(
+    context: DocumentSearchContext,
+    user_id: &str,
+    documents: &[(String, String)],
+) -> ExecutableTool {
+    let listed = documents
+        .iter()
+        .map(|(id, name)| format!("- {} (id: {})", name, id))
+        .collect::<Vec<_>>()
+        .join("\n");
+    ExecutableTool {
+        spec: ToolSpec {
+            name: SEARCH_DOCUMENTS_TOOL_NAME.to_string(),
+            description: format!(
+                "Search the documents attached to the chat by meaning and return the most \
+                 relevant chunks. Search again with refined queries when the results are not \
+                 enough, and cite the chunks the answer uses as [chunk:<id>]. Documents:\n{}",
+                listed
+            ),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "What to look for" },
+                    "document_ids": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Search these documents only (default: all of them)"
+                    },
+                    "limit": {
+                        "type": "number",
+                        "description": format!(
+                            "Maximum number of chunks to return (default {}, at most {})",
+                            RAG_QUERY_CHUNKS_LIMIT, SEARCH_DOCUMENTS_MAX_LIMIT
+                        )
+                    },
+                },
+                "required": ["query"],
+            }),
+        },
+        backend: ToolBackend::DocumentSearch {
+            user_id: user_id.to_string(),
+            document_ids: documents.iter().map(|(id, _)| id.clone()).collect(),
+            context: Some(Box::new(context)),
+        },
+        requires_approval: false,
+        timeout: None,
+    }
+}
+
+/// A chunk as returned by the document search tool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FoundChunk {
+    id: String,
+    document_id: String,
+    document_name: Option<String>,
+    page: i32,
+    page_index: i64,
+    relevance: f32,
+    content: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SearchResults {
+    chunks: Vec<FoundChunk>,
+}
+
+/// Run a `search_documents` call: rank the chunks of the tool's documents
+/// (or the requested subset of them) against the query.
+pub async fn search_documents(
+    context: &DocumentSearchContext,
+    user_id: &str,
+    document_ids: &[String],
+    arguments: &Value,
+) -> Result<String, AppError> {
+    let Some(query) = arguments
+        .get("query")
+        .and_then(|q| q.as_str())
+        .filter(|q| !q.trim().is_empty())
+    else {
+        return Ok("Error: Invalid 'query' argument for document search tool.".to_string());
+    };
+    let requested: Option<Vec<&str>> = arguments
+        .get("document_ids")
+        .and_then(|ids| ids.as_array())
+        .map(|ids| ids.iter().filter_map(|id| id.as_str()).collect())
+        .filter(|ids: &Vec<&str>| !ids.is_empty());
+    let document_ids: Vec<String> = match requested {
+        Some(requested) => document_ids
+            .iter()
+            .filter(|id| requested.contains(&id.as_str()))
+            .cloned()
+            .collect(),
+        None => document_ids.to_vec(),
+    };
+    if document_ids.is_empty() {
+        return Ok("Error: None of the requested documents is attached to the chat.".to_string());
+    }
+    let limit = arguments
+        .get("limit")
+        .and_then(|l| l.as_u64())
+        .map(|l| (l as usize).clamp(1, SEARCH_DOCUMENTS_MAX_LIMIT))
+        .unwrap_or(RAG_QUERY_CHUNKS_LIMIT);
+
+    debug!(
+        "Document search tool call: {} ({} documents, limit {})",
+        query,
+        document_ids.len(),
+        limit
+    );
+    let mut conn = context.db_pool.get()?;
+    let ai_service = AIService::new(context.config.clone());
+    let chunks = find_chunks(&mut conn, &ai_service, user_id, &document_ids, query, limit).await?;
+    if chunks.is_empty() {
+        return Ok(format!(
+            "No document content found for query: \"{}\"",
+            query
+        ));
+    }
+    Ok(chunks_to_tool_content(&chunks))
+}
+
+/// Format ranked chunks as the document search tool output (JSON, so that
+/// the cited ones can be picked out of it afterwards).
+pub fn chunks_to_tool_content(chunks: &[RankedChunk]) -> String {
+    let results = SearchResults {
+        chunks: chunks
+            .iter()
+            .map(|chunk| FoundChunk {
+                id: chunk.id.clone(),
+                document_id: chunk.document_id.clone(),
+                document_name: chunk.document_name.clone(),
+                page: chunk.page,
+                page_index: chunk.page_index,
+                relevance: chunk.relevance,
+                content: chunk.content.replace('\r', ""),
+            })
+            .collect(),
+    };
+    serde_json::to_string(&results).unwrap_or_default()
+}
+
+/// The chunks found by document search calls that the answer cites, for
+/// the message's `relevantsChunks`.
+pub fn cited_chunks<'a>(
+    tool_results: impl IntoIterator<Item = &'a str>,
+    answer: &str,
+) -> Vec<MessageRelevantChunk> {
+    let mut cited: Vec<MessageRelevantChunk> = Vec::new();
+    for result in tool_results {
+        let Ok(results) = serde_json::from_str::<SearchResults>(result) else {
+            continue;
+        };
+        for chunk in results.chunks {
+            if !answer.contains(&chunk.id) || cited.iter().any(|c| c.id == chunk.id) {
+                continue;
+            }
+            cited.push(MessageRelevantChunk {
+                id: chunk.id,
+                document_id: chunk.document_id,
+                document_name: chunk.document_name,
+                page: chunk.page as f64,
+                page_index: Some(chunk.page_index as f64),
+                content: chunk.content,
+                relevance: chunk.relevance as f64,
+            });
+        }
+    }
+    cited
+}
+
 /// Structured-answer schema embedded into the system prompt (Node's
 /// RAG_RESPONSE_SCHEMA).
 const RAG_RESPONSE_SCHEMA: &str = r#"{
@@ -218,12 +418,7 @@ pub fn rag_request(chunks: &[RankedChunk], question: &str) -> RagPrompt {
 /// Extract the JSON object from a model answer that may be wrapped in
 /// markdown fences or prefixed with commentary.
 pub fn extract_rag_json(content: &str) -> Option<serde_json::Value> {
-    let start = content.find('{')?;
-    let end = content.rfind('}')?;
-    if end < start {
-        return None;
-    }
-    let mut parsed: serde_json::Value = serde_json::from_str(&content[start..=end]).ok()?;
+    let mut parsed = structured::extract_json(content)?;
 
     // Some models return the schema structure with embedded `value` fields
     // instead of a flat object — flatten that format (Node parity)
@@ -260,6 +455,33 @@ mod tests {
         assert_eq!(parsed["final_answer"], "42");
     }
 
+    fn ranked(id: &str, content: &str) -> RankedChunk {
+        RankedChunk {
+            id: id.to_string(),
+            document_id: "doc-1".to_string(),
+            document_name: Some("manual.pdf".to_string()),
+            page: 3,
+            page_index: 1,
+            content: content.to_string(),
+            relevance: 0.8,
+        }
+    }
+
+    #[test]
+    fn cited_chunks_come_from_the_search_results() {
+        let first =
+            chunks_to_tool_content(&[ranked("chunk-1", "Reset"), ranked("chunk-2", "Boot")]);
+        let second =
+            chunks_to_tool_content(&[ranked("chunk-1", "Reset"), ranked("chunk-3", "Wipe")]);
+        let answer = "Hold the button [chunk:chunk-1], then wipe it [chunk:chunk-3].";
+
+        let cited = cited_chunks([first.as_str(), "not json", second.as_str()], answer);
+        let ids: Vec<&str> = cited.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, vec!["chunk-1", "chunk-3"]);
+        assert_eq!(cited[0].document_name.as_deref(), Some("manual.pdf"));
+        assert_eq!(cited[0].page, 3.0);
+    }
+
     #[test]
     fn flattens_schema_value_format() {
         let raw = r#"{"schema": {"properties": {"final_answer": {"value": "yes"}}}}"#;
@@ -0,0 +1,305 @@
+//! Retry policy for transient provider failures (rate limits, 5xx,
+//! timeouts): jittered exponential backoff that honours `Retry-After` /
+//! `x-ratelimit-reset*` hints, bounded by a max attempts count and a
+//! per-request deadline. Shared by the OpenAI-protocol clients (OpenAI,
+//! Yandex, custom models) and Bedrock.
+
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one; 1 disables retries.
+    pub max_attempts: u32,
+    /// Backoff before the first retry; doubles on every further one.
+    pub base_delay_ms: u64,
+    /// Cap of a single backoff (server hints are not capped).
+    pub max_delay_ms: u64,
+    /// No retry is scheduled past this much time since the first attempt;
+    /// Bedrock operations time out at it.
+    pub deadline_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay_ms: 500,
+            max_delay_ms: 8_000,
+            deadline_ms: 60_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(default)
+        }
+        let defaults = Self::default();
+        Self {
+            max_attempts: var("AI_RETRY_MAX_ATTEMPTS", defaults.max_attempts).max(1),
+            base_delay_ms: var("AI_RETRY_BASE_DELAY_MS", defaults.base_delay_ms),
+            max_delay_ms: var("AI_RETRY_MAX_DELAY_MS", defaults.max_delay_ms),
+            deadline_ms: var("AI_RETRY_DEADLINE_MS", defaults.deadline_ms),
+        }
+    }
+
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Jittered exponential backoff before retry number `retry` (0-based):
+    /// a random point in the upper half of `base * 2^retry`, capped.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exp = self
+            .base_delay_ms
+            .saturating_mul(1u64 << retry.min(16))
+            .min(self.max_delay_ms);
+        let jitter = (uuid::Uuid::new_v4().as_u128() % 1000) as u64;
+        Duration::from_millis(exp / 2 + exp / 2 * jitter / 1000)
+    }
+
+    pub fn start(&self) -> RetryState<'_> {
+        RetryState {
+            policy: self,
+            retries: 0,
+            started: Instant::now(),
+        }
+    }
+}
+
+/// Attempt bookkeeping for one logical request.
+pub struct RetryState<'a> {
+    policy: &'a RetryPolicy,
+    retries: u32,
+    started: Instant,
+}
+
+impl RetryState<'_> {
+    /// Delay before the next attempt — the server's hint when given,
+    /// otherwise the backoff — or `None` once attempts or the deadline are
+    /// exhausted.
+    pub fn next_delay(&mut self, hint: Option<Duration>) -> Option<Duration> {
+        if self.retries + 1 >= self.policy.max_attempts {
+            return None;
+        }
+        let delay = hint.unwrap_or_else(|| self.policy.backoff(self.retries));
+        if self.started.elapsed() + delay > Duration::from_millis(self.policy.deadline_ms) {
+            return None;
+        }
+        self.retries += 1;
+        Some(delay)
+    }
+}
+
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 408 | 429 | 500 | 502 | 503 | 504 | 529)
+}
+
+/// Bedrock (and other AWS) error codes worth retrying.
+pub fn is_retryable_aws_code(code: Option<&str>) -> bool {
+    matches!(
+        code,
+        Some(
+            "ThrottlingException"
+                | "ServiceUnavailableException"
+                | "InternalServerException"
+                | "ModelNotReadyException"
+                | "ModelTimeoutException"
+        )
+    )
+}
+
+/// Server-provided wait: `retry-after-ms`, `Retry-After` (seconds or an
+/// HTTP date), then the rate-limit reset headers (`x-ratelimit-reset` as
+/// seconds or a unix timestamp, OpenAI's `x-ratelimit-reset-requests` /
+/// `-tokens` as durations like `1s`, `6m0s`, `20ms`).
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name)?.to_str().ok().map(str::trim);
+
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.parse::<f64>().ok()) {
+        return Some(Duration::from_millis(ms.max(0.0) as u64));
+    }
+    if let Some(value) = header("retry-after") {
+        if let Ok(seconds) = value.parse::<f64>() {
+            return Some(Duration::from_secs_f64(seconds.max(0.0)));
+        }
+        if let Ok(date) = chrono::DateTime::parse_from_rfc2822(value) {
+            let wait = date.timestamp_millis() - chrono::Utc::now().timestamp_millis();
+            return Some(Duration::from_millis(wait.max(0) as u64));
+        }
+    }
+    if let Some(value) = header("x-ratelimit-reset").and_then(|v| v.parse::<f64>().ok()) {
+        // Large values are absolute unix timestamps
+        let seconds = if value > 1_000_000_000.0 {
+            value - chrono::Utc::now().timestamp() as f64
+        } else {
+            value
+        };
+        return Some(Duration::from_secs_f64(seconds.max(0.0)));
+    }
+    ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .iter()
+        .filter_map(|name| header(name).and_then(parse_go_duration))
+        .max()
+}
+
+/// `1h2m3.5s` / `20ms`-style durations.
+fn parse_go_duration(value: &str) -> Option<Duration> {
+    let mut total = 0f64;
+    let mut number = String::new();
+    let mut chars = value.chars().peekable();
+    let mut parsed = false;
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let amount: f64 = number.parse().ok()?;
+        number.clear();
+        let seconds = match c {
+            'h' => amount * 3600.0,
+            'm' if chars.peek() == Some(&'s') => {
+                chars.next();
+                amount / 1000.0
+            }
+            'm' => amount * 60.0,
+            's' => amount,
+            _ => return None,
+        };
+        total += seconds;
+        parsed = true;
+    }
+    if !number.is_empty() {
+        // A bare number means seconds
+        total += number.parse::<f64>().ok()?;
+        parsed = true;
+    }
+    parsed.then(|| Duration::from_secs_f64(total))
+}
+
+/// Send a request built by `build`, retrying transport timeouts / connect
+/// failures and retryable statuses per `policy`. Returns the last response
+/// even when its status is an error, so callers keep their own error
+/// mapping.
+pub async fn send_with_retry<F>(
+    policy: &RetryPolicy,
+    label: &str,
+    build: F,
+) -> Result<reqwest::Response, reqwest::Error>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+{
+    let mut state = policy.start();
+    loop {
+        match build().send().await {
+            Ok(response) if is_retryable_status(response.status()) => {
+                let Some(delay) = state.next_delay(retry_after(response.headers())) else {
+                    return Ok(response);
+                };
+                warn!("{}: {} — retrying in {:?}", label, response.status(), delay);
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) if e.is_timeout() || e.is_connect() => {
+                let Some(delay) = state.next_delay(None) else {
+                    return Err(e);
+                };
+                warn!("{}: {} — retrying in {:?}", label, e, delay);
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{StubResponse, StubServer};
+    use reqwest::header::HeaderValue;
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 1,
+            max_delay_ms: 5,
+            deadline_ms: 5_000,
+        }
+    }
+
+    #[test]
+    fn parses_retry_hints() {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("2"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(2)));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after-ms", HeaderValue::from_static("150"));
+        headers.insert("retry-after", HeaderValue::from_static("2"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_millis(150)));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-reset-requests", HeaderValue::from_static("1s"));
+        headers.insert("x-ratelimit-reset-tokens", HeaderValue::from_static("6m0s"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(360)));
+
+        assert_eq!(parse_go_duration("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_go_duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_go_duration("soon"), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn state_respects_attempts_and_deadline() {
+        let policy = fast_policy();
+        let mut state = policy.start();
+        assert!(state.next_delay(None).is_some());
+        assert!(state.next_delay(None).is_some());
+        assert!(state.next_delay(None).is_none());
+
+        let mut state = policy.start();
+        assert!(state.next_delay(Some(Duration::from_secs(10))).is_none());
+
+        let backoff = RetryPolicy::default().backoff(10);
+        assert!(backoff <= Duration::from_millis(8_000));
+        assert!(backoff >= Duration::from_millis(4_000));
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limited_requests() {
+        let server = StubServer::start(vec![
+            StubResponse::text(429, "application/json", "{}").with_header("retry-after-ms", "1"),
+            StubResponse::text(503, "application/json", "{}"),
+            StubResponse::json(serde_json::json!({"ok": true})),
+        ])
+        .await;
+        let client = reqwest::Client::new();
+
+        let response = send_with_retry(&fast_policy(), "test", || client.get(&server.base_url))
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(server.requests().len(), 3);
+
+        // Non-retryable statuses come straight back
+        let server =
+            StubServer::start(vec![StubResponse::text(400, "application/json", "{}")]).await;
+        let response = send_with_retry(&fast_policy(), "test", || client.get(&server.base_url))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(server.requests().len(), 1);
+    }
+}
@@ -0,0 +1,550 @@
+//! Search engines behind the web search tool. Each backend turns a query
+//! into the same `SearchResult` list: the Yandex Search API v2 (base64 XML,
+//! Node parity), a SearXNG instance (`format=json`), the Brave Search API,
+//! Tavily and a generic JSON endpoint. The backend comes from `AppConfig`
+//! (`WEB_SEARCH_BACKEND`), which per-user settings can override together
+//! with their own URL or key (a user's URL must resolve to a public
+//! address); without an explicit choice Yandex is used when its
+//! credentials are configured.
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::warn;
+use url::{Host, Url};
+
+use crate::config::AppConfig;
+use crate::services::web_search::{self, strip_tags, SearchResult};
+use crate::utils::errors::AppError;
+
+const YANDEX_SEARCH_API_URL: &str = "https://searchapi.api.cloud.yandex.net/v2/web/search";
+const BRAVE_SEARCH_API_URL: &str = "https://api.search.brave.com/res/v1/web/search";
+const TAVILY_SEARCH_API_URL: &str = "https://api.tavily.com/search";
+/// Most results one search returns, whatever the model asks for
+pub const MAX_RESULTS_LIMIT: usize = 20;
+
+/// A configured search engine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SearchBackend {
+    Yandex {
+        api_key: String,
+        folder_id: String,
+        api_url: Option<String>,
+    },
+    /// A SearXNG instance with the JSON output format enabled
+    Searxng {
+        api_url: String,
+        api_key: Option<String>,
+        /// The URL comes from a user: only public addresses are called
+        #[serde(default)]
+        public_only: bool,
+    },
+    Brave {
+        api_key: String,
+        api_url: Option<String>,
+        #[serde(default)]
+        public_only: bool,
+    },
+    Tavily {
+        api_key: String,
+        api_url: Option<String>,
+        #[serde(default)]
+        public_only: bool,
+    },
+    /// Any endpoint answering `GET ?q=&limit=` with a JSON list of results
+    Json {
+        api_url: String,
+        api_key: Option<String>,
+        /// Dot path of the results array, e.g. `data.items`
+        results_path: Option<String>,
+        #[serde(default)]
+        public_only: bool,
+    },
+}
+
+impl SearchBackend {
+    /// The backend selected by the (effective) config, if it is usable
+    pub fn from_config(config: &AppConfig) -> Option<Self> {
+        let api_url = config.web_search_api_url.clone();
+        let api_key = config.web_search_api_key.clone();
+        let public_only = config.web_search_user_url;
+        let name = config
+            .web_search_backend
+            .as_deref()
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty());
+
+        match name.as_deref() {
+            None | Some("yandex") => Some(Self::Yandex {
+                api_key: config.yandex_search_api_key.clone()?,
+                folder_id: config.yandex_folder_id.clone()?,
+                api_url: config.yandex_search_api_url.clone(),
+            }),
+            Some("searxng") => Some(Self::Searxng {
+                api_url: api_url?,
+                api_key,
+                public_only,
+            }),
+            Some("brave") => Some(Self::Brave {
+                api_key: api_key?,
+                api_url,
+                public_only,
+            }),
+            Some("tavily") => Some(Self::Tavily {
+                api_key: api_key?,
+                api_url,
+                public_only,
+            }),
+            Some("json") => Some(Self::Json {
+                api_url: api_url?,
+                api_key,
+                results_path: config.web_search_results_path.clone(),
+                public_only,
+            }),
+            Some(other) => {
+                warn!("Unknown web search backend: {}", other);
+                None
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Yandex { .. } => "yandex",
+            Self::Searxng { .. } => "searxng",
+            Self::Brave { .. } => "brave",
+            Self::Tavily { .. } => "tavily",
+            Self::Json { .. } => "json",
+        }
+    }
+
+    /// The endpoint called and whether it must be a public address.
+    fn endpoint(&self) -> (&str, bool) {
+        match self {
+            Self::Yandex { api_url, .. } => {
+                (api_url.as_deref().unwrap_or(YANDEX_SEARCH_API_URL), false)
+            }
+            Self::Searxng {
+                api_url,
+                public_only,
+                ..
+            }
+            | Self::Json {
+                api_url,
+                public_only,
+                ..
+            } => (api_url, *public_only),
+            Self::Brave {
+                api_url,
+                public_only,
+                ..
+            } => (
+                api_url.as_deref().unwrap_or(BRAVE_SEARCH_API_URL),
+                *public_only,
+            ),
+            Self::Tavily {
+                api_url,
+                public_only,
+                ..
+            } => (
+                api_url.as_deref().unwrap_or(TAVILY_SEARCH_API_URL),
+                *public_only,
+            ),
+        }
+    }
+
+    /// Search the web; at most `limit` results (capped at
+    /// `MAX_RESULTS_LIMIT`).
+    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, AppError> {
+        let limit = limit.clamp(1, MAX_RESULTS_LIMIT);
+        let (endpoint, public_only) = self.endpoint();
+        let client = if public_only {
+            public_client(endpoint).await?
+        } else {
+            reqwest::Client::new()
+        };
+        let mut results = match self {
+            Self::Yandex {
+                api_key,
+                folder_id,
+                api_url,
+            } => {
+                let request = client
+                    .post(api_url.as_deref().unwrap_or(YANDEX_SEARCH_API_URL))
+                    .header("Authorization", format!("Api-Key {}", api_key))
+                    .json(&json!({
+                        "query": { "searchType": "SEARCH_TYPE_COM", "queryText": query },
+                        "folderId": folder_id,
+                        "maxPassages": 5,
+                        "docsInGroup": 3,
+                        "l10n": "LOCALIZATION_EN",
+                        "responseFormat": "FORMAT_XML",
+                    }));
+                yandex_results(&send(request, public_only).await?)?
+            }
+            Self::Searxng {
+                api_url, api_key, ..
+            } => {
+                let base = api_url.trim_end_matches('/');
+                let url = if base.ends_with("/search") {
+                    base.to_string()
+                } else {
+                    format!("{}/search", base)
+                };
+                let request = client.get(url).query(&[("q", query), ("format", "json")]);
+                let payload = send(with_bearer(request, api_key.as_deref()), public_only).await?;
+                json_results(&payload, Some("results"))
+            }
+            Self::Brave {
+                api_key, api_url, ..
+            } => {
+                let request = client
+                    .get(api_url.as_deref().unwrap_or(BRAVE_SEARCH_API_URL))
+                    .header("X-Subscription-Token", api_key)
+                    .query(&[("q", query), ("count", &limit.to_string())]);
+                json_results(&send(request, public_only).await?, Some("web.results"))
+            }
+            Self::Tavily {
+                api_key, api_url, ..
+            } => {
+                let request = client
+                    .post(api_url.as_deref().unwrap_or(TAVILY_SEARCH_API_URL))
+                    .bearer_auth(api_key)
+                    .json(&json!({ "query": query, "max_results": limit }));
+                json_results(&send(request, public_only).await?, Some("results"))
+            }
+            Self::Json {
+                api_url,
+                api_key,
+                results_path,
+                ..
+            } => {
+                let request = client
+                    .get(api_url)
+                    .query(&[("q", query), ("limit", &limit.to_string())]);
+                let payload = send(with_bearer(request, api_key.as_deref()), public_only).await?;
+                json_results(&payload, results_path.as_deref())
+            }
+        };
+        results.truncate(limit);
+        Ok(results)
+    }
+}
+
+fn with_bearer(request: reqwest::RequestBuilder, api_key: Option<&str>) -> reqwest::RequestBuilder {
+    match api_key {
+        Some(api_key) => request.bearer_auth(api_key),
+        None => request,
+    }
+}
+
+/// A client calling a user's endpoint: the host must resolve to public
+/// addresses only, it is connected to the address that was checked, and
+/// redirects are not followed.
+async fn public_client(endpoint: &str) -> Result<reqwest::Client, AppError> {
+    let url = Url::parse(endpoint)
+        .map_err(|e| AppError::BadRequest(format!("Invalid web search URL: {}", e)))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(AppError::BadRequest(format!(
+            "Unsupported web search URL scheme: {}",
+            url.scheme()
+        )));
+    }
+    let address = web_search::checked_address(&url, false).await?;
+    let mut builder = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+    if let Some(Host::Domain(domain)) = url.host() {
+        builder = builder.resolve(domain, address);
+    }
+    builder
+        .build()
+        .map_err(|e| AppError::Internal(format!("Web search client: {}", e)))
+}
+
+/// Send a search request and parse its JSON answer. The error text of a
+/// user's endpoint is not passed on, only its status.
+async fn send(request: reqwest::RequestBuilder, public_only: bool) -> Result<Value, AppError> {
+    let response = request
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(|e| AppError::Http(format!("Web search request failed: {}", e)))?;
+    let status = response.status();
+    let payload: Value = response.json().await.unwrap_or(Value::Null);
+    if !status.is_success() {
+        let reason = status.canonical_reason().unwrap_or("request failed");
+        let message = ["message", "detail", "error"]
+            .iter()
+            .find_map(|key| payload.get(key).and_then(Value::as_str))
+            .filter(|_| !public_only)
+            .unwrap_or(reason);
+        return Err(AppError::Http(format!(
+            "Web search API error ({}): {}",
+            status.as_u16(),
+            message
+        )));
+    }
+    Ok(payload)
+}
+
+fn yandex_results(payload: &Value) -> Result<Vec<SearchResult>, AppError> {
+    let Some(raw) = payload.get("rawData").and_then(|v| v.as_str()) else {
+        let message = payload
+            .get("message")
+            .and_then(|m| m.as_str())
+            .unwrap_or("no rawData in response");
+        return Err(AppError::Http(format!("Web search API error: {}", message)));
+    };
+
+    let xml = base64::engine::general_purpose::STANDARD
+        .decode(raw)
+        .ok()
+        .and_then(|b| String::from_utf8(b).ok())
+        .ok_or_else(|| AppError::Internal("Invalid web search rawData".to_string()))?;
+
+    Ok(extract_results(&xml))
+}
+
+fn extract_results(xml: &str) -> Vec<SearchResult> {
+    let mut results = Vec::new();
+    // Each result doc looks like <doc …>…<url>…</url>…<title>…</title>…
+    // <passages>…</passages>…</doc>; hlword markup is stripped.
+    for doc in xml.split("<doc").skip(1) {
+        let doc = doc.split("</doc>").next().unwrap_or("");
+        let Some(url) = tag_text(doc, "url") else {
+            continue;
+        };
+        let title = tag_text(doc, "title").unwrap_or_else(|| url.clone());
+        let domain = tag_text(doc, "domain").unwrap_or_else(|| domain_of(&url));
+        let passages: Vec<String> = doc
+            .split("<passage>")
+            .skip(1)
+            .filter_map(|p| p.split("</passage>").next())
+            .map(strip_tags)
+            .filter(|p| !p.is_empty())
+            .collect();
+
+        results.push(SearchResult {
+            title: strip_tags(&title),
+            url,
+            domain,
+            summary: (!passages.is_empty()).then(|| passages.join(" ")),
+        });
+    }
+    results
+}
+
+fn tag_text(source: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = source.find(&open)? + open.len();
+    let end = source[start..].find(&close)? + start;
+    Some(source[start..end].trim().to_string())
+}
+
+/// Results of a JSON answer: the array at `path` (a top-level array, or
+/// one of the usual `results` / `items` / `data` keys without a path),
+/// items read with the field names common to search APIs.
+fn json_results(payload: &Value, path: Option<&str>) -> Vec<SearchResult> {
+    let items = match path {
+        Some(path) => path
+            .split('.')
+            .filter(|key| !key.is_empty())
+            .try_fold(payload, |value, key| value.get(key)),
+        None if payload.is_array() => Some(payload),
+        None => ["results", "items", "data"]
+            .iter()
+            .find_map(|key| payload.get(key).filter(|v| v.is_array())),
+    };
+    let Some(items) = items.and_then(Value::as_array) else {
+        return Vec::new();
+    };
+
+    let field = |item: &Value, keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| item.get(key).and_then(Value::as_str))
+            .map(strip_tags)
+            .filter(|text| !text.is_empty())
+    };
+    items
+        .iter()
+        .filter_map(|item| {
+            let url = field(item, &["url", "link", "href"])?;
+            Some(SearchResult {
+                title: field(item, &["title", "name"]).unwrap_or_else(|| url.clone()),
+                domain: domain_of(&url),
+                summary: field(item, &["summary", "snippet", "description", "content"]),
+                url,
+            })
+        })
+        .collect()
+}
+
+fn domain_of(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_else(|| url.split('/').nth(2).unwrap_or_default().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{StubResponse, StubServer};
+
+    fn config(backend: &str, api_url: Option<&str>, api_key: Option<&str>) -> AppConfig {
+        let mut config = AppConfig::from_env();
+        config.yandex_search_api_key = None;
+        config.web_search_backend = Some(backend.to_string());
+        config.web_search_api_url = api_url.map(String::from);
+        config.web_search_api_key = api_key.map(String::from);
+        config.web_search_results_path = None;
+        config
+    }
+
+    #[test]
+    fn backend_comes_from_the_config() {
+        let mut yandex = config("", None, None);
+        assert_eq!(SearchBackend::from_config(&yandex), None);
+        yandex.yandex_search_api_key = Some("key".to_string());
+        yandex.yandex_folder_id = Some("folder".to_string());
+        assert_eq!(
+            SearchBackend::from_config(&yandex).unwrap().name(),
+            "yandex"
+        );
+
+        // Missing credentials or an unknown name disable the tool
+        assert_eq!(
+            SearchBackend::from_config(&config("brave", None, None)),
+            None
+        );
+        assert_eq!(
+            SearchBackend::from_config(&config("bing", None, Some("k"))),
+            None
+        );
+        assert_eq!(
+            SearchBackend::from_config(&config("Tavily", None, Some("key"))),
+            Some(SearchBackend::Tavily {
+                api_key: "key".to_string(),
+                api_url: None,
+                public_only: false,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn user_urls_must_be_public() {
+        let stub = StubServer::start(vec![StubResponse::json(json!({ "results": [] }))]).await;
+        let mut user = config("searxng", Some(&stub.base_url), None);
+        user.web_search_user_url = true;
+
+        let searxng = SearchBackend::from_config(&user).unwrap();
+        let error = searxng.search("metadata", 5).await.unwrap_err();
+        assert!(
+            matches!(&error, AppError::BadRequest(message) if message.contains("not a public address")),
+            "{error}"
+        );
+        assert!(stub.requests().is_empty());
+    }
+
+    #[test]
+    fn extracts_docs_from_yandex_xml() {
+        let xml = r#"<response><results><grouping><group>
+            <doc id="1"><url>https://example.com/a</url><domain>example.com</domain>
+              <title>Hello <hlword>world</hlword></title>
+              <passages><passage>First <hlword>match</hlword>.</passage><passage>Second.</passage></passages>
+            </doc></group><group>
+            <doc id="2"><url>https://other.io/b</url><title>Other</title></doc>
+            </group></grouping></results></response>"#;
+        let results = extract_results(xml);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].title, "Hello world");
+        assert_eq!(results[0].domain, "example.com");
+        assert_eq!(results[0].summary.as_deref(), Some("First match. Second."));
+        assert_eq!(results[1].domain, "other.io");
+        assert_eq!(results[1].summary, None);
+    }
+
+    #[tokio::test]
+    async fn searxng_and_brave_results() {
+        let stub = StubServer::start(vec![
+            StubResponse::json(json!({ "results": [
+                { "url": "https://docs.rs/tokio", "title": "tokio", "content": "Runtime" },
+                { "title": "no url" },
+            ]})),
+            StubResponse::json(json!({ "web": { "results": [
+                { "url": "https://www.rust-lang.org/", "title": "Rust",
+                  "description": "A <strong>language</strong>" },
+            ]}})),
+        ])
+        .await;
+
+        let searxng =
+            SearchBackend::from_config(&config("searxng", Some(&stub.base_url), None)).unwrap();
+        let results = searxng.search("async rust", 5).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].domain, "docs.rs");
+        assert_eq!(results[0].summary.as_deref(), Some("Runtime"));
+
+        let brave = SearchBackend::from_config(&config(
+            "brave",
+            Some(&format!("{}/brave", stub.base_url)),
+            Some("brave-key"),
+        ))
+        .unwrap();
+        let results = brave.search("rust", 50).await.unwrap();
+        assert_eq!(results[0].summary.as_deref(), Some("A language"));
+
+        let requests = stub.requests();
+        assert_eq!(
+            requests[0].request_line,
+            "GET /search?q=async+rust&format=json HTTP/1.1"
+        );
+        assert_eq!(requests[0].header("authorization"), None);
+        assert_eq!(
+            requests[1].request_line,
+            "GET /brave?q=rust&count=20 HTTP/1.1"
+        );
+        assert_eq!(
+            requests[1].header("x-subscription-token"),
+            Some("brave-key")
+        );
+    }
+
+    #[tokio::test]
+    async fn tavily_and_json_results() {
+        let stub = StubServer::start(vec![
+            StubResponse::json(json!({ "results": [
+                { "url": "https://tavily.com/a", "title": "A", "content": "About A" },
+            ]})),
+            StubResponse::json(json!({ "data": { "hits": [
+                { "link": "https://example.org/x", "name": "X", "snippet": "S1" },
+                { "link": "https://example.org/y", "name": "Y" },
+            ]}})),
+            StubResponse::text(401, "application/json", r#"{"detail":"Invalid API key"}"#),
+        ])
+        .await;
+
+        let tavily =
+            SearchBackend::from_config(&config("tavily", Some(&stub.base_url), Some("tvly-key")))
+                .unwrap();
+        let results = tavily.search("a", 3).await.unwrap();
+        assert_eq!(results[0].title, "A");
+
+        let mut json_config = config("json", Some(&format!("{}/find", stub.base_url)), None);
+        json_config.web_search_results_path = Some("data.hits".to_string());
+        let generic = SearchBackend::from_config(&json_config).unwrap();
+        let results = generic.search("x", 1).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].title, "X");
+        assert_eq!(results[0].domain, "example.org");
+
+        let error = tavily.search("a", 3).await.unwrap_err();
+        assert!(error.to_string().contains("Invalid API key"));
+
+        let requests = stub.requests();
+        assert_eq!(requests[0].header("authorization"), Some("Bearer tvly-key"));
+        assert_eq!(requests[0].json()["max_results"], 3);
+        assert_eq!(requests[1].request_line, "GET /find?q=x&limit=1 HTTP/1.1");
+    }
+}
@@ -0,0 +1,142 @@
+//! Side-by-side answers. A prompt sent to several models gets an assistant
+//! message per model, each linked to the prompt through
+//! `linked_to_message_id`. The prompt's `metadata.selectedAnswerId` names
+//! the answer later turns continue from, the earliest one until another is
+//! selected; the other answers stay in the chat but out of the context.
+
+use std::collections::HashMap;
+
+use chrono::Utc;
+use diesel::prelude::*;
+
+use crate::database::DbConnection;
+use crate::models::{Message, MessageMetadata};
+use crate::schema::messages;
+use crate::utils::errors::AppError;
+
+fn metadata(message: &Message) -> MessageMetadata {
+    message
+        .metadata
+        .as_deref()
+        .and_then(|m| serde_json::from_str(m).ok())
+        .unwrap_or_default()
+}
+
+/// Leave out the answers of a prompt other than its selected one.
+pub fn without_unselected_answers(messages: Vec<Message>) -> Vec<Message> {
+    let selected: HashMap<&str, String> = messages
+        .iter()
+        .filter_map(|m| Some((m.id.as_str(), metadata(m).selected_answer_id?)))
+        .collect();
+
+    // prompt id → the answer kept for it: the selected one, else the
+    // earliest
+    let mut kept: HashMap<&str, &Message> = HashMap::new();
+    for answer in &messages {
+        let Some(prompt_id) = answer.linked_to_message_id.as_deref() else {
+            continue;
+        };
+        let is_selected = |m: &Message| selected.get(prompt_id) == Some(&m.id);
+        let better = match kept.get(prompt_id) {
+            None => true,
+            Some(_) if is_selected(answer) => true,
+            Some(current) if is_selected(current) => false,
+            Some(current) => answer.created_at < current.created_at,
+        };
+        if better {
+            kept.insert(prompt_id, answer);
+        }
+    }
+
+    let kept_ids: Vec<String> = kept.values().map(|m| m.id.clone()).collect();
+    messages
+        .into_iter()
+        .filter(|m| m.linked_to_message_id.is_none() || kept_ids.contains(&m.id))
+        .collect()
+}
+
+/// Make `answer` the one its prompt continues from; returns the prompt.
+pub fn select_answer(conn: &mut DbConnection, answer: &Message) -> Result<Message, AppError> {
+    let prompt_id = answer
+        .linked_to_message_id
+        .as_deref()
+        .ok_or_else(|| AppError::Validation("Message is not a side-by-side answer".to_string()))?;
+    let mut prompt: Message = messages::table
+        .filter(messages::id.eq(prompt_id))
+        .first(conn)
+        .map_err(|_| AppError::NotFound(format!("Message not found, id: {}", prompt_id)))?;
+
+    let mut prompt_metadata = metadata(&prompt);
+    prompt_metadata.selected_answer_id = Some(answer.id.clone());
+    prompt.metadata = serde_json::to_string(&prompt_metadata).ok();
+    prompt.updated_at = Utc::now().naive_utc();
+
+    diesel::update(messages::table.filter(messages::id.eq(&prompt.id)))
+        .set((
+            messages::metadata.eq(&prompt.metadata),
+            messages::updated_at.eq(prompt.updated_at),
+        ))
+        .execute(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    Ok(prompt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn message(id: &str, role: &str, linked_to: Option<&str>, minute: i64) -> Message {
+        let mut message = Message::new(
+            "chat".to_string(),
+            None,
+            id.to_string(),
+            role.to_string(),
+            "model".to_string(),
+            None,
+        );
+        message.id = id.to_string();
+        message.linked_to_message_id = linked_to.map(str::to_string);
+        message.created_at += Duration::minutes(minute);
+        message
+    }
+
+    fn ids(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|m| m.id.as_str()).collect()
+    }
+
+    #[test]
+    fn keeps_the_earliest_answer_until_one_is_selected() {
+        let history = vec![
+            message("q1", "user", None, 0),
+            message("a1-b", "assistant", Some("q1"), 2),
+            message("a1-a", "assistant", Some("q1"), 1),
+            message("q2", "user", None, 3),
+            message("a2", "assistant", None, 4),
+        ];
+        assert_eq!(
+            ids(&without_unselected_answers(history)),
+            vec!["q1", "a1-a", "q2", "a2"]
+        );
+    }
+
+    #[test]
+    fn keeps_the_selected_answer() {
+        let mut prompt = message("q1", "user", None, 0);
+        prompt.metadata = serde_json::to_string(&MessageMetadata {
+            selected_answer_id: Some("a1-b".to_string()),
+            ..Default::default()
+        })
+        .ok();
+        let history = vec![
+            prompt,
+            message("a1-a", "assistant", Some("q1"), 1),
+            message("a1-b", "assistant", Some("q1"), 2),
+            message("a1-c", "assistant", Some("q1"), 3),
+        ];
+        assert_eq!(
+            ids(&without_unselected_answers(history)),
+            vec!["q1", "a1-b"]
+        );
+    }
+}
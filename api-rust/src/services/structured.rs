@@ -0,0 +1,257 @@
+//! Structured output: a chat with a JSON Schema gets answers that are
+//! JSON objects conforming to it. Providers enforce the schema natively
+//! where they can (OpenAI `response_format: json_schema`, a forced tool
+//! call on Anthropic / Bedrock, Ollama `format`); every answer is then
+//! validated here, and an invalid one is sent back to the model with the
+//! validation errors for a bounded number of repair attempts.
+
+use jsonschema::Validator;
+use serde_json::Value;
+use std::future::Future;
+use tracing::warn;
+
+use crate::services::ai::{InvokeModelRequest, MessageRole, ModelMessage, ModelResponse, ToolSpec};
+use crate::utils::errors::AppError;
+
+/// Name of the tool used to emulate structured output through a forced
+/// tool call; its input is the answer.
+pub const STRUCTURED_OUTPUT_TOOL: &str = "structured_output";
+
+/// Repair round-trips after the first answer fails validation.
+pub const STRUCTURED_OUTPUT_MAX_REPAIRS: usize = 2;
+
+/// Validation errors reported back to the model per repair attempt.
+const MAX_REPORTED_ERRORS: usize = 10;
+
+/// Parse and check a chat's response schema. Only object schemas are
+/// accepted: a forced tool call's input and OpenAI's strict mode both
+/// require an object at the root.
+pub fn parse_schema(raw: &str) -> Result<Value, AppError> {
+    let schema: Value = serde_json::from_str(raw)
+        .map_err(|e| AppError::Validation(format!("Response schema is not valid JSON: {}", e)))?;
+    if schema.get("type").and_then(|t| t.as_str()) != Some("object") {
+        return Err(AppError::Validation(
+            "Response schema must describe an object (\"type\": \"object\")".to_string(),
+        ));
+    }
+    compile(&schema)?;
+    Ok(schema)
+}
+
+fn compile(schema: &Value) -> Result<Validator, AppError> {
+    jsonschema::validator_for(schema)
+        .map_err(|e| AppError::Validation(format!("Invalid response schema: {}", e)))
+}
+
+/// The tool a forced-tool-call provider is made to call.
+pub fn output_tool(schema: &Value) -> ToolSpec {
+    ToolSpec {
+        name: STRUCTURED_OUTPUT_TOOL.to_string(),
+        description: "Return the answer as structured data matching the input schema".to_string(),
+        input_schema: schema.clone(),
+    }
+}
+
+/// System prompt addition for providers without native schema support.
+pub fn instructions(schema: &Value) -> String {
+    format!(
+        "Answer only with a JSON object that strictly follows this JSON Schema, \
+        without any commentary:\n```\n{}\n```",
+        serde_json::to_string_pretty(schema).unwrap_or_default()
+    )
+}
+
+/// Extract the JSON object from an answer that may be wrapped in markdown
+/// fences or prefixed with commentary.
+pub fn extract_json(content: &str) -> Option<Value> {
+    let start = content.find('{')?;
+    let end = content.rfind('}')?;
+    if end < start {
+        return None;
+    }
+    serde_json::from_str(&content[start..=end]).ok()
+}
+
+/// Human-readable validation errors, `<path>: <error>` each.
+fn validation_errors(validator: &Validator, value: &Value) -> Vec<String> {
+    validator
+        .iter_errors(value)
+        .take(MAX_REPORTED_ERRORS)
+        .map(|e| {
+            let path = e.instance_path.to_string();
+            if path.is_empty() {
+                e.to_string()
+            } else {
+                format!("{}: {}", path, e)
+            }
+        })
+        .collect()
+}
+
+fn repair_prompt(errors: &[String]) -> String {
+    format!(
+        "Your answer does not match the required JSON Schema:\n- {}\n\n\
+        Reply again with only the corrected JSON object.",
+        errors.join("\n- ")
+    )
+}
+
+/// Invoke the model until it returns an object valid against `schema`,
+/// feeding validation errors back for up to
+/// [`STRUCTURED_OUTPUT_MAX_REPAIRS`] repair attempts. `invoke` performs one
+/// completion (e.g. `FallbackChain::invoke_model`); its extra result (the
+/// answering model) is returned with the validated object.
+pub async fn invoke_structured<T, F, Fut>(
+    mut request: InvokeModelRequest,
+    schema: &Value,
+    mut invoke: F,
+) -> Result<(Value, ModelResponse, T), AppError>
+where
+    F: FnMut(InvokeModelRequest) -> Fut,
+    Fut: Future<Output = Result<(ModelResponse, T), AppError>>,
+{
+    let validator = compile(schema)?;
+    request.response_schema = Some(schema.clone());
+    request.system_prompt = Some(match request.system_prompt.take() {
+        Some(prompt) if !prompt.trim().is_empty() => {
+            format!("{}\n\n{}", prompt, instructions(schema))
+        }
+        _ => instructions(schema),
+    });
+
+    let mut errors = Vec::new();
+    for attempt in 0..=STRUCTURED_OUTPUT_MAX_REPAIRS {
+        let (response, extra) = invoke(request.clone()).await?;
+        errors = match extract_json(&response.content) {
+            Some(value) => {
+                let errors = validation_errors(&validator, &value);
+                if errors.is_empty() {
+                    return Ok((value, response, extra));
+                }
+                errors
+            }
+            None => vec!["the answer is not a JSON object".to_string()],
+        };
+        warn!(
+            "Structured answer of {} failed validation (attempt {}): {}",
+            request.model_id,
+            attempt + 1,
+            errors.join("; ")
+        );
+
+        request
+            .messages
+            .push(ModelMessage::text(MessageRole::Assistant, response.content));
+        request.messages.push(ModelMessage::text(
+            MessageRole::User,
+            repair_prompt(&errors),
+        ));
+    }
+
+    Err(AppError::Validation(format!(
+        "The answer does not match the response schema: {}",
+        errors.join("; ")
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "age": { "type": "integer", "minimum": 0 }
+            },
+            "required": ["name", "age"],
+            "additionalProperties": false
+        })
+    }
+
+    fn request() -> InvokeModelRequest {
+        InvokeModelRequest {
+            model_id: "model".to_string(),
+            messages: vec![ModelMessage::text(MessageRole::User, "Extract: Bob, 42")],
+            temperature: None,
+            max_tokens: None,
+            top_p: None,
+            system_prompt: None,
+            tools: None,
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: Default::default(),
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
+        }
+    }
+
+    fn response(content: &str) -> ModelResponse {
+        ModelResponse {
+            content: content.to_string(),
+            model_id: "model".to_string(),
+            usage: None,
+            finish_reason: None,
+            tool_calls: Vec::new(),
+            reasoning: None,
+            executed_tool_calls: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn accepts_object_schemas_only() {
+        assert!(parse_schema(&schema().to_string()).is_ok());
+        assert!(parse_schema(r#"{"type": "array"}"#).is_err());
+        assert!(parse_schema(r#"{"type": "object", "minProperties": "x"}"#).is_err());
+        assert!(parse_schema("not json").is_err());
+    }
+
+    #[tokio::test]
+    async fn repairs_invalid_answers_with_validation_errors() {
+        let answers = Mutex::new(vec![
+            "Sure! ```json\n{\"name\": \"Bob\", \"age\": -1}\n```",
+            "{\"name\": \"Bob\", \"age\": 42}",
+        ]);
+        let sent = Mutex::new(Vec::new());
+
+        let (value, _, _) = invoke_structured(request(), &schema(), |request| {
+            sent.lock().unwrap().push(request);
+            let answer = answers.lock().unwrap().remove(0);
+            async move { Ok((response(answer), ())) }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(value, json!({"name": "Bob", "age": 42}));
+        let sent = sent.into_inner().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0].response_schema.is_some());
+        assert!(sent[0]
+            .system_prompt
+            .as_deref()
+            .unwrap()
+            .contains("JSON Schema"));
+        // the repair turn carries the rejected answer and the errors
+        let repair = &sent[1].messages;
+        assert_eq!(repair.len(), 3);
+        assert_eq!(repair[1].role, MessageRole::Assistant);
+        assert!(repair[2].content.contains("/age"));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_bounded_repairs() {
+        let mut calls = 0;
+        let result = invoke_structured(request(), &schema(), |_| {
+            calls += 1;
+            async { Ok((response("no json here"), ())) }
+        })
+        .await;
+
+        assert!(matches!(result, Err(AppError::Validation(_))));
+        assert_eq!(calls, STRUCTURED_OUTPUT_MAX_REPAIRS + 1);
+    }
+}
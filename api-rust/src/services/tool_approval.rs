@@ -0,0 +1,373 @@
+//! Human approval of tool calls. When a model calls a tool that requires
+//! approval, the tool loop persists the call, publishes it over the chat
+//! subscription and waits until `approveToolCall` / `rejectToolCall`
+//! (another request) hands over the decision. Waiters are kept per
+//! process, like the generations `stopMessage` cancels; a call nobody
+//! waits for anymore is expired.
+
+use chrono::Utc;
+use diesel::prelude::*;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::database::{DbConnection, DbPool};
+use crate::models::{
+    message, GqlToolCallApproval, ToolCallApproval, TOOL_CALL_APPROVED, TOOL_CALL_EXPIRED,
+    TOOL_CALL_PENDING, TOOL_CALL_REJECTED,
+};
+use crate::schema::tool_call_approvals;
+use crate::services::ai::{ExecutableTool, ToolBackend, ToolCallRequest};
+use crate::services::pubsub::get_global_pubsub;
+use crate::utils::errors::AppError;
+
+/// How long a generation waits for the user's decision
+pub const TOOL_APPROVAL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// The user's answer to an approval request
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolApprovalDecision {
+    /// Run the tool, with edited arguments when given
+    Approved {
+        arguments: Option<Value>,
+    },
+    Rejected {
+        reason: Option<String>,
+    },
+}
+
+struct Waiter {
+    user_id: String,
+    sender: oneshot::Sender<ToolApprovalDecision>,
+}
+
+lazy_static::lazy_static! {
+    static ref WAITERS: Mutex<HashMap<String, Waiter>> = Mutex::new(HashMap::new());
+}
+
+/// A registered wait for a decision; unregisters when dropped.
+struct PendingApproval {
+    approval_id: String,
+    receiver: oneshot::Receiver<ToolApprovalDecision>,
+}
+
+impl PendingApproval {
+    /// Register the wait for an approval of `user_id`, before the request
+    /// goes out so that no answer is missed.
+    fn register(approval_id: &str, user_id: &str) -> Self {
+        let (sender, receiver) = oneshot::channel();
+        if let Ok(mut waiters) = WAITERS.lock() {
+            waiters.insert(
+                approval_id.to_string(),
+                Waiter {
+                    user_id: user_id.to_string(),
+                    sender,
+                },
+            );
+        }
+        Self {
+            approval_id: approval_id.to_string(),
+            receiver,
+        }
+    }
+
+    /// The decision, or `None` on timeout
+    async fn wait(mut self, timeout: Duration) -> Option<ToolApprovalDecision> {
+        tokio::time::timeout(timeout, &mut self.receiver)
+            .await
+            .ok()?
+            .ok()
+    }
+}
+
+impl Drop for PendingApproval {
+    fn drop(&mut self) {
+        if let Ok(mut waiters) = WAITERS.lock() {
+            waiters.remove(&self.approval_id);
+        }
+    }
+}
+
+/// Whether a generation still waits for the approval
+pub fn is_waiting(approval_id: &str) -> bool {
+    WAITERS
+        .lock()
+        .map(|waiters| waiters.contains_key(approval_id))
+        .unwrap_or(false)
+}
+
+/// Hand the user's decision to the waiting generation; false when none
+/// waits for it (anymore).
+fn resolve(approval_id: &str, user_id: &str, decision: ToolApprovalDecision) -> bool {
+    let Ok(mut waiters) = WAITERS.lock() else {
+        return false;
+    };
+    match waiters.get(approval_id) {
+        Some(waiter) if waiter.user_id == user_id => {}
+        _ => return false,
+    }
+    let Some(waiter) = waiters.remove(approval_id) else {
+        return false;
+    };
+    info!(
+        "Tool call approval {} resolved: {:?}",
+        approval_id, decision
+    );
+    waiter.sender.send(decision).is_ok()
+}
+
+/// Record the user's decision on a pending tool call of theirs and hand it
+/// to the waiting generation. A call nobody waits for anymore (the
+/// generation was stopped, timed out or the API restarted) comes back
+/// expired instead.
+pub fn decide(
+    conn: &mut DbConnection,
+    user_id: &str,
+    approval_id: &str,
+    decision: ToolApprovalDecision,
+) -> Result<ToolCallApproval, AppError> {
+    let approval: ToolCallApproval = tool_call_approvals::table
+        .filter(tool_call_approvals::id.eq(approval_id))
+        .filter(tool_call_approvals::user_id.eq(user_id))
+        .first(conn)
+        .optional()
+        .map_err(|e| AppError::Database(e.to_string()))?
+        .ok_or_else(|| AppError::NotFound("Tool call not found".to_string()))?;
+    if approval.status != TOOL_CALL_PENDING {
+        return Err(AppError::Validation(
+            "The tool call is not awaiting approval".to_string(),
+        ));
+    }
+
+    let (status, arguments, reason) = match &decision {
+        ToolApprovalDecision::Approved { arguments } => (
+            TOOL_CALL_APPROVED,
+            arguments.as_ref().map(Value::to_string),
+            None,
+        ),
+        ToolApprovalDecision::Rejected { reason } => (TOOL_CALL_REJECTED, None, reason.clone()),
+    };
+    let resolved = resolve(approval_id, user_id, decision);
+    let (status, reason) = if resolved {
+        (status, reason)
+    } else {
+        (
+            TOOL_CALL_EXPIRED,
+            Some("The generation no longer waits for the decision".to_string()),
+        )
+    };
+
+    diesel::update(tool_call_approvals::table.filter(tool_call_approvals::id.eq(approval_id)))
+        .set((
+            tool_call_approvals::status.eq(status),
+            arguments.map(|a| tool_call_approvals::arguments.eq(a)),
+            tool_call_approvals::reason.eq(reason),
+            tool_call_approvals::updated_at.eq(Utc::now().naive_utc()),
+        ))
+        .get_result(conn)
+        .map_err(|e| AppError::Database(e.to_string()))
+}
+
+/// Expire the calls of a message still awaiting a decision, its generation
+/// being gone; returns them to publish.
+pub fn expire_pending(
+    conn: &mut DbConnection,
+    message_id: &str,
+    reason: &str,
+) -> Result<Vec<ToolCallApproval>, AppError> {
+    diesel::update(
+        tool_call_approvals::table
+            .filter(tool_call_approvals::message_id.eq(message_id))
+            .filter(tool_call_approvals::status.eq(TOOL_CALL_PENDING)),
+    )
+    .set((
+        tool_call_approvals::status.eq(TOOL_CALL_EXPIRED),
+        tool_call_approvals::reason.eq(reason),
+        tool_call_approvals::updated_at.eq(Utc::now().naive_utc()),
+    ))
+    .get_results(conn)
+    .map_err(|e| AppError::Database(e.to_string()))
+}
+
+/// Tool calls of a chat of the user still waiting for a decision
+pub fn pending(
+    conn: &mut DbConnection,
+    user_id: &str,
+    chat_id: &str,
+) -> Result<Vec<ToolCallApproval>, AppError> {
+    let approvals: Vec<ToolCallApproval> = tool_call_approvals::table
+        .filter(tool_call_approvals::chat_id.eq(chat_id))
+        .filter(tool_call_approvals::user_id.eq(user_id))
+        .filter(tool_call_approvals::status.eq(TOOL_CALL_PENDING))
+        .order(tool_call_approvals::created_at.asc())
+        .load(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    // Left over from generations that ended without a decision
+    Ok(approvals
+        .into_iter()
+        .filter(|approval| is_waiting(&approval.id))
+        .collect())
+}
+
+/// Publish an approval (state) over the chat subscription
+pub async fn publish(approval: GqlToolCallApproval) {
+    let chat_id = approval.chat_id.clone();
+    let pub_message = message::GqlNewMessage {
+        r#type: String::from(message::MessageType::ToolApproval),
+        error: None,
+        message: None,
+        streaming: Some(true),
+        chat: None,
+        tool_call_approval: Some(approval),
+    };
+    if let Err(e) = get_global_pubsub()
+        .publish_to_chat(&chat_id, pub_message)
+        .await
+    {
+        warn!("Failed to publish tool call approval: {:?}", e);
+    }
+}
+
+/// Asks the user to approve the tool calls of one assistant message
+/// generation.
+#[derive(Clone)]
+pub struct ToolApprover {
+    db_pool: DbPool,
+    user_id: String,
+    chat_id: String,
+    message_id: String,
+}
+
+impl fmt::Debug for ToolApprover {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ToolApprover")
+            .field("user_id", &self.user_id)
+            .field("chat_id", &self.chat_id)
+            .field("message_id", &self.message_id)
+            .finish()
+    }
+}
+
+impl ToolApprover {
+    pub fn new(db_pool: DbPool, user_id: &str, chat_id: &str, message_id: &str) -> Self {
+        Self {
+            db_pool,
+            user_id: user_id.to_string(),
+            chat_id: chat_id.to_string(),
+            message_id: message_id.to_string(),
+        }
+    }
+
+    /// Persist and publish the call, then wait for the user's decision. A
+    /// call that cannot be stored or is not answered in time is rejected.
+    pub async fn request(
+        &self,
+        tool: &ExecutableTool,
+        call: &ToolCallRequest,
+    ) -> ToolApprovalDecision {
+        let (tool_name, server_id) = match &tool.backend {
+            ToolBackend::Mcp {
+                server, tool_name, ..
+            } => (tool_name.clone(), Some(server.id.clone())),
+            _ => (tool.spec.name.clone(), None),
+        };
+        let now = Utc::now().naive_utc();
+        let approval = ToolCallApproval {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id: self.user_id.clone(),
+            chat_id: self.chat_id.clone(),
+            message_id: self.message_id.clone(),
+            tool_call_id: call.id.clone(),
+            tool_name,
+            server_id,
+            arguments: call.arguments.to_string(),
+            status: TOOL_CALL_PENDING.to_string(),
+            reason: None,
+            created_at: now,
+            updated_at: now,
+        };
+        let stored = self.db_pool.get().and_then(|mut conn| {
+            diesel::insert_into(tool_call_approvals::table)
+                .values(&approval)
+                .execute(&mut conn)
+                .map_err(|e| AppError::Database(e.to_string()))
+        });
+        if let Err(e) = stored {
+            warn!("Failed to store tool call approval: {}", e);
+            return ToolApprovalDecision::Rejected {
+                reason: Some("The approval request could not be stored".to_string()),
+            };
+        }
+
+        info!(
+            "Tool call {} of message {} awaits approval ({})",
+            approval.tool_name, approval.message_id, approval.id
+        );
+        let pending = PendingApproval::register(&approval.id, &self.user_id);
+        publish(GqlToolCallApproval::from(approval.clone())).await;
+        if let Some(decision) = pending.wait(TOOL_APPROVAL_TIMEOUT).await {
+            return decision;
+        }
+
+        let reason = "The user did not answer in time".to_string();
+        let expired = self.db_pool.get().and_then(|mut conn| {
+            diesel::update(
+                tool_call_approvals::table
+                    .filter(tool_call_approvals::id.eq(&approval.id))
+                    .filter(tool_call_approvals::status.eq(TOOL_CALL_PENDING)),
+            )
+            .set((
+                tool_call_approvals::status.eq(TOOL_CALL_EXPIRED),
+                tool_call_approvals::reason.eq(&reason),
+                tool_call_approvals::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .get_result::<ToolCallApproval>(&mut conn)
+            .map_err(|e| AppError::Database(e.to_string()))
+        });
+        if let Ok(expired) = expired {
+            publish(expired.into()).await;
+        }
+        ToolApprovalDecision::Rejected {
+            reason: Some(reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn resolve_hands_the_owners_decision_to_the_waiter() {
+        let pending = PendingApproval::register("approval-1", "user-1");
+        assert!(is_waiting("approval-1"));
+
+        let decision = ToolApprovalDecision::Approved {
+            arguments: Some(json!({ "title": "edited" })),
+        };
+        assert!(!resolve("approval-1", "user-2", decision.clone()));
+        assert!(!resolve("approval-2", "user-1", decision.clone()));
+        assert!(resolve("approval-1", "user-1", decision.clone()));
+
+        assert_eq!(pending.wait(Duration::from_secs(60)).await, Some(decision));
+        assert!(!is_waiting("approval-1"));
+    }
+
+    #[tokio::test]
+    async fn unanswered_approvals_time_out() {
+        let pending = PendingApproval::register("approval-3", "user-1");
+        assert_eq!(pending.wait(Duration::from_millis(10)).await, None);
+
+        // nobody waits anymore
+        assert!(!resolve(
+            "approval-3",
+            "user-1",
+            ToolApprovalDecision::Rejected { reason: None }
+        ));
+    }
+}
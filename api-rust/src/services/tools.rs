@@ -1,39 +1,100 @@
 //! In-chat tool execution: dispatches model-requested tool calls to the
 //! web search / MCP backends. Failures never abort the chat session — the
 //! error text is returned as the tool result so the model can recover
-//! (Node parity: openai.tools.ts).
+//! (Node parity: openai.tools.ts). Tools that require approval wait for
+//! the user's decision first (`services::tool_approval`). The calls of one
+//! turn run concurrently under a `ToolExecutionPolicy`: each has a
+//! deadline, and oversized results are cut.
 
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{debug, warn};
 
 use crate::services::ai::{
-    ExecutableTool, ExecutedToolCall, MessageRole, ModelMessage, ToolBackend, ToolCallRequest,
+    ExecutableTool, ExecutedToolCall, InvokeModelRequest, MessageRole, ModelMessage, ToolBackend,
+    ToolCallRequest,
 };
 use crate::services::mcp::McpClient;
+use crate::services::rag;
+use crate::services::tool_approval::{ToolApprovalDecision, ToolApprover};
 use crate::services::web_search;
 use crate::utils::errors::AppError;
 
+/// Limits of in-chat tool calls, from `AppConfig::tool_execution`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ToolExecutionPolicy {
+    /// Calls of one model turn running at the same time
+    pub concurrency: usize,
+    /// Deadline of a call whose tool sets none (approval waits excluded)
+    pub timeout_ms: u64,
+    /// Longer results are cut, with a note telling the model so
+    pub max_output_chars: usize,
+}
+
+impl Default for ToolExecutionPolicy {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            timeout_ms: 60_000,
+            max_output_chars: 100_000,
+        }
+    }
+}
+
 /// Execute a single tool call, producing the Tool-role message replayed to
 /// the model and the metadata record for the assistant message.
 pub async fn execute_tool_call(
     tools: &[ExecutableTool],
     call: &ToolCallRequest,
+    approver: Option<&ToolApprover>,
+    policy: &ToolExecutionPolicy,
 ) -> (ModelMessage, ExecutedToolCall) {
-    let content = match tools.iter().find(|t| t.spec.name == call.name) {
-        None => format!("Error: Unsupported function tool: {}", call.name),
-        Some(tool) => match run_tool(tool, call).await {
-            Ok(content) => content,
-            Err(e) => {
-                warn!("Tool {} failed: {}", call.name, e);
-                format!("Error calling tool {}: {}", call.name, e)
+    let started = Instant::now();
+    let mut arguments = call.arguments.clone();
+    let (content, error) = match tools.iter().find(|t| t.spec.name == call.name) {
+        None => {
+            let error = format!("Unsupported function tool: {}", call.name);
+            (format!("Error: {}", error), Some(error))
+        }
+        Some(tool) => match approved_arguments(tool, call, approver).await {
+            Err(refusal) => (refusal.clone(), Some(refusal)),
+            Ok(approved) => {
+                arguments = approved;
+                let timeout = tool
+                    .timeout
+                    .unwrap_or(Duration::from_millis(policy.timeout_ms));
+                let error = match tokio::time::timeout(timeout, run_tool(tool, &arguments)).await {
+                    Ok(Ok(content)) => Ok(content),
+                    Ok(Err(e)) => Err(e.to_string()),
+                    Err(_) => Err(format!("Timed out after {} ms", timeout.as_millis())),
+                };
+                match error {
+                    Ok(content) => (content, None),
+                    Err(error) => {
+                        warn!("Tool {} failed: {}", call.name, error);
+                        (
+                            format!("Error calling tool {}: {}", call.name, error),
+                            Some(error),
+                        )
+                    }
+                }
             }
         },
     };
+    let (content, truncated) = cap_output(content, policy.max_output_chars);
 
     let executed = ExecutedToolCall {
         id: call.id.clone(),
         name: call.name.clone(),
-        args_json: call.arguments.to_string(),
+        args_json: arguments.to_string(),
         content: content.clone(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        error,
+        truncated,
     };
     let message = ModelMessage {
         role: MessageRole::Tool,
@@ -45,39 +106,281 @@ pub async fn execute_tool_call(
     (message, executed)
 }
 
-async fn run_tool(tool: &ExecutableTool, call: &ToolCallRequest) -> Result<String, AppError> {
+/// Execute the tool calls of one model turn and extend the session: the
+/// assistant turn replays `assistant_tool_calls` (the provider's raw
+/// tool-call payload), each result becomes a Tool-role message. Calls run
+/// concurrently; results keep the order of the calls.
+pub async fn run_tool_calls(
+    session: &mut InvokeModelRequest,
+    executed: &mut Vec<ExecutedToolCall>,
+    assistant_tool_calls: serde_json::Value,
+    calls: Vec<ToolCallRequest>,
+) {
+    session.messages.push(ModelMessage {
+        role: MessageRole::Assistant,
+        content: String::new(),
+        timestamp: None,
+        tool_calls: Some(assistant_tool_calls),
+        tool_call_id: None,
+    });
+
+    // from here on the session may have side effects
+    session.tool_calls_ran.store(true, Ordering::SeqCst);
+    let tools = Arc::new(session.tools.clone().unwrap_or_default());
+    let approver = session.tool_approver.clone();
+    let policy = session.tool_execution.clone();
+    let concurrency = policy.concurrency.max(1);
+    let runs = calls.into_iter().map(|call| {
+        let tools = tools.clone();
+        let approver = approver.clone();
+        let policy = policy.clone();
+        async move { execute_tool_call(&tools, &call, approver.as_ref(), &policy).await }
+    });
+    let results: Vec<(ModelMessage, ExecutedToolCall)> = futures_util::stream::iter(runs)
+        .buffered(concurrency)
+        .collect()
+        .await;
+    if let Ok(mut log) = session.executed_tools.lock() {
+        log.extend(results.iter().map(|(_, record)| record.clone()));
+    }
+    for (message, record) in results {
+        executed.push(record);
+        session.messages.push(message);
+    }
+}
+
+/// Cut a result longer than `max_chars`, noting it for the model
+fn cap_output(content: String, max_chars: usize) -> (String, bool) {
+    let total = content.chars().count();
+    let (mut content, truncated) = web_search::truncate_chars(content, max_chars);
+    if truncated {
+        content.push_str(&format!(
+            "\n\n[Output truncated: {} of {} characters shown]",
+            max_chars, total
+        ));
+    }
+    (content, truncated)
+}
+
+/// The arguments to call the tool with: the model's, or the ones the user
+/// approved (possibly edited). A refused call yields the tool result that
+/// tells the model so.
+async fn approved_arguments(
+    tool: &ExecutableTool,
+    call: &ToolCallRequest,
+    approver: Option<&ToolApprover>,
+) -> Result<Value, String> {
+    if !tool.requires_approval {
+        return Ok(call.arguments.clone());
+    }
+    let Some(approver) = approver else {
+        return Err(format!(
+            "Error: Tool {} requires the user's approval, which cannot be asked for here",
+            call.name
+        ));
+    };
+    match approver.request(tool, call).await {
+        ToolApprovalDecision::Approved { arguments } => {
+            Ok(arguments.unwrap_or_else(|| call.arguments.clone()))
+        }
+        ToolApprovalDecision::Rejected { reason } => Err(match reason {
+            Some(reason) => format!(
+                "The user rejected the call of tool {}: {}",
+                call.name, reason
+            ),
+            None => format!("The user rejected the call of tool {}", call.name),
+        }),
+    }
+}
+
+async fn run_tool(tool: &ExecutableTool, arguments: &Value) -> Result<String, AppError> {
     match &tool.backend {
-        ToolBackend::WebSearch {
-            api_key,
-            folder_id,
-            api_url,
-        } => {
-            let Some(query) = call.arguments.get("query").and_then(|q| q.as_str()) else {
+        ToolBackend::WebSearch { backend } => {
+            let Some(query) = arguments.get("query").and_then(|q| q.as_str()) else {
                 return Ok("Error: Invalid 'query' argument for web search tool.".to_string());
             };
-            let limit = call
-                .arguments
+            let limit = arguments
                 .get("limit")
                 .and_then(|l| l.as_u64())
                 .map(|l| l as usize)
                 .unwrap_or(web_search::DEFAULT_RESULTS_LIMIT);
 
-            debug!("Web search tool call: {} (limit {})", query, limit);
-            let results =
-                web_search::search(api_key, folder_id, api_url.as_deref(), query, limit).await?;
+            debug!(
+                "Web search tool call ({}): {} (limit {})",
+                backend.name(),
+                query,
+                limit
+            );
+            let results = backend.search(query, limit).await?;
             if results.is_empty() {
                 return Ok(format!("No results found for query: \"{}\"", query));
             }
             Ok(web_search::results_to_tool_content(&results))
         }
+        ToolBackend::UrlFetch => {
+            let Some(url) = arguments.get("url").and_then(|u| u.as_str()) else {
+                return Ok("Error: Invalid 'url' argument for URL fetch tool.".to_string());
+            };
+            debug!("URL fetch tool call: {}", url);
+            let page = web_search::fetch_url(url).await?;
+            Ok(web_search::page_to_tool_content(&page))
+        }
+        ToolBackend::DocumentSearch {
+            user_id,
+            document_ids,
+            context,
+        } => {
+            let context = context.as_ref().ok_or_else(|| {
+                AppError::Internal("Document search is not available here".to_string())
+            })?;
+            rag::search_documents(context, user_id, document_ids, arguments).await
+        }
         ToolBackend::Mcp {
             server,
+            user_id,
             tool_name,
             auth_token,
         } => {
             debug!("MCP tool call: {} on {}", tool_name, server.name);
-            let mut client = McpClient::for_server(server, auth_token.as_deref());
-            client.call_tool(tool_name, call.arguments.clone()).await
+            let mut client = McpClient::for_server(server, user_id, auth_token.as_deref());
+            client.call_tool(tool_name, arguments.clone()).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::ai::ToolSpec;
+    use crate::services::search_backend::SearchBackend;
+    use crate::utils::test_server::{StubResponse, StubServer};
+    use serde_json::json;
+
+    fn search_tool(name: &str, api_url: &str, timeout: Option<Duration>) -> ExecutableTool {
+        ExecutableTool {
+            spec: ToolSpec {
+                name: name.to_string(),
+                description: "Search the web".to_string(),
+                input_schema: json!({ "type": "object" }),
+            },
+            backend: ToolBackend::WebSearch {
+                backend: SearchBackend::Searxng {
+                    api_url: api_url.to_string(),
+                    api_key: None,
+                    public_only: false,
+                },
+            },
+            requires_approval: false,
+            timeout,
+        }
+    }
+
+    fn call(id: &str, name: &str) -> ToolCallRequest {
+        ToolCallRequest {
+            id: id.to_string(),
+            name: name.to_string(),
+            arguments: json!({ "query": "deploy" }),
+            raw: json!({}),
         }
     }
+
+    #[tokio::test]
+    async fn calls_needing_approval_are_refused_without_an_approver() {
+        let tool = ExecutableTool {
+            requires_approval: true,
+            ..search_tool("web_search", "http://127.0.0.1:9", None)
+        };
+        let call = call("call-1", "web_search");
+
+        let (message, record) =
+            execute_tool_call(&[tool], &call, None, &ToolExecutionPolicy::default()).await;
+        assert_eq!(message.tool_call_id.as_deref(), Some("call-1"));
+        assert!(message.content.contains("requires the user's approval"));
+        assert_eq!(record.args_json, call.arguments.to_string());
+        assert!(record.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn hung_calls_time_out_concurrently() {
+        // Accepts connections and never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let hung_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let mut session = InvokeModelRequest {
+            model_id: "gpt-4o".to_string(),
+            messages: Vec::new(),
+            temperature: None,
+            max_tokens: None,
+            top_p: None,
+            system_prompt: None,
+            tools: Some(vec![
+                search_tool("quick", &hung_url, Some(Duration::from_millis(100))),
+                search_tool("slow", &hung_url, None),
+            ]),
+            thinking_budget: None,
+            response_schema: None,
+            tool_approver: None,
+            tool_execution: ToolExecutionPolicy {
+                concurrency: 3,
+                timeout_ms: 400,
+                ..ToolExecutionPolicy::default()
+            },
+            tool_calls_ran: Default::default(),
+            executed_tools: Default::default(),
+        };
+        let mut executed = Vec::new();
+        let started = Instant::now();
+        run_tool_calls(
+            &mut session,
+            &mut executed,
+            json!([]),
+            vec![call("a", "slow"), call("b", "quick"), call("c", "slow")],
+        )
+        .await;
+
+        // Three hung calls take one deadline, not three
+        assert!(started.elapsed() < Duration::from_millis(1000));
+        let ids: Vec<&str> = executed.iter().map(|call| call.id.as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+        assert_eq!(executed[0].error.as_deref(), Some("Timed out after 400 ms"));
+        assert_eq!(executed[1].error.as_deref(), Some("Timed out after 100 ms"));
+        assert!(executed[1].duration_ms < 400);
+        let replayed: Vec<Option<&str>> = session.messages[1..]
+            .iter()
+            .map(|message| message.tool_call_id.as_deref())
+            .collect();
+        assert_eq!(replayed, [Some("a"), Some("b"), Some("c")]);
+        // the caller sees them too, should the generation be stopped
+        assert_eq!(session.executed_tools.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn oversized_results_are_truncated() {
+        let stub = StubServer::start(vec![StubResponse::json(json!({ "results": [
+            { "url": "https://example.com", "title": "Long", "content": "x".repeat(500) },
+        ]}))])
+        .await;
+        let policy = ToolExecutionPolicy {
+            max_output_chars: 200,
+            ..ToolExecutionPolicy::default()
+        };
+
+        let tool = search_tool("web_search", &stub.base_url, None);
+        let (message, record) =
+            execute_tool_call(&[tool], &call("call-1", "web_search"), None, &policy).await;
+        assert!(record.truncated);
+        assert_eq!(record.error, None);
+        let (shown, note) = message
+            .content
+            .split_once("\n\n[Output truncated: ")
+            .unwrap();
+        assert_eq!(shown.chars().count(), 200);
+        assert!(note.starts_with("200 of ") && note.ends_with(" characters shown]"));
+    }
 }
@@ -0,0 +1,398 @@
+//! Token usage accounting. Every model answer (and chat summary) is
+//! recorded in the `message_usage` ledger with its token counts and cost,
+//! priced with the latest `model_prices` version an admin set for the
+//! model or, failing that, the built-in price list. Aggregates are
+//! computed in Rust so the same code serves every database backend.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::database::DbConnection;
+use crate::models::{
+    Chat, MessageUsage, MessageUsageRecord, Model, ModelPrice, UsageGroupBy, UsageStats, User,
+};
+use crate::schema::{chats, message_usage, model_prices, users};
+use crate::services::ai::{ApiProvider, Usage};
+use crate::utils::errors::AppError;
+
+pub const USAGE_CURRENCY: &str = "USD";
+
+type PriceEntry = (&'static str, f64, f64, Option<f64>, Option<f64>);
+
+/// Built-in prices, USD per 1M tokens: (model id fragment, input, output,
+/// cache read, cache write). The first matching fragment wins, so longer
+/// fragments go before their prefixes.
+const DEFAULT_PRICES: &[PriceEntry] = &[
+    ("claude-opus-4", 15.0, 75.0, Some(1.5), Some(18.75)),
+    ("claude-sonnet-4", 3.0, 15.0, Some(0.3), Some(3.75)),
+    ("claude-3-7-sonnet", 3.0, 15.0, Some(0.3), Some(3.75)),
+    ("claude-3-5-sonnet", 3.0, 15.0, Some(0.3), Some(3.75)),
+    ("claude-haiku-4", 1.0, 5.0, Some(0.1), Some(1.25)),
+    ("claude-3-5-haiku", 0.8, 4.0, Some(0.08), Some(1.0)),
+    ("claude-3-haiku", 0.25, 1.25, Some(0.03), Some(0.3)),
+    ("gpt-5-mini", 0.25, 2.0, Some(0.025), None),
+    ("gpt-5-nano", 0.05, 0.4, Some(0.005), None),
+    ("gpt-5", 1.25, 10.0, Some(0.125), None),
+    ("gpt-4.1-mini", 0.4, 1.6, Some(0.1), None),
+    ("gpt-4.1-nano", 0.1, 0.4, Some(0.025), None),
+    ("gpt-4.1", 2.0, 8.0, Some(0.5), None),
+    ("gpt-4o-mini", 0.15, 0.6, Some(0.075), None),
+    ("gpt-4o", 2.5, 10.0, Some(1.25), None),
+    ("gemini-2.5-pro", 1.25, 10.0, Some(0.31), None),
+    ("gemini-2.5-flash-lite", 0.1, 0.4, Some(0.025), None),
+    ("gemini-2.5-flash", 0.3, 2.5, Some(0.075), None),
+    ("amazon.nova-pro", 0.8, 3.2, Some(0.2), None),
+    ("amazon.nova-lite", 0.06, 0.24, Some(0.015), None),
+    ("amazon.nova-micro", 0.035, 0.14, Some(0.00875), None),
+    ("mistral.mistral-large", 2.0, 6.0, None, None),
+    ("meta.llama3-3-70b", 0.72, 0.72, None, None),
+];
+
+/// Price of a model, USD per 1M tokens; cache prices default to the input
+/// price.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+    pub cache_read: Option<f64>,
+    pub cache_write: Option<f64>,
+    /// `model_prices` version, 0 for the built-in list
+    pub version: i32,
+}
+
+impl From<&ModelPrice> for Price {
+    fn from(price: &ModelPrice) -> Self {
+        Self {
+            input: price.input_price,
+            output: price.output_price,
+            cache_read: price.cache_read_price,
+            cache_write: price.cache_write_price,
+            version: price.version,
+        }
+    }
+}
+
+pub fn default_price(model_id: &str) -> Option<Price> {
+    DEFAULT_PRICES
+        .iter()
+        .find(|(fragment, ..)| model_id.contains(fragment))
+        .map(|&(_, input, output, cache_read, cache_write)| Price {
+            input,
+            output,
+            cache_read,
+            cache_write,
+            version: 0,
+        })
+}
+
+/// The latest admin override of the model's price, else the built-in one.
+pub fn current_price(conn: &mut DbConnection, model_id: &str) -> Result<Option<Price>, AppError> {
+    let latest: Option<ModelPrice> = model_prices::table
+        .filter(model_prices::model_id.eq(model_id))
+        .order(model_prices::version.desc())
+        .first(conn)
+        .optional()
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    Ok(latest
+        .as_ref()
+        .map(Price::from)
+        .or_else(|| default_price(model_id)))
+}
+
+/// Next version number for a model's price override.
+pub fn next_price_version(conn: &mut DbConnection, model_id: &str) -> Result<i32, AppError> {
+    let latest: Option<i32> = model_prices::table
+        .filter(model_prices::model_id.eq(model_id))
+        .select(diesel::dsl::max(model_prices::version))
+        .first(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    Ok(latest.unwrap_or(0) + 1)
+}
+
+/// Token counts normalized across providers: `input` includes the cached
+/// tokens. Anthropic (direct and on Bedrock) reports cache reads and writes
+/// apart from `input_tokens`; OpenAI-compatible APIs and Gemini count
+/// cached tokens as part of the input.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TokenCounts {
+    pub input: i32,
+    pub output: i32,
+    pub cache_read: i32,
+    pub cache_write: i32,
+}
+
+impl TokenCounts {
+    pub fn from_usage(usage: &Usage, api_provider: &ApiProvider) -> Self {
+        let cache_read = usage.cache_read_input_tokens.unwrap_or(0);
+        let cache_write = usage.cache_write_input_tokens.unwrap_or(0);
+        let mut input = usage.input_tokens.unwrap_or(0);
+        if matches!(
+            api_provider,
+            ApiProvider::AwsBedrock | ApiProvider::Anthropic
+        ) {
+            input += cache_read + cache_write;
+        }
+        Self {
+            input,
+            output: usage.output_tokens.unwrap_or(0),
+            cache_read,
+            cache_write,
+        }
+    }
+
+    pub fn cost(&self, price: &Price) -> f64 {
+        let uncached = (self.input - self.cache_read - self.cache_write).max(0) as f64;
+        (uncached * price.input
+            + self.cache_read as f64 * price.cache_read.unwrap_or(price.input)
+            + self.cache_write as f64 * price.cache_write.unwrap_or(price.input)
+            + self.output as f64 * price.output)
+            / 1_000_000.0
+    }
+}
+
+/// Record the usage of an answer in the ledger; returns it for the
+/// message metadata. `model` is the model that answered (the target of a
+/// virtual model), whose price applies.
+pub fn record(
+    conn: &mut DbConnection,
+    message_id: &str,
+    chat_id: &str,
+    user_id: &str,
+    model: &Model,
+    usage: &Usage,
+) -> Result<MessageUsage, AppError> {
+    let counts = TokenCounts::from_usage(usage, &ApiProvider::from(model.api_provider.clone()));
+    let price = current_price(conn, &model.model_id)?;
+    let cost = price.as_ref().map(|price| counts.cost(price));
+
+    let record = MessageUsageRecord {
+        id: Uuid::new_v4().to_string(),
+        message_id: message_id.to_string(),
+        chat_id: chat_id.to_string(),
+        user_id: user_id.to_string(),
+        model_id: model.model_id.clone(),
+        input_tokens: counts.input,
+        output_tokens: counts.output,
+        cache_read_input_tokens: counts.cache_read,
+        cache_write_input_tokens: counts.cache_write,
+        cost,
+        price_version: price.as_ref().map(|price| price.version),
+        created_at: Utc::now().naive_utc(),
+    };
+    diesel::insert_into(message_usage::table)
+        .values(&record)
+        .execute(conn)
+        .map_err(|e| AppError::Database(e.to_string()))?;
+    debug!(
+        "Usage of message {}: {:?}, cost {:?}",
+        message_id, counts, cost
+    );
+
+    Ok(MessageUsage {
+        input_tokens: counts.input,
+        output_tokens: counts.output,
+        cache_read_input_tokens: Some(counts.cache_read),
+        cache_write_input_tokens: Some(counts.cache_write),
+        cost,
+    })
+}
+
+/// Ledger rows in `[start, end)`, of one user or of everyone.
+pub fn load_records(
+    conn: &mut DbConnection,
+    user_id: Option<&str>,
+    start: Option<NaiveDateTime>,
+    end: Option<NaiveDateTime>,
+) -> Result<Vec<MessageUsageRecord>, AppError> {
+    let mut query = message_usage::table.into_boxed();
+    if let Some(user_id) = user_id {
+        query = query.filter(message_usage::user_id.eq(user_id.to_string()));
+    }
+    if let Some(start) = start {
+        query = query.filter(message_usage::created_at.ge(start));
+    }
+    if let Some(end) = end {
+        query = query.filter(message_usage::created_at.lt(end));
+    }
+    query
+        .order(message_usage::created_at.asc())
+        .load(conn)
+        .map_err(|e| AppError::Database(e.to_string()))
+}
+
+fn add_record(stats: &mut UsageStats, record: &MessageUsageRecord) {
+    stats.requests += 1;
+    stats.input_tokens += record.input_tokens as i64;
+    stats.output_tokens += record.output_tokens as i64;
+    stats.cache_read_input_tokens += record.cache_read_input_tokens as i64;
+    stats.cache_write_input_tokens += record.cache_write_input_tokens as i64;
+    match record.cost {
+        Some(cost) => stats.cost += cost,
+        None => stats.unpriced_requests += 1,
+    }
+}
+
+/// Group ledger rows; returns the groups (most expensive first, days in
+/// date order) and the grand total.
+pub fn aggregate(
+    records: &[MessageUsageRecord],
+    group_by: UsageGroupBy,
+) -> (Vec<UsageStats>, UsageStats) {
+    let mut groups: HashMap<String, UsageStats> = HashMap::new();
+    let mut total = UsageStats {
+        key: "total".to_string(),
+        ..Default::default()
+    };
+    for record in records {
+        let key = match group_by {
+            UsageGroupBy::Chat => record.chat_id.clone(),
+            UsageGroupBy::Model => record.model_id.clone(),
+            UsageGroupBy::User => record.user_id.clone(),
+            UsageGroupBy::Day => record.created_at.date().format("%Y-%m-%d").to_string(),
+        };
+        let stats = groups.entry(key.clone()).or_insert_with(|| UsageStats {
+            key,
+            ..Default::default()
+        });
+        add_record(stats, record);
+        add_record(&mut total, record);
+    }
+
+    let mut items: Vec<UsageStats> = groups.into_values().collect();
+    match group_by {
+        UsageGroupBy::Day => items.sort_by(|a, b| a.key.cmp(&b.key)),
+        _ => items.sort_by(|a, b| {
+            b.cost
+                .total_cmp(&a.cost)
+                .then(b.input_tokens.cmp(&a.input_tokens))
+                .then(a.key.cmp(&b.key))
+        }),
+    }
+    (items, total)
+}
+
+/// Chat titles and user emails for the grouped keys.
+pub fn add_labels(
+    conn: &mut DbConnection,
+    items: &mut [UsageStats],
+    group_by: UsageGroupBy,
+) -> Result<(), AppError> {
+    let keys: Vec<String> = items.iter().map(|item| item.key.clone()).collect();
+    let labels: HashMap<String, String> = match group_by {
+        UsageGroupBy::Chat => chats::table
+            .filter(chats::id.eq_any(&keys))
+            .load::<Chat>(conn)
+            .map_err(|e| AppError::Database(e.to_string()))?
+            .into_iter()
+            .map(|chat| (chat.id, chat.title))
+            .collect(),
+        UsageGroupBy::User => users::table
+            .filter(users::id.eq_any(&keys))
+            .load::<User>(conn)
+            .map_err(|e| AppError::Database(e.to_string()))?
+            .into_iter()
+            .map(|user| (user.id, user.email))
+            .collect(),
+        UsageGroupBy::Model | UsageGroupBy::Day => return Ok(()),
+    };
+    for item in items {
+        item.label = labels.get(&item.key).cloned();
+    }
+    Ok(())
+}
+
+/// Unix seconds to a UTC timestamp.
+pub fn timestamp(seconds: i64) -> Option<NaiveDateTime> {
+    DateTime::<Utc>::from_timestamp(seconds, 0).map(|t| t.naive_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(input: i32, output: i32, cache_read: i32, cache_write: i32) -> Usage {
+        Usage {
+            input_tokens: Some(input),
+            output_tokens: Some(output),
+            total_tokens: None,
+            cache_read_input_tokens: Some(cache_read),
+            cache_write_input_tokens: Some(cache_write),
+        }
+    }
+
+    fn record(chat_id: &str, model_id: &str, day: u32, cost: Option<f64>) -> MessageUsageRecord {
+        MessageUsageRecord {
+            id: format!("{}-{}-{}", chat_id, model_id, day),
+            message_id: format!("{}-{}-{}", chat_id, model_id, day),
+            chat_id: chat_id.to_string(),
+            user_id: "u1".to_string(),
+            model_id: model_id.to_string(),
+            input_tokens: 100,
+            output_tokens: 10,
+            cache_read_input_tokens: 0,
+            cache_write_input_tokens: 0,
+            cost,
+            price_version: cost.map(|_| 0),
+            created_at: chrono::NaiveDate::from_ymd_opt(2026, 10, day)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn default_prices_match_longest_fragment_first() {
+        assert_eq!(default_price("gpt-4o-mini-2024-07-18").unwrap().input, 0.15);
+        assert_eq!(default_price("gpt-4o").unwrap().input, 2.5);
+        assert_eq!(
+            default_price("us.anthropic.claude-sonnet-4-20250514-v1:0")
+                .unwrap()
+                .output,
+            15.0
+        );
+        assert!(default_price("my-local-llama").is_none());
+    }
+
+    #[test]
+    fn cached_tokens_are_normalized_and_priced() {
+        let price = default_price("claude-sonnet-4").unwrap();
+        // Anthropic reports cache reads and writes apart from the input
+        let anthropic =
+            TokenCounts::from_usage(&usage(1000, 500, 8000, 1000), &ApiProvider::Anthropic);
+        assert_eq!(anthropic.input, 10_000);
+        let expected = (1000.0 * 3.0 + 8000.0 * 0.3 + 1000.0 * 3.75 + 500.0 * 15.0) / 1e6;
+        assert!((anthropic.cost(&price) - expected).abs() < 1e-12);
+
+        // OpenAI counts them in the input
+        let openai = TokenCounts::from_usage(&usage(10_000, 500, 8000, 0), &ApiProvider::OpenAi);
+        assert_eq!(openai.input, 10_000);
+        assert_eq!(openai.cache_read, 8000);
+    }
+
+    #[test]
+    fn aggregates_by_group() {
+        let records = vec![
+            record("c1", "gpt-4o", 1, Some(0.5)),
+            record("c2", "gpt-4o", 2, Some(1.5)),
+            record("c1", "local", 2, None),
+        ];
+
+        let (by_chat, total) = aggregate(&records, UsageGroupBy::Chat);
+        assert_eq!(by_chat[0].key, "c2");
+        assert_eq!(by_chat[1].requests, 2);
+        assert_eq!(by_chat[1].unpriced_requests, 1);
+        assert_eq!(total.requests, 3);
+        assert_eq!(total.input_tokens, 300);
+        assert!((total.cost - 2.0).abs() < 1e-12);
+
+        let (by_day, _) = aggregate(&records, UsageGroupBy::Day);
+        assert_eq!(
+            by_day.iter().map(|s| s.key.as_str()).collect::<Vec<_>>(),
+            vec!["2026-10-01", "2026-10-02"]
+        );
+    }
+}
@@ -1,20 +1,35 @@
-//! Web search tool backed by the Yandex Search API v2 (Node parity:
-//! yandex.web_search.ts + openai.tools.ts CustomWebSearchTool). The API
-//! returns base64-encoded XML; results are extracted with lightweight tag
-//! scanning (url/title/passages).
+//! Web search tool (Node parity: openai.tools.ts CustomWebSearchTool) over
+//! the configured search engine, see `services::search_backend`.
+//!
+//! The URL fetch tool reads the page behind a result (or a link the user
+//! pasted): HTML is reduced to Markdown with the same kind of tag scanning,
+//! PDFs to their text. Only public addresses are fetched, redirects
+//! included, and both the download and the returned text are capped.
 
-use base64::Engine;
+use regex::{Captures, Regex};
 use serde::Serialize;
 use serde_json::json;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tracing::debug;
+use url::{Host, Url};
 
 use crate::config::AppConfig;
 use crate::services::ai::{ExecutableTool, ToolBackend, ToolSpec};
+use crate::services::search_backend::SearchBackend;
 use crate::utils::errors::AppError;
 
 pub const WEB_SEARCH_TOOL_NAME: &str = "internal_web_search";
-const DEFAULT_SEARCH_API_URL: &str = "https://searchapi.api.cloud.yandex.net/v2/web/search";
 pub const DEFAULT_RESULTS_LIMIT: usize = 5;
 
+pub const URL_FETCH_TOOL_NAME: &str = "internal_url_fetch";
+/// Largest response body downloaded
+const URL_FETCH_MAX_BYTES: usize = 5 * 1024 * 1024;
+/// Longest page text returned to the model
+const URL_FETCH_MAX_CHARS: usize = 50_000;
+const URL_FETCH_TIMEOUT: Duration = Duration::from_secs(20);
+const URL_FETCH_MAX_REDIRECTS: usize = 5;
+
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SearchResult {
@@ -25,14 +40,13 @@ pub struct SearchResult {
 }
 
 pub fn web_search_available(config: &AppConfig) -> bool {
-    config.yandex_search_api_key.is_some() && config.yandex_folder_id.is_some()
+    SearchBackend::from_config(config).is_some()
 }
 
-/// The web search tool for a chat session, when the config has Yandex
-/// Search credentials (Node's CustomWebSearchTool).
+/// The web search tool for a chat session, when the config selects a
+/// usable search backend (Node's CustomWebSearchTool).
 pub fn web_search_tool(config: &AppConfig) -> Option<ExecutableTool> {
-    let api_key = config.yandex_search_api_key.clone()?;
-    let folder_id = config.yandex_folder_id.clone()?;
+    let backend = SearchBackend::from_config(config)?;
     Some(ExecutableTool {
         spec: ToolSpec {
             name: WEB_SEARCH_TOOL_NAME.to_string(),
@@ -49,61 +63,32 @@ pub fn web_search_tool(config: &AppConfig) -> Option<ExecutableTool> {
                 "required": ["query"],
             }),
         },
-        backend: ToolBackend::WebSearch {
-            api_key,
-            folder_id,
-            api_url: config.yandex_search_api_url.clone(),
-        },
+        backend: ToolBackend::WebSearch { backend },
+        requires_approval: false,
+        timeout: None,
     })
 }
 
-pub async fn search(
-    api_key: &str,
-    folder_id: &str,
-    api_url: Option<&str>,
-    query: &str,
-    limit: usize,
-) -> Result<Vec<SearchResult>, AppError> {
-    let url = api_url.unwrap_or(DEFAULT_SEARCH_API_URL);
-
-    let body = json!({
-        "query": { "searchType": "SEARCH_TYPE_COM", "queryText": query },
-        "folderId": folder_id,
-        "maxPassages": 5,
-        "docsInGroup": 3,
-        "l10n": "LOCALIZATION_EN",
-        "responseFormat": "FORMAT_XML",
-    });
-
-    let response = reqwest::Client::new()
-        .post(url)
-        .header("Content-Type", "application/json")
-        .header("Authorization", format!("Api-Key {}", api_key))
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| AppError::Http(format!("Web search request failed: {}", e)))?;
-
-    let payload: serde_json::Value = response
-        .json()
-        .await
-        .map_err(|e| AppError::Internal(format!("Web search response parse failed: {}", e)))?;
-
-    let Some(raw) = payload.get("rawData").and_then(|v| v.as_str()) else {
-        let message = payload
-            .get("message")
-            .and_then(|m| m.as_str())
-            .unwrap_or("no rawData in response");
-        return Err(AppError::Http(format!("Web search API error: {}", message)));
-    };
-
-    let xml = base64::engine::general_purpose::STANDARD
-        .decode(raw)
-        .ok()
-        .and_then(|b| String::from_utf8(b).ok())
-        .ok_or_else(|| AppError::Internal("Invalid web search rawData".to_string()))?;
-
-    Ok(extract_results(&xml, limit))
+/// The URL fetch tool; needs no configuration.
+pub fn url_fetch_tool() -> ExecutableTool {
+    ExecutableTool {
+        spec: ToolSpec {
+            name: URL_FETCH_TOOL_NAME.to_string(),
+            description: "Fetch a web page (HTML, PDF or text) by its URL and return its \
+                          readable content as Markdown"
+                .to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "url": { "type": "string", "description": "The http(s) URL to fetch" },
+                },
+                "required": ["url"],
+            }),
+        },
+        backend: ToolBackend::UrlFetch,
+        requires_approval: false,
+        timeout: None,
+    }
 }
 
 /// Format results as the tool output the model consumes (Node's
@@ -131,48 +116,421 @@ pub fn results_to_tool_content(results: &[SearchResult]) -> String {
     )
 }
 
-fn extract_results(xml: &str, limit: usize) -> Vec<SearchResult> {
-    let mut results = Vec::new();
-    // Each result doc looks like <doc …>…<url>…</url>…<title>…</title>…
-    // <passages>…</passages>…</doc>; hlword markup is stripped.
-    for doc in xml.split("<doc").skip(1) {
-        let doc = doc.split("</doc>").next().unwrap_or("");
-        let Some(url) = tag_text(doc, "url") else {
+/// Readable content of a fetched URL
+#[derive(Debug, Clone)]
+pub struct FetchedPage {
+    /// After redirects
+    pub url: String,
+    pub title: Option<String>,
+    /// Markdown for HTML, plain text otherwise
+    pub content: String,
+    pub truncated: bool,
+}
+
+/// Fetch a public http(s) URL and extract its readable content.
+pub async fn fetch_url(url: &str) -> Result<FetchedPage, AppError> {
+    fetch(url, false).await
+}
+
+async fn fetch(url: &str, allow_private: bool) -> Result<FetchedPage, AppError> {
+    let mut url = Url::parse(url.trim())
+        .map_err(|e| AppError::BadRequest(format!("Invalid URL {}: {}", url, e)))?;
+
+    for _ in 0..=URL_FETCH_MAX_REDIRECTS {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(AppError::BadRequest(format!(
+                "Unsupported URL scheme: {}",
+                url.scheme()
+            )));
+        }
+        // Connect to the address that was checked, not to whatever the
+        // name resolves to on the next lookup
+        let address = checked_address(&url, allow_private).await?;
+        let mut builder = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(URL_FETCH_TIMEOUT);
+        if let Some(Host::Domain(domain)) = url.host() {
+            builder = builder.resolve(domain, address);
+        }
+        let client = builder
+            .build()
+            .map_err(|e| AppError::Internal(format!("URL fetch client: {}", e)))?;
+
+        debug!("Fetching {}", url);
+        let response = client
+            .get(url.clone())
+            .header(
+                "Accept",
+                "text/html,application/xhtml+xml,application/pdf,text/plain;q=0.9,*/*;q=0.5",
+            )
+            .send()
+            .await
+            .map_err(|e| AppError::Http(format!("URL fetch failed: {}", e)))?;
+
+        let status = response.status();
+        if status.is_redirection() {
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|l| l.to_str().ok())
+                .ok_or_else(|| AppError::Http(format!("Redirect without location ({})", status)))?;
+            url = url
+                .join(location)
+                .map_err(|e| AppError::Http(format!("Invalid redirect location: {}", e)))?;
             continue;
-        };
-        let title = tag_text(doc, "title").unwrap_or_else(|| url.clone());
-        let domain = tag_text(doc, "domain")
-            .unwrap_or_else(|| url.split('/').nth(2).unwrap_or_default().to_string());
-        let passages: Vec<String> = doc
-            .split("<passage>")
-            .skip(1)
-            .filter_map(|p| p.split("</passage>").next())
-            .map(strip_tags)
-            .filter(|p| !p.is_empty())
-            .collect();
-
-        results.push(SearchResult {
-            title: strip_tags(&title),
-            url,
-            domain,
-            summary: (!passages.is_empty()).then(|| passages.join(" ")),
+        }
+        if !status.is_success() {
+            return Err(AppError::Http(format!("URL fetch failed: HTTP {}", status)));
+        }
+
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|t| t.to_str().ok())
+            .map(|t| t.split(';').next().unwrap_or("").trim().to_lowercase())
+            .unwrap_or_default();
+        let bytes = read_limited(response).await?;
+        let (title, content) = extract_content(&content_type, bytes, &url).await?;
+        let (content, truncated) = truncate_chars(content, URL_FETCH_MAX_CHARS);
+        return Ok(FetchedPage {
+            url: url.to_string(),
+            title,
+            content,
+            truncated,
         });
-        if results.len() >= limit {
-            break;
+    }
+    Err(AppError::Http("Too many redirects".to_string()))
+}
+
+/// The address to connect to for the URL; every address the host resolves
+/// to must be public.
+pub(crate) async fn checked_address(
+    url: &Url,
+    allow_private: bool,
+) -> Result<SocketAddr, AppError> {
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| AppError::BadRequest("URL without a port".to_string()))?;
+    let addresses: Vec<SocketAddr> = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+        Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|e| AppError::Http(format!("Cannot resolve {}: {}", domain, e)))?
+            .collect(),
+        None => return Err(AppError::BadRequest("URL without a host".to_string())),
+    };
+    if !allow_private && addresses.iter().any(|a| !is_public_ip(a.ip())) {
+        return Err(AppError::BadRequest(format!(
+            "URL host {} is not a public address",
+            url.host_str().unwrap_or_default()
+        )));
+    }
+    addresses
+        .into_iter()
+        .next()
+        .ok_or_else(|| AppError::Http("URL host has no address".to_string()))
+}
+
+/// Whether the address is reachable on the internet: not loopback,
+/// private, link-local, carrier-grade NAT, unique-local or reserved.
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                || (a == 198 && (18..20).contains(&b))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            // IPv4-mapped and -compatible addresses
+            if let Some(v4) = ip.to_ipv4() {
+                return is_public_ip(IpAddr::V4(v4));
+            }
+            let [first, second, ..] = ip.segments();
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                || (first == 0x2001 && second == 0x0db8)
+                // NAT64 embeds IPv4 addresses
+                || (first == 0x0064 && second == 0xff9b))
         }
     }
-    results
 }
 
-fn tag_text(source: &str, tag: &str) -> Option<String> {
-    let open = format!("<{}>", tag);
-    let close = format!("</{}>", tag);
-    let start = source.find(&open)? + open.len();
-    let end = source[start..].find(&close)? + start;
-    Some(source[start..end].trim().to_string())
+async fn read_limited(mut response: reqwest::Response) -> Result<Vec<u8>, AppError> {
+    let too_large = || {
+        AppError::BadRequest(format!(
+            "The response is larger than {} MB",
+            URL_FETCH_MAX_BYTES / (1024 * 1024)
+        ))
+    };
+    if response
+        .content_length()
+        .is_some_and(|length| length as usize > URL_FETCH_MAX_BYTES)
+    {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| AppError::Http(format!("URL fetch failed: {}", e)))?
+    {
+        if bytes.len() + chunk.len() > URL_FETCH_MAX_BYTES {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
 }
 
-fn strip_tags(text: &str) -> String {
+/// Title and readable text of a response body, by content type (sniffed
+/// when the server sent none).
+async fn extract_content(
+    content_type: &str,
+    bytes: Vec<u8>,
+    url: &Url,
+) -> Result<(Option<String>, String), AppError> {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(512)]).to_lowercase();
+    let content_type = match content_type {
+        "" | "application/octet-stream" if bytes.starts_with(b"%PDF-") => "application/pdf",
+        "" if head.trim_start().starts_with("<!doctype html") || head.contains("<html") => {
+            "text/html"
+        }
+        "" => "text/plain",
+        other => other,
+    };
+
+    match content_type {
+        "text/html" | "application/xhtml+xml" => Ok(html_to_markdown(
+            &String::from_utf8_lossy(&bytes),
+            Some(url),
+        )),
+        "application/pdf" => {
+            let text =
+                tokio::task::spawn_blocking(move || pdf_extract::extract_text_from_mem(&bytes))
+                    .await
+                    .map_err(|_| AppError::Internal("PDF text extraction failed".to_string()))?
+                    .map_err(|e| {
+                        AppError::Internal(format!("PDF text extraction failed: {}", e))
+                    })?;
+            Ok((None, collapse_blank_lines(&text)))
+        }
+        t if t.starts_with("text/")
+            || t == "application/json"
+            || t == "application/xml"
+            || t.ends_with("+json")
+            || t.ends_with("+xml") =>
+        {
+            Ok((None, String::from_utf8_lossy(&bytes).trim().to_string()))
+        }
+        other => Err(AppError::BadRequest(format!(
+            "Unsupported content type: {}",
+            other
+        ))),
+    }
+}
+
+pub(crate) fn truncate_chars(text: String, max_chars: usize) -> (String, bool) {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => (text[..end].to_string(), true),
+        None => (text, false),
+    }
+}
+
+/// Format a fetched page as the tool output the model consumes.
+pub fn page_to_tool_content(page: &FetchedPage) -> String {
+    format!(
+        "# Page content\nurl: {}\ntitle: {}\n\n{}{}",
+        page.url,
+        page.title.as_deref().unwrap_or("N/A"),
+        page.content,
+        if page.truncated {
+            "\n\n[The content was truncated]"
+        } else {
+            ""
+        },
+    )
+}
+
+lazy_static::lazy_static! {
+    static ref TITLE: Regex = Regex::new(r"(?is)<title\b[^>]*>(.*?)</title\s*>").unwrap();
+    static ref COMMENT: Regex = Regex::new(r"(?s)<!--.*?-->").unwrap();
+    static ref PRE: Regex = Regex::new(r"(?is)<pre\b[^>]*>(.*?)</pre\s*>").unwrap();
+    static ref WHITESPACE: Regex = Regex::new(r"\s+").unwrap();
+    static ref LINK: Regex =
+        Regex::new(r#"(?is)<a\b[^>]*?\bhref\s*=\s*["']([^"']*)["'][^>]*>(.*?)</a\s*>"#).unwrap();
+    static ref BOLD: Regex = Regex::new(r"(?i)</?(strong|b)\s*>").unwrap();
+    static ref ITALIC: Regex = Regex::new(r"(?i)</?(em|i)\s*>").unwrap();
+    static ref CODE: Regex = Regex::new(r"(?i)</?code\b[^>]*>").unwrap();
+    static ref LINE_BREAK: Regex = Regex::new(r"(?i)<br\s*/?>").unwrap();
+    static ref LIST_ITEM: Regex = Regex::new(r"(?i)<li\b[^>]*>").unwrap();
+    static ref TABLE_CELL: Regex = Regex::new(r"(?i)</t[dh]\s*>").unwrap();
+    static ref BLOCKQUOTE: Regex = Regex::new(r"(?i)<blockquote\b[^>]*>").unwrap();
+    static ref BLOCK: Regex = Regex::new(
+        r"(?i)</?(p|div|section|article|main|header|ul|ol|dl|dt|dd|table|thead|tbody|tr|blockquote|figure|figcaption|hr)\b[^>]*>"
+    )
+    .unwrap();
+    static ref TAG: Regex = Regex::new(r"(?s)<[^>]*>").unwrap();
+    static ref ENTITY: Regex = Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").unwrap();
+    static ref BLANK_LINES: Regex = Regex::new(r"\n{3,}").unwrap();
+    static ref SKIPPED: [Regex; 11] = SKIPPED_ELEMENTS
+        .map(|element| Regex::new(&format!(r"(?is)<{0}\b[^>]*>.*?</{0}\s*>", element)).unwrap());
+    static ref MAIN_CONTENT: [Regex; 3] = MAIN_ELEMENTS
+        .map(|element| Regex::new(&format!(r"(?is)<{0}\b[^>]*>(.*)</{0}\s*>", element)).unwrap());
+    /// `<h1>` to `<h6>`, by level - 1
+    static ref HEADINGS: [Regex; 6] = [1, 2, 3, 4, 5, 6]
+        .map(|level| Regex::new(&format!(r"(?is)<h{0}\b[^>]*>(.*?)</h{0}\s*>", level)).unwrap());
+}
+
+/// Elements without readable content
+const SKIPPED_ELEMENTS: [&str; 11] = [
+    "head", "script", "style", "noscript", "template", "svg", "nav", "footer", "aside", "form",
+    "iframe",
+];
+
+/// Elements holding the main content, most specific first
+const MAIN_ELEMENTS: [&str; 3] = ["article", "main", "body"];
+
+/// Reduce an HTML page to its title and the Markdown of its main content
+/// (the article, else main, else the body). Relative links are resolved
+/// against `base`.
+pub fn html_to_markdown(html: &str, base: Option<&Url>) -> (Option<String>, String) {
+    let title = TITLE
+        .captures(html)
+        .map(|c| inline_text(&c[1]))
+        .filter(|t| !t.is_empty());
+
+    let mut html = COMMENT.replace_all(html, " ").into_owned();
+    for skipped in SKIPPED.iter() {
+        html = skipped.replace_all(&html, " ").into_owned();
+    }
+    let main = MAIN_CONTENT
+        .iter()
+        .find_map(|element| element.captures(&html).map(|c| c[1].to_string()))
+        .unwrap_or(html);
+
+    // Preformatted blocks keep their whitespace: set aside until the end
+    let mut preformatted = Vec::new();
+    let text = PRE.replace_all(&main, |c: &Captures| {
+        preformatted.push(format!(
+            "\n\n```\n{}\n```\n\n",
+            decode_entities(&strip_tags(&c[1]))
+        ));
+        format!("\u{0}{}\u{0}", preformatted.len() - 1)
+    });
+    let mut text = WHITESPACE.replace_all(&text, " ").into_owned();
+
+    for (ndx, heading) in HEADINGS.iter().enumerate() {
+        let level = ndx + 1;
+        text = heading
+            .replace_all(&text, |c: &Captures| {
+                format!("\n\n{} {}\n\n", "#".repeat(level), inline_text(&c[1]))
+            })
+            .into_owned();
+    }
+    let text = LINK.replace_all(&text, |c: &Captures| {
+        let label = inline_text(&c[2]);
+        let href = decode_entities(&c[1]);
+        let target = match base {
+            _ if href.starts_with('#') || href.starts_with("javascript:") => None,
+            Some(base) => base.join(&href).ok().map(|u| u.to_string()),
+            None => Some(href),
+        };
+        match target {
+            Some(target) if !label.is_empty() => format!("[{}]({})", label, target),
+            _ => label,
+        }
+    });
+    let text = BOLD.replace_all(&text, "**");
+    let text = ITALIC.replace_all(&text, "_");
+    let text = CODE.replace_all(&text, "`");
+    let text = LINE_BREAK.replace_all(&text, "\n");
+    let text = LIST_ITEM.replace_all(&text, "\n- ");
+    let text = TABLE_CELL.replace_all(&text, " | ");
+    let text = BLOCKQUOTE.replace_all(&text, "\n\n> ");
+    let text = BLOCK.replace_all(&text, "\n\n");
+    let text = TAG.replace_all(&text, "");
+    let text = decode_entities(&text);
+
+    let mut markdown = collapse_blank_lines(&text);
+    for (ndx, block) in preformatted.iter().enumerate() {
+        markdown = markdown.replace(&format!("\u{0}{}\u{0}", ndx), block);
+    }
+    let markdown = BLANK_LINES
+        .replace_all(&markdown, "\n\n")
+        .trim()
+        .to_string();
+    (title, markdown)
+}
+
+/// Text of an inline fragment: tags stripped, entities decoded, whitespace
+/// collapsed.
+fn inline_text(html: &str) -> String {
+    WHITESPACE
+        .replace_all(&decode_entities(&strip_tags(html)), " ")
+        .trim()
+        .to_string()
+}
+
+/// Trim the lines and keep at most one blank line between paragraphs
+fn collapse_blank_lines(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    BLANK_LINES
+        .replace_all(&lines.join("\n"), "\n\n")
+        .trim()
+        .to_string()
+}
+
+fn decode_entities(text: &str) -> String {
+    ENTITY
+        .replace_all(text, |c: &Captures| {
+            let entity = &c[1];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                "ndash" => Some('–'),
+                "mdash" => Some('—'),
+                "hellip" => Some('…'),
+                "laquo" => Some('«'),
+                "raquo" => Some('»'),
+                "lsquo" => Some('‘'),
+                "rsquo" => Some('’'),
+                "ldquo" => Some('“'),
+                "rdquo" => Some('”'),
+                "copy" => Some('©'),
+                _ => match entity.strip_prefix('#') {
+                    Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16)
+                        .ok()
+                        .and_then(char::from_u32),
+                    Some(decimal) => decimal.parse().ok().and_then(char::from_u32),
+                    None => None,
+                },
+            };
+            decoded
+                .map(String::from)
+                .unwrap_or_else(|| c[0].to_string())
+        })
+        .into_owned()
+}
+
+pub(crate) fn strip_tags(text: &str) -> String {
     let mut out = String::with_capacity(text.len());
     let mut in_tag = false;
     for ch in text.chars() {
@@ -189,23 +547,99 @@ fn strip_tags(text: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::test_server::{StubResponse, StubServer};
+
+    const PAGE: &str = r#"<!DOCTYPE html><html><head><title>Release &amp; notes</title>
+        <style>body { color: red }</style><script>track()</script></head>
+        <body><nav><a href="/">Home</a></nav>
+        <article><h1>Version   2.0</h1>
+          <p>New <strong>tools</strong> and a <a href="/docs/tools">guide</a>.</p>
+          <ul><li>Fetch &lt;URLs&gt;</li><li>Approve calls</li></ul>
+          <pre><code>cargo   run
+  --release</code></pre>
+        </article><footer>Copyright</footer></body></html>"#;
+
+    #[test]
+    fn html_pages_become_markdown() {
+        let base = Url::parse("https://example.com/blog/post").unwrap();
+        let (title, markdown) = html_to_markdown(PAGE, Some(&base));
+        assert_eq!(title.as_deref(), Some("Release & notes"));
+        assert_eq!(
+            markdown,
+            "# Version 2.0\n\nNew **tools** and a [guide](https://example.com/docs/tools).\n\n\
+             - Fetch <URLs>\n- Approve calls\n\n```\ncargo   run\n  --release\n```"
+        );
+    }
 
     #[test]
-    fn extracts_docs_from_yandex_xml() {
-        let xml = r#"<response><results><grouping><group>
-            <doc id="1"><url>https://example.com/a</url><domain>example.com</domain>
-              <title>Hello <hlword>world</hlword></title>
-              <passages><passage>First <hlword>match</hlword>.</passage><passage>Second.</passage></passages>
-            </doc></group><group>
-            <doc id="2"><url>https://other.io/b</url><title>Other</title></doc>
-            </group></grouping></results></response>"#;
-        let results = extract_results(xml, 10);
-        assert_eq!(results.len(), 2);
-        assert_eq!(results[0].title, "Hello world");
-        assert_eq!(results[0].domain, "example.com");
-        assert_eq!(results[0].summary.as_deref(), Some("First match. Second."));
-        assert_eq!(results[1].domain, "other.io");
-        assert_eq!(results[1].summary, None);
+    fn only_public_addresses_are_fetched() {
+        for private in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public_ip(private.parse().unwrap()), "{}", private);
+        }
+        for public in ["93.184.216.34", "8.8.8.8", "2606:4700::1111"] {
+            assert!(is_public_ip(public.parse().unwrap()), "{}", public);
+        }
+    }
+
+    #[tokio::test]
+    async fn private_and_non_http_urls_are_refused() {
+        let error = fetch_url("http://127.0.0.1:8080/admin").await.unwrap_err();
+        assert!(error.to_string().contains("not a public address"));
+        let error = fetch_url("http://[::1]/").await.unwrap_err();
+        assert!(error.to_string().contains("not a public address"));
+        let error = fetch_url("file:///etc/passwd").await.unwrap_err();
+        assert!(error.to_string().contains("Unsupported URL scheme"));
+    }
+
+    #[tokio::test]
+    async fn fetches_pages_following_redirects() {
+        let stub = StubServer::start(vec![
+            StubResponse::text(301, "text/html", "").with_header("Location", "/final"),
+            StubResponse::text(200, "text/html; charset=utf-8", PAGE),
+        ])
+        .await;
+
+        let page = fetch(&format!("{}/start", stub.base_url), true)
+            .await
+            .unwrap();
+        assert_eq!(page.url, format!("{}/final", stub.base_url));
+        assert_eq!(page.title.as_deref(), Some("Release & notes"));
+        assert!(page.content.starts_with("# Version 2.0"));
+        assert!(!page.truncated);
+        assert!(stub.requests()[1].request_line.starts_with("GET /final"));
+
+        let content = page_to_tool_content(&page);
+        assert!(content.contains("title: Release & notes"));
+    }
+
+    #[tokio::test]
+    async fn text_is_capped_and_binaries_refused() {
+        let stub = StubServer::start(vec![
+            StubResponse::text(200, "text/plain", "a".repeat(URL_FETCH_MAX_CHARS + 10)),
+            StubResponse::text(200, "image/png", "png"),
+        ])
+        .await;
+
+        let page = fetch(&stub.base_url, true).await.unwrap();
+        assert_eq!(page.content.len(), URL_FETCH_MAX_CHARS);
+        assert!(page.truncated);
+
+        let error = fetch(&stub.base_url, true).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Unsupported content type: image/png"));
     }
 
     #[test]
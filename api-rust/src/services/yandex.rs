@@ -54,7 +54,9 @@ impl YandexService {
             .yandex_openai_api_url
             .clone()
             .unwrap_or_else(|| YANDEX_OPENAI_API_URL.to_string());
-        Ok(OpenAIProtocol::new(base_url, None, None, "Yandex").with_auth_header(auth_header))
+        Ok(OpenAIProtocol::new(base_url, None, None, "Yandex")
+            .with_auth_header(auth_header)
+            .with_retry(self.config.retry.clone()))
     }
 
     /// Model ids are stored with a `{folder}` placeholder
@@ -88,15 +90,16 @@ impl AIProviderService for YandexService {
         self.protocol()?.invoke(&request).await
     }
 
-    async fn invoke_model_stream<F, C, E>(
+    async fn invoke_model_stream<F, C, E, R>(
         &self,
         request: InvokeModelRequest,
-        callbacks: StreamCallbacks<F, C, E>,
+        callbacks: StreamCallbacks<F, C, E, R>,
     ) -> Result<Vec<ExecutedToolCall>, AppError>
     where
         F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
         C: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
         E: Fn(AppError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
+        R: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync,
     {
         let request = self.resolve_request(request)?;
         self.protocol()?.invoke_stream(&request, &callbacks).await
@@ -162,6 +165,12 @@ impl AIProviderService for YandexService {
                 top_p: None,
                 system_prompt: None,
                 tools: None,
+                thinking_budget: None,
+                response_schema: None,
+                tool_approver: None,
+                tool_execution: Default::default(),
+                tool_calls_ran: Default::default(),
+                executed_tools: Default::default(),
             };
 
             match self.invoke_model(test_request).await {
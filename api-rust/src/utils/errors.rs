@@ -27,6 +27,9 @@ pub enum AppError {
     #[error("JSON error: {0}")]
     Json(String),
 
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
     #[error("Not found: {0}")]
     #[allow(dead_code)]
     NotFound(String),
@@ -51,6 +54,7 @@ impl Clone for AppError {
             AppError::Validation(msg) => AppError::Validation(msg.clone()),
             AppError::BadRequest(msg) => AppError::BadRequest(msg.clone()),
             AppError::Json(msg) => AppError::Json(msg.clone()),
+            AppError::QuotaExceeded(msg) => AppError::QuotaExceeded(msg.clone()),
             AppError::NotFound(msg) => AppError::NotFound(msg.clone()),
             AppError::Internal(msg) => AppError::Internal(msg.clone()),
             AppError::Aws(msg) => AppError::Aws(msg.clone()),
@@ -93,6 +97,7 @@ impl<'r> Responder<'r, 'static> for AppError {
             AppError::NotFound(_) => (Status::NotFound, self.to_string()),
             AppError::Validation(_) => (Status::BadRequest, self.to_string()),
             AppError::BadRequest(_) => (Status::BadRequest, self.to_string()),
+            AppError::QuotaExceeded(_) => (Status::TooManyRequests, self.to_string()),
             _ => (Status::InternalServerError, self.to_string()),
         };
 
@@ -1,3 +1,5 @@
 pub mod errors;
 pub mod jwt;
 pub mod logger;
+#[cfg(test)]
+pub mod test_server;